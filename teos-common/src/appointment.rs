@@ -4,9 +4,15 @@ use hex;
 use std::array::TryFromSliceError;
 use std::{convert::TryInto, fmt};
 
-use bitcoin::Txid;
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::hashes::{ripemd160, Hash};
+use bitcoin::{Transaction, Txid};
+
+use crate::cryptography;
+use crate::UserId;
 
 pub const LOCATOR_LEN: usize = 16;
+pub const UUID_LEN: usize = 20;
 
 /// User identifier for appointments.
 #[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
@@ -18,6 +24,15 @@ impl Locator {
         Locator(txid[..LOCATOR_LEN].try_into().unwrap())
     }
 
+    /// Computes the [Locator] of a transaction given its hex encoded id.
+    ///
+    /// The txid is expected in the same format `bitcoind` reports it (i.e. as a 32-byte hex string).
+    pub fn from_txid_hex(txid: &str) -> Result<Self, String> {
+        Txid::from_hex(txid)
+            .map(Locator::new)
+            .map_err(|_| "Txid must be a 32-byte hex encoded value".into())
+    }
+
     /// Encodes a locator into its byte representation.
     pub fn serialize(&self) -> Vec<u8> {
         self.0.to_vec()
@@ -45,6 +60,16 @@ impl fmt::Display for Locator {
     }
 }
 
+/// Computes the unique identifier a tower assigns to the appointment of a given user for a given [Locator].
+///
+/// The identifier is computed as `RIPEMD160(locator || user_id)`, so users can correlate their appointments with the
+/// ones reported by the tower.
+pub fn compute_uuid(user_id: UserId, locator: Locator) -> [u8; UUID_LEN] {
+    let mut uuid_data = locator.serialize();
+    uuid_data.extend(&user_id.0.serialize());
+    ripemd160::Hash::hash(&uuid_data).into_inner()
+}
+
 /// Contains data regarding an appointment between a client and the tower.
 ///
 /// An appointment is requested for every new channel update.
@@ -56,8 +81,8 @@ pub struct Appointment {
     /// Should match an encrypted penalty transaction.
     pub encrypted_blob: Vec<u8>,
     /// The delay of the `to_self` output in the penalty transaction.
-    /// Can be used by the tower to decide whether the job is worth accepting or not
-    /// (useful for accountable towers). Currently not used.
+    /// Used by the tower to decide whether the job is worth accepting or not: appointments with a delay
+    /// too short to react in time are rejected.
     pub to_self_delay: u32,
 }
 
@@ -112,6 +137,22 @@ impl Appointment {
         }
     }
 
+    /// Builds the [Appointment] that allows a tower to respond to `dispute_txid` with `penalty_tx`.
+    ///
+    /// The [Locator] is derived from the dispute txid, which is also used to encrypt the penalty transaction (check
+    /// [cryptography::encrypt]). The appointment still needs to be signed by the user before being sent to a tower.
+    pub fn from_penalty(
+        dispute_txid: &Txid,
+        penalty_tx: &Transaction,
+        to_self_delay: u32,
+    ) -> Result<Self, chacha20poly1305::aead::Error> {
+        Ok(Appointment::new(
+            Locator::new(*dispute_txid),
+            cryptography::encrypt(penalty_tx, dispute_txid)?,
+            to_self_delay,
+        ))
+    }
+
     /// Serializes an appointment to be signed.
     /// The serialization follows the same ordering as the fields in the appointment:
     ///
@@ -125,3 +166,76 @@ impl Appointment {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+
+    use bitcoin::{OutPoint, Script, TxIn, TxOut};
+
+    #[test]
+    fn test_locator_from_txid_hex() {
+        let txid_hex = "d6ac4a5e61657c4c604dcde855a1db74ec6b3e54f32695d72c5e11c7761ea1b4";
+        let locator = Locator::from_txid_hex(txid_hex).unwrap();
+        assert_eq!(locator, Locator::new(Txid::from_hex(txid_hex).unwrap()));
+        assert_eq!(locator.to_string(), "b4a11e76c7115e2cd79526f3543e6bec");
+
+        // Non-hex, short and long inputs are rejected
+        assert!(Locator::from_txid_hex("not a txid").is_err());
+        assert!(Locator::from_txid_hex(&txid_hex[..32]).is_err());
+        assert!(Locator::from_txid_hex(&format!("{}00", txid_hex)).is_err());
+    }
+
+    #[test]
+    fn test_compute_uuid() {
+        let locator = Locator::from_str("b4a11e76c7115e2cd79526f3543e6bec").unwrap();
+        let user_id =
+            UserId::from_str("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+                .unwrap();
+
+        assert_eq!(
+            hex::encode(compute_uuid(user_id, locator)),
+            "b0142098078f70bc7586f914637803b9fb11919e"
+        );
+    }
+
+    #[test]
+    fn test_appointment_from_penalty() {
+        let dispute_txid =
+            Txid::from_hex("d6ac4a5e61657c4c604dcde855a1db74ec6b3e54f32695d72c5e11c7761ea1b4")
+                .unwrap();
+        let penalty_tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(dispute_txid, 0),
+                script_sig: Script::new(),
+                sequence: 0,
+                witness: Vec::new(),
+            }],
+            output: vec![TxOut {
+                value: 42,
+                script_pubkey: Script::new(),
+            }],
+        };
+
+        let appointment = Appointment::from_penalty(&dispute_txid, &penalty_tx, 42).unwrap();
+        assert_eq!(appointment.locator, Locator::new(dispute_txid));
+        assert_eq!(appointment.to_self_delay, 42);
+        assert_eq!(
+            cryptography::decrypt(&appointment.encrypted_blob, &dispute_txid).unwrap(),
+            penalty_tx
+        );
+
+        // The built appointment can be signed by the user as any other
+        let (user_sk, user_pk) = cryptography::get_random_keypair();
+        let signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        assert!(cryptography::verify(
+            &appointment.serialize(),
+            &signature,
+            &user_pk
+        ));
+    }
+}
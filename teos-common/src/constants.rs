@@ -7,3 +7,9 @@ pub const IRREVOCABLY_RESOLVED: u32 = 100;
 // Temporary constants, may be changed
 /// Maximum size of encrypted blobs in appointments.
 pub const ENCRYPTED_BLOB_MAX_SIZE: usize = 2048;
+
+/// Minimum size of encrypted blobs in appointments.
+///
+/// Blobs are `chacha20poly1305` ciphertexts of a penalty transaction, so they are at least as big as the authentication
+/// tag (16 bytes) plus the smallest serialized transaction with one input and one output (60 bytes).
+pub const ENCRYPTED_BLOB_MIN_SIZE: usize = 76;
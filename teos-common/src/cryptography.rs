@@ -102,6 +102,9 @@ mod tests {
     use super::*;
     use bitcoin::consensus;
     use bitcoin::hashes::hex::FromHex;
+    use bitcoin::{OutPoint, Script, TxIn, TxOut};
+
+    use crate::constants::ENCRYPTED_BLOB_MIN_SIZE;
 
     const HEX_TX: &str = "010000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff54038e830a1b4d696e656420627920416e74506f6f6c373432c2005b005e7a0ae3fabe6d6d7841cd582ead8ea5dd8e3de1173cae6fcd2a53c7362ebb7fb6f815604fe07cbe0200000000000000ac0e060005f90000ffffffff04d9476026000000001976a91411dbe48cc6b617f9c6adaf4d9ed5f625b1c7cb5988ac0000000000000000266a24aa21a9ed7248c6efddd8d99bfddd7f499f0b915bffa8253003cc934df1ff14a81301e2340000000000000000266a24b9e11b6d7054937e13f39529d6ad7e685e9dd4efa426f247d5f5a5bed58cdddb2d0fa60100000000000000002b6a2952534b424c4f434b3a054a68aa5368740e8b3e3c67bce45619c2cfd07d4d4f0936a5612d2d0034fa0a0120000000000000000000000000000000000000000000000000000000000000000000000000";
     const HEX_TXID: &str = "d6ac4a5e61657c4c604dcde855a1db74ec6b3e54f32695d72c5e11c7761ea1b4";
@@ -117,6 +120,27 @@ mod tests {
         assert_eq!(encrypt(&tx, &txid).unwrap(), expected_enc_blob);
     }
 
+    #[test]
+    fn test_encrypt_min_size() {
+        // The smallest transaction that can be encrypted into a blob has one input and one output, both empty
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::new(),
+                sequence: 0,
+                witness: Vec::new(),
+            }],
+            output: vec![TxOut {
+                value: 0,
+                script_pubkey: Script::new(),
+            }],
+        };
+        let txid = Txid::from_hex(HEX_TXID).unwrap();
+        assert_eq!(encrypt(&tx, &txid).unwrap().len(), ENCRYPTED_BLOB_MIN_SIZE);
+    }
+
     #[test]
     fn test_decrypt() {
         let expected_tx = consensus::deserialize(&Vec::from_hex(HEX_TX).unwrap()).unwrap();
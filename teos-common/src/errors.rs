@@ -6,6 +6,8 @@ pub const WRONG_FIELD_SIZE: u8 = 4;
 pub const WRONG_FIELD_FORMAT: u8 = 5;
 pub const INVALID_REQUEST_FORMAT: u8 = 6;
 pub const INVALID_SIGNATURE_OR_SUBSCRIPTION_ERROR: u8 = 7;
+pub const RATE_LIMIT_EXCEEDED: u8 = 8;
+pub const USER_BANNED: u8 = 9;
 pub const SERVICE_UNAVAILABLE: u8 = 32;
 
 /// Appointment errors [33, 64]
@@ -13,6 +15,7 @@ pub const APPOINTMENT_FIELD_TOO_SMALL: u8 = 33;
 pub const APPOINTMENT_FIELD_TOO_BIG: u8 = 34;
 pub const APPOINTMENT_ALREADY_TRIGGERED: u8 = 35;
 pub const APPOINTMENT_NOT_FOUND: u8 = 36;
+pub const APPOINTMENT_LIMIT_REACHED: u8 = 37;
 
 /// Registration errors [65, 96]
 pub const REGISTRATION_RESOURCE_EXHAUSTED: u8 = 65;
@@ -22,6 +22,21 @@ impl RegistrationReceipt {
         }
     }
 
+    /// Builds a receipt from the data handed by a tower, so its signature can be verified.
+    pub fn with_signature(
+        user_id: UserId,
+        available_slots: u32,
+        subscription_expiry: u32,
+        signature: String,
+    ) -> Self {
+        RegistrationReceipt {
+            user_id,
+            available_slots,
+            subscription_expiry,
+            signature: Some(signature),
+        }
+    }
+
     pub fn user_id(&self) -> UserId {
         self.user_id
     }
@@ -51,6 +66,14 @@ impl RegistrationReceipt {
         // TODO: Check if there's any case where this can actually fail. Don't unwrap if so.
         self.signature = Some(cryptography::sign(&self.serialize(), sk).unwrap());
     }
+
+    /// Checks whether the receipt has been signed by the given tower. Unsigned receipts are never valid.
+    pub fn verify(&self, tower_id: &UserId) -> bool {
+        match &self.signature {
+            Some(signature) => cryptography::verify(&self.serialize(), signature, &tower_id.0),
+            None => false,
+        }
+    }
 }
 #[derive(Debug)]
 pub struct AppointmentReceipt {
@@ -93,3 +116,31 @@ impl AppointmentReceipt {
         self.signature = Some(cryptography::sign(&self.serialize(), sk).unwrap());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cryptography::get_random_keypair;
+
+    #[test]
+    fn test_registration_receipt_verify() {
+        let (tower_sk, tower_pk) = get_random_keypair();
+        let tower_id = UserId(tower_pk);
+        let user_id = UserId(get_random_keypair().1);
+
+        let mut receipt = RegistrationReceipt::new(user_id, 21, 420);
+        // Unsigned receipts cannot be verified
+        assert!(!receipt.verify(&tower_id));
+
+        receipt.sign(&tower_sk);
+        let signature = receipt.signature().unwrap();
+        let received = RegistrationReceipt::with_signature(user_id, 21, 420, signature.clone());
+        assert!(received.verify(&tower_id));
+
+        // Receipts signed by someone else or with tampered data are rejected
+        assert!(!received.verify(&user_id));
+        let tampered = RegistrationReceipt::with_signature(user_id, 42, 420, signature);
+        assert!(!tampered.verify(&tower_id));
+    }
+}
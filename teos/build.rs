@@ -25,6 +25,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             "GetAppointmentResponse.status",
             "#[serde(with = \"crate::api::serde_status\")]",
         )
+        .field_attribute(
+            "GetAppointmentsRequest.status",
+            "#[serde(with = \"crate::api::serde_status\")]",
+        )
+        // Challenges are only handed out through the events subscription, so they are not accepted over HTTP
+        .field_attribute("GetSubscriptionInfoRequest.challenge", "#[serde(skip)]")
         .compile(
             &[
                 "proto/teos/appointment.proto",
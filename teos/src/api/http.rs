@@ -1,23 +1,124 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures_util::{SinkExt, StreamExt};
+use serde::de::DeserializeOwned;
 use serde::{ser::SerializeSeq, Deserialize, Serialize, Serializer};
 use std::convert::Infallible;
 use std::error::Error;
+use std::fmt;
+use std::io::{Read, Write};
 use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
 use tonic::transport::Channel;
 use triggered::Listener;
+use warp::http::header::{HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, VARY};
+use warp::hyper::body::{self, Body, Bytes};
+use warp::ws::{Message, WebSocket, Ws};
 use warp::{http::StatusCode, reject, reply, Filter, Rejection, Reply};
 
-use teos_common::appointment::LOCATOR_LEN;
-use teos_common::{errors, USER_ID_LEN};
+use teos_common::appointment::{Appointment, Locator, LOCATOR_LEN};
+use teos_common::cryptography;
+use teos_common::{errors, UserId, USER_ID_LEN};
 
+use crate::api::rate_limiter::{self, RateLimiter};
+use crate::events::{Event, EventSender};
 use crate::protos as msgs;
 use crate::protos::public_tower_services_client::PublicTowerServicesClient;
 
-// TODO: Limit the body length for /add_appointment should not be needed, since slots are consumed proportionally to it.
-// Setting a limit for now just to prevent spam to some extend, but this is likely to be lifted.
-const REGISTER_BODY_LEN: u64 = 87;
-const ADD_APPOINTMENT_BODY_LEN: u64 = 2048;
+const REGISTER_BODY_LEN: u64 = 120;
+// Room taken by everything in an /add_appointment body but the (hex encoded) encrypted blob.
+const ADD_APPOINTMENT_ENVELOPE_LEN: u64 = 512;
 const GET_APPOINTMENT_BODY_LEN: u64 = 178;
 const GET_SUBSCRIPTION_INFO_BODY_LEN: u64 = 127;
+// Responses smaller than this are not worth compressing.
+const COMPRESSION_THRESHOLD: usize = 1024;
+// Version of the API reported to clients.
+const API_VERSION: &str = "v2";
+
+/// Limits on the size of the request bodies carrying appointments, derived from the limits the tower enforces on them.
+#[derive(Debug, Clone, Copy)]
+pub struct BodyLimits {
+    /// Maximum size (in bytes) of an appointment encrypted blob.
+    max_appointment_size: usize,
+    /// Maximum number of appointments accepted by a single `/batch` request.
+    max_batch_size: usize,
+}
+
+impl BodyLimits {
+    /// Creates a new [BodyLimits] instance.
+    pub fn new(max_appointment_size: usize, max_batch_size: usize) -> Self {
+        BodyLimits {
+            max_appointment_size,
+            max_batch_size,
+        }
+    }
+
+    /// Maximum length of a body holding a single appointment. Blobs are hex encoded, so they take twice their size.
+    fn appointment_body_len(&self) -> u64 {
+        2 * self.max_appointment_size as u64 + ADD_APPOINTMENT_ENVELOPE_LEN
+    }
+
+    /// Maximum length of a `/batch` body: as many appointments as allowed, plus the array brackets and separators.
+    fn batch_body_len(&self) -> u64 {
+        (self.appointment_body_len() + 1) * self.max_batch_size as u64 + 1
+    }
+}
+
+/// Result of processing one of the appointments of a `/batch` request.
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+enum BatchResult {
+    Accepted(msgs::AddAppointmentResponse),
+    Rejected(ApiError),
+}
+
+/// Features and limits advertised by the tower (through `/get_capabilities`), so clients can check what they can use
+/// instead of finding out by failed requests.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Capabilities {
+    /// Version of the API (matches the proto package).
+    version: String,
+    /// Whether gzip compressed requests are accepted and responses compressed.
+    compression: bool,
+    /// Whether `/batch` is available.
+    batch: bool,
+    /// Maximum number of appointments accepted by a single `/batch` request.
+    max_batch_size: usize,
+    /// Whether events can be subscribed to through `/events`.
+    events: bool,
+    /// Maximum size (in bytes) of an appointment encrypted blob. Both the Watcher and the HTTP body limits must let it
+    /// through, so the smallest of the two is reported.
+    max_appointment_size: u64,
+    /// Minimum `to_self_delay` accepted for appointments.
+    min_to_self_delay: u32,
+}
+
+impl Capabilities {
+    /// Creates a new [Capabilities] instance. The rest of the appointment limits are set by the Watcher, so they are
+    /// filled in using [with_limits](Self::with_limits).
+    fn new(compression: bool, body_limits: BodyLimits, events: bool) -> Self {
+        Capabilities {
+            version: API_VERSION.to_owned(),
+            compression,
+            batch: body_limits.max_batch_size > 0,
+            max_batch_size: body_limits.max_batch_size,
+            events,
+            max_appointment_size: body_limits.max_appointment_size as u64,
+            min_to_self_delay: 0,
+        }
+    }
+
+    /// Sets the appointment limits reported by the Watcher.
+    fn with_limits(self, limits: msgs::GetCapabilitiesResponse) -> Self {
+        Capabilities {
+            max_appointment_size: self.max_appointment_size.min(limits.max_appointment_size),
+            min_to_self_delay: limits.min_to_self_delay,
+            ..self
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub(crate) struct ApiError {
@@ -27,11 +128,43 @@ pub(crate) struct ApiError {
 
 impl reject::Reject for ApiError {}
 
+impl Reply for ApiError {
+    fn into_response(self) -> reply::Response {
+        let status_code = self.status_code();
+        reply::with_status(reply::json(&self), status_code).into_response()
+    }
+}
+
 impl ApiError {
     fn new(error: String, error_code: u8) -> Self {
         ApiError { error, error_code }
     }
 
+    /// Gets the HTTP status code the error is reported with.
+    fn status_code(&self) -> StatusCode {
+        match self.error_code {
+            errors::RATE_LIMIT_EXCEEDED => StatusCode::TOO_MANY_REQUESTS,
+            errors::UNEXPECTED_ERROR => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// Builds an error out of the cause of a request body deserialization failure.
+    fn deserialization_error(mut error: String) -> Self {
+        let error_code = if error.contains("invalid type") {
+            errors::WRONG_FIELD_TYPE
+        } else if error.contains("missing field") {
+            error = error.split(" at").take(1).next().unwrap_or(&error).into();
+            errors::MISSING_FIELD
+        } else if error.contains("Odd number of digits") | error.contains("Invalid character") {
+            errors::WRONG_FIELD_FORMAT
+        } else {
+            errors::INVALID_REQUEST_FORMAT
+        };
+
+        Self::new(error, error_code)
+    }
+
     fn missing_field(field_name: &str) -> Rejection {
         reject::custom(Self::new(
             format!("missing field `{}`", field_name),
@@ -55,6 +188,23 @@ impl ApiError {
             errors::WRONG_FIELD_SIZE,
         ))
     }
+
+    fn rate_limit_exceeded() -> Rejection {
+        reject::custom(Self::new(
+            "Rate limit exceeded. Try again later".into(),
+            errors::RATE_LIMIT_EXCEEDED,
+        ))
+    }
+
+    /// Builds the error returned when the tower fails to process a request on its side. The cause is logged but not
+    /// disclosed to the user.
+    fn internal_error(cause: impl fmt::Debug) -> Self {
+        log::error!("Internal error processing request: {:?}", cause);
+        Self::new(
+            "Internal error. Try again later".into(),
+            errors::UNEXPECTED_ERROR,
+        )
+    }
 }
 
 pub fn serialize_vec_bytes<S>(v: &[Vec<u8>], s: S) -> Result<S::Ok, S::Error>
@@ -74,6 +224,29 @@ fn with_grpc(
     warp::any().map(move || grpc_endpoint.clone())
 }
 
+fn with_rate_limiter(
+    rate_limiter: Option<RateLimiter>,
+) -> impl Filter<Extract = (Option<RateLimiter>,), Error = Infallible> + Clone {
+    warp::any().map(move || rate_limiter.clone())
+}
+
+/// Checks whether a request is allowed by the rate limiter (if any).
+///
+/// The user is only identified (`get_user_id`) if rate limiting is enabled. Requests whose user cannot be identified
+/// are accounted for altogether.
+fn check_rate_limit(
+    rate_limiter: &Option<RateLimiter>,
+    get_user_id: impl FnOnce() -> Option<UserId>,
+) -> Result<(), Rejection> {
+    match rate_limiter {
+        Some(limiter) if !limiter.try_acquire(get_user_id(), 1) => {
+            log::info!("Rate limit exceeded");
+            Err(ApiError::rate_limit_exceeded())
+        }
+        _ => Ok(()),
+    }
+}
+
 fn match_status(s: &tonic::Status) -> (StatusCode, u8) {
     let mut status_code = StatusCode::BAD_REQUEST;
     let error_code = match s.code() {
@@ -84,16 +257,28 @@ fn match_status(s: &tonic::Status) -> (StatusCode, u8) {
         }
         tonic::Code::AlreadyExists => errors::APPOINTMENT_ALREADY_TRIGGERED,
         tonic::Code::ResourceExhausted => errors::REGISTRATION_RESOURCE_EXHAUSTED,
+        tonic::Code::FailedPrecondition => errors::APPOINTMENT_LIMIT_REACHED,
+        // Out of range errors carry the offending field error code as details
+        tonic::Code::OutOfRange => match s.details() {
+            [error_code] => *error_code,
+            _ => errors::APPOINTMENT_FIELD_TOO_BIG,
+        },
         tonic::Code::Unauthenticated => {
             status_code = StatusCode::UNAUTHORIZED;
             errors::INVALID_SIGNATURE_OR_SUBSCRIPTION_ERROR
         }
+        tonic::Code::PermissionDenied => {
+            status_code = StatusCode::FORBIDDEN;
+            errors::USER_BANNED
+        }
         tonic::Code::Unavailable => {
             status_code = StatusCode::SERVICE_UNAVAILABLE;
             errors::SERVICE_UNAVAILABLE
         }
+        // Anything else is an error on the tower side (e.g. a database failure)
         _ => {
-            log::debug!("Unexpected error ocurred: {}", s.message());
+            log::error!("Unexpected error ocurred: {}", s.message());
+            status_code = StatusCode::INTERNAL_SERVER_ERROR;
             errors::UNEXPECTED_ERROR
         }
     };
@@ -152,6 +337,7 @@ async fn register(
 async fn add_appointment(
     req: msgs::AddAppointmentRequest,
     addr: Option<std::net::SocketAddr>,
+    rate_limiter: Option<RateLimiter>,
     mut grpc_conn: PublicTowerServicesClient<Channel>,
 ) -> std::result::Result<impl Reply, Rejection> {
     match addr {
@@ -159,6 +345,33 @@ async fn add_appointment(
         None => log::info!("Received add_appointment request from unknown address"),
     }
 
+    check_add_appointment_request(&req)?;
+    check_rate_limit(&rate_limiter, || add_appointment_user(&req))?;
+
+    let (body, status) = parse_grpc_response(grpc_conn.add_appointment(req).await);
+    Ok(reply::with_status(body, status))
+}
+
+async fn update_appointment(
+    req: msgs::AddAppointmentRequest,
+    addr: Option<std::net::SocketAddr>,
+    rate_limiter: Option<RateLimiter>,
+    mut grpc_conn: PublicTowerServicesClient<Channel>,
+) -> std::result::Result<impl Reply, Rejection> {
+    match addr {
+        Some(a) => log::info!("Received update_appointment request from {}", a),
+        None => log::info!("Received update_appointment request from unknown address"),
+    }
+
+    check_add_appointment_request(&req)?;
+    check_rate_limit(&rate_limiter, || add_appointment_user(&req))?;
+
+    let (body, status) = parse_grpc_response(grpc_conn.update_appointment(req).await);
+    Ok(reply::with_status(body, status))
+}
+
+/// Checks an [AddAppointmentRequest](msgs::AddAppointmentRequest) is well formed before handing it to the tower.
+fn check_add_appointment_request(req: &msgs::AddAppointmentRequest) -> Result<(), Rejection> {
     if let Some(a) = &req.appointment {
         if a.locator.is_empty() {
             return Err(ApiError::empty_field("locator"));
@@ -177,13 +390,91 @@ async fn add_appointment(
         return Err(ApiError::empty_field("signature"));
     }
 
-    let (body, status) = parse_grpc_response(grpc_conn.add_appointment(req).await);
-    Ok(reply::with_status(body, status))
+    Ok(())
+}
+
+/// Identifies the user behind an (already checked) [AddAppointmentRequest](msgs::AddAppointmentRequest), if possible.
+fn add_appointment_user(req: &msgs::AddAppointmentRequest) -> Option<UserId> {
+    let a = req.appointment.as_ref()?;
+    let appointment = Appointment::new(
+        Locator::deserialize(&a.locator).ok()?,
+        a.encrypted_blob.clone(),
+        a.to_self_delay,
+    );
+    cryptography::recover_pk(&appointment.serialize(), &req.signature)
+        .ok()
+        .map(UserId)
+}
+
+/// Adds a batch of appointments in a single request.
+///
+/// Appointments are processed in order, each one authenticated by its own signature, and a result is returned for each of
+/// them in the same order: either an `AddAppointmentResponse` or an error (`error` and `error_code`). Rejected appointments
+/// do not abort the batch. Each appointment is accounted for separately by the rate limiter.
+///
+/// The whole batch is rejected with `400 Bad Request` if it is empty ([EMPTY_FIELD](errors::EMPTY_FIELD)) or holds more
+/// than `max_batch_size` appointments ([WRONG_FIELD_SIZE](errors::WRONG_FIELD_SIZE)). Bodies that cannot possibly fit in
+/// `max_batch_size` appointments are rejected with `413 Payload Too Large` before being parsed.
+async fn batch(
+    reqs: Vec<msgs::AddAppointmentRequest>,
+    addr: Option<std::net::SocketAddr>,
+    max_batch_size: usize,
+    rate_limiter: Option<RateLimiter>,
+    mut grpc_conn: PublicTowerServicesClient<Channel>,
+) -> std::result::Result<impl Reply, Rejection> {
+    match addr {
+        Some(a) => log::info!("Received batch request from {}", a),
+        None => log::info!("Received batch request from unknown address"),
+    }
+
+    if reqs.is_empty() {
+        return Err(reject::custom(ApiError::new(
+            "Batch is empty".into(),
+            errors::EMPTY_FIELD,
+        )));
+    }
+    if reqs.len() > max_batch_size {
+        return Err(reject::custom(ApiError::new(
+            format!(
+                "Batch too big. Expected at most {} appointments, received {}",
+                max_batch_size,
+                reqs.len()
+            ),
+            errors::WRONG_FIELD_SIZE,
+        )));
+    }
+
+    let mut results = Vec::with_capacity(reqs.len());
+    for req in reqs.into_iter() {
+        if let Err(rejection) = check_add_appointment_request(&req)
+            .and_then(|_| check_rate_limit(&rate_limiter, || add_appointment_user(&req)))
+        {
+            // Both checks only reject with ApiErrors
+            results.push(BatchResult::Rejected(match rejection.find::<ApiError>() {
+                Some(e) => ApiError::new(e.error.clone(), e.error_code),
+                None => ApiError::internal_error(rejection),
+            }));
+            continue;
+        }
+
+        let result = match grpc_conn.add_appointment(req).await {
+            Ok(r) => BatchResult::Accepted(r.into_inner()),
+            Err(s) => {
+                let (_, error_code) = match_status(&s);
+                BatchResult::Rejected(ApiError::new(s.message().into(), error_code))
+            }
+        };
+        results.push(result);
+    }
+
+    log::info!("Batch processed ({} appointments)", results.len());
+    Ok(reply::with_status(reply::json(&results), StatusCode::OK))
 }
 
 async fn get_appointment(
     req: msgs::GetAppointmentRequest,
     addr: Option<std::net::SocketAddr>,
+    rate_limiter: Option<RateLimiter>,
     mut grpc_conn: PublicTowerServicesClient<Channel>,
 ) -> std::result::Result<impl Reply, Rejection> {
     match addr {
@@ -204,6 +495,15 @@ async fn get_appointment(
     if req.signature.is_empty() {
         return Err(ApiError::empty_field("signature"));
     }
+    check_rate_limit(&rate_limiter, || {
+        let locator = Locator::deserialize(&req.locator).ok()?;
+        cryptography::recover_pk(
+            format!("get appointment {}", locator).as_bytes(),
+            &req.signature,
+        )
+        .ok()
+        .map(UserId)
+    })?;
 
     let (body, status) = parse_grpc_response(grpc_conn.get_appointment(req).await);
     Ok(reply::with_status(body, status))
@@ -227,81 +527,376 @@ async fn get_subscription_info(
     Ok(reply::with_status(body, status))
 }
 
+async fn get_capabilities(
+    capabilities: Capabilities,
+    addr: Option<std::net::SocketAddr>,
+    mut grpc_conn: PublicTowerServicesClient<Channel>,
+) -> std::result::Result<impl Reply, Rejection> {
+    match addr {
+        Some(a) => log::info!("Received get_capabilities request from {}", a),
+        None => log::info!("Received get_capabilities request from unknown address"),
+    }
+
+    let (body, status) = parse_grpc_response(
+        grpc_conn
+            .get_capabilities(())
+            .await
+            .map(|r| r.map(|limits| capabilities.with_limits(limits))),
+    );
+    Ok(reply::with_status(body, status))
+}
+
+/// Time events subscribers have to answer the authentication challenge before being disconnected.
+const EVENTS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn subscribe_events(
+    ws: Ws,
+    addr: Option<std::net::SocketAddr>,
+    grpc_conn: PublicTowerServicesClient<Channel>,
+    event_sender: EventSender,
+) -> std::result::Result<impl Reply, Rejection> {
+    match addr {
+        Some(a) => log::info!("Received events subscription from {}", a),
+        None => log::info!("Received events subscription from unknown address"),
+    }
+
+    // Subscribe before upgrading so no event is missed while the client authenticates
+    let receiver = event_sender.subscribe();
+    Ok(ws.on_upgrade(move |socket| stream_events(socket, grpc_conn, receiver)))
+}
+
+/// Streams the events of a user through the given socket.
+///
+/// A fresh challenge (a hex encoded nonce) is sent to the client as soon as it connects. The client must answer with
+/// its signature of the challenge within [EVENTS_HANDSHAKE_TIMEOUT], which is checked against the tower the same way
+/// `get_subscription_info` requests are. Only events regarding the authenticated user are forwarded. Subscribers that
+/// cannot keep up with the events rate are disconnected.
+async fn stream_events(
+    mut socket: WebSocket,
+    mut grpc_conn: PublicTowerServicesClient<Channel>,
+    mut receiver: broadcast::Receiver<Event>,
+) {
+    // Challenges are unique per connection, so signatures cannot be replayed to subscribe to someone else's events
+    let challenge = hex::encode(cryptography::get_random_bytes(32));
+    let msg = serde_json::json!({ "challenge": challenge }).to_string();
+    if socket.send(Message::text(msg)).await.is_err() {
+        return;
+    }
+
+    let signature = match tokio::time::timeout(EVENTS_HANDSHAKE_TIMEOUT, socket.next()).await {
+        Ok(Some(Ok(msg))) if msg.is_text() => msg.to_str().unwrap().to_owned(),
+        Ok(_) => {
+            socket.close().await.ok();
+            return;
+        }
+        Err(_) => {
+            log::info!("Events subscriber did not answer the challenge in time");
+            socket.close().await.ok();
+            return;
+        }
+    };
+
+    let user_id = match grpc_conn
+        .get_subscription_info(msgs::GetSubscriptionInfoRequest {
+            signature: signature.clone(),
+            challenge: Some(challenge.clone()),
+        })
+        .await
+    {
+        // The signature has already been checked by the tower at this point
+        Ok(_) => {
+            UserId(cryptography::recover_pk(challenge.as_bytes(), &signature).unwrap()).to_string()
+        }
+        Err(s) => {
+            let (_, error_code) = match_status(&s);
+            log::info!("Events subscription rejected, error_code={}", error_code);
+            let error = serde_json::to_string(&ApiError::new(s.message().into(), error_code));
+            socket.send(Message::text(error.unwrap())).await.ok();
+            socket.close().await.ok();
+            return;
+        }
+    };
+
+    loop {
+        tokio::select! {
+            event = receiver.recv() => match event {
+                Ok(event) => {
+                    if event.user_id() == user_id {
+                        let msg = Message::text(serde_json::to_string(&event).unwrap());
+                        if socket.send(msg).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                Err(RecvError::Lagged(n)) => {
+                    log::info!("Dropping slow events subscriber {} ({} events behind)", user_id, n);
+                    break;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            // Anything else sent by the client is ignored, we only care about it disconnecting
+            msg = socket.next() => if !matches!(msg, Some(Ok(m)) if !m.is_close()) {
+                break;
+            }
+        }
+    }
+
+    socket.close().await.ok();
+}
+
+/// Decompresses a gzip encoded body, making sure the decompressed data is not bigger than `limit`.
+fn gunzip(data: &[u8], limit: u64) -> Result<Vec<u8>, Rejection> {
+    let mut decompressed = Vec::new();
+    GzDecoder::new(data)
+        .take(limit + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|_| {
+            reject::custom(ApiError::new(
+                "Invalid gzip encoded body".into(),
+                errors::INVALID_REQUEST_FORMAT,
+            ))
+        })?;
+
+    if decompressed.len() as u64 > limit {
+        Err(reject::custom(ApiError::new(
+            "Decompressed body is too large".into(),
+            errors::INVALID_REQUEST_FORMAT,
+        )))
+    } else {
+        Ok(decompressed)
+    }
+}
+
+/// Extracts a JSON body of at most `limit` bytes.
+///
+/// If `compression` is enabled, bodies sent with `Content-Encoding: gzip` are decompressed before being deserialized.
+fn json_body<T: DeserializeOwned + Send>(
+    limit: u64,
+    compression: bool,
+) -> impl Filter<Extract = (T,), Error = Rejection> + Clone {
+    let gzip = warp::header::exact_ignore_case("content-encoding", "gzip")
+        .and_then(move || async move {
+            if compression {
+                Ok(())
+            } else {
+                Err(reject::not_found())
+            }
+        })
+        .untuple_one()
+        .and(warp::body::bytes())
+        .and_then(move |data: Bytes| async move {
+            serde_json::from_slice(&gunzip(&data, limit)?)
+                .map_err(|e| reject::custom(ApiError::deserialization_error(e.to_string())))
+        });
+
+    warp::body::content_length_limit(limit).and(gzip.or(warp::body::json()).unify())
+}
+
+/// Checks whether the client accepts gzip encoded responses given its `Accept-Encoding` header.
+fn accepts_gzip(accept_encoding: &str) -> bool {
+    accept_encoding.split(',').any(|encoding| {
+        let mut params = encoding.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default();
+        let quality = params
+            .find_map(|p| p.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        (name.eq_ignore_ascii_case("gzip") || name == "*") && quality > 0.0
+    })
+}
+
+/// Compresses the response body if compression is enabled, the client accepts gzip and the body is big enough
+/// to be worth it.
+async fn compress_reply(
+    compression: bool,
+    accept_encoding: Option<String>,
+    reply: impl Reply,
+) -> Result<reply::Response, Rejection> {
+    let mut response = reply.into_response();
+    if !compression {
+        return Ok(response);
+    }
+
+    response
+        .headers_mut()
+        .insert(VARY, HeaderValue::from_static("accept-encoding"));
+    if !matches!(accept_encoding, Some(a) if accepts_gzip(&a)) {
+        return Ok(response);
+    }
+
+    let (mut parts, data) = response.into_parts();
+    let data = match body::to_bytes(data).await {
+        Ok(data) => data,
+        Err(e) => return Ok(ApiError::internal_error(e).into_response()),
+    };
+    if data.len() < COMPRESSION_THRESHOLD {
+        return Ok(reply::Response::from_parts(parts, Body::from(data)));
+    }
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    let compressed = match encoder.write_all(&data).and_then(|_| encoder.finish()) {
+        Ok(compressed) => compressed,
+        Err(e) => {
+            log::error!(
+                "Couldn't compress response, sending it uncompressed. Error: {:?}",
+                e
+            );
+            return Ok(reply::Response::from_parts(parts, Body::from(data)));
+        }
+    };
+
+    parts
+        .headers
+        .insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    parts.headers.remove(CONTENT_LENGTH);
+    Ok(reply::Response::from_parts(parts, Body::from(compressed)))
+}
+
 fn router(
     grpc_conn: PublicTowerServicesClient<Channel>,
+    body_limits: BodyLimits,
+    compression: bool,
+    event_sender: Option<EventSender>,
+    rate_limiter: Option<RateLimiter>,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
     let register = warp::post()
         .and(warp::path("register"))
-        .and(warp::body::content_length_limit(REGISTER_BODY_LEN).and(warp::body::json()))
+        .and(json_body(REGISTER_BODY_LEN, compression))
         .and(warp::addr::remote())
         .and(with_grpc(grpc_conn.clone()))
         .and_then(register);
 
     let add_appointment = warp::post()
         .and(warp::path("add_appointment"))
-        .and(warp::body::content_length_limit(ADD_APPOINTMENT_BODY_LEN).and(warp::body::json()))
+        .and(json_body(body_limits.appointment_body_len(), compression))
         .and(warp::addr::remote())
+        .and(with_rate_limiter(rate_limiter.clone()))
         .and(with_grpc(grpc_conn.clone()))
         .and_then(add_appointment);
 
+    // Updates carry the same data new appointments do
+    let update_appointment = warp::post()
+        .and(warp::path("update_appointment"))
+        .and(json_body(body_limits.appointment_body_len(), compression))
+        .and(warp::addr::remote())
+        .and(with_rate_limiter(rate_limiter.clone()))
+        .and(with_grpc(grpc_conn.clone()))
+        .and_then(update_appointment);
+
     let get_appointment = warp::post()
         .and(warp::path("get_appointment"))
-        .and(warp::body::content_length_limit(GET_APPOINTMENT_BODY_LEN).and(warp::body::json()))
+        .and(json_body(GET_APPOINTMENT_BODY_LEN, compression))
         .and(warp::addr::remote())
+        .and(with_rate_limiter(rate_limiter.clone()))
         .and(with_grpc(grpc_conn.clone()))
         .and_then(get_appointment);
 
     let get_subscription_info = warp::post()
         .and(warp::path("get_subscription_info"))
-        .and(
-            warp::body::content_length_limit(GET_SUBSCRIPTION_INFO_BODY_LEN)
-                .and(warp::body::json()),
-        )
+        .and(json_body(GET_SUBSCRIPTION_INFO_BODY_LEN, compression))
         .and(warp::addr::remote())
-        .and(with_grpc(grpc_conn))
+        .and(with_grpc(grpc_conn.clone()))
         .and_then(get_subscription_info);
 
-    register
+    let max_batch_size = body_limits.max_batch_size;
+    let batch = warp::post()
+        .and(warp::path("batch"))
+        .and(json_body(body_limits.batch_body_len(), compression))
+        .and(warp::addr::remote())
+        .and(warp::any().map(move || max_batch_size))
+        .and(with_rate_limiter(rate_limiter))
+        .and(with_grpc(grpc_conn.clone()))
+        .and_then(batch);
+
+    let capabilities = Capabilities::new(compression, body_limits, event_sender.is_some());
+    // The path is matched before the method so unknown endpoints are still reported as not found
+    let get_capabilities = warp::path("get_capabilities")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::any().map(move || capabilities.clone()))
+        .and(warp::addr::remote())
+        .and(with_grpc(grpc_conn.clone()))
+        .and_then(get_capabilities);
+
+    // Events are only served if enabled
+    let events = warp::path("events")
+        .and(warp::path::end())
+        .and(warp::ws())
+        .and(warp::addr::remote())
+        .and(with_grpc(grpc_conn))
+        .and(warp::any().and_then(move || {
+            let event_sender = event_sender.clone();
+            async move { event_sender.ok_or_else(reject::not_found) }
+        }))
+        .and_then(subscribe_events);
+
+    let routes = register
         .or(add_appointment)
+        .or(update_appointment)
         .or(get_appointment)
         .or(get_subscription_info)
-        .recover(handle_rejection)
+        .or(batch)
+        .or(get_capabilities)
+        .recover(handle_rejection);
+
+    warp::any()
+        .map(move || compression)
+        .and(warp::header::optional::<String>(ACCEPT_ENCODING.as_str()))
+        .and(routes)
+        .and_then(compress_reply)
+        .or(events)
 }
 
 async fn handle_rejection(err: Rejection) -> Result<impl Reply, Rejection> {
     match err.find::<warp::body::BodyDeserializeError>() {
-        Some(e) => {
-            let mut error = e
-                .source()
-                .map(|cause| cause.to_string())
-                .unwrap_or_else(|| "Invalid Body".to_string());
-
-            let error_code = if error.contains("invalid type") {
-                errors::WRONG_FIELD_TYPE
-            } else if error.contains("missing field") {
-                error = error.split(" at").take(1).next().unwrap_or(&error).into();
-                errors::MISSING_FIELD
-            } else if error.contains("Odd number of digits") | error.contains("Invalid character") {
-                errors::WRONG_FIELD_FORMAT
-            } else {
-                errors::INVALID_REQUEST_FORMAT
-            };
-            Ok(reply::with_status(
-                reply::json(&ApiError { error, error_code }),
-                StatusCode::BAD_REQUEST,
-            ))
-        }
+        Some(e) => Ok(reply::with_status(
+            reply::json(&ApiError::deserialization_error(
+                e.source()
+                    .map(|cause| cause.to_string())
+                    .unwrap_or_else(|| "Invalid Body".to_string()),
+            )),
+            StatusCode::BAD_REQUEST,
+        )),
         None => match err.find::<ApiError>() {
-            Some(x) => Ok(reply::with_status(reply::json(x), StatusCode::BAD_REQUEST)),
+            Some(x) => Ok(reply::with_status(reply::json(x), x.status_code())),
             None => Err(err),
         },
     }
 }
 
-pub async fn serve(http_bind: SocketAddr, grpc_bind: String, shutdown_signal: Listener) {
+pub async fn serve(
+    http_bind: SocketAddr,
+    grpc_bind: String,
+    body_limits: BodyLimits,
+    compression: bool,
+    event_sender: Option<EventSender>,
+    rate_limiter: Option<RateLimiter>,
+    shutdown_signal: Listener,
+) {
+    // Periodically forget about the users that have not made any request lately
+    if let Some(limiter) = rate_limiter.clone() {
+        let shutdown_signal = shutdown_signal.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(rate_limiter::PRUNE_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => limiter.prune(),
+                    _ = shutdown_signal.clone() => break,
+                }
+            }
+        });
+    }
+
     let grpc_conn = PublicTowerServicesClient::connect(grpc_bind).await.unwrap();
-    let (_, server) = warp::serve(router(grpc_conn))
-        .bind_with_graceful_shutdown(http_bind, async { shutdown_signal.await });
+    let (_, server) = warp::serve(router(
+        grpc_conn,
+        body_limits,
+        compression,
+        event_sender,
+        rate_limiter,
+    ))
+    .bind_with_graceful_shutdown(http_bind, async { shutdown_signal.await });
     server.await
 }
 
@@ -317,7 +912,9 @@ mod test_helpers {
 
     use crate::api::internal::InternalAPI;
     use crate::protos::public_tower_services_server::PublicTowerServicesServer;
-    use crate::test_utils::{create_api_with_config, ApiConfig};
+    use crate::test_utils::{create_api_with_config, ApiConfig, MAX_APPOINTMENT_SIZE};
+
+    pub(crate) const MAX_BATCH_SIZE: usize = 5;
 
     pub(crate) enum RequestBody<'a> {
         Jsonify(&'a str),
@@ -377,7 +974,15 @@ mod test_helpers {
             RequestBody::Body(b) => warp::test::request().method("POST").path(endpoint).body(b),
         };
 
-        let res = req.reply(&router(grpc_conn)).await;
+        let res = req
+            .reply(&router(
+                grpc_conn,
+                BodyLimits::new(MAX_APPOINTMENT_SIZE, MAX_BATCH_SIZE),
+                false,
+                None,
+                None,
+            ))
+            .await;
         (
             serde_json::from_slice::<ApiError>(res.body()).unwrap(),
             res.status(),
@@ -405,7 +1010,13 @@ mod test_helpers {
             .method("POST")
             .path(endpoint)
             .json(&serde_json::json!(body))
-            .reply(&router(grpc_conn))
+            .reply(&router(
+                grpc_conn,
+                BodyLimits::new(MAX_APPOINTMENT_SIZE, MAX_BATCH_SIZE),
+                false,
+                None,
+                None,
+            ))
             .await;
 
         serde_json::from_slice::<T>(res.body())
@@ -416,8 +1027,10 @@ mod test_helpers {
 mod tests_failures {
     use super::*;
 
-    use super::test_helpers::{check_api_error, run_tower_in_background, RequestBody};
-    use crate::test_utils::get_random_user_id;
+    use super::test_helpers::{
+        check_api_error, run_tower_in_background, RequestBody, MAX_BATCH_SIZE,
+    };
+    use crate::test_utils::{get_random_user_id, MAX_APPOINTMENT_SIZE};
 
     #[tokio::test]
     async fn test_no_json_request_body() {
@@ -551,7 +1164,13 @@ mod tests_failures {
         let res = warp::test::request()
             .method("POST")
             .path("/register")
-            .reply(&router(grpc_conn))
+            .reply(&router(
+                grpc_conn,
+                BodyLimits::new(MAX_APPOINTMENT_SIZE, MAX_BATCH_SIZE),
+                false,
+                None,
+                None,
+            ))
             .await;
 
         assert_eq!(res.status(), StatusCode::LENGTH_REQUIRED)
@@ -572,7 +1191,13 @@ mod tests_failures {
             .method("POST")
             .path("/register")
             .json(&format!("{}{}", get_random_user_id(), get_random_user_id()))
-            .reply(&router(grpc_conn))
+            .reply(&router(
+                grpc_conn,
+                BodyLimits::new(MAX_APPOINTMENT_SIZE, MAX_BATCH_SIZE),
+                false,
+                None,
+                None,
+            ))
             .await;
 
         assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE)
@@ -593,7 +1218,13 @@ mod tests_failures {
             .method("POST")
             .path("/")
             .json(&"")
-            .reply(&router(grpc_conn))
+            .reply(&router(
+                grpc_conn,
+                BodyLimits::new(MAX_APPOINTMENT_SIZE, MAX_BATCH_SIZE),
+                false,
+                None,
+                None,
+            ))
             .await;
 
         assert_eq!(res.status(), StatusCode::NOT_FOUND)
@@ -613,7 +1244,13 @@ mod tests_failures {
         let res = warp::test::request()
             .path("/")
             .json(&"")
-            .reply(&router(grpc_conn))
+            .reply(&router(
+                grpc_conn,
+                BodyLimits::new(MAX_APPOINTMENT_SIZE, MAX_BATCH_SIZE),
+                false,
+                None,
+                None,
+            ))
             .await;
 
         assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED)
@@ -626,11 +1263,12 @@ mod tests_methods {
 
     use super::test_helpers::{
         check_api_error, request_to_api, run_tower_in_background,
-        run_tower_in_background_with_config, RequestBody,
+        run_tower_in_background_with_config, RequestBody, MAX_BATCH_SIZE,
     };
     use crate::extended_appointment::UUID;
     use crate::test_utils::{
-        generate_dummy_appointment, get_random_user_id, ApiConfig, DURATION, SLOTS,
+        generate_dummy_appointment, get_random_user_id, ApiConfig, DURATION, MAX_APPOINTMENT_SIZE,
+        MIN_TO_SELF_DELAY, SLOTS,
     };
     use teos_common::{cryptography, UserId};
 
@@ -641,6 +1279,35 @@ mod tests_methods {
             "/register",
             msgs::RegisterRequest {
                 user_id: get_random_user_id().serialize(),
+                requested_slots: None,
+            },
+            server_addr,
+        )
+        .await;
+        assert!(matches!(response, Ok(msgs::RegisterResponse { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_register_tor_failure() {
+        // A failing onion service must not prevent the clearnet API from serving requests
+        let server_addr = run_tower_in_background().await;
+        let (_, shutdown_signal) = triggered::trigger();
+        tokio::spawn(crate::api::tor::run_onion_service(
+            1,
+            server_addr.port(),
+            2121,
+            None,
+            shutdown_signal,
+            Default::default(),
+        ))
+        .await
+        .unwrap();
+
+        let response = request_to_api::<msgs::RegisterRequest, msgs::RegisterResponse>(
+            "/register",
+            msgs::RegisterRequest {
+                user_id: get_random_user_id().serialize(),
+                requested_slots: None,
             },
             server_addr,
         )
@@ -659,6 +1326,7 @@ mod tests_methods {
             "/register",
             msgs::RegisterRequest {
                 user_id: user_id.serialize(),
+                requested_slots: None,
             },
             server_addr,
         )
@@ -671,6 +1339,7 @@ mod tests_methods {
                 "/register",
                 RequestBody::Json(serde_json::json!(msgs::RegisterRequest {
                     user_id: user_id.serialize(),
+                    requested_slots: None,
                 })),
                 server_addr,
             )
@@ -685,6 +1354,33 @@ mod tests_methods {
         );
     }
 
+    #[tokio::test]
+    async fn test_register_db_error() {
+        let (server_addr, internal_api) =
+            run_tower_in_background_with_config(ApiConfig::default()).await;
+
+        // Users cannot be stored if the database fails, which is reported as an internal error
+        internal_api.get_watcher().make_db_read_only();
+        assert_eq!(
+            check_api_error(
+                "/register",
+                RequestBody::Json(serde_json::json!(msgs::RegisterRequest {
+                    user_id: get_random_user_id().serialize(),
+                    requested_slots: None,
+                })),
+                server_addr,
+            )
+            .await,
+            (
+                ApiError::new(
+                    "Internal error. Try again later".into(),
+                    errors::UNEXPECTED_ERROR
+                ),
+                StatusCode::INTERNAL_SERVER_ERROR
+            )
+        );
+    }
+
     #[tokio::test]
     async fn test_register_service_unavailable() {
         let (server_addr, _) = run_tower_in_background_with_config(
@@ -699,6 +1395,7 @@ mod tests_methods {
                 "/register",
                 RequestBody::Json(serde_json::json!(msgs::RegisterRequest {
                     user_id: user_id.serialize(),
+                    requested_slots: None,
                 })),
                 server_addr,
             )
@@ -723,6 +1420,7 @@ mod tests_methods {
             "/register",
             msgs::RegisterRequest {
                 user_id: user_pk.serialize().to_vec(),
+                requested_slots: None,
             },
             server_addr,
         )
@@ -747,55 +1445,241 @@ mod tests_methods {
     }
 
     #[tokio::test]
-    async fn test_add_appointment_non_registered() {
+    async fn test_update_appointment() {
         let server_addr = run_tower_in_background().await;
-        let (user_sk, _) = cryptography::get_random_keypair();
-        let appointment = generate_dummy_appointment(None).inner;
-        let signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
-
-        assert_eq!(
-            check_api_error(
-                "/add_appointment",
-                RequestBody::Json(serde_json::json!(msgs::AddAppointmentRequest {
-                    appointment: Some(appointment.into()),
-                    signature,
-                })),
-                server_addr,
-            )
-            .await,
-            (
-                ApiError::new(
-                    "Invalid signature or user does not have enough slots available".into(),
-                    errors::INVALID_SIGNATURE_OR_SUBSCRIPTION_ERROR
-                ),
-                StatusCode::UNAUTHORIZED
-            )
-        );
-    }
-
-    #[tokio::test]
-    async fn test_add_appointment_already_triggered() {
-        // Get the InternalAPI so we can mess with the inner state
-        let (server_addr, internal_api) =
-            run_tower_in_background_with_config(ApiConfig::new(u32::MAX, DURATION)).await;
 
-        // Register
         let (user_sk, user_pk) = cryptography::get_random_keypair();
         request_to_api::<msgs::RegisterRequest, msgs::RegisterResponse>(
             "/register",
             msgs::RegisterRequest {
                 user_id: user_pk.serialize().to_vec(),
+                requested_slots: None,
             },
             server_addr,
         )
         .await
         .unwrap();
 
-        // Add the appointment to the Responder so it counts as triggered
-        let appointment = generate_dummy_appointment(None).inner;
+        // Appointments that have not been sent cannot be updated
+        let mut appointment = generate_dummy_appointment(None).inner;
         let signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
-        internal_api
-            .get_watcher()
+        let request = msgs::AddAppointmentRequest {
+            appointment: Some(appointment.clone().into()),
+            signature,
+        };
+        assert_eq!(
+            check_api_error(
+                "/update_appointment",
+                RequestBody::Json(serde_json::json!(request)),
+                server_addr,
+            )
+            .await,
+            (
+                ApiError::new(
+                    "Appointment not found".into(),
+                    errors::APPOINTMENT_NOT_FOUND
+                ),
+                StatusCode::NOT_FOUND
+            )
+        );
+
+        // Existing ones can
+        request_to_api::<msgs::AddAppointmentRequest, msgs::AddAppointmentResponse>(
+            "/add_appointment",
+            request,
+            server_addr,
+        )
+        .await
+        .unwrap();
+
+        appointment.encrypted_blob.reverse();
+        let signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        let response =
+            request_to_api::<msgs::AddAppointmentRequest, msgs::UpdateAppointmentResponse>(
+                "/update_appointment",
+                msgs::AddAppointmentRequest {
+                    appointment: Some(appointment.into()),
+                    signature,
+                },
+                server_addr,
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.version, 1);
+    }
+
+    #[tokio::test]
+    async fn test_add_appointment_big_blob() {
+        let server_addr = run_tower_in_background().await;
+
+        let (user_sk, user_pk) = cryptography::get_random_keypair();
+        request_to_api::<msgs::RegisterRequest, msgs::RegisterResponse>(
+            "/register",
+            msgs::RegisterRequest {
+                user_id: user_pk.serialize().to_vec(),
+                requested_slots: None,
+            },
+            server_addr,
+        )
+        .await
+        .unwrap();
+
+        // Body limits follow the configured max_appointment_size, so blobs way bigger than a couple of KB make it to
+        // the tower
+        let mut appointment = generate_dummy_appointment(None).inner;
+        appointment.encrypted_blob = cryptography::get_random_bytes(MAX_APPOINTMENT_SIZE / 10);
+        assert!(appointment.encrypted_blob.len() > 2048);
+        let signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+
+        let response = request_to_api::<msgs::AddAppointmentRequest, msgs::AddAppointmentResponse>(
+            "/add_appointment",
+            msgs::AddAppointmentRequest {
+                appointment: Some(appointment.into()),
+                signature,
+            },
+            server_addr,
+        )
+        .await;
+        assert!(matches!(response, Ok(msgs::AddAppointmentResponse { .. })));
+
+        // Anything that cannot fit a blob of that size is still rejected before reaching the tower
+        let mut appointment = generate_dummy_appointment(None).inner;
+        appointment.encrypted_blob =
+            vec![0; MAX_APPOINTMENT_SIZE + ADD_APPOINTMENT_ENVELOPE_LEN as usize];
+        let signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        let grpc_conn = PublicTowerServicesClient::connect(format!(
+            "http://{}:{}",
+            server_addr.ip(),
+            server_addr.port()
+        ))
+        .await
+        .unwrap();
+        let res = warp::test::request()
+            .method("POST")
+            .path("/add_appointment")
+            .json(&msgs::AddAppointmentRequest {
+                appointment: Some(appointment.into()),
+                signature,
+            })
+            .reply(&router(
+                grpc_conn,
+                BodyLimits::new(MAX_APPOINTMENT_SIZE, MAX_BATCH_SIZE),
+                false,
+                None,
+                None,
+            ))
+            .await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_add_appointment_non_registered() {
+        let server_addr = run_tower_in_background().await;
+        let (user_sk, _) = cryptography::get_random_keypair();
+        let appointment = generate_dummy_appointment(None).inner;
+        let signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+
+        assert_eq!(
+            check_api_error(
+                "/add_appointment",
+                RequestBody::Json(serde_json::json!(msgs::AddAppointmentRequest {
+                    appointment: Some(appointment.into()),
+                    signature,
+                })),
+                server_addr,
+            )
+            .await,
+            (
+                ApiError::new(
+                    "Invalid signature or user does not have enough slots available".into(),
+                    errors::INVALID_SIGNATURE_OR_SUBSCRIPTION_ERROR
+                ),
+                StatusCode::UNAUTHORIZED
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_appointment_user_banned() {
+        let (server_addr, internal_api) =
+            run_tower_in_background_with_config(ApiConfig::new(SLOTS, DURATION)).await;
+
+        // Register two users and ban one of them
+        let (banned_sk, banned_pk) = cryptography::get_random_keypair();
+        let (user_sk, user_pk) = cryptography::get_random_keypair();
+        for user_pk in [banned_pk, user_pk] {
+            request_to_api::<msgs::RegisterRequest, msgs::RegisterResponse>(
+                "/register",
+                msgs::RegisterRequest {
+                    user_id: user_pk.serialize().to_vec(),
+                    requested_slots: None,
+                },
+                server_addr,
+            )
+            .await
+            .unwrap();
+        }
+        internal_api
+            .get_watcher()
+            .ban_user(UserId(banned_pk), false);
+
+        // The banned user is rejected
+        let appointment = generate_dummy_appointment(None).inner;
+        let signature = cryptography::sign(&appointment.serialize(), &banned_sk).unwrap();
+        assert_eq!(
+            check_api_error(
+                "/add_appointment",
+                RequestBody::Json(serde_json::json!(msgs::AddAppointmentRequest {
+                    appointment: Some(appointment.clone().into()),
+                    signature,
+                })),
+                server_addr,
+            )
+            .await,
+            (
+                ApiError::new("User banned from the tower".into(), errors::USER_BANNED),
+                StatusCode::FORBIDDEN
+            )
+        );
+
+        // While the rest can carry on
+        let signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        let response = request_to_api::<msgs::AddAppointmentRequest, msgs::AddAppointmentResponse>(
+            "/add_appointment",
+            msgs::AddAppointmentRequest {
+                appointment: Some(appointment.into()),
+                signature,
+            },
+            server_addr,
+        )
+        .await;
+        assert!(matches!(response, Ok(msgs::AddAppointmentResponse { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_add_appointment_already_triggered() {
+        // Get the InternalAPI so we can mess with the inner state
+        let (server_addr, internal_api) =
+            run_tower_in_background_with_config(ApiConfig::new(u32::MAX, DURATION)).await;
+
+        // Register
+        let (user_sk, user_pk) = cryptography::get_random_keypair();
+        request_to_api::<msgs::RegisterRequest, msgs::RegisterResponse>(
+            "/register",
+            msgs::RegisterRequest {
+                user_id: user_pk.serialize().to_vec(),
+                requested_slots: None,
+            },
+            server_addr,
+        )
+        .await
+        .unwrap();
+
+        // Add the appointment to the Responder so it counts as triggered
+        let appointment = generate_dummy_appointment(None).inner;
+        let signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        internal_api
+            .get_watcher()
             .add_random_tracker_to_responder(UUID::new(appointment.locator, UserId(user_pk)));
 
         // Try to add it via the http API
@@ -819,6 +1703,50 @@ mod tests_methods {
         );
     }
 
+    #[tokio::test]
+    async fn test_add_appointment_to_self_delay_too_small() {
+        let server_addr = run_tower_in_background().await;
+
+        // Register
+        let (user_sk, user_pk) = cryptography::get_random_keypair();
+        request_to_api::<msgs::RegisterRequest, msgs::RegisterResponse>(
+            "/register",
+            msgs::RegisterRequest {
+                user_id: user_pk.serialize().to_vec(),
+                requested_slots: None,
+            },
+            server_addr,
+        )
+        .await
+        .unwrap();
+
+        let mut appointment = generate_dummy_appointment(None).inner;
+        appointment.to_self_delay = MIN_TO_SELF_DELAY - 1;
+        let signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+
+        assert_eq!(
+            check_api_error(
+                "/add_appointment",
+                RequestBody::Json(serde_json::json!(msgs::AddAppointmentRequest {
+                    appointment: Some(appointment.into()),
+                    signature,
+                })),
+                server_addr,
+            )
+            .await,
+            (
+                ApiError::new(
+                    format!(
+                        "to_self_delay too small. Expected at least {}",
+                        MIN_TO_SELF_DELAY
+                    ),
+                    errors::APPOINTMENT_FIELD_TOO_SMALL
+                ),
+                StatusCode::BAD_REQUEST
+            )
+        );
+    }
+
     #[tokio::test]
     async fn test_add_appointment_service_unavailable() {
         let (server_addr, _) = run_tower_in_background_with_config(
@@ -859,6 +1787,7 @@ mod tests_methods {
             "/register",
             msgs::RegisterRequest {
                 user_id: user_pk.serialize().to_vec(),
+                requested_slots: None,
             },
             server_addr,
         )
@@ -941,6 +1870,7 @@ mod tests_methods {
             "/register",
             msgs::RegisterRequest {
                 user_id: user_pk.serialize().to_vec(),
+                requested_slots: None,
             },
             server_addr,
         )
@@ -1019,6 +1949,7 @@ mod tests_methods {
             "/register",
             msgs::RegisterRequest {
                 user_id: user_pk.serialize().to_vec(),
+                requested_slots: None,
             },
             server_addr,
         )
@@ -1032,6 +1963,7 @@ mod tests_methods {
                 msgs::GetSubscriptionInfoRequest {
                     signature: cryptography::sign("get subscription info".as_bytes(), &user_sk)
                         .unwrap(),
+                    challenge: None,
                 },
                 server_addr,
             )
@@ -1056,6 +1988,7 @@ mod tests_methods {
                 RequestBody::Json(serde_json::json!(msgs::GetSubscriptionInfoRequest {
                     signature: cryptography::sign("get subscription info".as_bytes(), &user_sk)
                         .unwrap(),
+                    challenge: None,
                 })),
                 server_addr,
             )
@@ -1084,6 +2017,7 @@ mod tests_methods {
                 RequestBody::Json(serde_json::json!(msgs::GetSubscriptionInfoRequest {
                     signature: cryptography::sign("get subscription info".as_bytes(), &user_sk)
                         .unwrap(),
+                    challenge: None,
                 })),
                 server_addr,
             )
@@ -1097,4 +2031,750 @@ mod tests_methods {
             )
         );
     }
+
+    #[tokio::test]
+    async fn test_batch() {
+        let server_addr = run_tower_in_background().await;
+
+        // Register a user
+        let (user_sk, user_pk) = cryptography::get_random_keypair();
+        request_to_api::<msgs::RegisterRequest, msgs::RegisterResponse>(
+            "/register",
+            msgs::RegisterRequest {
+                user_id: user_pk.serialize().to_vec(),
+                requested_slots: None,
+            },
+            server_addr,
+        )
+        .await
+        .unwrap();
+
+        // Build a batch with a valid appointment, one signed by a non-registered user and a malformed one
+        let appointment = generate_dummy_appointment(None).inner;
+        let signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        let valid = msgs::AddAppointmentRequest {
+            appointment: Some(appointment.into()),
+            signature,
+        };
+
+        let (other_sk, _) = cryptography::get_random_keypair();
+        let appointment = generate_dummy_appointment(None).inner;
+        let signature = cryptography::sign(&appointment.serialize(), &other_sk).unwrap();
+        let non_registered = msgs::AddAppointmentRequest {
+            appointment: Some(appointment.into()),
+            signature,
+        };
+
+        let appointment = generate_dummy_appointment(None).inner;
+        let signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        let mut malformed_appointment: msgs::Appointment = appointment.into();
+        malformed_appointment.locator = Vec::new();
+        let malformed = msgs::AddAppointmentRequest {
+            appointment: Some(malformed_appointment),
+            signature,
+        };
+
+        let response = request_to_api::<Vec<msgs::AddAppointmentRequest>, Vec<serde_json::Value>>(
+            "/batch",
+            vec![valid, non_registered, malformed],
+            server_addr,
+        )
+        .await
+        .unwrap();
+
+        // Results are returned in order and a failure does not abort the rest of the batch
+        assert_eq!(response.len(), 3);
+        assert!(
+            serde_json::from_value::<msgs::AddAppointmentResponse>(response[0].clone()).is_ok()
+        );
+        assert_eq!(
+            serde_json::from_value::<ApiError>(response[1].clone()).unwrap(),
+            ApiError::new(
+                "Invalid signature or user does not have enough slots available".into(),
+                errors::INVALID_SIGNATURE_OR_SUBSCRIPTION_ERROR
+            )
+        );
+        assert_eq!(
+            serde_json::from_value::<ApiError>(response[2].clone()).unwrap(),
+            ApiError::new("`locator` field is empty".into(), errors::EMPTY_FIELD)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batch_empty() {
+        let server_addr = run_tower_in_background().await;
+
+        assert_eq!(
+            check_api_error(
+                "/batch",
+                RequestBody::Json(serde_json::json!(Vec::<msgs::AddAppointmentRequest>::new())),
+                server_addr,
+            )
+            .await,
+            (
+                ApiError::new("Batch is empty".into(), errors::EMPTY_FIELD),
+                StatusCode::BAD_REQUEST
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batch_too_big() {
+        let server_addr = run_tower_in_background().await;
+        let (user_sk, _) = cryptography::get_random_keypair();
+
+        let reqs = (0..MAX_BATCH_SIZE + 1)
+            .map(|_| {
+                let appointment = generate_dummy_appointment(None).inner;
+                let signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+                msgs::AddAppointmentRequest {
+                    appointment: Some(appointment.into()),
+                    signature,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            check_api_error(
+                "/batch",
+                RequestBody::Json(serde_json::json!(reqs)),
+                server_addr
+            )
+            .await,
+            (
+                ApiError::new(
+                    format!(
+                        "Batch too big. Expected at most {} appointments, received {}",
+                        MAX_BATCH_SIZE,
+                        MAX_BATCH_SIZE + 1
+                    ),
+                    errors::WRONG_FIELD_SIZE
+                ),
+                StatusCode::BAD_REQUEST
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_capabilities() {
+        let server_addr = run_tower_in_background().await;
+        let grpc_conn = PublicTowerServicesClient::connect(format!(
+            "http://{}:{}",
+            server_addr.ip(),
+            server_addr.port()
+        ))
+        .await
+        .unwrap();
+
+        let get_capabilities = |body_limits: BodyLimits, compression: bool| {
+            let router = router(grpc_conn.clone(), body_limits, compression, None, None);
+            async move {
+                let res = warp::test::request()
+                    .method("GET")
+                    .path("/get_capabilities")
+                    .reply(&router)
+                    .await;
+                assert_eq!(res.status(), StatusCode::OK);
+                serde_json::from_slice::<Capabilities>(res.body()).unwrap()
+            }
+        };
+
+        assert_eq!(
+            get_capabilities(BodyLimits::new(MAX_APPOINTMENT_SIZE, MAX_BATCH_SIZE), true).await,
+            Capabilities {
+                version: API_VERSION.to_owned(),
+                compression: true,
+                batch: true,
+                max_batch_size: MAX_BATCH_SIZE,
+                events: false,
+                max_appointment_size: MAX_APPOINTMENT_SIZE as u64,
+                min_to_self_delay: MIN_TO_SELF_DELAY,
+            }
+        );
+
+        // Towers that do not accept batches do not advertise them
+        let capabilities = get_capabilities(BodyLimits::new(MAX_APPOINTMENT_SIZE, 0), false).await;
+        assert!(!capabilities.batch);
+        assert!(!capabilities.compression);
+
+        // The advertised appointment size is the one HTTP bodies can actually carry, even if the Watcher would take
+        // bigger appointments
+        let capabilities = get_capabilities(
+            BodyLimits::new(MAX_APPOINTMENT_SIZE / 2, MAX_BATCH_SIZE),
+            false,
+        )
+        .await;
+        assert_eq!(
+            capabilities.max_appointment_size,
+            (MAX_APPOINTMENT_SIZE / 2) as u64
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_compression {
+    use super::*;
+
+    use super::test_helpers::{request_to_api, run_tower_in_background, MAX_BATCH_SIZE};
+    use crate::test_utils::{generate_dummy_appointment, MAX_APPOINTMENT_SIZE};
+    use teos_common::cryptography;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    async fn connect(server_addr: SocketAddr) -> PublicTowerServicesClient<Channel> {
+        PublicTowerServicesClient::connect(format!(
+            "http://{}:{}",
+            server_addr.ip(),
+            server_addr.port()
+        ))
+        .await
+        .unwrap()
+    }
+
+    #[test]
+    fn test_accepts_gzip() {
+        assert!(accepts_gzip("gzip"));
+        assert!(accepts_gzip("GZIP"));
+        assert!(accepts_gzip("deflate, gzip;q=0.5"));
+        assert!(accepts_gzip("*"));
+        assert!(!accepts_gzip("gzip;q=0"));
+        assert!(!accepts_gzip("deflate, br"));
+        assert!(!accepts_gzip(""));
+    }
+
+    #[tokio::test]
+    async fn test_gzip_round_trip() {
+        let server_addr = run_tower_in_background().await;
+
+        // Register a user
+        let (user_sk, user_pk) = cryptography::get_random_keypair();
+        request_to_api::<msgs::RegisterRequest, msgs::RegisterResponse>(
+            "/register",
+            msgs::RegisterRequest {
+                user_id: user_pk.serialize().to_vec(),
+                requested_slots: None,
+            },
+            server_addr,
+        )
+        .await
+        .unwrap();
+
+        // Build a batch big enough for the response to be compressed
+        let reqs = (0..MAX_BATCH_SIZE)
+            .map(|_| {
+                let appointment = generate_dummy_appointment(None).inner;
+                let signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+                msgs::AddAppointmentRequest {
+                    appointment: Some(appointment.into()),
+                    signature,
+                }
+            })
+            .collect::<Vec<_>>();
+        let body = gzip(&serde_json::to_vec(&reqs).unwrap());
+
+        let res = warp::test::request()
+            .method("POST")
+            .path("/batch")
+            .header("content-encoding", "gzip")
+            .header("accept-encoding", "gzip")
+            .body(body)
+            .reply(&router(
+                connect(server_addr).await,
+                BodyLimits::new(MAX_APPOINTMENT_SIZE, MAX_BATCH_SIZE),
+                true,
+                None,
+                None,
+            ))
+            .await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_ENCODING], "gzip");
+        let mut decompressed = Vec::new();
+        GzDecoder::new(res.body().as_ref())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert!(decompressed.len() >= COMPRESSION_THRESHOLD);
+
+        let response =
+            serde_json::from_slice::<Vec<msgs::AddAppointmentResponse>>(&decompressed).unwrap();
+        assert_eq!(response.len(), MAX_BATCH_SIZE);
+    }
+
+    #[tokio::test]
+    async fn test_no_compression_if_not_accepted() {
+        let server_addr = run_tower_in_background().await;
+        let (_, user_pk) = cryptography::get_random_keypair();
+        let body = serde_json::to_vec(&msgs::RegisterRequest {
+            user_id: user_pk.serialize().to_vec(),
+            requested_slots: None,
+        })
+        .unwrap();
+        let router = router(
+            connect(server_addr).await,
+            BodyLimits::new(MAX_APPOINTMENT_SIZE, MAX_BATCH_SIZE),
+            true,
+            None,
+            None,
+        );
+
+        // Clients that do not advertise gzip get plain responses
+        let res = warp::test::request()
+            .method("POST")
+            .path("/register")
+            .body(body.clone())
+            .reply(&router)
+            .await;
+        assert!(!res.headers().contains_key(CONTENT_ENCODING));
+        assert!(serde_json::from_slice::<msgs::RegisterResponse>(res.body()).is_ok());
+
+        // Responses below the threshold are not compressed either
+        let res = warp::test::request()
+            .method("POST")
+            .path("/register")
+            .header("accept-encoding", "gzip")
+            .body(body)
+            .reply(&router)
+            .await;
+        assert!(res.body().len() < COMPRESSION_THRESHOLD);
+        assert!(!res.headers().contains_key(CONTENT_ENCODING));
+        assert!(serde_json::from_slice::<msgs::RegisterResponse>(res.body()).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_compressed_request_errors() {
+        let server_addr = run_tower_in_background().await;
+        let grpc_conn = connect(server_addr).await;
+
+        // Deserialization errors are reported as for plain requests
+        let res = warp::test::request()
+            .method("POST")
+            .path("/register")
+            .header("content-encoding", "gzip")
+            .body(gzip(b"{\"user_id\": 1}"))
+            .reply(&router(
+                grpc_conn.clone(),
+                BodyLimits::new(MAX_APPOINTMENT_SIZE, MAX_BATCH_SIZE),
+                true,
+                None,
+                None,
+            ))
+            .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            serde_json::from_slice::<ApiError>(res.body())
+                .unwrap()
+                .error_code,
+            errors::WRONG_FIELD_TYPE
+        );
+
+        // Bodies that are not actually gzip encoded are rejected
+        let res = warp::test::request()
+            .method("POST")
+            .path("/register")
+            .header("content-encoding", "gzip")
+            .body("{}")
+            .reply(&router(
+                grpc_conn.clone(),
+                BodyLimits::new(MAX_APPOINTMENT_SIZE, MAX_BATCH_SIZE),
+                true,
+                None,
+                None,
+            ))
+            .await;
+        assert_eq!(
+            serde_json::from_slice::<ApiError>(res.body()).unwrap(),
+            ApiError::new(
+                "Invalid gzip encoded body".into(),
+                errors::INVALID_REQUEST_FORMAT
+            )
+        );
+
+        // As are bodies that exceed the limit once decompressed
+        let res = warp::test::request()
+            .method("POST")
+            .path("/register")
+            .header("content-encoding", "gzip")
+            .body(gzip(&[b' '; REGISTER_BODY_LEN as usize * 10]))
+            .reply(&router(
+                grpc_conn.clone(),
+                BodyLimits::new(MAX_APPOINTMENT_SIZE, MAX_BATCH_SIZE),
+                true,
+                None,
+                None,
+            ))
+            .await;
+        assert_eq!(
+            serde_json::from_slice::<ApiError>(res.body()).unwrap(),
+            ApiError::new(
+                "Decompressed body is too large".into(),
+                errors::INVALID_REQUEST_FORMAT
+            )
+        );
+
+        // If compression is disabled compressed bodies are not understood
+        let res = warp::test::request()
+            .method("POST")
+            .path("/register")
+            .header("content-encoding", "gzip")
+            .body(gzip(b"{}"))
+            .reply(&router(
+                grpc_conn,
+                BodyLimits::new(MAX_APPOINTMENT_SIZE, MAX_BATCH_SIZE),
+                false,
+                None,
+                None,
+            ))
+            .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+}
+
+#[cfg(test)]
+mod tests_events {
+    use super::*;
+
+    use super::test_helpers::{
+        request_to_api, run_tower_in_background_with_config, MAX_BATCH_SIZE,
+    };
+    use crate::events;
+    use crate::test_utils::{generate_dummy_appointment, ApiConfig, MAX_APPOINTMENT_SIZE};
+    use bitcoin::secp256k1::SecretKey;
+    use warp::test::WsClient;
+
+    /// Signs the challenge sent by the tower on connection and sends the signature back.
+    async fn answer_challenge(client: &mut WsClient, user_sk: &SecretKey) {
+        let msg = client.recv().await.unwrap();
+        let challenge = serde_json::from_str::<serde_json::Value>(msg.to_str().unwrap()).unwrap()
+            ["challenge"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+        client
+            .send_text(cryptography::sign(challenge.as_bytes(), user_sk).unwrap())
+            .await;
+    }
+
+    async fn connect(server_addr: SocketAddr) -> PublicTowerServicesClient<Channel> {
+        PublicTowerServicesClient::connect(format!(
+            "http://{}:{}",
+            server_addr.ip(),
+            server_addr.port()
+        ))
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_events() {
+        let event_sender = events::channel();
+        let (server_addr, _) = run_tower_in_background_with_config(
+            ApiConfig::default().event_sender(event_sender.clone()),
+        )
+        .await;
+        let router = router(
+            connect(server_addr).await,
+            BodyLimits::new(MAX_APPOINTMENT_SIZE, MAX_BATCH_SIZE),
+            false,
+            Some(event_sender),
+            None,
+        );
+
+        // Register a user and subscribe to its events
+        let (user_sk, user_pk) = cryptography::get_random_keypair();
+        request_to_api::<msgs::RegisterRequest, msgs::RegisterResponse>(
+            "/register",
+            msgs::RegisterRequest {
+                user_id: user_pk.serialize().to_vec(),
+                requested_slots: None,
+            },
+            server_addr,
+        )
+        .await
+        .unwrap();
+
+        let mut client = warp::test::ws()
+            .path("/events")
+            .handshake(router)
+            .await
+            .unwrap();
+        answer_challenge(&mut client, &user_sk).await;
+
+        // Add an appointment and check the event is received
+        let appointment = generate_dummy_appointment(None).inner;
+        let signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        request_to_api::<msgs::AddAppointmentRequest, msgs::AddAppointmentResponse>(
+            "/add_appointment",
+            msgs::AddAppointmentRequest {
+                appointment: Some(appointment.clone().into()),
+                signature,
+            },
+            server_addr,
+        )
+        .await
+        .unwrap();
+
+        let msg = client.recv().await.unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(msg.to_str().unwrap()).unwrap(),
+            serde_json::json!({
+                "event": "appointment_accepted",
+                "user_id": UserId(user_pk).to_string(),
+                "locator": appointment.locator.to_string(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_events_non_registered() {
+        let event_sender = events::channel();
+        let (server_addr, _) = run_tower_in_background_with_config(
+            ApiConfig::default().event_sender(event_sender.clone()),
+        )
+        .await;
+        let router = router(
+            connect(server_addr).await,
+            BodyLimits::new(MAX_APPOINTMENT_SIZE, MAX_BATCH_SIZE),
+            false,
+            Some(event_sender),
+            None,
+        );
+
+        let (user_sk, _) = cryptography::get_random_keypair();
+        let mut client = warp::test::ws()
+            .path("/events")
+            .handshake(router)
+            .await
+            .unwrap();
+        answer_challenge(&mut client, &user_sk).await;
+
+        let msg = client.recv().await.unwrap();
+        assert_eq!(
+            serde_json::from_str::<ApiError>(msg.to_str().unwrap())
+                .unwrap()
+                .error_code,
+            errors::INVALID_SIGNATURE_OR_SUBSCRIPTION_ERROR
+        );
+        assert!(client.recv_closed().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_events_replayed_signature() {
+        let event_sender = events::channel();
+        let (server_addr, _) = run_tower_in_background_with_config(
+            ApiConfig::default().event_sender(event_sender.clone()),
+        )
+        .await;
+        let router = router(
+            connect(server_addr).await,
+            BodyLimits::new(MAX_APPOINTMENT_SIZE, MAX_BATCH_SIZE),
+            false,
+            Some(event_sender),
+            None,
+        );
+
+        let (user_sk, user_pk) = cryptography::get_random_keypair();
+        request_to_api::<msgs::RegisterRequest, msgs::RegisterResponse>(
+            "/register",
+            msgs::RegisterRequest {
+                user_id: user_pk.serialize().to_vec(),
+                requested_slots: None,
+            },
+            server_addr,
+        )
+        .await
+        .unwrap();
+
+        // Signatures of anything but the challenge handed to this connection are rejected (e.g. one sent to get the
+        // subscription info)
+        let mut client = warp::test::ws()
+            .path("/events")
+            .handshake(router)
+            .await
+            .unwrap();
+        client.recv().await.unwrap();
+        client
+            .send_text(cryptography::sign("get subscription info".as_bytes(), &user_sk).unwrap())
+            .await;
+
+        let msg = client.recv().await.unwrap();
+        assert_eq!(
+            serde_json::from_str::<ApiError>(msg.to_str().unwrap())
+                .unwrap()
+                .error_code,
+            errors::INVALID_SIGNATURE_OR_SUBSCRIPTION_ERROR
+        );
+        assert!(client.recv_closed().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_events_handshake_timeout() {
+        let event_sender = events::channel();
+        let (server_addr, _) = run_tower_in_background_with_config(
+            ApiConfig::default().event_sender(event_sender.clone()),
+        )
+        .await;
+        let router = router(
+            connect(server_addr).await,
+            BodyLimits::new(MAX_APPOINTMENT_SIZE, MAX_BATCH_SIZE),
+            false,
+            Some(event_sender),
+            None,
+        );
+
+        // Clients that do not answer the challenge are disconnected after a while
+        let mut client = warp::test::ws()
+            .path("/events")
+            .handshake(router)
+            .await
+            .unwrap();
+        client.recv().await.unwrap();
+
+        let before = std::time::Instant::now();
+        assert!(client.recv_closed().await.is_ok());
+        assert!(before.elapsed() >= EVENTS_HANDSHAKE_TIMEOUT - Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_events_disabled() {
+        let server_addr = run_tower_in_background_with_config(ApiConfig::default())
+            .await
+            .0;
+
+        let res = warp::test::ws()
+            .path("/events")
+            .handshake(router(
+                connect(server_addr).await,
+                BodyLimits::new(MAX_APPOINTMENT_SIZE, MAX_BATCH_SIZE),
+                false,
+                None,
+                None,
+            ))
+            .await;
+        assert!(res.is_err());
+    }
+}
+
+#[cfg(test)]
+mod tests_rate_limit {
+    use super::*;
+
+    use serde_json::{json, Value};
+
+    use super::test_helpers::{
+        request_to_api, run_tower_in_background_with_config, MAX_BATCH_SIZE,
+    };
+    use crate::test_utils::{generate_dummy_appointment, ApiConfig, MAX_APPOINTMENT_SIZE};
+
+    async fn add_appointment(
+        filter: &(impl Filter<Extract = impl Reply, Error = Rejection> + Clone + 'static),
+        user_sk: &bitcoin::secp256k1::SecretKey,
+    ) -> (StatusCode, Bytes) {
+        let appointment = generate_dummy_appointment(None).inner;
+        let signature = cryptography::sign(&appointment.serialize(), user_sk).unwrap();
+        let res = warp::test::request()
+            .method("POST")
+            .path("/add_appointment")
+            .json(&msgs::AddAppointmentRequest {
+                appointment: Some(appointment.into()),
+                signature,
+            })
+            .reply(filter)
+            .await;
+        (res.status(), res.body().clone())
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let (server_addr, internal_api) =
+            run_tower_in_background_with_config(ApiConfig::default()).await;
+        let grpc_conn = PublicTowerServicesClient::connect(format!(
+            "http://{}:{}",
+            server_addr.ip(),
+            server_addr.port()
+        ))
+        .await
+        .unwrap();
+        let router = router(
+            grpc_conn,
+            BodyLimits::new(MAX_APPOINTMENT_SIZE, MAX_BATCH_SIZE),
+            false,
+            None,
+            Some(RateLimiter::new(
+                1,
+                2,
+                internal_api.get_watcher().get_gatekeeper(),
+            )),
+        );
+
+        let mut user_sks = Vec::new();
+        for _ in 0..2 {
+            let (user_sk, user_pk) = cryptography::get_random_keypair();
+            request_to_api::<msgs::RegisterRequest, msgs::RegisterResponse>(
+                "/register",
+                msgs::RegisterRequest {
+                    user_id: user_pk.serialize().to_vec(),
+                    requested_slots: None,
+                },
+                server_addr,
+            )
+            .await
+            .unwrap();
+            user_sks.push(user_sk);
+        }
+
+        // The user can send up to the burst size in a row
+        for _ in 0..2 {
+            assert_eq!(
+                add_appointment(&router, &user_sks[0]).await.0,
+                StatusCode::OK
+            );
+        }
+        let (status, body) = add_appointment(&router, &user_sks[0]).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            serde_json::from_slice::<ApiError>(&body)
+                .unwrap()
+                .error_code,
+            errors::RATE_LIMIT_EXCEEDED
+        );
+
+        // Other users are not affected. Each appointment in a batch counts as a separate request
+        let reqs = (0..3)
+            .map(|_| {
+                let appointment = generate_dummy_appointment(None).inner;
+                let signature = cryptography::sign(&appointment.serialize(), &user_sks[1]).unwrap();
+                msgs::AddAppointmentRequest {
+                    appointment: Some(appointment.into()),
+                    signature,
+                }
+            })
+            .collect::<Vec<_>>();
+        let res = warp::test::request()
+            .method("POST")
+            .path("/batch")
+            .json(&reqs)
+            .reply(&router)
+            .await;
+        let results = serde_json::from_slice::<Vec<Value>>(res.body()).unwrap();
+        assert!(results[0].get("error_code").is_none());
+        assert!(results[1].get("error_code").is_none());
+        assert_eq!(results[2]["error_code"], json!(errors::RATE_LIMIT_EXCEEDED));
+
+        // Unregistered users share a single, stricter, bucket. Requests are accounted for even if the tower ends up
+        // rejecting them, so a second random key is already limited
+        let (other_sk, _) = cryptography::get_random_keypair();
+        assert_eq!(
+            add_appointment(&router, &other_sk).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        let (other_sk, _) = cryptography::get_random_keypair();
+        assert_eq!(
+            add_appointment(&router, &other_sk).await.0,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
 }
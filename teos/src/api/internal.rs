@@ -1,4 +1,7 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
+use std::vec::IntoIter;
 use tonic::{Code, Request, Response, Status};
 use triggered::Trigger;
 
@@ -6,13 +9,80 @@ use crate::protos as msgs;
 use crate::protos::private_tower_services_server::PrivateTowerServices;
 use crate::protos::public_tower_services_server::PublicTowerServices;
 
+use crate::config::Config;
+use crate::export::ExportedData;
+use crate::extended_appointment::UUID;
+use crate::responder::{BroadcastPenaltyFailure, ConfirmationStatus};
 use crate::watcher::{
     AddAppointmentFailure, AppointmentInfo, GetAppointmentFailure, GetSubscriptionInfoFailure,
-    Watcher,
+    ImportDataFailure, RegisterFailure, UpdateAppointmentFailure, Watcher,
 };
 
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::Txid;
 use teos_common::appointment::{Appointment, AppointmentStatus, Locator};
-use teos_common::UserId;
+use teos_common::cryptography;
+use teos_common::{errors, UserId};
+
+/// Size of the chunks the exported data is streamed in.
+const EXPORT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Builds the [Status] returned to users banned from the tower.
+fn user_banned() -> Status {
+    Status::new(Code::PermissionDenied, "User banned from the tower")
+}
+
+/// Builds the [Status] returned if a request holds a malformed locator.
+fn wrong_locator() -> Status {
+    Status::new(
+        Code::InvalidArgument,
+        "Provided locator does not match expected format (16-byte value)",
+    )
+}
+
+/// Builds the [Status] returned if an appointment is rejected.
+fn appointment_rejected(e: AddAppointmentFailure) -> Status {
+    match e {
+        AddAppointmentFailure::AuthenticationFailure | AddAppointmentFailure::NotEnoughSlots => {
+            Status::new(
+                Code::Unauthenticated,
+                "Invalid signature or user does not have enough slots available",
+            )
+        }
+        AddAppointmentFailure::UserBanned => user_banned(),
+        AddAppointmentFailure::MaxAppointmentsReached(x) => Status::new(
+            Code::FailedPrecondition,
+            format!("Maximum number of appointments per user reached ({})", x),
+        ),
+        AddAppointmentFailure::BlobTooBig(x) => Status::with_details(
+            Code::OutOfRange,
+            format!("Encrypted blob too big. Expected at most {} bytes", x),
+            vec![errors::APPOINTMENT_FIELD_TOO_BIG].into(),
+        ),
+        AddAppointmentFailure::BlobTooSmall(x) => Status::with_details(
+            Code::OutOfRange,
+            format!("Encrypted blob too small. Expected at least {} bytes", x),
+            vec![errors::APPOINTMENT_FIELD_TOO_SMALL].into(),
+        ),
+        AddAppointmentFailure::ToSelfDelayTooSmall(x) => Status::with_details(
+            Code::OutOfRange,
+            format!("to_self_delay too small. Expected at least {}", x),
+            vec![errors::APPOINTMENT_FIELD_TOO_SMALL].into(),
+        ),
+        AddAppointmentFailure::SubscriptionExpired(x) => Status::new(
+            Code::Unauthenticated,
+            format!("Your subscription expired at {}", x),
+        ),
+        AddAppointmentFailure::AlreadyTriggered => Status::new(
+            Code::AlreadyExists,
+            "The provided appointment has already been triggered",
+        ),
+        AddAppointmentFailure::DBError => {
+            Status::new(Code::Internal, "Internal error. Try again later")
+        }
+    }
+}
 
 /// Internal API of the tower.
 /// Holds the [Watcher] (which is the single entry point of the tower's core) and offers interfaces
@@ -25,6 +95,14 @@ pub struct InternalAPI {
     bitcoind_reachable: Arc<(Mutex<bool>, Condvar)>,
     /// A signal indicating the tower is shuting down.
     shutdown_trigger: Trigger,
+    /// A flag that indicates whether the onion service is up or not.
+    tor_available: Arc<AtomicBool>,
+    /// A flag that indicates whether any background task has panicked.
+    task_panicked: Arc<AtomicBool>,
+    /// The effective configuration the tower is running with.
+    config: Config,
+    /// The last challenge handed out to sign a stop request (if any). Challenges can only be used once.
+    stop_challenge: Mutex<Option<String>>,
 }
 
 impl InternalAPI {
@@ -33,14 +111,39 @@ impl InternalAPI {
         watcher: Arc<Watcher>,
         bitcoind_reachable: Arc<(Mutex<bool>, Condvar)>,
         shutdown_trigger: Trigger,
+        tor_available: Arc<AtomicBool>,
+        task_panicked: Arc<AtomicBool>,
+        config: Config,
     ) -> Self {
         Self {
             watcher,
             bitcoind_reachable,
             shutdown_trigger,
+            tor_available,
+            task_panicked,
+            config,
+            stop_challenge: Mutex::new(None),
         }
     }
 
+    /// Runs a [Watcher] call on tokio's blocking thread pool and waits for it.
+    ///
+    /// Most [Watcher] calls end up hitting the database (or bitcoind), which is synchronous. Offloading them keeps the
+    /// executor threads free to serve other requests while the I/O is going on.
+    async fn run_blocking<F, T>(&self, f: F) -> Result<T, Status>
+    where
+        F: FnOnce(&Watcher) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let watcher = self.watcher.clone();
+        tokio::task::spawn_blocking(move || f(&watcher))
+            .await
+            .map_err(|e| {
+                log::error!("Blocking watcher call did not complete. Error: {}", e);
+                Status::new(Code::Internal, "Internal error. Try again later")
+            })
+    }
+
     /// Checks whether bitcoind is reachable.
     fn check_service_unavailable(&self) -> Result<(), Status> {
         if *self.bitcoind_reachable.0.lock().unwrap() {
@@ -73,17 +176,33 @@ impl PublicTowerServices for Arc<InternalAPI> {
             )
         })?;
 
-        match self.watcher.register(user_id) {
+        if req_data.requested_slots == Some(0) {
+            return Err(Status::new(
+                Code::InvalidArgument,
+                "requested_slots must be greater than zero",
+            ));
+        }
+
+        let requested_slots = req_data.requested_slots;
+        match self
+            .run_blocking(move |watcher| watcher.register(user_id, requested_slots))
+            .await?
+        {
             Ok(receipt) => Ok(Response::new(msgs::RegisterResponse {
                 user_id: req_data.user_id,
                 available_slots: receipt.available_slots(),
                 subscription_expiry: receipt.subscription_expiry(),
                 subscription_signature: receipt.signature().unwrap(),
             })),
-            Err(_) => Err(Status::new(
+            Err(RegisterFailure::MaxSlotsReached) => Err(Status::new(
                 Code::ResourceExhausted,
                 "Subscription maximum slots count reached",
             )),
+            Err(RegisterFailure::UserBanned) => Err(user_banned()),
+            Err(RegisterFailure::DBError) => Err(Status::new(
+                Code::Internal,
+                "Internal error. Try again later",
+            )),
         }
     }
 
@@ -94,18 +213,21 @@ impl PublicTowerServices for Arc<InternalAPI> {
     ) -> Result<Response<msgs::AddAppointmentResponse>, Status> {
         self.check_service_unavailable()?;
         let req_data = request.into_inner();
-        let app_data = req_data.appointment.unwrap();
+        let app_data = req_data
+            .appointment
+            .ok_or_else(|| Status::new(Code::InvalidArgument, "Missing appointment"))?;
 
         let appointment = Appointment::new(
-            Locator::deserialize(&app_data.locator).unwrap(),
+            Locator::deserialize(&app_data.locator).map_err(|_| wrong_locator())?,
             app_data.encrypted_blob,
             app_data.to_self_delay,
         );
         let locator = appointment.locator;
 
+        let signature = req_data.signature;
         match self
-            .watcher
-            .add_appointment(appointment, req_data.signature)
+            .run_blocking(move |watcher| watcher.add_appointment(appointment, signature))
+            .await?
         {
             Ok((receipt, available_slots, subscription_expiry)) => {
                 Ok(Response::new(msgs::AddAppointmentResponse {
@@ -116,21 +238,47 @@ impl PublicTowerServices for Arc<InternalAPI> {
                     subscription_expiry,
                 }))
             }
-            Err(e) => match e {
-                AddAppointmentFailure::AuthenticationFailure
-                | AddAppointmentFailure::NotEnoughSlots => Err(Status::new(
-                    Code::Unauthenticated,
-                    "Invalid signature or user does not have enough slots available",
-                )),
-                AddAppointmentFailure::SubscriptionExpired(x) => Err(Status::new(
-                    Code::Unauthenticated,
-                    format!("Your subscription expired at {}", x),
-                )),
-                AddAppointmentFailure::AlreadyTriggered => Err(Status::new(
-                    Code::AlreadyExists,
-                    "The provided appointment has already been triggered",
-                )),
-            },
+            Err(e) => Err(appointment_rejected(e)),
+        }
+    }
+
+    /// Update appointment endpoint. Part of the public API. Internally calls [Watcher::update_appointment].
+    async fn update_appointment(
+        &self,
+        request: Request<msgs::AddAppointmentRequest>,
+    ) -> Result<Response<msgs::UpdateAppointmentResponse>, Status> {
+        self.check_service_unavailable()?;
+        let req_data = request.into_inner();
+        let app_data = req_data
+            .appointment
+            .ok_or_else(|| Status::new(Code::InvalidArgument, "Missing appointment"))?;
+
+        let appointment = Appointment::new(
+            Locator::deserialize(&app_data.locator).map_err(|_| wrong_locator())?,
+            app_data.encrypted_blob,
+            app_data.to_self_delay,
+        );
+        let locator = appointment.locator;
+
+        let signature = req_data.signature;
+        match self
+            .run_blocking(move |watcher| watcher.update_appointment(appointment, signature))
+            .await?
+        {
+            Ok((receipt, available_slots, subscription_expiry, version)) => {
+                Ok(Response::new(msgs::UpdateAppointmentResponse {
+                    locator: locator.serialize(),
+                    start_block: receipt.start_block(),
+                    signature: receipt.signature().unwrap(),
+                    available_slots,
+                    subscription_expiry,
+                    version,
+                }))
+            }
+            Err(UpdateAppointmentFailure::NotFound) => {
+                Err(Status::new(Code::NotFound, "Appointment not found"))
+            }
+            Err(UpdateAppointmentFailure::Rejected(e)) => Err(appointment_rejected(e)),
         }
     }
 
@@ -141,11 +289,31 @@ impl PublicTowerServices for Arc<InternalAPI> {
     ) -> Result<Response<msgs::GetAppointmentResponse>, Status> {
         self.check_service_unavailable()?;
         let req_data = request.into_inner();
-        let locator = Locator::deserialize(&req_data.locator).unwrap();
+        let locator = Locator::deserialize(&req_data.locator).map_err(|_| wrong_locator())?;
+
+        let result = self
+            .run_blocking(move |watcher| {
+                watcher
+                    .get_appointment(locator, &req_data.signature)
+                    .map(|info| {
+                        let penalty_info = match &info {
+                            AppointmentInfo::Appointment(_) => None,
+                            AppointmentInfo::Tracker(tracker) => {
+                                let confirmations = watcher.get_penalty_confirmations(tracker);
+                                // Fees only matter while the penalty is yet to be confirmed
+                                let likely_underfunded = confirmations == 0
+                                    && watcher.is_penalty_likely_underfunded(tracker);
+                                Some((confirmations, likely_underfunded))
+                            }
+                        };
+                        (info, penalty_info)
+                    })
+            })
+            .await?;
 
-        match self.watcher.get_appointment(locator, &req_data.signature) {
-            Ok(info) => {
-                let (appointment_data, status) = match info {
+        match result {
+            Ok((info, penalty_info)) => {
+                let (appointment_data, status, confirmations, likely_underfunded) = match info {
                     AppointmentInfo::Appointment(appointment) => (
                         msgs::AppointmentData {
                             appointment_data: Some(
@@ -155,19 +323,30 @@ impl PublicTowerServices for Arc<InternalAPI> {
                             ),
                         },
                         AppointmentStatus::BeingWatched,
+                        None,
+                        false,
                     ),
-                    AppointmentInfo::Tracker(tracker) => (
-                        msgs::AppointmentData {
-                            appointment_data: Some(
-                                msgs::appointment_data::AppointmentData::Tracker(tracker.into()),
-                            ),
-                        },
-                        AppointmentStatus::DisputeResponded,
-                    ),
+                    AppointmentInfo::Tracker(tracker) => {
+                        let (confirmations, likely_underfunded) = penalty_info.unwrap();
+                        (
+                            msgs::AppointmentData {
+                                appointment_data: Some(
+                                    msgs::appointment_data::AppointmentData::Tracker(
+                                        tracker.into(),
+                                    ),
+                                ),
+                            },
+                            AppointmentStatus::DisputeResponded,
+                            Some(confirmations),
+                            likely_underfunded,
+                        )
+                    }
                 };
                 Ok(Response::new(msgs::GetAppointmentResponse {
                     appointment_data: Some(appointment_data),
                     status: status as i32,
+                    confirmations,
+                    likely_underfunded,
                 }))
             }
             Err(e) => match e {
@@ -178,6 +357,7 @@ impl PublicTowerServices for Arc<InternalAPI> {
                     Code::Unauthenticated,
                     "User cannot be authenticated",
                 )),
+                GetAppointmentFailure::UserBanned => Err(user_banned()),
                 GetAppointmentFailure::SubscriptionExpired(x) => Err(Status::new(
                     Code::Unauthenticated,
                     format!("Your subscription expired at {}", x),
@@ -192,14 +372,18 @@ impl PublicTowerServices for Arc<InternalAPI> {
         request: Request<msgs::GetSubscriptionInfoRequest>,
     ) -> Result<Response<msgs::GetSubscriptionInfoResponse>, Status> {
         self.check_service_unavailable()?;
+        let req_data = request.into_inner();
         let (subscription_info, locators) = self
-            .watcher
-            .get_subscription_info(&request.into_inner().signature)
+            .run_blocking(move |watcher| {
+                watcher.get_subscription_info(&req_data.signature, req_data.challenge.as_deref())
+            })
+            .await?
             .map_err(|e| match e {
                 GetSubscriptionInfoFailure::AuthenticationFailure => Status::new(
                     Code::Unauthenticated,
                     "User not found. Have you registered?",
                 ),
+                GetSubscriptionInfoFailure::UserBanned => user_banned(),
                 GetSubscriptionInfoFailure::SubscriptionExpired(x) => Status::new(
                     Code::Unauthenticated,
                     format!("Your subscription expired at {}", x),
@@ -212,6 +396,17 @@ impl PublicTowerServices for Arc<InternalAPI> {
             locators: locators.iter().map(|x| x.serialize()).collect(),
         }))
     }
+
+    /// Get capabilities endpoint. Part of the public API. Reports the limits the [Watcher] enforces on appointments.
+    async fn get_capabilities(
+        &self,
+        _: Request<()>,
+    ) -> Result<Response<msgs::GetCapabilitiesResponse>, Status> {
+        Ok(Response::new(msgs::GetCapabilitiesResponse {
+            max_appointment_size: self.watcher.get_max_appointment_size() as u64,
+            min_to_self_delay: self.watcher.get_min_to_self_delay(),
+        }))
+    }
 }
 
 /// Private tower API. Only accessible by the tower admin via RPC.
@@ -223,9 +418,17 @@ impl PrivateTowerServices for Arc<InternalAPI> {
         &self,
         _: Request<()>,
     ) -> Result<Response<msgs::GetAllAppointmentsResponse>, Status> {
+        let (watcher_appointments, responder_trackers) = self
+            .run_blocking(|watcher| {
+                (
+                    watcher.get_all_watcher_appointments(),
+                    watcher.get_all_responder_trackers(),
+                )
+            })
+            .await?;
         let mut all_appointments = Vec::new();
 
-        for (_, appointment) in self.watcher.get_all_watcher_appointments().into_iter() {
+        for (_, appointment) in watcher_appointments.into_iter() {
             all_appointments.push(msgs::AppointmentData {
                 appointment_data: Some(msgs::appointment_data::AppointmentData::Appointment(
                     appointment.inner.into(),
@@ -233,7 +436,7 @@ impl PrivateTowerServices for Arc<InternalAPI> {
             })
         }
 
-        for (_, tracker) in self.watcher.get_all_responder_trackers().into_iter() {
+        for (_, tracker) in responder_trackers.into_iter() {
             all_appointments.push(msgs::AppointmentData {
                 appointment_data: Some(msgs::appointment_data::AppointmentData::Tracker(
                     tracker.into(),
@@ -246,6 +449,74 @@ impl PrivateTowerServices for Arc<InternalAPI> {
         }))
     }
 
+    /// Get appointments endpoint. Gets a page of the appointments in the tower, optionally filtered by user and status.
+    /// Part of the private API. Internally calls [Watcher::get_appointments].
+    async fn get_appointments(
+        &self,
+        request: Request<msgs::GetAppointmentsRequest>,
+    ) -> Result<Response<msgs::GetAppointmentsResponse>, Status> {
+        let req_data = request.into_inner();
+
+        let user_id = if req_data.user_id.is_empty() {
+            None
+        } else {
+            Some(UserId::deserialize(&req_data.user_id).map_err(|_| {
+                Status::new(
+                    Code::InvalidArgument,
+                    "Provided public key does not match expected format (33-byte compressed key)",
+                )
+            })?)
+        };
+        let status = match req_data.status {
+            0 => None,
+            1 | 2 => Some(AppointmentStatus::from(req_data.status)),
+            _ => {
+                return Err(Status::new(
+                    Code::InvalidArgument,
+                    "Unknown appointment status",
+                ))
+            }
+        };
+
+        let appointments = self
+            .run_blocking(move |watcher| {
+                watcher.get_appointments(user_id, status, req_data.limit, req_data.offset)
+            })
+            .await?
+            .into_iter()
+            .map(|(_, info)| info.into())
+            .collect();
+
+        Ok(Response::new(msgs::GetAppointmentsResponse {
+            appointments,
+        }))
+    }
+
+    /// Get appointment by txid endpoint. Gets the appointments, from any user, triggered by a given dispute
+    /// transaction. Part of the private API. Internally calls [Watcher::get_appointments_by_txid].
+    async fn get_appointment_by_txid(
+        &self,
+        request: Request<msgs::GetAppointmentByTxidRequest>,
+    ) -> Result<Response<msgs::GetAppointmentsResponse>, Status> {
+        let dispute_txid = Txid::from_slice(&request.into_inner().dispute_txid).map_err(|_| {
+            Status::new(
+                Code::InvalidArgument,
+                "Provided txid does not match expected format (32-byte hash)",
+            )
+        })?;
+
+        let appointments = self
+            .run_blocking(move |watcher| watcher.get_appointments_by_txid(dispute_txid))
+            .await?
+            .into_iter()
+            .map(|(_, info)| info.into())
+            .collect();
+
+        Ok(Response::new(msgs::GetAppointmentsResponse {
+            appointments,
+        }))
+    }
+
     /// Get tower info endpoint. Gets information about the tower state. Part of the private API.
     /// Internally calls [Watcher::get_registered_users_count], [Watcher::get_appointments_count]
     /// and [Watcher::get_trackers_count].
@@ -254,14 +525,68 @@ impl PrivateTowerServices for Arc<InternalAPI> {
         _: Request<()>,
     ) -> Result<Response<msgs::GetTowerInfoResponse>, Status> {
         Ok(Response::new(msgs::GetTowerInfoResponse {
-            tower_id: self.watcher.tower_id.serialize(),
+            tower_id: self.watcher.tower_id().serialize(),
             n_registered_users: self.watcher.get_registered_users_count() as u32,
             n_watcher_appointments: self.watcher.get_appointments_count() as u32,
             n_responder_trackers: self.watcher.get_trackers_count() as u32,
             bitcoind_reachable: self.check_service_unavailable().is_ok(),
+            tor_available: self.tor_available.load(Ordering::Acquire),
         }))
     }
 
+    /// Get health endpoint. Gets the liveness status of the tower components. Part of the private API.
+    /// Internally calls [Watcher::get_last_known_block_height] and [Watcher::is_db_reachable].
+    async fn get_health(
+        &self,
+        _: Request<()>,
+    ) -> Result<Response<msgs::GetHealthResponse>, Status> {
+        Ok(Response::new(msgs::GetHealthResponse {
+            bitcoind_reachable: self.check_service_unavailable().is_ok(),
+            db_reachable: self
+                .run_blocking(|watcher| watcher.is_db_reachable())
+                .await?,
+            last_known_block_height: self.watcher.get_last_known_block_height(),
+            task_panicked: self.task_panicked.load(Ordering::Acquire),
+        }))
+    }
+
+    /// Get stats endpoint. Gets aggregate figures about the data held by the tower. Part of the private API.
+    /// Internally calls [Watcher::get_stats].
+    async fn get_stats(&self, _: Request<()>) -> Result<Response<msgs::GetStatsResponse>, Status> {
+        match self.run_blocking(|watcher| watcher.get_stats()).await? {
+            Ok(stats) => Ok(Response::new(msgs::GetStatsResponse {
+                n_registered_users: stats.n_users,
+                n_watched_appointments: stats.n_watched_appointments,
+                n_responded_appointments: stats.n_responded_appointments,
+                used_slots: stats.used_slots,
+                available_slots: stats.available_slots,
+                db_size: stats.db_size,
+            })),
+            Err(e) => {
+                log::error!("Cannot load the tower stats. Error: {:?}", e);
+                Err(Status::new(Code::Internal, "cannot load the tower stats"))
+            }
+        }
+    }
+
+    /// Get config endpoint. Gets the effective configuration of the tower, with its secrets redacted. Part of the
+    /// private API.
+    async fn get_config(
+        &self,
+        _: Request<()>,
+    ) -> Result<Response<msgs::GetConfigResponse>, Status> {
+        match serde_json::to_string(&self.config) {
+            Ok(config) => Ok(Response::new(msgs::GetConfigResponse { config })),
+            Err(e) => {
+                log::error!("Cannot serialize the tower config. Error: {}", e);
+                Err(Status::new(
+                    Code::Internal,
+                    "cannot serialize the tower config",
+                ))
+            }
+        }
+    }
+
     /// Get user endpoint. Gets all users in the tower. Part of the private API.
     /// Internally calls [Watcher::get_user_ids].
     async fn get_users(&self, _: Request<()>) -> Result<Response<msgs::GetUsersResponse>, Status> {
@@ -302,8 +627,251 @@ impl PrivateTowerServices for Arc<InternalAPI> {
         }
     }
 
+    /// Get user subscription history endpoint. Gets the subscription events (registrations, renewals and expiries)
+    /// of a given user. Part of the private API. Internally calls [Watcher::get_subscription_history].
+    async fn get_user_subscription_history(
+        &self,
+        request: Request<msgs::GetUserRequest>,
+    ) -> Result<Response<msgs::GetUserSubscriptionHistoryResponse>, Status> {
+        let user_id = UserId::deserialize(&request.into_inner().user_id).map_err(|_| {
+            Status::new(
+                Code::InvalidArgument,
+                "Provided public key does not match expected format (33-byte compressed key)",
+            )
+        })?;
+
+        let events: Vec<msgs::SubscriptionEvent> = self
+            .run_blocking(move |watcher| watcher.get_subscription_history(user_id))
+            .await?
+            .into_iter()
+            .map(|event| msgs::SubscriptionEvent {
+                event: event.kind.to_string(),
+                available_slots: event.available_slots,
+                subscription_expiry: event.subscription_expiry,
+                height: event.height,
+            })
+            .collect();
+
+        if events.is_empty() {
+            Err(Status::new(Code::NotFound, "User not found"))
+        } else {
+            Ok(Response::new(msgs::GetUserSubscriptionHistoryResponse {
+                events,
+            }))
+        }
+    }
+
+    /// Ban user endpoint. Bans a user from the tower, optionally deleting the appointments being watched for them.
+    /// Part of the private API. Internally calls [Watcher::ban_user].
+    async fn ban_user(
+        &self,
+        request: Request<msgs::BanUserRequest>,
+    ) -> Result<Response<msgs::BanUserResponse>, Status> {
+        let req_data = request.into_inner();
+        let user_id = UserId::deserialize(&req_data.user_id).map_err(|_| {
+            Status::new(
+                Code::InvalidArgument,
+                "Provided public key does not match expected format (33-byte compressed key)",
+            )
+        })?;
+
+        let drop_appointments = req_data.drop_appointments;
+        match self
+            .run_blocking(move |watcher| watcher.ban_user(user_id, drop_appointments))
+            .await?
+        {
+            Some(n) => Ok(Response::new(msgs::BanUserResponse {
+                n_dropped_appointments: n as u32,
+            })),
+            None => Err(Status::new(Code::AlreadyExists, "User already banned")),
+        }
+    }
+
+    /// Unban user endpoint. Lifts the ban of a given user. Part of the private API.
+    /// Internally calls [Watcher::unban_user].
+    async fn unban_user(
+        &self,
+        request: Request<msgs::GetUserRequest>,
+    ) -> Result<Response<()>, Status> {
+        let user_id = UserId::deserialize(&request.into_inner().user_id).map_err(|_| {
+            Status::new(
+                Code::InvalidArgument,
+                "Provided public key does not match expected format (33-byte compressed key)",
+            )
+        })?;
+
+        if self
+            .run_blocking(move |watcher| watcher.unban_user(user_id))
+            .await?
+        {
+            Ok(Response::new(()))
+        } else {
+            Err(Status::new(Code::NotFound, "User not banned"))
+        }
+    }
+
+    type export_dataStream = tokio_stream::Iter<IntoIter<Result<msgs::ExportDataChunk, Status>>>;
+
+    /// Export data endpoint. Streams a versioned JSON document with all the data the tower holds about its users.
+    /// Part of the private API. Internally calls [Watcher::export_data].
+    async fn export_data(
+        &self,
+        _: Request<()>,
+    ) -> Result<Response<Self::export_dataStream>, Status> {
+        let chunks = self
+            .run_blocking(|watcher| watcher.export_data())
+            .await?
+            .to_json()
+            .chunks(EXPORT_CHUNK_SIZE)
+            .map(|chunk| msgs::ExportDataChunk {
+                data: chunk.to_vec(),
+            })
+            .map(Ok)
+            .collect::<Vec<_>>();
+
+        Ok(Response::new(tokio_stream::iter(chunks)))
+    }
+
+    /// Import data endpoint. Imports a document obtained through [export_data](Self::export_data) into a fresh tower.
+    /// Part of the private API. Internally calls [Watcher::import_data].
+    async fn import_data(
+        &self,
+        request: Request<msgs::ImportDataRequest>,
+    ) -> Result<Response<msgs::ImportDataResponse>, Status> {
+        let data = ExportedData::from_json(&request.into_inner().data)
+            .map_err(|e| Status::new(Code::InvalidArgument, e))?;
+
+        let n_users = data.users.len() as u32;
+        let n_appointments = data.appointments.len() as u32;
+        let n_trackers = data
+            .appointments
+            .iter()
+            .filter(|a| a.tracker.is_some())
+            .count() as u32;
+
+        match self
+            .run_blocking(move |watcher| watcher.import_data(&data))
+            .await?
+        {
+            Ok(()) => Ok(Response::new(msgs::ImportDataResponse {
+                n_users,
+                n_appointments,
+                n_trackers,
+            })),
+            Err(ImportDataFailure::NotFresh) => Err(Status::new(
+                Code::FailedPrecondition,
+                "Data can only be imported into a fresh tower",
+            )),
+            Err(ImportDataFailure::InvalidData) => Err(Status::new(
+                Code::InvalidArgument,
+                "The provided data is not consistent and cannot be imported",
+            )),
+        }
+    }
+
+    /// Rotate key endpoint. Replaces the tower signing key with a fresh one. Part of the private API.
+    /// Internally calls [Watcher::rotate_key].
+    async fn rotate_key(
+        &self,
+        request: Request<msgs::RotateKeyRequest>,
+    ) -> Result<Response<msgs::RotateKeyResponse>, Status> {
+        let req = request.into_inner();
+        let (tower_id, receipts) = self
+            .run_blocking(move |watcher| watcher.rotate_key(req.grace_period, req.reissue_receipts))
+            .await?
+            .map_err(|e| {
+                log::error!("Couldn't rotate the tower key. Error: {:?}", e);
+                Status::new(Code::Internal, "Couldn't store the new key")
+            })?;
+
+        Ok(Response::new(msgs::RotateKeyResponse {
+            tower_id: tower_id.serialize(),
+            receipts: receipts
+                .into_iter()
+                .map(|r| msgs::RegisterResponse {
+                    user_id: r.user_id().serialize(),
+                    available_slots: r.available_slots(),
+                    subscription_expiry: r.subscription_expiry(),
+                    subscription_signature: r.signature().unwrap(),
+                })
+                .collect(),
+        }))
+    }
+
+    /// Broadcast penalty endpoint. Sends the penalty transaction of a given tracker to the network again. Part of the
+    /// private API. Internally calls [Watcher::broadcast_penalty].
+    async fn broadcast_penalty(
+        &self,
+        request: Request<msgs::BroadcastPenaltyRequest>,
+    ) -> Result<Response<msgs::BroadcastPenaltyResponse>, Status> {
+        let uuid = UUID::deserialize(&request.into_inner().uuid).map_err(|_| {
+            Status::new(
+                Code::InvalidArgument,
+                "Provided uuid does not match expected format (20-byte hash)",
+            )
+        })?;
+        self.check_service_unavailable()?;
+
+        match self
+            .run_blocking(move |watcher| watcher.broadcast_penalty(uuid))
+            .await?
+        {
+            Ok((penalty_txid, status)) => {
+                let (accepted, rejection_reason) = match status {
+                    ConfirmationStatus::Rejected(reason) => (false, reason),
+                    _ => (true, 0),
+                };
+                Ok(Response::new(msgs::BroadcastPenaltyResponse {
+                    penalty_txid: penalty_txid.to_vec(),
+                    accepted,
+                    rejection_reason,
+                }))
+            }
+            Err(BroadcastPenaltyFailure::NotFound) => {
+                Err(Status::new(Code::NotFound, "Tracker not found"))
+            }
+            Err(BroadcastPenaltyFailure::Unavailable) => Err(Status::new(
+                Code::Unavailable,
+                "Service currently unavailable",
+            )),
+        }
+    }
+
+    /// Get stop challenge endpoint. Gets a fresh challenge to be signed in order to stop the tower, replacing any
+    /// previous one. Part of the private API.
+    async fn get_stop_challenge(
+        &self,
+        _: Request<()>,
+    ) -> Result<Response<msgs::GetStopChallengeResponse>, Status> {
+        let challenge = hex::encode(cryptography::get_random_bytes(32));
+        *self.stop_challenge.lock().unwrap() = Some(challenge.clone());
+
+        Ok(Response::new(msgs::GetStopChallengeResponse { challenge }))
+    }
+
     /// Stop endpoint. Stops the tower daemon. Part of the private API.
-    async fn stop(&self, _: Request<()>) -> Result<Response<()>, Status> {
+    ///
+    /// If the tower is configured to require signed stops, the request must carry a signature of the last challenge
+    /// (check [InternalAPI::get_stop_challenge]) by the operator key. The challenge is consumed either way.
+    async fn stop(&self, request: Request<msgs::StopRequest>) -> Result<Response<()>, Status> {
+        if self.config.require_signed_stop {
+            let challenge = self.stop_challenge.lock().unwrap().take();
+            let authorized = match (challenge, PublicKey::from_str(&self.config.stop_auth_key)) {
+                (Some(challenge), Ok(pk)) => {
+                    cryptography::verify(challenge.as_bytes(), &request.into_inner().signature, &pk)
+                }
+                _ => false,
+            };
+
+            if !authorized {
+                log::warn!("Rejected unauthorized stop request");
+                return Err(Status::new(
+                    Code::PermissionDenied,
+                    "stop requires a valid signature of a fresh challenge",
+                ));
+            }
+        }
+
         self.shutdown_trigger.trigger();
 
         log::debug!("Received shutting down signal, notifying components");
@@ -327,12 +895,37 @@ mod tests_private_api {
     use super::*;
     use std::collections::HashSet;
     use std::iter::FromIterator;
+    use tokio_stream::StreamExt;
 
+    use crate::config::REDACTED;
     use crate::extended_appointment::UUID;
     use crate::test_utils::{
-        create_api, generate_dummy_appointment, DURATION, SLOTS, START_HEIGHT,
+        create_api, create_api_with_config, generate_dummy_appointment, generate_uuid,
+        get_random_tx, ApiConfig, DURATION, SLOTS, START_HEIGHT,
     };
     use teos_common::cryptography::{self, get_random_keypair};
+    use teos_common::receipts::RegistrationReceipt;
+
+    #[tokio::test]
+    async fn test_run_blocking() {
+        let internal_api = create_api().await;
+
+        // The test runtime has a single thread. If the (slow) watcher call below was run on it, the spawned task could
+        // not make any progress until the call was done
+        let task_ran = Arc::new(AtomicBool::new(false));
+        let flag = task_ran.clone();
+        tokio::spawn(async move { flag.store(true, Ordering::Relaxed) });
+
+        let ran_during_call = internal_api
+            .run_blocking(move |watcher| {
+                std::thread::sleep(std::time::Duration::from_millis(500));
+                watcher.get_stats().unwrap();
+                task_ran.load(Ordering::Relaxed)
+            })
+            .await
+            .unwrap();
+        assert!(ran_during_call);
+    }
 
     #[tokio::test]
     async fn test_get_all_appointments() {
@@ -353,7 +946,10 @@ mod tests_private_api {
 
         // Add data to the Watcher so we can retrieve it later on
         let (user_sk, user_pk) = get_random_keypair();
-        internal_api.watcher.register(UserId(user_pk)).unwrap();
+        internal_api
+            .watcher
+            .register(UserId(user_pk), None)
+            .unwrap();
 
         let appointment = generate_dummy_appointment(None).inner;
         let user_signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
@@ -382,7 +978,7 @@ mod tests_private_api {
         // Add data to the Responser so we can retrieve it later on
         let (_, user_pk) = get_random_keypair();
         let user_id = UserId(user_pk);
-        internal_api.watcher.register(user_id).unwrap();
+        internal_api.watcher.register(user_id, None).unwrap();
 
         let appointment = generate_dummy_appointment(None).inner;
         internal_api
@@ -403,43 +999,221 @@ mod tests_private_api {
     }
 
     #[tokio::test]
-    async fn test_get_tower_info_empty() {
-        let internal_api = create_api().await;
-
-        let response = internal_api
-            .get_tower_info(Request::new(()))
-            .await
-            .unwrap()
-            .into_inner();
-
-        assert_eq!(response.tower_id, internal_api.watcher.tower_id.serialize());
-        assert_eq!(response.n_registered_users, 0);
-        assert_eq!(response.n_watcher_appointments, 0);
-        assert_eq!(response.n_responder_trackers, 0);
-    }
-
-    #[tokio::test]
-    async fn test_get_tower_info() {
+    async fn test_get_appointments() {
         let internal_api = create_api().await;
 
-        // Register a user
-        let (user_sk, user_pk) = get_random_keypair();
-        let user_id = UserId(user_pk);
-        internal_api.watcher.register(user_id).unwrap();
-
-        // Add data to the Watcher
+        // Add an appointment to the Watcher for two different users, and a tracker to the Responder (for a third one)
+        let mut users = Vec::new();
         for _ in 0..2 {
+            let (user_sk, user_pk) = get_random_keypair();
+            let user_id = UserId(user_pk);
+            internal_api.watcher.register(user_id, None).unwrap();
+
             let appointment = generate_dummy_appointment(None).inner;
             let user_signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
             internal_api
                 .watcher
-                .add_appointment(appointment.clone(), user_signature)
+                .add_appointment(appointment, user_signature)
                 .unwrap();
+            users.push(user_id);
         }
+        let appointment = generate_dummy_appointment(None).inner;
+        internal_api
+            .watcher
+            .add_random_tracker_to_responder(UUID::new(appointment.locator, users[0]));
+
+        let get_appointments = |user_id: Vec<u8>, status: AppointmentStatus, limit: u32| {
+            let request = msgs::GetAppointmentsRequest {
+                user_id,
+                status: status as i32,
+                limit,
+                offset: 0,
+            };
+            async {
+                internal_api
+                    .get_appointments(Request::new(request))
+                    .await
+                    .unwrap()
+                    .into_inner()
+                    .appointments
+            }
+        };
+        let is_tracker = |data: &msgs::AppointmentData| {
+            matches!(
+                data.appointment_data,
+                Some(msgs::appointment_data::AppointmentData::Tracker { .. })
+            )
+        };
 
-        // And the Responder
-        for _ in 0..3 {
-            let appointment = generate_dummy_appointment(None).inner;
+        // No filters
+        assert_eq!(
+            get_appointments(Vec::new(), AppointmentStatus::NotFound, 0)
+                .await
+                .len(),
+            3
+        );
+        assert_eq!(
+            get_appointments(Vec::new(), AppointmentStatus::NotFound, 2)
+                .await
+                .len(),
+            2
+        );
+
+        // By status
+        let watched = get_appointments(Vec::new(), AppointmentStatus::BeingWatched, 0).await;
+        assert_eq!(watched.len(), 2);
+        assert!(!watched.iter().any(is_tracker));
+        let responded = get_appointments(Vec::new(), AppointmentStatus::DisputeResponded, 0).await;
+        assert_eq!(responded.len(), 1);
+        assert!(responded.iter().all(is_tracker));
+
+        // By user (and status)
+        for user_id in users.iter() {
+            assert_eq!(
+                get_appointments(user_id.serialize(), AppointmentStatus::NotFound, 0)
+                    .await
+                    .len(),
+                1
+            );
+        }
+        assert!(
+            get_appointments(users[0].serialize(), AppointmentStatus::DisputeResponded, 0)
+                .await
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_appointment_by_txid() {
+        let internal_api = create_api().await;
+
+        // Add appointments for two different disputes
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        internal_api.watcher.register(user_id, None).unwrap();
+
+        let dispute_txid = get_random_tx().txid();
+        for txid in [Some(&dispute_txid), None] {
+            let appointment = generate_dummy_appointment(txid).inner;
+            let user_signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+            internal_api
+                .watcher
+                .add_appointment(appointment, user_signature)
+                .unwrap();
+        }
+
+        // Only the one matching the dispute txid is returned
+        let appointments = internal_api
+            .get_appointment_by_txid(Request::new(msgs::GetAppointmentByTxidRequest {
+                dispute_txid: dispute_txid.to_vec(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .appointments;
+        assert_eq!(appointments.len(), 1);
+        match &appointments[0].appointment_data {
+            Some(msgs::appointment_data::AppointmentData::Appointment(a)) => {
+                assert_eq!(a.locator, Locator::new(dispute_txid).serialize())
+            }
+            _ => panic!("Appointment data expected"),
+        }
+
+        // Unknown txids match nothing
+        assert!(internal_api
+            .get_appointment_by_txid(Request::new(msgs::GetAppointmentByTxidRequest {
+                dispute_txid: get_random_tx().txid().to_vec(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .appointments
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_appointment_by_txid_wrong_txid() {
+        let internal_api = create_api().await;
+
+        let status = internal_api
+            .get_appointment_by_txid(Request::new(msgs::GetAppointmentByTxidRequest {
+                dispute_txid: vec![1; 16],
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_get_appointments_wrong_params() {
+        let internal_api = create_api().await;
+
+        let status = internal_api
+            .get_appointments(Request::new(msgs::GetAppointmentsRequest {
+                user_id: vec![1; 32],
+                status: 0,
+                limit: 0,
+                offset: 0,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        let status = internal_api
+            .get_appointments(Request::new(msgs::GetAppointmentsRequest {
+                user_id: Vec::new(),
+                status: 3,
+                limit: 0,
+                offset: 0,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(status.message(), "Unknown appointment status");
+    }
+
+    #[tokio::test]
+    async fn test_get_tower_info_empty() {
+        let internal_api = create_api().await;
+
+        let response = internal_api
+            .get_tower_info(Request::new(()))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(
+            response.tower_id,
+            internal_api.watcher.tower_id().serialize()
+        );
+        assert_eq!(response.n_registered_users, 0);
+        assert_eq!(response.n_watcher_appointments, 0);
+        assert_eq!(response.n_responder_trackers, 0);
+        assert!(!response.tor_available);
+    }
+
+    #[tokio::test]
+    async fn test_get_tower_info() {
+        let internal_api = create_api().await;
+
+        // Register a user
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        internal_api.watcher.register(user_id, None).unwrap();
+
+        // Add data to the Watcher
+        for _ in 0..2 {
+            let appointment = generate_dummy_appointment(None).inner;
+            let user_signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+            internal_api
+                .watcher
+                .add_appointment(appointment.clone(), user_signature)
+                .unwrap();
+        }
+
+        // And the Responder
+        for _ in 0..3 {
+            let appointment = generate_dummy_appointment(None).inner;
             internal_api
                 .watcher
                 .add_random_tracker_to_responder(UUID::new(appointment.locator, user_id));
@@ -452,12 +1226,103 @@ mod tests_private_api {
             .into_inner();
 
         // Given get_tower_info checks data in memory, the data added to the Responder in the test won't be added to the Watcher too.
-        assert_eq!(response.tower_id, internal_api.watcher.tower_id.serialize());
+        assert_eq!(
+            response.tower_id,
+            internal_api.watcher.tower_id().serialize()
+        );
         assert_eq!(response.n_registered_users, 1);
         assert_eq!(response.n_watcher_appointments, 2);
         assert_eq!(response.n_responder_trackers, 3);
     }
 
+    #[tokio::test]
+    async fn test_get_stats() {
+        let internal_api = create_api().await;
+
+        // Register a user and add some appointments
+        let (user_sk, user_pk) = get_random_keypair();
+        internal_api
+            .watcher
+            .register(UserId(user_pk), None)
+            .unwrap();
+        for _ in 0..2 {
+            let appointment = generate_dummy_appointment(None).inner;
+            let user_signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+            internal_api
+                .watcher
+                .add_appointment(appointment, user_signature)
+                .unwrap();
+        }
+
+        let response = internal_api
+            .get_stats(Request::new(()))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(response.n_registered_users, 1);
+        assert_eq!(response.n_watched_appointments, 2);
+        assert_eq!(response.n_responded_appointments, 0);
+        assert_eq!(response.used_slots, 2);
+        assert_eq!(response.available_slots, SLOTS as u64 - 2);
+        assert!(response.db_size > 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_health() {
+        let internal_api = create_api().await;
+
+        let response = internal_api
+            .get_health(Request::new(()))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert!(response.bitcoind_reachable);
+        assert!(response.db_reachable);
+        assert_eq!(response.last_known_block_height, START_HEIGHT as u32);
+        assert!(!response.task_panicked);
+
+        // Flag a panic and check it is reported
+        internal_api.task_panicked.store(true, Ordering::Release);
+        let response = internal_api
+            .get_health(Request::new(()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.task_panicked);
+    }
+
+    #[tokio::test]
+    async fn test_get_config() {
+        let internal_api = create_api().await;
+
+        let response = internal_api
+            .get_config(Request::new(()))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let config: serde_json::Value = serde_json::from_str(&response.config).unwrap();
+        assert_eq!(config["btc_rpc_password"], REDACTED);
+        assert_eq!(config["api_port"], internal_api.config.api_port);
+    }
+
+    #[tokio::test]
+    async fn test_get_health_bitcoind_unreachable() {
+        let internal_api =
+            create_api_with_config(ApiConfig::default().bitcoind_unreachable()).await;
+
+        let response = internal_api
+            .get_health(Request::new(()))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert!(!response.bitcoind_reachable);
+        assert!(response.db_reachable);
+    }
+
     #[tokio::test]
     async fn test_get_users() {
         let internal_api = create_api().await;
@@ -467,7 +1332,7 @@ mod tests_private_api {
         for _ in 0..2 {
             let (_, user_pk) = get_random_keypair();
             let user_id = UserId(user_pk);
-            internal_api.watcher.register(user_id).unwrap();
+            internal_api.watcher.register(user_id, None).unwrap();
             users.insert(user_id.serialize());
         }
 
@@ -500,7 +1365,7 @@ mod tests_private_api {
         // Register a user and get it back
         let (user_sk, user_pk) = get_random_keypair();
         let user_id = UserId(user_pk);
-        internal_api.watcher.register(user_id).unwrap();
+        internal_api.watcher.register(user_id, None).unwrap();
 
         let response = internal_api
             .get_user(Request::new(msgs::GetUserRequest {
@@ -514,56 +1379,418 @@ mod tests_private_api {
         assert_eq!(response.subscription_expiry, START_HEIGHT as u32 + DURATION);
         assert!(response.appointments.is_empty());
 
-        // Add an appointment and check back
+        // Add an appointment and check back
+        let appointment = generate_dummy_appointment(None).inner;
+        let uuid = UUID::new(appointment.locator, user_id);
+        let user_signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        internal_api
+            .watcher
+            .add_appointment(appointment.clone(), user_signature)
+            .unwrap();
+
+        let response = internal_api
+            .get_user(Request::new(msgs::GetUserRequest {
+                user_id: user_id.serialize(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(response.available_slots, SLOTS - 1);
+        assert_eq!(response.subscription_expiry, START_HEIGHT as u32 + DURATION);
+        assert_eq!(response.appointments, Vec::from([uuid.serialize()]));
+    }
+
+    #[tokio::test]
+    async fn test_get_user_not_found() {
+        let internal_api = create_api().await;
+
+        // Non-registered user
+        let (_, user_pk) = get_random_keypair();
+
+        match internal_api
+            .get_user(Request::new(msgs::GetUserRequest {
+                user_id: UserId(user_pk).serialize(),
+            }))
+            .await
+        {
+            Err(status) => {
+                assert_eq!(status.code(), Code::NotFound);
+                assert_eq!(status.message(), "User not found")
+            }
+            _ => panic!("Test should have returned Err"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_user_subscription_history() {
+        let internal_api = create_api().await;
+
+        // Register a user twice (registration + renewal) and get its history back
+        let (_, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        internal_api.watcher.register(user_id, None).unwrap();
+        internal_api.watcher.register(user_id, None).unwrap();
+
+        let response = internal_api
+            .get_user_subscription_history(Request::new(msgs::GetUserRequest {
+                user_id: user_id.serialize(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let events: Vec<(&str, u32)> = response
+            .events
+            .iter()
+            .map(|e| (e.event.as_str(), e.available_slots))
+            .collect();
+        assert_eq!(events, vec![("registered", SLOTS), ("renewed", SLOTS * 2)]);
+    }
+
+    #[tokio::test]
+    async fn test_get_user_subscription_history_not_found() {
+        let internal_api = create_api().await;
+
+        // Non-registered user
+        let (_, user_pk) = get_random_keypair();
+
+        match internal_api
+            .get_user_subscription_history(Request::new(msgs::GetUserRequest {
+                user_id: UserId(user_pk).serialize(),
+            }))
+            .await
+        {
+            Err(status) => {
+                assert_eq!(status.code(), Code::NotFound);
+                assert_eq!(status.message(), "User not found")
+            }
+            _ => panic!("Test should have returned Err"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ban_unban_user() {
+        let internal_api = create_api().await;
+        let (_, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk).serialize();
+
+        let response = internal_api
+            .ban_user(Request::new(msgs::BanUserRequest {
+                user_id: user_id.clone(),
+                drop_appointments: true,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.n_dropped_appointments, 0);
+
+        // Banned users cannot register
+        let status = internal_api
+            .register(Request::new(msgs::RegisterRequest {
+                user_id: user_id.clone(),
+                requested_slots: None,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert_eq!(status.message(), "User banned from the tower");
+
+        // Users cannot be banned twice
+        let status = internal_api
+            .ban_user(Request::new(msgs::BanUserRequest {
+                user_id: user_id.clone(),
+                drop_appointments: false,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::AlreadyExists);
+
+        // Once unbanned, the user can register
+        internal_api
+            .unban_user(Request::new(msgs::GetUserRequest {
+                user_id: user_id.clone(),
+            }))
+            .await
+            .unwrap();
+        internal_api
+            .register(Request::new(msgs::RegisterRequest {
+                user_id: user_id.clone(),
+                requested_slots: None,
+            }))
+            .await
+            .unwrap();
+
+        let status = internal_api
+            .unban_user(Request::new(msgs::GetUserRequest { user_id }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+        assert_eq!(status.message(), "User not banned");
+    }
+
+    #[tokio::test]
+    async fn test_rotate_key() {
+        let internal_api = create_api().await;
+        let old_tower_id = internal_api.watcher.tower_id();
+
+        let (_, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        let old_receipt = internal_api.watcher.register(user_id, None).unwrap();
+
+        let response = internal_api
+            .rotate_key(Request::new(msgs::RotateKeyRequest {
+                grace_period: 6,
+                reissue_receipts: true,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let tower_id = UserId::deserialize(&response.tower_id).unwrap();
+        assert_ne!(tower_id, old_tower_id);
+        assert_eq!(tower_id, internal_api.watcher.tower_id());
+
+        // The receipt has been reissued and signed with the new key
+        assert_eq!(response.receipts.len(), 1);
+        let receipt = &response.receipts[0];
+        assert_eq!(receipt.user_id, user_id.serialize());
+        assert!(RegistrationReceipt::with_signature(
+            user_id,
+            receipt.available_slots,
+            receipt.subscription_expiry,
+            receipt.subscription_signature.clone(),
+        )
+        .verify(&tower_id));
+
+        // The old receipt is still honoured
+        assert!(internal_api
+            .watcher
+            .verify_tower_signature(&old_receipt.serialize(), &old_receipt.signature().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_penalty() {
+        let internal_api = create_api().await;
+
+        let uuid = generate_uuid();
+        internal_api.watcher.add_random_tracker_to_responder(uuid);
+        let tracker = internal_api.watcher.get_all_responder_trackers()[&uuid].clone();
+
+        let response = internal_api
+            .broadcast_penalty(Request::new(msgs::BroadcastPenaltyRequest {
+                uuid: uuid.serialize(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(
+            response,
+            msgs::BroadcastPenaltyResponse {
+                penalty_txid: tracker.penalty_tx.txid().to_vec(),
+                accepted: true,
+                rejection_reason: 0,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_penalty_not_found() {
+        let internal_api = create_api().await;
+
+        match internal_api
+            .broadcast_penalty(Request::new(msgs::BroadcastPenaltyRequest {
+                uuid: generate_uuid().serialize(),
+            }))
+            .await
+        {
+            Err(status) => {
+                assert_eq!(status.code(), Code::NotFound);
+                assert_eq!(status.message(), "Tracker not found")
+            }
+            _ => panic!("Test should have returned an error"),
+        }
+
+        match internal_api
+            .broadcast_penalty(Request::new(msgs::BroadcastPenaltyRequest {
+                uuid: vec![1, 2, 3],
+            }))
+            .await
+        {
+            Err(status) => assert_eq!(status.code(), Code::InvalidArgument),
+            _ => panic!("Test should have returned an error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stop() {
+        let internal_api = create_api().await;
+
+        assert!(!internal_api.shutdown_trigger.is_triggered());
+        internal_api
+            .stop(Request::new(msgs::StopRequest::default()))
+            .await
+            .unwrap();
+        assert!(internal_api.shutdown_trigger.is_triggered());
+    }
+
+    #[tokio::test]
+    async fn test_stop_signed() {
+        let (operator_sk, operator_pk) = get_random_keypair();
+        let internal_api =
+            create_api_with_config(ApiConfig::default().require_signed_stop(operator_pk)).await;
+
+        // Unsigned stops are rejected
+        match internal_api
+            .stop(Request::new(msgs::StopRequest::default()))
+            .await
+        {
+            Err(status) => assert_eq!(status.code(), Code::PermissionDenied),
+            _ => panic!("Test should have returned an error"),
+        }
+
+        // So are the ones signed by someone else
+        let challenge = internal_api
+            .get_stop_challenge(Request::new(()))
+            .await
+            .unwrap()
+            .into_inner()
+            .challenge;
+        let (other_sk, _) = get_random_keypair();
+        let signature = cryptography::sign(challenge.as_bytes(), &other_sk).unwrap();
+        assert!(internal_api
+            .stop(Request::new(msgs::StopRequest { signature }))
+            .await
+            .is_err());
+
+        // Challenges cannot be reused
+        let signature = cryptography::sign(challenge.as_bytes(), &operator_sk).unwrap();
+        assert!(internal_api
+            .stop(Request::new(msgs::StopRequest {
+                signature: signature.clone()
+            }))
+            .await
+            .is_err());
+        assert!(!internal_api.shutdown_trigger.is_triggered());
+
+        // A fresh challenge signed by the operator stops the tower
+        let challenge = internal_api
+            .get_stop_challenge(Request::new(()))
+            .await
+            .unwrap()
+            .into_inner()
+            .challenge;
+        let signature = cryptography::sign(challenge.as_bytes(), &operator_sk).unwrap();
+        internal_api
+            .stop(Request::new(msgs::StopRequest { signature }))
+            .await
+            .unwrap();
+        assert!(internal_api.shutdown_trigger.is_triggered());
+    }
+
+    async fn export_data(internal_api: &Arc<InternalAPI>) -> Vec<u8> {
+        internal_api
+            .export_data(Request::new(()))
+            .await
+            .unwrap()
+            .into_inner()
+            .map(|chunk| chunk.unwrap().data)
+            .collect::<Vec<_>>()
+            .await
+            .concat()
+    }
+
+    #[tokio::test]
+    async fn test_export_import_data() {
+        let internal_api = create_api().await;
+
+        // Add some data to the tower: a couple of users with appointments, and a tracker
+        for _ in 0..2 {
+            let (user_sk, user_pk) = get_random_keypair();
+            internal_api
+                .watcher
+                .register(UserId(user_pk), None)
+                .unwrap();
+
+            let appointment = generate_dummy_appointment(None).inner;
+            let user_signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+            internal_api
+                .watcher
+                .add_appointment(appointment, user_signature)
+                .unwrap();
+        }
+        let (_, user_pk) = get_random_keypair();
         let appointment = generate_dummy_appointment(None).inner;
-        let uuid = UUID::new(appointment.locator, user_id);
-        let user_signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
         internal_api
             .watcher
-            .add_appointment(appointment.clone(), user_signature)
-            .unwrap();
+            .add_random_tracker_to_responder(UUID::new(appointment.locator, UserId(user_pk)));
 
-        let response = internal_api
-            .get_user(Request::new(msgs::GetUserRequest {
-                user_id: user_id.serialize(),
+        let exported = export_data(&internal_api).await;
+
+        // Import the data into a fresh tower
+        let new_internal_api = create_api().await;
+        let response = new_internal_api
+            .import_data(Request::new(msgs::ImportDataRequest {
+                data: exported.clone(),
             }))
             .await
             .unwrap()
             .into_inner();
-
-        assert_eq!(response.available_slots, SLOTS - 1);
-        assert_eq!(response.subscription_expiry, START_HEIGHT as u32 + DURATION);
-        assert_eq!(response.appointments, Vec::from([uuid.serialize()]));
+        // The tracker comes with its own user
+        assert_eq!(response.n_users, 3);
+        assert_eq!(response.n_appointments, 3);
+        assert_eq!(response.n_trackers, 1);
+
+        // Every single piece of data should be preserved, both in the database and in memory
+        assert_eq!(
+            ExportedData::from_json(&export_data(&new_internal_api).await).unwrap(),
+            ExportedData::from_json(&exported).unwrap()
+        );
+        assert_eq!(new_internal_api.watcher.get_registered_users_count(), 3);
+        assert_eq!(new_internal_api.watcher.get_appointments_count(), 2);
+        assert_eq!(new_internal_api.watcher.get_trackers_count(), 1);
+        for user_id in internal_api.watcher.get_user_ids() {
+            assert_eq!(
+                new_internal_api.watcher.get_user_info(user_id),
+                internal_api.watcher.get_user_info(user_id)
+            );
+        }
     }
 
     #[tokio::test]
-    async fn test_get_user_not_found() {
+    async fn test_import_data_non_fresh_tower() {
         let internal_api = create_api().await;
-
-        // Non-registered user
         let (_, user_pk) = get_random_keypair();
+        internal_api
+            .watcher
+            .register(UserId(user_pk), None)
+            .unwrap();
+        let exported = export_data(&internal_api).await;
 
-        match internal_api
-            .get_user(Request::new(msgs::GetUserRequest {
-                user_id: UserId(user_pk).serialize(),
-            }))
+        let status = internal_api
+            .import_data(Request::new(msgs::ImportDataRequest { data: exported }))
             .await
-        {
-            Err(status) => {
-                assert_eq!(status.code(), Code::NotFound);
-                assert_eq!(status.message(), "User not found")
-            }
-            _ => panic!("Test should have returned Err"),
-        }
+            .unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert_eq!(
+            status.message(),
+            "Data can only be imported into a fresh tower"
+        );
     }
 
     #[tokio::test]
-    async fn test_stop() {
+    async fn test_import_data_invalid() {
         let internal_api = create_api().await;
 
-        assert!(!internal_api.shutdown_trigger.is_triggered());
-        internal_api.stop(Request::new(())).await.unwrap();
-        assert!(internal_api.shutdown_trigger.is_triggered());
+        let status = internal_api
+            .import_data(Request::new(msgs::ImportDataRequest {
+                data: b"not json".to_vec(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(internal_api.watcher.get_registered_users_count(), 0);
     }
 }
 
@@ -573,8 +1800,10 @@ mod tests_public_api {
 
     use crate::extended_appointment::UUID;
     use crate::test_utils::{
-        create_api, create_api_with_config, generate_dummy_appointment, ApiConfig, DURATION, SLOTS,
+        create_api, create_api_with_config, generate_dummy_appointment, ApiConfig, DURATION,
+        MAX_APPOINTMENT_SIZE, MIN_TO_SELF_DELAY, SLOTS,
     };
+    use teos_common::constants::ENCRYPTED_BLOB_MIN_SIZE;
     use teos_common::cryptography::{self, get_random_keypair};
 
     #[tokio::test]
@@ -588,6 +1817,7 @@ mod tests_public_api {
             let response = internal_api
                 .register(Request::new(msgs::RegisterRequest {
                     user_id: UserId(user_pk).serialize(),
+                    requested_slots: None,
                 }))
                 .await
                 .unwrap()
@@ -616,7 +1846,10 @@ mod tests_public_api {
 
         for user_id in user_ids {
             match internal_api
-                .register(Request::new(msgs::RegisterRequest { user_id }))
+                .register(Request::new(msgs::RegisterRequest {
+                    user_id,
+                    requested_slots: None,
+                }))
                 .await
             {
                 Err(status) => {
@@ -628,6 +1861,53 @@ mod tests_public_api {
         }
     }
 
+    #[tokio::test]
+    async fn test_register_requested_slots() {
+        let internal_api = create_api().await;
+        let (_, user_pk) = get_random_keypair();
+
+        // Users can ask for a given number of slots
+        let response = internal_api
+            .register(Request::new(msgs::RegisterRequest {
+                user_id: UserId(user_pk).serialize(),
+                requested_slots: Some(5),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.available_slots, 5);
+
+        // No more than the default subscription can be requested at once if no per-user limit is set
+        let (_, other_pk) = get_random_keypair();
+        let response = internal_api
+            .register(Request::new(msgs::RegisterRequest {
+                user_id: UserId(other_pk).serialize(),
+                requested_slots: Some(u32::MAX),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.available_slots, SLOTS);
+
+        // But asking for no slots at all is rejected
+        match internal_api
+            .register(Request::new(msgs::RegisterRequest {
+                user_id: UserId(user_pk).serialize(),
+                requested_slots: Some(0),
+            }))
+            .await
+        {
+            Err(status) => {
+                assert_eq!(status.code(), Code::InvalidArgument);
+                assert_eq!(
+                    status.message(),
+                    "requested_slots must be greater than zero"
+                )
+            }
+            _ => panic!("Test should have returned Err"),
+        }
+    }
+
     #[tokio::test]
     async fn test_register_max_slots() {
         let internal_api = create_api_with_config(ApiConfig::new(u32::MAX, DURATION)).await;
@@ -639,13 +1919,17 @@ mod tests_public_api {
         internal_api
             .register(Request::new(msgs::RegisterRequest {
                 user_id: user_id.clone(),
+                requested_slots: None,
             }))
             .await
             .unwrap();
 
         // Trying to add more slots (re-register) must fail
         match internal_api
-            .register(Request::new(msgs::RegisterRequest { user_id }))
+            .register(Request::new(msgs::RegisterRequest {
+                user_id,
+                requested_slots: None,
+            }))
             .await
         {
             Err(status) => {
@@ -665,7 +1949,10 @@ mod tests_public_api {
         let user_id = UserId(user_pk).serialize();
 
         match internal_api
-            .register(Request::new(msgs::RegisterRequest { user_id }))
+            .register(Request::new(msgs::RegisterRequest {
+                user_id,
+                requested_slots: None,
+            }))
             .await
         {
             Err(status) => {
@@ -682,7 +1969,10 @@ mod tests_public_api {
 
         // User must be registered
         let (user_sk, user_pk) = get_random_keypair();
-        internal_api.watcher.register(UserId(user_pk)).unwrap();
+        internal_api
+            .watcher
+            .register(UserId(user_pk), None)
+            .unwrap();
 
         let appointment = generate_dummy_appointment(None).inner;
         let user_signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
@@ -733,7 +2023,10 @@ mod tests_public_api {
 
         // User is registered but has no slots
         let (user_sk, user_pk) = get_random_keypair();
-        internal_api.watcher.register(UserId(user_pk)).unwrap();
+        internal_api
+            .watcher
+            .register(UserId(user_pk), None)
+            .unwrap();
 
         let appointment = generate_dummy_appointment(None).inner;
         let user_signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
@@ -756,13 +2049,51 @@ mod tests_public_api {
         }
     }
 
+    #[tokio::test]
+    async fn test_add_appointment_max_appointments_reached() {
+        let internal_api =
+            create_api_with_config(ApiConfig::default().max_appointments_per_user(1)).await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        internal_api
+            .watcher
+            .register(UserId(user_pk), None)
+            .unwrap();
+
+        // The first appointment fits, the second one goes over the limit
+        for i in 0..2 {
+            let appointment = generate_dummy_appointment(None).inner;
+            let user_signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+            let response = internal_api
+                .add_appointment(Request::new(msgs::AddAppointmentRequest {
+                    appointment: Some(appointment.into()),
+                    signature: user_signature,
+                }))
+                .await;
+
+            if i == 0 {
+                assert!(response.is_ok());
+            } else {
+                let status = response.unwrap_err();
+                assert_eq!(status.code(), Code::FailedPrecondition);
+                assert_eq!(
+                    status.message(),
+                    "Maximum number of appointments per user reached (1)"
+                );
+            }
+        }
+    }
+
     #[tokio::test]
     async fn test_add_appointment_subscription_expired() {
         let internal_api = create_api_with_config(ApiConfig::new(SLOTS, 0)).await;
 
         // User is registered but subscription is expired
         let (user_sk, user_pk) = get_random_keypair();
-        internal_api.watcher.register(UserId(user_pk)).unwrap();
+        internal_api
+            .watcher
+            .register(UserId(user_pk), None)
+            .unwrap();
 
         let appointment = generate_dummy_appointment(None).inner;
         let user_signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
@@ -788,7 +2119,7 @@ mod tests_public_api {
 
         let (user_sk, user_pk) = get_random_keypair();
         let user_id = UserId(user_pk);
-        internal_api.watcher.register(user_id).unwrap();
+        internal_api.watcher.register(user_id, None).unwrap();
 
         let appointment = generate_dummy_appointment(None).inner;
         let user_signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
@@ -813,6 +2144,113 @@ mod tests_public_api {
         }
     }
 
+    #[tokio::test]
+    async fn test_add_appointment_blob_too_small() {
+        let internal_api = create_api().await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        internal_api
+            .watcher
+            .register(UserId(user_pk), None)
+            .unwrap();
+
+        let mut appointment = generate_dummy_appointment(None).inner;
+        appointment.encrypted_blob = vec![0; ENCRYPTED_BLOB_MIN_SIZE - 1];
+        let user_signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+
+        match internal_api
+            .add_appointment(Request::new(msgs::AddAppointmentRequest {
+                appointment: Some(appointment.into()),
+                signature: user_signature,
+            }))
+            .await
+        {
+            Err(status) => {
+                assert_eq!(status.code(), Code::OutOfRange);
+                assert_eq!(status.details(), [errors::APPOINTMENT_FIELD_TOO_SMALL]);
+                assert_eq!(
+                    status.message(),
+                    format!(
+                        "Encrypted blob too small. Expected at least {} bytes",
+                        ENCRYPTED_BLOB_MIN_SIZE
+                    )
+                );
+            }
+            _ => panic!("Test should have returned Err"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_appointment_blob_too_big() {
+        let internal_api = create_api().await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        internal_api
+            .watcher
+            .register(UserId(user_pk), None)
+            .unwrap();
+
+        let mut appointment = generate_dummy_appointment(None).inner;
+        appointment.encrypted_blob = vec![0; MAX_APPOINTMENT_SIZE + 1];
+        let user_signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+
+        match internal_api
+            .add_appointment(Request::new(msgs::AddAppointmentRequest {
+                appointment: Some(appointment.into()),
+                signature: user_signature,
+            }))
+            .await
+        {
+            Err(status) => {
+                assert_eq!(status.code(), Code::OutOfRange);
+                assert_eq!(
+                    status.message(),
+                    format!(
+                        "Encrypted blob too big. Expected at most {} bytes",
+                        MAX_APPOINTMENT_SIZE
+                    )
+                );
+            }
+            _ => panic!("Test should have returned Err"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_appointment_to_self_delay_too_small() {
+        let internal_api = create_api().await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        internal_api
+            .watcher
+            .register(UserId(user_pk), None)
+            .unwrap();
+
+        let mut appointment = generate_dummy_appointment(None).inner;
+        appointment.to_self_delay = MIN_TO_SELF_DELAY - 1;
+        let user_signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+
+        match internal_api
+            .add_appointment(Request::new(msgs::AddAppointmentRequest {
+                appointment: Some(appointment.into()),
+                signature: user_signature,
+            }))
+            .await
+        {
+            Err(status) => {
+                assert_eq!(status.code(), Code::OutOfRange);
+                assert_eq!(
+                    status.message(),
+                    format!(
+                        "to_self_delay too small. Expected at least {}",
+                        MIN_TO_SELF_DELAY
+                    )
+                );
+                assert_eq!(status.details(), [errors::APPOINTMENT_FIELD_TOO_SMALL]);
+            }
+            _ => panic!("Test should have returned Err"),
+        }
+    }
+
     #[tokio::test]
     async fn test_add_appointment_service_unavailable() {
         let internal_api =
@@ -843,7 +2281,10 @@ mod tests_public_api {
 
         // The user must be registered
         let (user_sk, user_pk) = get_random_keypair();
-        internal_api.watcher.register(UserId(user_pk)).unwrap();
+        internal_api
+            .watcher
+            .register(UserId(user_pk), None)
+            .unwrap();
 
         // Add the appointment
         let appointment = generate_dummy_appointment(None).inner;
@@ -864,7 +2305,39 @@ mod tests_public_api {
             .unwrap()
             .into_inner();
 
-        assert!(matches!(response, msgs::GetAppointmentResponse { .. }))
+        assert_eq!(response.status, AppointmentStatus::BeingWatched as i32);
+        // Appointments that are being watched have no penalty to confirm
+        assert_eq!(response.confirmations, None);
+    }
+
+    #[tokio::test]
+    async fn test_get_appointment_responded() {
+        let internal_api = create_api().await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        internal_api.watcher.register(user_id, None).unwrap();
+
+        // Add a tracker for the user straight to the Responder
+        let appointment = generate_dummy_appointment(None).inner;
+        let uuid = UUID::new(appointment.locator, user_id);
+        internal_api.watcher.add_random_tracker_to_responder(uuid);
+
+        let message = format!("get appointment {}", appointment.locator);
+        let response = internal_api
+            .get_appointment(Request::new(msgs::GetAppointmentRequest {
+                locator: appointment.locator.serialize(),
+                signature: cryptography::sign(message.as_bytes(), &user_sk).unwrap(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(response.status, AppointmentStatus::DisputeResponded as i32);
+        // The tracker was confirmed in the last block processed by the tower
+        assert_eq!(response.confirmations, Some(1));
+        // Nor it can estimate fees, so the penalty cannot be flagged as underfunded
+        assert!(!response.likely_underfunded);
     }
 
     #[tokio::test]
@@ -873,7 +2346,10 @@ mod tests_public_api {
 
         // Add a first user to link the appointment to him
         let (user_sk, user_pk) = get_random_keypair();
-        internal_api.watcher.register(UserId(user_pk)).unwrap();
+        internal_api
+            .watcher
+            .register(UserId(user_pk), None)
+            .unwrap();
 
         // There's no need to add the appointment given the subscription status is checked first
         let appointment = generate_dummy_appointment(None).inner;
@@ -901,7 +2377,10 @@ mod tests_public_api {
 
         // The user is registered but the appointment does not exist
         let (user_sk, user_pk) = get_random_keypair();
-        internal_api.watcher.register(UserId(user_pk)).unwrap();
+        internal_api
+            .watcher
+            .register(UserId(user_pk), None)
+            .unwrap();
 
         // Try to get the appointment through the API
         let appointment = generate_dummy_appointment(None).inner;
@@ -928,7 +2407,10 @@ mod tests_public_api {
 
         // Register the user
         let (user_sk, user_pk) = get_random_keypair();
-        internal_api.watcher.register(UserId(user_pk)).unwrap();
+        internal_api
+            .watcher
+            .register(UserId(user_pk), None)
+            .unwrap();
 
         // There s no need to add the appointment given the subscription status is checked first.
         let appointment = generate_dummy_appointment(None).inner;
@@ -979,13 +2461,17 @@ mod tests_public_api {
 
         // The user must be registered
         let (user_sk, user_pk) = get_random_keypair();
-        internal_api.watcher.register(UserId(user_pk)).unwrap();
+        internal_api
+            .watcher
+            .register(UserId(user_pk), None)
+            .unwrap();
 
         // Get the subscription info though the API
         let message = "get subscription info".to_string();
         let response = internal_api
             .get_subscription_info(Request::new(msgs::GetSubscriptionInfoRequest {
                 signature: cryptography::sign(message.as_bytes(), &user_sk).unwrap(),
+                challenge: None,
             }))
             .await
             .unwrap()
@@ -994,6 +2480,40 @@ mod tests_public_api {
         assert!(matches!(response, msgs::GetSubscriptionInfoResponse { .. }))
     }
 
+    #[tokio::test]
+    async fn test_get_subscription_info_challenge() {
+        let internal_api = create_api().await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        internal_api
+            .watcher
+            .register(UserId(user_pk), None)
+            .unwrap();
+
+        // A nonce can be signed instead of the default message
+        let nonce = hex::encode(cryptography::get_random_bytes(32));
+        assert!(internal_api
+            .get_subscription_info(Request::new(msgs::GetSubscriptionInfoRequest {
+                signature: cryptography::sign(nonce.as_bytes(), &user_sk).unwrap(),
+                challenge: Some(nonce),
+            }))
+            .await
+            .is_ok());
+
+        // But any other challenge is rejected, even if properly signed
+        let challenge = "not a nonce".to_string();
+        match internal_api
+            .get_subscription_info(Request::new(msgs::GetSubscriptionInfoRequest {
+                signature: cryptography::sign(challenge.as_bytes(), &user_sk).unwrap(),
+                challenge: Some(challenge),
+            }))
+            .await
+        {
+            Err(status) => assert_eq!(status.code(), Code::Unauthenticated),
+            _ => panic!("Test should have returned Err"),
+        }
+    }
+
     #[tokio::test]
     async fn test_get_subscription_info_non_registered() {
         let internal_api = create_api_with_config(ApiConfig::new(SLOTS, 0)).await;
@@ -1006,6 +2526,7 @@ mod tests_public_api {
         match internal_api
             .get_subscription_info(Request::new(msgs::GetSubscriptionInfoRequest {
                 signature: cryptography::sign(message.as_bytes(), &user_sk).unwrap(),
+                challenge: None,
             }))
             .await
         {
@@ -1023,13 +2544,17 @@ mod tests_public_api {
 
         // The user is registered but the subscription has expired
         let (user_sk, user_pk) = get_random_keypair();
-        internal_api.watcher.register(UserId(user_pk)).unwrap();
+        internal_api
+            .watcher
+            .register(UserId(user_pk), None)
+            .unwrap();
 
         // Try to get the subscription info though the API
         let message = "get subscription info".to_string();
         match internal_api
             .get_subscription_info(Request::new(msgs::GetSubscriptionInfoRequest {
                 signature: cryptography::sign(message.as_bytes(), &user_sk).unwrap(),
+                challenge: None,
             }))
             .await
         {
@@ -1051,6 +2576,7 @@ mod tests_public_api {
         match internal_api
             .get_subscription_info(Request::new(msgs::GetSubscriptionInfoRequest {
                 signature: cryptography::sign(message.as_bytes(), &user_sk).unwrap(),
+                challenge: None,
             }))
             .await
         {
@@ -1061,4 +2587,18 @@ mod tests_public_api {
             _ => panic!("Test should have returned Err"),
         }
     }
+
+    #[tokio::test]
+    async fn test_get_capabilities() {
+        let internal_api = create_api().await;
+
+        let response = internal_api
+            .get_capabilities(Request::new(()))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(response.max_appointment_size, MAX_APPOINTMENT_SIZE as u64);
+        assert_eq!(response.min_to_self_delay, MIN_TO_SELF_DELAY);
+    }
 }
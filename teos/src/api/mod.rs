@@ -1,6 +1,8 @@
 pub mod http;
 pub mod internal;
+pub mod rate_limiter;
 pub mod tor;
+pub mod uds;
 
 pub mod serde_status {
     use serde::de::{self, Deserializer};
@@ -0,0 +1,276 @@
+//! Logic related to rate limiting the public API.
+//!
+//! Requests are limited using a token bucket per registered user. Requests whose user cannot be identified, or is
+//! not registered to the tower, all share a single (stricter) bucket, so they cannot be used to get around the
+//! per-user limits.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use teos_common::UserId;
+
+use crate::gatekeeper::Gatekeeper;
+
+/// How often buckets that have been fully refilled are pruned.
+pub const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A token bucket. Requests take tokens from it, and tokens are added back at a constant rate.
+#[derive(Debug, Clone, PartialEq)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    /// Creates a new full [Bucket].
+    fn new(burst: u32, now: Instant) -> Self {
+        Bucket {
+            tokens: burst as f64,
+            last_refill: now,
+        }
+    }
+
+    /// Adds the tokens accrued since the last refill, up to `burst`.
+    fn refill(&mut self, refill_rate: f64, burst: u32, now: Instant) {
+        if now > self.last_refill {
+            let elapsed = (now - self.last_refill).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * refill_rate).min(burst as f64);
+            self.last_refill = now;
+        }
+    }
+
+    /// Tries to take `n` tokens from the bucket. Returns whether there were enough.
+    fn try_take(&mut self, n: u32, refill_rate: f64, burst: u32, now: Instant) -> bool {
+        self.refill(refill_rate, burst, now);
+        if self.tokens >= n as f64 {
+            self.tokens -= n as f64;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Component in charge of rate limiting the requests to the public API.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    /// Number of tokens added to a bucket per second.
+    refill_rate: f64,
+    /// Maximum number of tokens a bucket can hold, that is, the number of requests that can be made in a row.
+    burst: u32,
+    /// Number of tokens added to the shared bucket per second.
+    shared_refill_rate: f64,
+    /// Maximum number of tokens the shared bucket can hold.
+    shared_burst: u32,
+    /// Buckets of the registered users that have recently made requests.
+    buckets: Arc<Mutex<HashMap<UserId, Bucket>>>,
+    /// Bucket shared by all requests whose user cannot be identified or is not registered.
+    shared: Arc<Mutex<Bucket>>,
+    /// A [Gatekeeper] instance. Used to tell registered users apart.
+    gatekeeper: Arc<Gatekeeper>,
+}
+
+impl RateLimiter {
+    /// Creates a new [RateLimiter] instance.
+    ///
+    /// `refill` is the number of tokens added to a bucket per minute, `burst` the capacity of the buckets. The shared
+    /// bucket gets a quarter of each (at least one) unless set otherwise using [RateLimiter::with_shared_limits].
+    pub fn new(refill: u32, burst: u32, gatekeeper: Arc<Gatekeeper>) -> Self {
+        let shared_burst = (burst / 4).max(1);
+        RateLimiter {
+            refill_rate: refill as f64 / 60.0,
+            burst,
+            shared_refill_rate: (refill / 4).max(1) as f64 / 60.0,
+            shared_burst,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+            shared: Arc::new(Mutex::new(Bucket::new(shared_burst, Instant::now()))),
+            gatekeeper,
+        }
+    }
+
+    /// Sets the refill rate (per minute) and capacity of the bucket shared by unidentified and unregistered users.
+    pub fn with_shared_limits(self, refill: u32, burst: u32) -> Self {
+        RateLimiter {
+            shared_refill_rate: refill as f64 / 60.0,
+            shared_burst: burst,
+            shared: Arc::new(Mutex::new(Bucket::new(burst, Instant::now()))),
+            ..self
+        }
+    }
+
+    /// Tries to take `n` tokens from the bucket of the given user, or from the shared one if the user is unknown or
+    /// not registered. Returns whether the request is allowed.
+    ///
+    /// Any signature recovers to some public key, so recovered ids only get a bucket of their own if they are
+    /// registered. Otherwise, requests signed with random keys would get a fresh bucket each.
+    pub(crate) fn try_acquire(&self, user_id: Option<UserId>, n: u32) -> bool {
+        self.try_acquire_at(user_id, n, Instant::now())
+    }
+
+    fn try_acquire_at(&self, user_id: Option<UserId>, n: u32, now: Instant) -> bool {
+        match user_id {
+            Some(user_id) if self.gatekeeper.get_user_info(user_id).is_some() => self
+                .buckets
+                .lock()
+                .unwrap()
+                .entry(user_id)
+                .or_insert_with(|| Bucket::new(self.burst, now))
+                .try_take(n, self.refill_rate, self.burst, now),
+            _ => self.shared.lock().unwrap().try_take(
+                n,
+                self.shared_refill_rate,
+                self.shared_burst,
+                now,
+            ),
+        }
+    }
+
+    /// Removes the buckets that are full. They are indistinguishable from the ones created for new users.
+    pub(crate) fn prune(&self) {
+        self.prune_at(Instant::now())
+    }
+
+    fn prune_at(&self, now: Instant) {
+        self.buckets.lock().unwrap().retain(|_, bucket| {
+            bucket.refill(self.refill_rate, self.burst, now);
+            bucket.tokens < self.burst as f64
+        });
+    }
+
+    /// Gets the number of buckets being tracked.
+    #[cfg(test)]
+    fn buckets_count(&self) -> usize {
+        self.buckets.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::dbm::DBM;
+    use crate::test_utils::{get_random_user_id, DURATION, EXPIRY_DELTA, SLOTS, START_HEIGHT};
+
+    const REFILL: u32 = 60;
+    const BURST: u32 = 5;
+
+    fn init_limiter() -> RateLimiter {
+        let dbm = Arc::new(DBM::in_memory().unwrap());
+        let gatekeeper =
+            Gatekeeper::new(START_HEIGHT as u32, SLOTS, DURATION, EXPIRY_DELTA, 0, dbm);
+        RateLimiter::new(REFILL, BURST, Arc::new(gatekeeper))
+    }
+
+    fn register_user(limiter: &RateLimiter) -> UserId {
+        let user_id = get_random_user_id();
+        limiter.gatekeeper.add_update_user(user_id, None).unwrap();
+        user_id
+    }
+
+    #[test]
+    fn test_burst() {
+        let limiter = init_limiter();
+        let user_id = register_user(&limiter);
+        let now = Instant::now();
+
+        // A user can make up to BURST requests in a row
+        for _ in 0..BURST {
+            assert!(limiter.try_acquire_at(Some(user_id), 1, now));
+        }
+        assert!(!limiter.try_acquire_at(Some(user_id), 1, now));
+
+        // Other users are not affected
+        assert!(limiter.try_acquire_at(Some(register_user(&limiter)), BURST, now));
+
+        // Requests taking more tokens than the burst can never be fulfilled
+        assert!(!limiter.try_acquire_at(Some(register_user(&limiter)), BURST + 1, now));
+    }
+
+    #[test]
+    fn test_refill() {
+        let limiter = init_limiter();
+        let user_id = register_user(&limiter);
+        let now = Instant::now();
+
+        assert!(limiter.try_acquire_at(Some(user_id), BURST, now));
+        assert!(!limiter.try_acquire_at(Some(user_id), 1, now));
+
+        // REFILL is one token per second
+        let now = now + Duration::from_millis(500);
+        assert!(!limiter.try_acquire_at(Some(user_id), 1, now));
+        let now = now + Duration::from_millis(500);
+        assert!(limiter.try_acquire_at(Some(user_id), 1, now));
+        assert!(!limiter.try_acquire_at(Some(user_id), 1, now));
+
+        // Buckets never go past the burst, no matter how long they have been idle
+        let now = now + Duration::from_secs(3600);
+        assert!(limiter.try_acquire_at(Some(user_id), BURST, now));
+        assert!(!limiter.try_acquire_at(Some(user_id), 1, now));
+    }
+
+    #[test]
+    fn test_unidentified() {
+        let limiter = init_limiter().with_shared_limits(REFILL, 2);
+        let now = Instant::now();
+
+        // Unidentified requests share a single bucket, with its own limits
+        for _ in 0..2 {
+            assert!(limiter.try_acquire_at(None, 1, now));
+        }
+        assert!(!limiter.try_acquire_at(None, 1, now));
+        assert_eq!(limiter.buckets_count(), 0);
+
+        // Which does not affect registered users
+        assert!(limiter.try_acquire_at(Some(register_user(&limiter)), 1, now));
+
+        let now = now + Duration::from_secs(1);
+        assert!(limiter.try_acquire_at(None, 1, now));
+    }
+
+    #[test]
+    fn test_unregistered() {
+        let limiter = init_limiter();
+        let now = Instant::now();
+
+        // The shared bucket is stricter than the per-user ones by default
+        assert_eq!(limiter.shared_burst, 1);
+        assert!(limiter.shared_refill_rate < limiter.refill_rate);
+
+        // Requests signed with random keys all share the same bucket, and no bucket is created for them
+        assert!(limiter.try_acquire_at(Some(get_random_user_id()), 1, now));
+        for _ in 0..10 {
+            assert!(!limiter.try_acquire_at(Some(get_random_user_id()), 1, now));
+        }
+        assert!(!limiter.try_acquire_at(None, 1, now));
+        assert_eq!(limiter.buckets_count(), 0);
+
+        // Once registered, a user gets a bucket of their own
+        let user_id = get_random_user_id();
+        assert!(!limiter.try_acquire_at(Some(user_id), 1, now));
+        limiter.gatekeeper.add_update_user(user_id, None).unwrap();
+        assert!(limiter.try_acquire_at(Some(user_id), BURST, now));
+        assert_eq!(limiter.buckets_count(), 1);
+    }
+
+    #[test]
+    fn test_prune() {
+        let limiter = init_limiter();
+        let now = Instant::now();
+
+        let user_id = register_user(&limiter);
+        assert!(limiter.try_acquire_at(Some(user_id), 1, now));
+        let now = now + Duration::from_secs(2);
+        assert!(limiter.try_acquire_at(Some(register_user(&limiter)), BURST, now));
+        assert_eq!(limiter.buckets_count(), 2);
+
+        // The first bucket is full again, the second one is not
+        limiter.prune_at(now);
+        assert_eq!(limiter.buckets_count(), 1);
+        assert!(!limiter.buckets.lock().unwrap().contains_key(&user_id));
+
+        let now = now + Duration::from_secs(BURST as u64);
+        limiter.prune_at(now);
+        assert_eq!(limiter.buckets_count(), 0);
+    }
+}
@@ -1,17 +1,57 @@
+use std::convert::TryInto;
+use std::fs;
 use std::io::{Error, ErrorKind};
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use tokio::net::TcpStream;
 use tokio::time::{sleep, Duration};
 use torut::control::UnauthenticatedConn;
 use torut::onion::TorSecretKeyV3;
 use triggered::Listener;
 
+/// Runs an onion service that re-directs to the public api until the shutdown signal is received.
+///
+/// Errors are logged instead of propagated. Tor is an additional interface, so failing to set it up (or losing it)
+/// must not take the rest of the tower down. `tor_available` reflects whether the service is currently up.
+///
+/// If `key_path` is set, the onion service key is loaded from (or, on first run, stored to) it, so the onion address
+/// is kept across restarts. Otherwise a new address is used every time.
+pub async fn run_onion_service(
+    tor_control_port: u16,
+    api_port: u16,
+    onion_port: u16,
+    key_path: Option<PathBuf>,
+    shutdown_signal_tor: Listener,
+    tor_available: Arc<AtomicBool>,
+) {
+    if let Err(e) = expose_onion_service(
+        tor_control_port,
+        api_port,
+        onion_port,
+        key_path,
+        shutdown_signal_tor,
+        tor_available.clone(),
+    )
+    .await
+    {
+        log::error!(
+            "Onion service not available, the tower can only be reached via clearnet. Error: {}",
+            e
+        );
+    }
+    tor_available.store(false, Ordering::Release);
+}
+
 /// Expose an onion service that re-directs to the public api.
 pub async fn expose_onion_service(
     tor_control_port: u16,
     api_port: u16,
     onion_port: u16,
+    key_path: Option<PathBuf>,
     shutdown_signal_tor: Listener,
+    tor_available: Arc<AtomicBool>,
 ) -> Result<(), Error> {
     let stream = connect_tor_cp(format!("127.0.0.1:{}", tor_control_port).parse().unwrap())
         .await
@@ -39,7 +79,10 @@ pub async fn expose_onion_service(
 
     auth_conn.set_async_event_handler(Some(|_| async move { Ok(()) }));
 
-    let key = TorSecretKeyV3::generate();
+    let key = match key_path {
+        Some(path) => load_or_create_key(&path)?,
+        None => TorSecretKeyV3::generate(),
+    };
 
     auth_conn
         .add_onion_v3(
@@ -63,6 +106,7 @@ pub async fn expose_onion_service(
         })?;
 
     print_onion_service(key.clone(), onion_port);
+    tor_available.store(true, Ordering::Release);
 
     // NOTE: Needed to keep connection with control port & hidden service running, as soon as we leave
     // this function the control port stream is dropped and the hidden service is killed
@@ -80,8 +124,34 @@ pub async fn expose_onion_service(
                 .get_address_without_dot_onion(),
         )
         .await
-        .unwrap();
-    Ok(())
+        .map_err(|e| Error::other(format!("failed to remove onion hidden service: {}", e)))
+}
+
+/// Loads the onion service key stored at `path`. If there is no key yet, a new one is generated and stored there.
+pub fn load_or_create_key(path: &Path) -> Result<TorSecretKeyV3, Error> {
+    if path.exists() {
+        let bytes: [u8; 64] = fs::read(path)?.try_into().map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid onion service key found at {}", path.display()),
+            )
+        })?;
+        return Ok(TorSecretKeyV3::from(bytes));
+    }
+
+    let key = TorSecretKeyV3::generate();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, key.as_bytes())?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    log::info!("New onion service key stored at {}", path.display());
+
+    Ok(key)
 }
 
 async fn connect_tor_cp(addr: SocketAddr) -> Result<TcpStream, Error> {
@@ -115,4 +185,40 @@ mod tests {
             }
         }
     }
+
+    #[tokio::test]
+    async fn test_run_onion_service_fail() {
+        // Failing to reach the control port must not panic, just flag Tor as not available
+        let tor_available = Arc::new(AtomicBool::new(true));
+        let (_, shutdown_signal) = triggered::trigger();
+        run_onion_service(1, 9814, 2121, None, shutdown_signal, tor_available.clone()).await;
+        assert!(!tor_available.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_load_or_create_key() {
+        let dir = std::env::temp_dir().join(format!(
+            "teos_tor_{}",
+            hex::encode(teos_common::cryptography::get_random_bytes(8))
+        ));
+        let path = dir.join("onion_v3_sk");
+
+        // The key is created on first run and reloaded afterwards, so the onion address does not change
+        let key = load_or_create_key(&path).unwrap();
+        assert!(path.exists());
+        let reloaded = load_or_create_key(&path).unwrap();
+        assert_eq!(
+            key.public().get_onion_address(),
+            reloaded.public().get_onion_address()
+        );
+
+        // Corrupted keys are not silently replaced
+        fs::write(&path, [0; 32]).unwrap();
+        assert_eq!(
+            load_or_create_key(&path).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
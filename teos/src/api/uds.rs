@@ -0,0 +1,165 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_util::{Stream, TryStreamExt};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::UnixListener;
+use tokio_stream::wrappers::UnixListenerStream;
+use tonic::transport::server::Connected;
+use tonic::transport::{Channel, Endpoint, Uri};
+use tower::service_fn;
+
+/// A Unix domain socket connection that can be served by a gRPC server.
+#[derive(Debug)]
+pub struct UnixStream(tokio::net::UnixStream);
+
+impl Connected for UnixStream {
+    type ConnectInfo = ();
+
+    fn connect_info(&self) -> Self::ConnectInfo {}
+}
+
+impl AsyncRead for UnixStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for UnixStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+/// Listens for connections on the Unix domain socket at `path`. Meant to be fed to a gRPC server.
+///
+/// The socket file left behind by a previous run (if any) is replaced. Any other kind of file found at `path` is not
+/// touched, and an error is returned instead.
+pub fn incoming(path: &Path) -> Result<impl Stream<Item = Result<UnixStream, Error>>, Error> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ))
+        }
+        Err(_) => (),
+    }
+
+    Ok(UnixListenerStream::new(UnixListener::bind(path)?).map_ok(UnixStream))
+}
+
+/// Creates a gRPC channel to a server listening on the Unix domain socket at `path`.
+pub async fn connect(path: PathBuf) -> Result<Channel, tonic::transport::Error> {
+    // The endpoint URI is required by tonic but ignored, the connector always dials the socket
+    Endpoint::from_static("http://localhost")
+        .connect_with_connector(service_fn(move |_: Uri| {
+            tokio::net::UnixStream::connect(path.clone())
+        }))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tonic::Request;
+
+    use crate::cli_config::Config as CliConfig;
+    use crate::protos::private_tower_services_client::PrivateTowerServicesClient;
+    use crate::protos::private_tower_services_server::PrivateTowerServicesServer;
+    use crate::test_utils::create_api;
+
+    fn socket_path() -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "teos_rpc_{}.sock",
+            hex::encode(teos_common::cryptography::get_random_bytes(8))
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_serve_and_connect() {
+        let path = socket_path();
+        let internal_api = create_api().await;
+        let (shutdown_trigger, shutdown_signal) = triggered::trigger();
+
+        let server = tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(PrivateTowerServicesServer::new(internal_api.clone()))
+                .serve_with_incoming_shutdown(incoming(&path).unwrap(), shutdown_signal),
+        );
+
+        // A client connected over the socket can query the tower as it would over TCP. The CLI reaches it whether
+        // the path is relative to its data dir or absolute
+        let data_dir = path.parent().unwrap();
+        for rpc_socket_path in [path.file_name().unwrap(), path.as_os_str()] {
+            let conf = CliConfig {
+                rpc_socket_path: rpc_socket_path.to_str().unwrap().to_owned(),
+                ..Default::default()
+            };
+            let mut client = PrivateTowerServicesClient::new(conf.connect(data_dir).await.unwrap());
+            let response = client
+                .get_tower_info(Request::new(()))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(
+                response.tower_id,
+                internal_api.get_watcher().tower_id().serialize()
+            );
+        }
+
+        // Paths are not looked up anywhere else
+        let conf = CliConfig {
+            rpc_socket_path: path.file_name().unwrap().to_str().unwrap().to_owned(),
+            ..Default::default()
+        };
+        assert!(conf.connect(&data_dir.join("elsewhere")).await.is_err());
+
+        shutdown_trigger.trigger();
+        server.await.unwrap().unwrap();
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_incoming_replaces_stale_socket() {
+        let path = socket_path();
+
+        // The socket left behind by a previous run is replaced
+        drop(incoming(&path).unwrap());
+        assert!(path.exists());
+        drop(incoming(&path).unwrap());
+
+        // But regular files are left alone
+        fs::remove_file(&path).unwrap();
+        fs::write(&path, "not a socket").unwrap();
+        assert_eq!(
+            incoming(&path).err().unwrap().kind(),
+            ErrorKind::AlreadyExists
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "not a socket");
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+//! Logic related to the periodic backup of the tower database.
+//!
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::task;
+use tokio::time::timeout;
+use triggered::Listener;
+
+use crate::dbm::DBM;
+
+/// Prefix used to name (and recognize) database snapshots.
+const BACKUP_PREFIX: &str = "teos_db_";
+/// Extension used by database snapshots.
+const BACKUP_EXTENSION: &str = "sql3";
+
+/// Creates a snapshot of the database in `backup_dir`, naming it after the current timestamp.
+///
+/// Returns the path of the newly created snapshot.
+pub fn create_backup(dbm: &DBM, backup_dir: &Path) -> Result<PathBuf, io::Error> {
+    fs::create_dir_all(backup_dir)?;
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    // Zero padding the timestamp so snapshots are sorted chronologically when sorted by name.
+    let backup_path = backup_dir.join(format!(
+        "{}{:020}.{}",
+        BACKUP_PREFIX, timestamp, BACKUP_EXTENSION
+    ));
+    dbm.backup(&backup_path).map_err(io::Error::other)?;
+
+    Ok(backup_path)
+}
+
+/// Removes the oldest snapshots from `backup_dir` so only the last `retention` are kept.
+///
+/// Files not created by [create_backup] are ignored. Returns the number of removed snapshots.
+pub fn prune_backups(backup_dir: &Path, retention: usize) -> Result<usize, io::Error> {
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(backup_dir)? {
+        let path = entry?.path();
+        let is_backup = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| {
+                name.starts_with(BACKUP_PREFIX) && name.ends_with(&format!(".{}", BACKUP_EXTENSION))
+            })
+            .unwrap_or(false);
+        if is_backup {
+            backups.push(path);
+        }
+    }
+
+    if backups.len() <= retention {
+        return Ok(0);
+    }
+
+    backups.sort();
+    let to_remove = backups.len() - retention;
+    for path in backups.iter().take(to_remove) {
+        fs::remove_file(path)?;
+    }
+
+    Ok(to_remove)
+}
+
+/// Backs up the database every `interval`, keeping the last `retention` snapshots, until the shutdown signal is received.
+///
+/// Failing to create a snapshot is logged but not fatal, the next attempt will be made on the following interval.
+pub async fn backup_periodically(
+    dbm: Arc<DBM>,
+    backup_dir: PathBuf,
+    interval: Duration,
+    retention: usize,
+    shutdown_signal: Listener,
+) {
+    loop {
+        // Sleep for interval seconds or shutdown if the signal is received.
+        if timeout(interval, shutdown_signal.clone()).await.is_ok() {
+            log::debug!("Received shutting down signal. Shutting down database backups");
+            break;
+        }
+
+        // Backing up (and pruning) blocks on SQLite and the filesystem, so it is kept off the async runtime
+        let dbm = dbm.clone();
+        let backup_dir = backup_dir.clone();
+        let backup = task::spawn_blocking(move || match create_backup(&dbm, &backup_dir) {
+            Ok(path) => {
+                log::info!("Database backed up to {}", path.display());
+                match prune_backups(&backup_dir, retention) {
+                    Ok(0) => (),
+                    Ok(n) => log::debug!("Removed {} old database backup(s)", n),
+                    Err(e) => log::error!("Couldn't prune old database backups. Error: {}", e),
+                }
+            }
+            Err(e) => log::error!("Couldn't back up the database. Error: {}", e),
+        });
+        if let Err(e) = backup.await {
+            log::error!("Database backup task failed. Error: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::gatekeeper::UserInfo;
+    use crate::test_utils::get_random_user_id;
+    use teos_common::cryptography::get_random_bytes;
+
+    fn get_random_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("teos_backup_{}", hex::encode(get_random_bytes(8))))
+    }
+
+    #[test]
+    fn test_create_backup() {
+        let dbm = DBM::in_memory().unwrap();
+        for _ in 0..10 {
+            dbm.store_user(get_random_user_id(), &UserInfo::new(21, 42))
+                .unwrap();
+        }
+
+        let backup_dir = get_random_dir();
+        let backup_path = create_backup(&dbm, &backup_dir).unwrap();
+        assert!(backup_path.exists());
+
+        // The backup must be a valid database holding the same data
+        let restored = DBM::new(backup_path).unwrap();
+        assert_eq!(restored.load_all_users(), dbm.load_all_users());
+
+        fs::remove_dir_all(backup_dir).unwrap();
+    }
+
+    #[test]
+    fn test_prune_backups() {
+        let dbm = DBM::in_memory().unwrap();
+        let backup_dir = get_random_dir();
+
+        let mut backups = Vec::new();
+        for _ in 0..5 {
+            backups.push(create_backup(&dbm, &backup_dir).unwrap());
+            // Make sure timestamps differ between snapshots
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        // Files that are not snapshots must be left untouched
+        let other_file = backup_dir.join("other_file");
+        fs::write(&other_file, "data").unwrap();
+
+        assert_eq!(prune_backups(&backup_dir, 2).unwrap(), 3);
+        for path in backups.iter().take(3) {
+            assert!(!path.exists());
+        }
+        for path in backups.iter().skip(3) {
+            assert!(path.exists());
+        }
+        assert!(other_file.exists());
+
+        // Nothing to prune if we are within the retention limit
+        assert_eq!(prune_backups(&backup_dir, 2).unwrap(), 0);
+
+        fs::remove_dir_all(backup_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_backup_periodically() {
+        let dbm = Arc::new(DBM::in_memory().unwrap());
+        let backup_dir = get_random_dir();
+        let (shutdown_trigger, shutdown_signal) = triggered::trigger();
+
+        let task = tokio::spawn(backup_periodically(
+            dbm,
+            backup_dir.clone(),
+            Duration::from_millis(20),
+            2,
+            shutdown_signal,
+        ));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        shutdown_trigger.trigger();
+        task.await.unwrap();
+
+        // Backups are taken every interval (off the async runtime), keeping only the last ones
+        assert_eq!(fs::read_dir(&backup_dir).unwrap().count(), 2);
+
+        fs::remove_dir_all(backup_dir).unwrap();
+    }
+}
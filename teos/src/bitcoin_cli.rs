@@ -9,18 +9,193 @@
  * at your option.
 */
 
+use std::convert::TryFrom;
+use std::fmt;
+use std::future::Future;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
 
 use bitcoin::base64;
 use bitcoin::hash_types::{BlockHash, Txid};
 use bitcoin::hashes::hex::ToHex;
-use bitcoin::{Block, Transaction};
+use bitcoin::{Block, Network, Transaction};
 use lightning::util::ser::Writeable;
-use lightning_block_sync::http::HttpEndpoint;
+use lightning_block_sync::http::{HttpEndpoint, JsonResponse};
 use lightning_block_sync::rpc::RpcClient;
 use lightning_block_sync::{AsyncBlockSourceResult, BlockHeaderData, BlockSource};
 
+/// Minimum `bitcoind` version supported by the tower (v0.21.0).
+///
+/// Required for `signet` support and for the `getnetworkinfo` / `getrawtransaction` interfaces the tower relies on.
+pub const MIN_BITCOIND_VERSION: u32 = 210000;
+
+/// Maximum wait between attempts to reach `bitcoind` (check [with_retries]).
+pub const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Error raised if the `bitcoind` the tower is connected to is older than [MIN_BITCOIND_VERSION].
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnsupportedVersion {
+    pub detected: u32,
+    pub required: u32,
+}
+
+impl fmt::Display for UnsupportedVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Unsupported bitcoind version. Detected: {}, required: {} or newer",
+            format_version(self.detected),
+            format_version(self.required)
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedVersion {}
+
+/// Formats a `bitcoind` version number (as reported by `getnetworkinfo`) in human readable form.
+///
+/// Versions prior to v22 used the `0.x.y` notation.
+pub fn format_version(version: u32) -> String {
+    let (major, minor, patch) = (version / 10000, version / 100 % 100, version % 100);
+    if major < 22 {
+        format!("v0.{}.{}", major, minor)
+    } else {
+        format!("v{}.{}.{}", major, minor, patch)
+    }
+}
+
+/// Checks whether a given `bitcoind` version is supported by the tower.
+pub fn check_version(version: u32) -> Result<(), UnsupportedVersion> {
+    if version < MIN_BITCOIND_VERSION {
+        Err(UnsupportedVersion {
+            detected: version,
+            required: MIN_BITCOIND_VERSION,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Error raised if the `bitcoind` the tower is connected to is running on a different chain than the one the tower
+/// is configured for.
+#[derive(Debug, PartialEq, Eq)]
+pub struct NetworkMismatch {
+    pub expected: Network,
+    pub detected: String,
+}
+
+impl fmt::Display for NetworkMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "bitcoind is running on a different network. Expected: {}, detected: {}",
+            self.expected, self.detected
+        )
+    }
+}
+
+impl std::error::Error for NetworkMismatch {}
+
+/// Checks whether the chain reported by `bitcoind` (`getblockchaininfo`) matches the network the tower is configured
+/// for.
+///
+/// `bitcoind` names the chains `main`, `test`, `signet` and `regtest`.
+pub fn check_network(chain: &str, network: Network) -> Result<(), NetworkMismatch> {
+    let detected = match chain {
+        "main" => Some(Network::Bitcoin),
+        "test" => Some(Network::Testnet),
+        "signet" => Some(Network::Signet),
+        "regtest" => Some(Network::Regtest),
+        _ => None,
+    };
+
+    if detected == Some(network) {
+        Ok(())
+    } else {
+        Err(NetworkMismatch {
+            expected: network,
+            detected: chain.to_owned(),
+        })
+    }
+}
+
+/// Runs `f` until it succeeds, retrying up to `retries` times if it fails.
+///
+/// The wait between attempts starts at `backoff` and is doubled after every failure, up to [MAX_RETRY_BACKOFF] (or
+/// `backoff`, if bigger). With no retries the first error is returned straightaway.
+pub async fn with_retries<T, E, F, Fut>(retries: u32, backoff: Duration, mut f: F) -> Result<T, E>
+where
+    E: fmt::Debug,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut wait = backoff;
+    for attempt in 1..=retries {
+        match f().await {
+            Ok(x) => return Ok(x),
+            Err(e) => {
+                log::warn!(
+                    "Cannot reach bitcoind ({:?}). Retrying in {:?} ({}/{})",
+                    e,
+                    wait,
+                    attempt,
+                    retries
+                );
+                tokio::time::sleep(wait).await;
+                wait = next_backoff(wait, backoff);
+            }
+        }
+    }
+    f().await
+}
+
+/// Doubles the wait between attempts to reach `bitcoind`, capping it at [MAX_RETRY_BACKOFF] (or the initial `backoff`,
+/// if bigger).
+fn next_backoff(wait: Duration, backoff: Duration) -> Duration {
+    wait.saturating_mul(2).min(MAX_RETRY_BACKOFF.max(backoff))
+}
+
+/// The subset of `getnetworkinfo` the tower cares about.
+struct NetworkInfo {
+    version: u32,
+}
+
+impl TryFrom<JsonResponse> for NetworkInfo {
+    type Error = std::io::Error;
+
+    fn try_from(response: JsonResponse) -> std::io::Result<Self> {
+        match response.0["version"].as_u64().map(u32::try_from) {
+            Some(Ok(version)) => Ok(NetworkInfo { version }),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "expected JSON number for version",
+            )),
+        }
+    }
+}
+
+/// The subset of `getblockchaininfo` the tower cares about.
+struct BlockchainInfo {
+    chain: String,
+}
+
+impl TryFrom<JsonResponse> for BlockchainInfo {
+    type Error = std::io::Error;
+
+    fn try_from(response: JsonResponse) -> std::io::Result<Self> {
+        match response.0["chain"].as_str() {
+            Some(chain) => Ok(BlockchainInfo {
+                chain: chain.to_owned(),
+            }),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "expected JSON string for chain",
+            )),
+        }
+    }
+}
+
 /// A simple implementation of a bitcoind client (`bitcoin-cli`) with the minimal functionality required by the tower.
 pub struct BitcoindClient<'a> {
     /// The underlying RPC client.
@@ -113,6 +288,22 @@ impl<'a> BitcoindClient<'a> {
             .await
     }
 
+    /// Gets the version of the `bitcoind` node we are connected to.
+    pub async fn get_version(&self) -> Result<u32, std::io::Error> {
+        let mut rpc = self.bitcoind_rpc_client.lock().await;
+        rpc.call_method::<NetworkInfo>("getnetworkinfo", &[])
+            .await
+            .map(|info| info.version)
+    }
+
+    /// Gets the name of the chain the `bitcoind` node we are connected to is running on.
+    pub async fn get_chain(&self) -> Result<String, std::io::Error> {
+        let mut rpc = self.bitcoind_rpc_client.lock().await;
+        rpc.call_method::<BlockchainInfo>("getblockchaininfo", &[])
+            .await
+            .map(|info| info.chain)
+    }
+
     /// Sends a transaction to the network.
     pub async fn send_raw_transaction(&self, raw_tx: &Transaction) -> Result<Txid, std::io::Error> {
         let mut rpc = self.bitcoind_rpc_client.lock().await;
@@ -131,3 +322,171 @@ impl<'a> BitcoindClient<'a> {
             .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::thread;
+    use std::time::Instant;
+
+    /// Answers a single `getblockchaininfo` request on the given address.
+    fn serve_getblockchaininfo(address: SocketAddr) {
+        let (mut stream, _) = TcpListener::bind(address).unwrap().accept().unwrap();
+
+        // Read the whole request before answering
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        loop {
+            let n = stream.read(&mut buf).unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let request = String::from_utf8_lossy(&request);
+            if let Some(i) = request.find("\r\n\r\n") {
+                let content_length = request[..i]
+                    .lines()
+                    .find_map(|l| {
+                        l.to_lowercase()
+                            .strip_prefix("content-length: ")
+                            .map(|x| x.parse::<usize>().unwrap())
+                    })
+                    .unwrap_or(0);
+                if request.len() >= i + 4 + content_length {
+                    break;
+                }
+            }
+        }
+
+        let body = serde_json::json!({
+            "result": {"bestblockhash": BlockHash::default().to_hex(), "blocks": 0},
+            "error": null,
+            "id": "0"
+        })
+        .to_string();
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_format_version() {
+        assert_eq!(format_version(210000), "v0.21.0");
+        assert_eq!(format_version(210100), "v0.21.1");
+        assert_eq!(format_version(220000), "v22.0.0");
+        assert_eq!(format_version(230100), "v23.1.0");
+    }
+
+    #[test]
+    fn test_check_version() {
+        // A mocked getnetworkinfo response reporting a version older than the required one
+        let response =
+            JsonResponse(serde_json::json!({"version": 200100, "subversion": "/Satoshi:0.20.1/"}));
+        let version = NetworkInfo::try_from(response).unwrap().version;
+        assert_eq!(
+            check_version(version),
+            Err(UnsupportedVersion {
+                detected: 200100,
+                required: MIN_BITCOIND_VERSION
+            })
+        );
+
+        // Equal or newer versions are supported
+        assert_eq!(check_version(MIN_BITCOIND_VERSION), Ok(()));
+        let response =
+            JsonResponse(serde_json::json!({"version": 220000, "subversion": "/Satoshi:22.0.0/"}));
+        let version = NetworkInfo::try_from(response).unwrap().version;
+        assert_eq!(check_version(version), Ok(()));
+    }
+
+    #[test]
+    fn test_network_info_malformed() {
+        let response = JsonResponse(serde_json::json!({"subversion": "/Satoshi:22.0.0/"}));
+        assert!(NetworkInfo::try_from(response).is_err());
+    }
+
+    #[test]
+    fn test_check_network() {
+        for (chain, network) in [
+            ("main", Network::Bitcoin),
+            ("test", Network::Testnet),
+            ("signet", Network::Signet),
+            ("regtest", Network::Regtest),
+        ] {
+            let response = JsonResponse(serde_json::json!({"chain": chain, "blocks": 0}));
+            let chain = BlockchainInfo::try_from(response).unwrap().chain;
+            assert_eq!(check_network(&chain, network), Ok(()));
+        }
+
+        // A mainnet tower connected to a regtest node is rejected
+        let response = JsonResponse(serde_json::json!({"chain": "regtest", "blocks": 0}));
+        let chain = BlockchainInfo::try_from(response).unwrap().chain;
+        assert_eq!(
+            check_network(&chain, Network::Bitcoin),
+            Err(NetworkMismatch {
+                expected: Network::Bitcoin,
+                detected: "regtest".to_owned()
+            })
+        );
+
+        // So is any chain we do not know about
+        assert!(check_network("unknown", Network::Bitcoin).is_err());
+    }
+
+    #[test]
+    fn test_blockchain_info_malformed() {
+        let response = JsonResponse(serde_json::json!({"blocks": 0}));
+        assert!(BlockchainInfo::try_from(response).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_with_retries() {
+        // Get a free port and start bitcoind there only after some time, so the first attempts to reach it fail
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let backoff = Duration::from_millis(100);
+
+        // With no retries the first failure is returned
+        assert!(with_retries(0, backoff, || {
+            BitcoindClient::new("127.0.0.1", address.port(), "user", "passwd")
+        })
+        .await
+        .is_err());
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(250));
+            serve_getblockchaininfo(address);
+        });
+
+        let start = Instant::now();
+        with_retries(5, backoff, || {
+            BitcoindClient::new("127.0.0.1", address.port(), "user", "passwd")
+        })
+        .await
+        .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(250));
+    }
+
+    #[test]
+    fn test_next_backoff() {
+        // The wait doubles until reaching the cap, and stays there no matter how many retries are left
+        let backoff = Duration::from_secs(1);
+        let mut wait = backoff;
+        for expected in [2, 4, 8, 16, 32, 60, 60] {
+            wait = next_backoff(wait, backoff);
+            assert_eq!(wait, Duration::from_secs(expected));
+        }
+
+        // Initial backoffs above the cap are kept as they are, and big ones do not overflow
+        let backoff = MAX_RETRY_BACKOFF * 2;
+        assert_eq!(next_backoff(backoff, backoff), backoff);
+        let backoff = Duration::from_secs(u64::MAX);
+        assert_eq!(next_backoff(backoff, backoff), backoff);
+    }
+}
@@ -1,29 +1,36 @@
 //! Logic related to the Carrier, the component in charge or sending/requesting transaction data from/to `bitcoind`.
 
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
 
 use crate::responder::ConfirmationStatus;
 use crate::{errors, rpc_errors};
 
-use bitcoin::{BlockHash, Transaction, Txid};
+use bitcoin::{BlockHash, OutPoint, Transaction, Txid};
 use bitcoincore_rpc::{
     jsonrpc::error::Error::Rpc as RpcError, jsonrpc::error::Error::Transport as TransportError,
     Client as BitcoindClient, Error::JsonRpc as JsonRpcError, RpcApi,
 };
 
 /// Component in charge of the interaction with Bitcoind by sending / querying transactions via RPC.
+///
+/// All methods take a shared reference, so transactions can be sent concurrently.
 #[derive(Debug)]
 pub struct Carrier {
     /// The underlying bitcoin client used by the [Carrier].
     bitcoin_cli: Arc<BitcoindClient>,
+    /// Additional bitcoin clients transactions are broadcast through, in order, if the main one fails to do so.
+    fallback_clis: Vec<Arc<BitcoindClient>>,
     /// A flag that indicates wether bitcoind is reachable or not.
     bitcoind_reachable: Arc<(Mutex<bool>, Condvar)>,
     /// A map of receipts already issued by the [Carrier].
     /// Used to prevent potentially re-sending the same transaction over and over.
-    issued_receipts: HashMap<Txid, ConfirmationStatus>,
+    issued_receipts: Mutex<HashMap<Txid, ConfirmationStatus>>,
     /// The last known block header.
-    block_height: u32,
+    block_height: AtomicU32,
+    /// Whether the [Carrier] is running in dry-run mode. If so, transactions are never broadcast.
+    dry_run: bool,
 }
 
 impl Carrier {
@@ -35,23 +42,48 @@ impl Carrier {
     ) -> Self {
         Carrier {
             bitcoin_cli,
+            fallback_clis: Vec::new(),
             bitcoind_reachable,
-            issued_receipts: HashMap::new(),
-            block_height: last_known_block_height,
+            issued_receipts: Mutex::new(HashMap::new()),
+            block_height: AtomicU32::new(last_known_block_height),
+            dry_run: false,
         }
     }
 
+    /// Sets whether the [Carrier] runs in dry-run mode, that is, whether transactions are never broadcast.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Sets the clients used to broadcast transactions if the main one fails to do so. Clients are tried in order.
+    pub fn with_fallbacks(mut self, fallback_clis: Vec<Arc<BitcoindClient>>) -> Self {
+        self.fallback_clis = fallback_clis;
+        self
+    }
+
     /// Clears the receipts cached by the [Carrier]. Should be called periodically to prevent it from
     /// growing unbounded.
-    pub(crate) fn clear_receipts(&mut self) {
-        if !self.issued_receipts.is_empty() {
-            self.issued_receipts = HashMap::new()
+    pub(crate) fn clear_receipts(&self) {
+        let mut issued_receipts = self.issued_receipts.lock().unwrap();
+        if !issued_receipts.is_empty() {
+            *issued_receipts = HashMap::new()
         }
     }
 
+    /// Forgets the receipt cached for a given transaction (if any), so it is actually sent next time.
+    pub(crate) fn forget_receipt(&self, txid: &Txid) {
+        self.issued_receipts.lock().unwrap().remove(txid);
+    }
+
     /// Updates the last known block height by the [Carrier].
-    pub(crate) fn update_height(&mut self, height: u32) {
-        self.block_height = height
+    pub(crate) fn update_height(&self, height: u32) {
+        self.block_height.store(height, Ordering::Release)
+    }
+
+    /// Gets the last known block height by the [Carrier].
+    pub(crate) fn get_height(&self) -> u32 {
+        self.block_height.load(Ordering::Acquire)
     }
 
     /// Hangs the process until bitcoind is reachable. If bitcoind is already reachable it just passes trough.
@@ -63,6 +95,16 @@ impl Carrier {
         }
     }
 
+    /// Checks whether bitcoind is flagged as reachable.
+    pub(crate) fn is_bitcoind_reachable(&self) -> bool {
+        *self.bitcoind_reachable.0.lock().unwrap()
+    }
+
+    /// Gets the flag that indicates whether bitcoind is reachable, alongside its notifier.
+    pub(crate) fn bitcoind_reachable(&self) -> Arc<(Mutex<bool>, Condvar)> {
+        self.bitcoind_reachable.clone()
+    }
+
     /// Flags bitcoind as unreachable.
     fn flag_bitcoind_unreachable(&self) {
         let (lock, _) = &*self.bitcoind_reachable;
@@ -72,21 +114,60 @@ impl Carrier {
     /// Sends a [Transaction] to the Bitcoin network.
     ///
     /// Returns a [ConfirmationStatus] indicating whether the transaction was accepted by the node or not.
-    pub(crate) fn send_transaction(&mut self, tx: &Transaction) -> ConfirmationStatus {
-        self.hang_until_bitcoind_reachable();
+    /// In dry-run mode the transaction is only logged, and it is reported as accepted.
+    ///
+    /// If bitcoind is unreachable, this hangs until it is back. Use [try_send_transaction](Self::try_send_transaction)
+    /// to avoid so.
+    pub(crate) fn send_transaction(&self, tx: &Transaction) -> ConfirmationStatus {
+        loop {
+            self.hang_until_bitcoind_reachable();
+            if let Some(receipt) = self.try_send_transaction(tx) {
+                return receipt;
+            }
+        }
+    }
 
-        if let Some(receipt) = self.issued_receipts.get(&tx.txid()) {
+    /// Sends a [Transaction] to the Bitcoin network without waiting for bitcoind to be reachable.
+    ///
+    /// Works like [send_transaction](Self::send_transaction), but returns [None] if bitcoind is unreachable and none
+    /// of the fallbacks accepted the transaction either.
+    pub(crate) fn try_send_transaction(&self, tx: &Transaction) -> Option<ConfirmationStatus> {
+        if let Some(receipt) = self.issued_receipts.lock().unwrap().get(&tx.txid()) {
             log::info!("Transaction already sent: {}", tx.txid());
-            return *receipt;
+            return Some(*receipt);
+        }
+
+        if self.dry_run {
+            log::info!(
+                "Dry run. Would have pushed transaction to the network: {} ({})",
+                tx.txid(),
+                hex::encode(bitcoin::consensus::serialize(tx))
+            );
+            let receipt = ConfirmationStatus::InMempoolSince(self.get_height());
+            self.issued_receipts
+                .lock()
+                .unwrap()
+                .insert(tx.txid(), receipt);
+            return Some(receipt);
+        }
+
+        if !self.is_bitcoind_reachable() {
+            log::error!("bitcoind is unreachable, trying the fallbacks (if any)");
+            let receipt = self.send_transaction_to_fallbacks(tx)?;
+            self.issued_receipts
+                .lock()
+                .unwrap()
+                .insert(tx.txid(), receipt);
+            return Some(receipt);
         }
 
         log::info!("Pushing transaction to the network: {}", tx.txid());
-        let receipt = match self.bitcoin_cli.send_raw_transaction(tx) {
+        let mut receipt = match self.bitcoin_cli.send_raw_transaction(tx) {
             Ok(_) => {
                 // Here the transaction could, potentially, have been in mempool before the current height.
                 // This shouldn't really matter though.
                 log::info!("Transaction successfully delivered: {}", tx.txid());
-                ConfirmationStatus::InMempoolSince(self.block_height)
+                ConfirmationStatus::InMempoolSince(self.get_height())
             }
             Err(JsonRpcError(RpcError(rpcerr))) => match rpcerr.code {
                 // Since we're pushing a raw transaction to the network we can face several rejections
@@ -122,10 +203,11 @@ impl Carrier {
                 }
             },
             Err(JsonRpcError(TransportError(_))) => {
-                // Connection refused, bitcoind is down.
+                // Connection refused, bitcoind is down. Penalties are time critical, so try the fallbacks (if any)
+                // before giving up until bitcoind is back.
                 log::error!("Connection lost with bitcoind, retrying request when possible");
                 self.flag_bitcoind_unreachable();
-                self.send_transaction(tx)
+                self.send_transaction_to_fallbacks(tx)?
             }
             Err(e) => {
                 // TODO: This may need finer catching.
@@ -134,9 +216,53 @@ impl Carrier {
             }
         };
 
-        self.issued_receipts.insert(tx.txid(), receipt);
+        // Rejections may be specific to the node policy, so give the fallbacks a chance. Transactions that cannot
+        // be deserialized will be rejected by any node though.
+        if let ConfirmationStatus::Rejected(code) = receipt {
+            if code != rpc_errors::RPC_DESERIALIZATION_ERROR {
+                if let Some(fallback_receipt) = self.send_transaction_to_fallbacks(tx) {
+                    receipt = fallback_receipt;
+                }
+            }
+        }
+
+        self.issued_receipts
+            .lock()
+            .unwrap()
+            .insert(tx.txid(), receipt);
+
+        Some(receipt)
+    }
+
+    /// Sends a [Transaction] to the Bitcoin network using the fallback clients, in order, until one of them accepts it.
+    ///
+    /// Returns the [ConfirmationStatus] of the first accepted broadcast, or [None] if all of them failed (or there
+    /// are no fallbacks).
+    fn send_transaction_to_fallbacks(&self, tx: &Transaction) -> Option<ConfirmationStatus> {
+        for (i, fallback_cli) in self.fallback_clis.iter().enumerate() {
+            log::info!(
+                "Pushing transaction to the network using fallback #{}: {}",
+                i,
+                tx.txid()
+            );
+            match fallback_cli.send_raw_transaction(tx) {
+                Ok(_) => {
+                    log::info!(
+                        "Transaction successfully delivered using fallback #{}: {}",
+                        i,
+                        tx.txid()
+                    );
+                    return Some(ConfirmationStatus::InMempoolSince(self.get_height()));
+                }
+                Err(e) => log::error!(
+                    "Transaction couldn't be broadcast using fallback #{}. {:?}",
+                    i,
+                    e
+                ),
+            }
+        }
 
-        receipt
+        None
     }
 
     /// Gets the block height at where a given [Transaction] was confirmed at (if any).
@@ -148,6 +274,76 @@ impl Carrier {
         }
     }
 
+    /// Checks whether a given output has already been spent in the chain. Returns [None] if it cannot be told.
+    ///
+    /// Notice outputs that are not in the chain (yet) are reported as spent, since `bitcoind` cannot tell them apart.
+    /// The check is opportunistic, so `bitcoind` being unreachable is not waited on.
+    pub(crate) fn is_output_spent(&self, outpoint: &OutPoint) -> Option<bool> {
+        if !self.is_bitcoind_reachable() {
+            return None;
+        }
+
+        match self
+            .bitcoin_cli
+            .get_tx_out(&outpoint.txid, outpoint.vout, Some(false))
+        {
+            Ok(txout) => Some(txout.is_none()),
+            Err(JsonRpcError(TransportError(_))) => {
+                // Connection refused, bitcoind is down.
+                log::error!("Connection lost with bitcoind, output cannot be checked");
+                self.flag_bitcoind_unreachable();
+                None
+            }
+            Err(e) => {
+                log::error!("Unexpected JSONRPCError when calling gettxout: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Checks whether a given transaction is in the chain. Returns [None] if it cannot be told.
+    ///
+    /// Works like [get_block_hash_for_tx](Self::get_block_hash_for_tx), but does not wait for `bitcoind` to be reachable.
+    pub(crate) fn is_in_chain(&self, txid: &Txid) -> Option<bool> {
+        if !self.is_bitcoind_reachable() {
+            return None;
+        }
+
+        match self.bitcoin_cli.get_raw_transaction_info(txid, None) {
+            Ok(tx_data) => Some(tx_data.blockhash.is_some()),
+            Err(JsonRpcError(RpcError(rpcerr)))
+                if rpcerr.code == rpc_errors::RPC_INVALID_ADDRESS_OR_KEY =>
+            {
+                Some(false)
+            }
+            Err(JsonRpcError(TransportError(_))) => {
+                // Connection refused, bitcoind is down.
+                log::error!("Connection lost with bitcoind, transaction cannot be checked");
+                self.flag_bitcoind_unreachable();
+                None
+            }
+            Err(e) => {
+                log::error!("Unexpected error when calling getrawtransaction: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Queries `bitcoind` for the fee rate (in sat/kw) a transaction needs to pay to confirm within `conf_target` blocks.
+    /// Returns [None] if no estimate is available.
+    ///
+    /// Estimates are advisory, so `bitcoind` being unreachable is not waited on.
+    pub(crate) fn estimate_fee_rate(&self, conf_target: u16) -> Option<u64> {
+        match self.bitcoin_cli.estimate_smart_fee(conf_target, None) {
+            // bitcoind reports fee rates per kvB, which is four times a kw
+            Ok(estimate) => estimate.fee_rate.map(|rate| rate.as_sat() / 4),
+            Err(e) => {
+                log::error!("Unexpected error when calling estimatesmartfee: {}", e);
+                None
+            }
+        }
+    }
+
     /// Queries the height of a given [Block](bitcoin::Block). Returns it if the block can be found. Returns [None] otherwise.
     fn get_block_height(&self, block_hash: &BlockHash) -> Option<u32> {
         self.hang_until_bitcoind_reachable();
@@ -218,6 +414,7 @@ impl Carrier {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::MutexGuard;
     use std::thread;
 
     use crate::test_utils::{
@@ -230,13 +427,10 @@ mod tests {
 
     impl Carrier {
         // Helper function to access issued_receipts in tests
-        pub(crate) fn get_issued_receipts(&mut self) -> &mut HashMap<Txid, ConfirmationStatus> {
-            &mut self.issued_receipts
-        }
-
-        // Helper function to access height in tests
-        pub(crate) fn get_height(&self) -> u32 {
-            self.block_height
+        pub(crate) fn get_issued_receipts(
+            &self,
+        ) -> MutexGuard<'_, HashMap<Txid, ConfirmationStatus>> {
+            self.issued_receipts.lock().unwrap()
         }
     }
 
@@ -248,20 +442,20 @@ mod tests {
         let start_height = START_HEIGHT as u32;
         start_server(bitcoind_mock);
 
-        let mut carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, start_height);
+        let carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, start_height);
 
         // Lets add some dummy data into the cache
         for i in 0..10 {
-            carrier.issued_receipts.insert(
+            carrier.issued_receipts.lock().unwrap().insert(
                 get_random_tx().txid(),
                 ConfirmationStatus::ConfirmedIn(start_height - i),
             );
         }
 
         // Check it empties on request
-        assert!(!carrier.issued_receipts.is_empty());
+        assert!(!carrier.issued_receipts.lock().unwrap().is_empty());
         carrier.clear_receipts();
-        assert!(carrier.issued_receipts.is_empty());
+        assert!(carrier.issued_receipts.lock().unwrap().is_empty());
     }
 
     #[test]
@@ -272,14 +466,50 @@ mod tests {
         let start_height = START_HEIGHT as u32;
         start_server(bitcoind_mock);
 
-        let mut carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, start_height);
+        let carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, start_height);
         let tx = consensus::deserialize(&Vec::from_hex(TX_HEX).unwrap()).unwrap();
         let r = carrier.send_transaction(&tx);
 
         assert_eq!(r, ConfirmationStatus::InMempoolSince(start_height));
 
         // Check the receipt is on the cache
-        assert_eq!(carrier.issued_receipts.get(&tx.txid()).unwrap(), &r);
+        assert_eq!(
+            carrier
+                .issued_receipts
+                .lock()
+                .unwrap()
+                .get(&tx.txid())
+                .unwrap(),
+            &r
+        );
+    }
+
+    #[test]
+    fn test_send_transaction_dry_run() {
+        // The mock rejects every transaction, so getting it accepted means bitcoind was never called
+        let bitcoind_mock = BitcoindMock::new(MockOptions::with_error(
+            rpc_errors::RPC_VERIFY_REJECTED as i64,
+        ));
+        let bitcoind_reachable = Arc::new((Mutex::new(true), Condvar::new()));
+        let bitcoin_cli = Arc::new(BitcoindClient::new(bitcoind_mock.url(), Auth::None).unwrap());
+        let start_height = START_HEIGHT as u32;
+        start_server(bitcoind_mock);
+
+        let carrier =
+            Carrier::new(bitcoin_cli, bitcoind_reachable, start_height).with_dry_run(true);
+        let tx = consensus::deserialize(&Vec::from_hex(TX_HEX).unwrap()).unwrap();
+        let r = carrier.send_transaction(&tx);
+
+        assert_eq!(r, ConfirmationStatus::InMempoolSince(start_height));
+        assert_eq!(
+            carrier
+                .issued_receipts
+                .lock()
+                .unwrap()
+                .get(&tx.txid())
+                .unwrap(),
+            &r
+        );
     }
 
     #[test]
@@ -292,7 +522,7 @@ mod tests {
         let start_height = START_HEIGHT as u32;
         start_server(bitcoind_mock);
 
-        let mut carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, start_height);
+        let carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, start_height);
         let tx = consensus::deserialize(&Vec::from_hex(TX_HEX).unwrap()).unwrap();
         let r = carrier.send_transaction(&tx);
 
@@ -302,7 +532,15 @@ mod tests {
         );
 
         // Check the receipt is on the cache
-        assert_eq!(carrier.issued_receipts.get(&tx.txid()).unwrap(), &r);
+        assert_eq!(
+            carrier
+                .issued_receipts
+                .lock()
+                .unwrap()
+                .get(&tx.txid())
+                .unwrap(),
+            &r
+        );
     }
 
     #[test]
@@ -314,7 +552,7 @@ mod tests {
         let start_height = START_HEIGHT as u32;
         start_server(bitcoind_mock);
 
-        let mut carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, start_height);
+        let carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, start_height);
         let tx = consensus::deserialize(&Vec::from_hex(TX_HEX).unwrap()).unwrap();
         let r = carrier.send_transaction(&tx);
 
@@ -324,7 +562,15 @@ mod tests {
         );
 
         // Check the receipt is on the cache
-        assert_eq!(carrier.issued_receipts.get(&tx.txid()).unwrap(), &r);
+        assert_eq!(
+            carrier
+                .issued_receipts
+                .lock()
+                .unwrap()
+                .get(&tx.txid())
+                .unwrap(),
+            &r
+        );
     }
 
     #[test]
@@ -339,14 +585,22 @@ mod tests {
         let start_height = START_HEIGHT as u32;
         start_server(bitcoind_mock);
 
-        let mut carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, start_height);
+        let carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, start_height);
         let tx = consensus::deserialize(&Vec::from_hex(TX_HEX).unwrap()).unwrap();
         let r = carrier.send_transaction(&tx);
 
         assert_eq!(r, ConfirmationStatus::ConfirmedIn(start_height));
 
         // Check the receipt is on the cache
-        assert_eq!(carrier.issued_receipts.get(&tx.txid()).unwrap(), &r);
+        assert_eq!(
+            carrier
+                .issued_receipts
+                .lock()
+                .unwrap()
+                .get(&tx.txid())
+                .unwrap(),
+            &r
+        );
     }
 
     #[test]
@@ -358,7 +612,7 @@ mod tests {
         let start_height = START_HEIGHT as u32;
         start_server(bitcoind_mock);
 
-        let mut carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, start_height);
+        let carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, start_height);
         let tx = consensus::deserialize(&Vec::from_hex(TX_HEX).unwrap()).unwrap();
         let r = carrier.send_transaction(&tx);
 
@@ -368,7 +622,15 @@ mod tests {
         );
 
         // Check the receipt is on the cache
-        assert_eq!(carrier.issued_receipts.get(&tx.txid()).unwrap(), &r);
+        assert_eq!(
+            carrier
+                .issued_receipts
+                .lock()
+                .unwrap()
+                .get(&tx.txid())
+                .unwrap(),
+            &r
+        );
     }
 
     #[test]
@@ -378,7 +640,7 @@ mod tests {
         let bitcoind_reachable = Arc::new((Mutex::new(false), Condvar::new()));
         let bitcoin_cli = Arc::new(BitcoindClient::new(bitcoind_mock.url(), Auth::None).unwrap());
         let start_height = START_HEIGHT as u32;
-        let mut carrier = Carrier::new(bitcoin_cli, bitcoind_reachable.clone(), start_height);
+        let carrier = Carrier::new(bitcoin_cli, bitcoind_reachable.clone(), start_height);
 
         let tx = consensus::deserialize(&Vec::from_hex(TX_HEX).unwrap()).unwrap();
         let delay = std::time::Duration::new(3, 0);
@@ -400,6 +662,111 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_try_send_transaction_unreachable() {
+        // If bitcoind is unreachable the transaction cannot be sent, but the Carrier does not wait for it either
+        let bitcoind_mock = BitcoindMock::new(MockOptions::empty());
+        let bitcoind_reachable = Arc::new((Mutex::new(false), Condvar::new()));
+        let bitcoin_cli = Arc::new(BitcoindClient::new(bitcoind_mock.url(), Auth::None).unwrap());
+        let start_height = START_HEIGHT as u32;
+        start_server(bitcoind_mock);
+
+        let carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, start_height);
+        let tx = consensus::deserialize(&Vec::from_hex(TX_HEX).unwrap()).unwrap();
+        assert_eq!(carrier.try_send_transaction(&tx), None);
+        assert!(carrier.get_issued_receipts().is_empty());
+
+        // Fallbacks are still tried though
+        let fallback_mock = BitcoindMock::new(MockOptions::empty());
+        let fallback_cli = Arc::new(BitcoindClient::new(fallback_mock.url(), Auth::None).unwrap());
+        start_server(fallback_mock);
+        let carrier = carrier.with_fallbacks(vec![fallback_cli]);
+        assert_eq!(
+            carrier.try_send_transaction(&tx),
+            Some(ConfirmationStatus::InMempoolSince(start_height))
+        );
+    }
+
+    #[test]
+    fn test_send_transaction_fallback() {
+        // The main client rejects the transaction but the fallback accepts it
+        let bitcoind_mock = BitcoindMock::new(MockOptions::with_error(
+            rpc_errors::RPC_VERIFY_REJECTED as i64,
+        ));
+        let fallback_mock = BitcoindMock::new(MockOptions::empty());
+        let bitcoind_reachable = Arc::new((Mutex::new(true), Condvar::new()));
+        let bitcoin_cli = Arc::new(BitcoindClient::new(bitcoind_mock.url(), Auth::None).unwrap());
+        let fallback_cli = Arc::new(BitcoindClient::new(fallback_mock.url(), Auth::None).unwrap());
+        let start_height = START_HEIGHT as u32;
+        start_server(bitcoind_mock);
+        start_server(fallback_mock);
+
+        let carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, start_height)
+            .with_fallbacks(vec![fallback_cli]);
+        let tx = consensus::deserialize(&Vec::from_hex(TX_HEX).unwrap()).unwrap();
+        let r = carrier.send_transaction(&tx);
+
+        assert_eq!(r, ConfirmationStatus::InMempoolSince(start_height));
+        assert_eq!(
+            carrier
+                .issued_receipts
+                .lock()
+                .unwrap()
+                .get(&tx.txid())
+                .unwrap(),
+            &r
+        );
+    }
+
+    #[test]
+    fn test_send_transaction_fallback_all_fail() {
+        // If every client rejects the transaction, the error from the main one is returned
+        let bitcoind_mock = BitcoindMock::new(MockOptions::with_error(
+            rpc_errors::RPC_VERIFY_REJECTED as i64,
+        ));
+        let fallback_mock =
+            BitcoindMock::new(MockOptions::with_error(rpc_errors::RPC_VERIFY_ERROR as i64));
+        let bitcoind_reachable = Arc::new((Mutex::new(true), Condvar::new()));
+        let bitcoin_cli = Arc::new(BitcoindClient::new(bitcoind_mock.url(), Auth::None).unwrap());
+        let fallback_cli = Arc::new(BitcoindClient::new(fallback_mock.url(), Auth::None).unwrap());
+        // A fallback that cannot be reached
+        let unreachable_cli =
+            Arc::new(BitcoindClient::new("http://127.0.0.1:1", Auth::None).unwrap());
+        let start_height = START_HEIGHT as u32;
+        start_server(bitcoind_mock);
+        start_server(fallback_mock);
+
+        let carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, start_height)
+            .with_fallbacks(vec![unreachable_cli, fallback_cli]);
+        let tx = consensus::deserialize(&Vec::from_hex(TX_HEX).unwrap()).unwrap();
+        let r = carrier.send_transaction(&tx);
+
+        assert_eq!(
+            r,
+            ConfirmationStatus::Rejected(rpc_errors::RPC_VERIFY_REJECTED)
+        );
+    }
+
+    #[test]
+    fn test_send_transaction_fallback_connection_error() {
+        // If the main client cannot be reached, the fallback is used straightaway
+        let fallback_mock = BitcoindMock::new(MockOptions::empty());
+        let bitcoind_reachable = Arc::new((Mutex::new(true), Condvar::new()));
+        let bitcoin_cli = Arc::new(BitcoindClient::new("http://127.0.0.1:1", Auth::None).unwrap());
+        let fallback_cli = Arc::new(BitcoindClient::new(fallback_mock.url(), Auth::None).unwrap());
+        let start_height = START_HEIGHT as u32;
+        start_server(fallback_mock);
+
+        let carrier = Carrier::new(bitcoin_cli, bitcoind_reachable.clone(), start_height)
+            .with_fallbacks(vec![fallback_cli]);
+        let tx = consensus::deserialize(&Vec::from_hex(TX_HEX).unwrap()).unwrap();
+        let r = carrier.send_transaction(&tx);
+
+        assert_eq!(r, ConfirmationStatus::InMempoolSince(start_height));
+        // The main bitcoind is still flagged as unreachable
+        assert!(!*bitcoind_reachable.0.lock().unwrap());
+    }
+
     #[test]
     fn test_get_tx_height_ok() {
         let target_height = 21;
@@ -488,4 +855,71 @@ mod tests {
         let carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, start_height);
         assert_eq!(carrier.get_block_hash_for_tx(&tx.txid()), None);
     }
+
+    #[test]
+    fn test_is_output_spent() {
+        let outpoint = OutPoint::new(get_random_tx().txid(), 0);
+
+        for (options, expected) in [
+            (MockOptions::with_txout_spent(true), Some(true)),
+            (MockOptions::with_txout_spent(false), Some(false)),
+            // Any error other than a connection error means the output cannot be checked
+            (MockOptions::empty(), None),
+        ] {
+            let bitcoind_mock = BitcoindMock::new(options);
+            let bitcoind_reachable = Arc::new((Mutex::new(true), Condvar::new()));
+            let bitcoin_cli =
+                Arc::new(BitcoindClient::new(bitcoind_mock.url(), Auth::None).unwrap());
+            start_server(bitcoind_mock);
+
+            let carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, START_HEIGHT as u32);
+            assert_eq!(carrier.is_output_spent(&outpoint), expected);
+        }
+    }
+
+    #[test]
+    fn test_is_output_spent_unreachable() {
+        // Connection errors are not retried, the output simply cannot be checked
+        let bitcoind_reachable = Arc::new((Mutex::new(true), Condvar::new()));
+        let bitcoin_cli = Arc::new(BitcoindClient::new("http://127.0.0.1:1", Auth::None).unwrap());
+        let carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, START_HEIGHT as u32);
+        let outpoint = OutPoint::new(get_random_tx().txid(), 0);
+
+        assert_eq!(carrier.is_output_spent(&outpoint), None);
+        assert!(!carrier.is_bitcoind_reachable());
+
+        // Further checks do not wait for bitcoind to be back either
+        assert_eq!(carrier.is_output_spent(&outpoint), None);
+    }
+
+    #[test]
+    fn test_is_in_chain() {
+        let bitcoind_reachable = Arc::new((Mutex::new(true), Condvar::new()));
+        let tx = consensus::deserialize::<Transaction>(&Vec::from_hex(TX_HEX).unwrap()).unwrap();
+
+        for (options, expected) in [
+            (
+                MockOptions::with_block(BlockHash::default(), START_HEIGHT),
+                Some(true),
+            ),
+            // Any error other than a connection error means the transaction cannot be checked
+            (MockOptions::empty(), None),
+        ] {
+            let bitcoind_mock = BitcoindMock::new(options);
+            let bitcoin_cli =
+                Arc::new(BitcoindClient::new(bitcoind_mock.url(), Auth::None).unwrap());
+            start_server(bitcoind_mock);
+
+            let carrier =
+                Carrier::new(bitcoin_cli, bitcoind_reachable.clone(), START_HEIGHT as u32);
+            assert_eq!(carrier.is_in_chain(&tx.txid()), expected);
+        }
+
+        // If bitcoind is unreachable the check is not waited on
+        let bitcoin_cli = Arc::new(BitcoindClient::new("http://127.0.0.1:1", Auth::None).unwrap());
+        let carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, START_HEIGHT as u32);
+        assert_eq!(carrier.is_in_chain(&tx.txid()), None);
+        assert!(!carrier.is_bitcoind_reachable());
+        assert_eq!(carrier.is_in_chain(&tx.txid()), None);
+    }
 }
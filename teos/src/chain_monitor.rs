@@ -3,16 +3,82 @@
 
 use std::ops::Deref;
 use std::sync::{Arc, Condvar, Mutex};
-use std::time;
+use std::time::{self, Instant};
 use tokio::time::timeout;
 use triggered::Listener;
 
+use futures_util::future::join_all;
 use lightning::chain;
-use lightning_block_sync::poll::{ChainTip, Poll, ValidatedBlockHeader};
-use lightning_block_sync::{BlockSourceErrorKind, Cache, SpvClient};
+use lightning_block_sync::poll::{ChainTip, Poll, Validate, ValidatedBlockHeader};
+use lightning_block_sync::{
+    BlockSource, BlockSourceErrorKind, BlockSourceResult, Cache, SpvClient,
+};
 
 use crate::dbm::DBM;
 
+/// Minimum time between polls, in seconds. Prevents a misconfigured tower from hammering `bitcoind`.
+pub const MIN_POLLING_DELTA: u16 = 5;
+
+/// Gets the headers of the blocks from `tip` (excluded) up to `best` (included), sorted by height.
+///
+/// Returns [None] if `best` does not build on top of `tip` (i.e. there has been a reorg).
+pub async fn headers_between<P: Poll>(
+    poller: &mut P,
+    tip: &ValidatedBlockHeader,
+    best: ValidatedBlockHeader,
+) -> BlockSourceResult<Option<Vec<ValidatedBlockHeader>>> {
+    let mut headers = Vec::new();
+    let mut current = best;
+    while current.height > tip.height {
+        let previous = poller.look_up_previous_header(&current).await?;
+        headers.push(current);
+        current = previous;
+    }
+
+    if current.header.block_hash() != tip.header.block_hash() {
+        return Ok(None);
+    }
+    headers.reverse();
+    Ok(Some(headers))
+}
+
+/// Connects the blocks of the given headers (sorted by height) to `listener`, fetching as many of them at a time as
+/// `sources` are provided.
+///
+/// Blocks are handed to the listener in order, no matter the order they are received in. `tip` is updated with every
+/// connected block, so it points to the last one if a block cannot be fetched and the rest are left unconnected.
+pub async fn connect_blocks<S, L>(
+    sources: &mut [S],
+    headers: &[ValidatedBlockHeader],
+    listener: L,
+    tip: &mut ValidatedBlockHeader,
+) -> BlockSourceResult<()>
+where
+    S: BlockSource,
+    L: Deref,
+    L::Target: chain::Listen,
+{
+    for chunk in headers.chunks(sources.len()) {
+        let blocks = join_all(chunk.iter().zip(sources.iter_mut()).map(
+            |(header, source)| async move {
+                source
+                    .get_block(&header.header.block_hash())
+                    .await?
+                    .validate(header.header.block_hash())
+            },
+        ))
+        .await;
+
+        for (header, block) in chunk.iter().zip(blocks) {
+            let block = block?;
+            chain::Listen::block_connected(&*listener, &block, header.height);
+            *tip = *header;
+        }
+    }
+
+    Ok(())
+}
+
 /// Component in charge of monitoring the chain for new blocks.
 ///
 /// Takes care of polling `bitcoind` for new tips and hand it to subscribers.
@@ -29,7 +95,7 @@ where
     /// The lat known block header by the [ChainMonitor].
     last_known_block_header: ValidatedBlockHeader,
     /// A [DBM] (database manager) instance. Used to persist block data into disk.
-    dbm: Arc<Mutex<DBM>>,
+    dbm: Arc<DBM>,
     /// The time between polls.
     polling_delta: time::Duration,
     /// A signal from the main thread indicating the tower is shuting down.
@@ -49,7 +115,7 @@ where
     pub async fn new(
         spv_client: SpvClient<'a, P, C, L>,
         last_known_block_header: ValidatedBlockHeader,
-        dbm: Arc<Mutex<DBM>>,
+        dbm: Arc<DBM>,
         polling_delta_sec: u16,
         shutdown_signal: Listener,
         bitcoind_reachable: Arc<(Mutex<bool>, Condvar)>,
@@ -76,8 +142,6 @@ where
                         log::debug!("Updating best tip: {}", new_best.header.block_hash());
                         self.last_known_block_header = new_best;
                         self.dbm
-                            .lock()
-                            .unwrap()
                             .store_last_known_block(&new_best.header.block_hash())
                             .unwrap();
                     }
@@ -111,20 +175,47 @@ where
         };
     }
 
+    /// Gets how long to wait until the next poll given when the current one started.
+    ///
+    /// The time spent processing the polled data is subtracted from [polling_delta](Self::polling_delta) so the tower
+    /// polls at a steady rate. If processing took longer than that, the next poll is performed straightaway.
+    fn time_until_next_poll(&self, poll_start: Instant) -> time::Duration {
+        self.polling_delta.saturating_sub(poll_start.elapsed())
+    }
+
     /// Monitors `bitcoind` polling the best chain tip every [polling_delta](Self::polling_delta).
     pub async fn monitor_chain(&mut self) {
         loop {
+            let poll_start = Instant::now();
             self.poll_best_tip().await;
-            // Sleep for self.polling_delta seconds or shutdown if the signal is received.
-            if timeout(self.polling_delta, self.shutdown_signal.clone())
-                .await
-                .is_ok()
+            // Sleep until the next poll is due or shutdown if the signal is received.
+            if timeout(
+                self.time_until_next_poll(poll_start),
+                self.shutdown_signal.clone(),
+            )
+            .await
+            .is_ok()
             {
                 log::debug!("Received shutting down signal. Shutting down");
                 break;
             }
         }
     }
+
+    /// Drains the [ChainMonitor] so no block data is lost on shutdown.
+    ///
+    /// The [ChainMonitor] is driven from a single task, so any block-processing cycle that was in progress when the shutdown
+    /// signal was received is completed by the time this can be called. No new tip is polled, so no more blocks are handed
+    /// to the listeners (and no more penalties are broadcast) once the tower is shutting down. Blocks found in the meantime
+    /// are processed on restart, resuming from the last known block, which is persisted here.
+    pub async fn drain(&mut self) {
+        log::debug!("Draining chain monitor");
+        let block_hash = self.last_known_block_header.header.block_hash();
+        if let Err(e) = self.dbm.store_last_known_block(&block_hash) {
+            log::error!("Couldn't persist the last known block. Error: {:?}", e);
+        }
+        log::debug!("Chain monitor drained. Last known block: {}", block_hash);
+    }
 }
 
 #[cfg(test)]
@@ -139,7 +230,15 @@ mod tests {
     use bitcoin::BlockHash;
     use lightning_block_sync::{poll::ChainPoller, SpvClient, UnboundedCache};
 
-    use crate::test_utils::{Blockchain, START_HEIGHT};
+    use teos_common::cryptography::{self, get_random_keypair};
+    use teos_common::UserId;
+
+    use crate::extended_appointment::UUID;
+    use crate::gatekeeper::Gatekeeper;
+    use crate::test_utils::{
+        create_responder, create_watcher, generate_dummy_appointment, get_random_tx, BitcoindMock,
+        Blockchain, MockOptions, DURATION, EXPIRY_DELTA, SLOTS, START_HEIGHT,
+    };
 
     pub(crate) struct DummyListener {
         pub connected_blocks: RefCell<HashSet<BlockHash>>,
@@ -169,12 +268,85 @@ mod tests {
         }
     }
 
+    /// A listener that triggers the shutdown signal as soon as it is handed a block, simulating a stop mid-poll.
+    struct ShutdownListener {
+        inner: DummyListener,
+        shutdown_trigger: triggered::Trigger,
+    }
+
+    impl chain::Listen for ShutdownListener {
+        fn block_connected(&self, block: &bitcoin::Block, height: u32) {
+            self.shutdown_trigger.trigger();
+            self.inner.block_connected(block, height);
+        }
+
+        fn block_disconnected(&self, header: &bitcoin::BlockHeader, height: u32) {
+            self.inner.block_disconnected(header, height);
+        }
+    }
+
+    /// A listener that takes some time to process every block it is handed.
+    struct SlowListener {
+        inner: DummyListener,
+        processing_time: time::Duration,
+    }
+
+    impl chain::Listen for SlowListener {
+        fn block_connected(&self, block: &bitcoin::Block, height: u32) {
+            thread::sleep(self.processing_time);
+            self.inner.block_connected(block, height);
+        }
+
+        fn block_disconnected(&self, header: &bitcoin::BlockHeader, height: u32) {
+            self.inner.block_disconnected(header, height);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_time_until_next_poll() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let old_tip = chain.at_height(START_HEIGHT - 1);
+        let processing_time = time::Duration::from_millis(500);
+
+        let dbm = Arc::new(DBM::in_memory().unwrap());
+        let (_, shutdown_signal) = triggered::trigger();
+        let listener = SlowListener {
+            inner: DummyListener::new(),
+            processing_time,
+        };
+
+        let poller = ChainPoller::new(&mut chain, Network::Bitcoin);
+        let cache = &mut UnboundedCache::new();
+        let spv_client = SpvClient::new(old_tip, poller, cache, &listener);
+        let bitcoind_reachable = Arc::new((Mutex::new(true), Condvar::new()));
+
+        let mut cm = ChainMonitor::new(
+            spv_client,
+            old_tip,
+            dbm,
+            1,
+            shutdown_signal,
+            bitcoind_reachable,
+        )
+        .await;
+
+        // The time spent processing the new block is taken from the wait until the next poll, not added to it
+        let poll_start = Instant::now();
+        cm.poll_best_tip().await;
+        assert_eq!(listener.inner.connected_blocks.borrow().len(), 1);
+        assert!(cm.time_until_next_poll(poll_start) <= cm.polling_delta - processing_time);
+
+        // If processing takes longer than the polling delta, the next poll is due straightaway
+        let poll_start = Instant::now() - cm.polling_delta - processing_time;
+        assert_eq!(cm.time_until_next_poll(poll_start), time::Duration::ZERO);
+    }
+
     #[tokio::test]
     async fn test_poll_best_tip_common() {
         let mut chain = Blockchain::default().with_height(START_HEIGHT);
         let tip = chain.tip();
 
-        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let dbm = Arc::new(DBM::in_memory().unwrap());
         let (_, shutdown_signal) = triggered::trigger();
         let listener = DummyListener::new();
 
@@ -198,7 +370,7 @@ mod tests {
         let new_tip = chain.tip();
         let old_tip = chain.at_height(START_HEIGHT - 1);
 
-        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let dbm = Arc::new(DBM::in_memory().unwrap());
         let (_, shutdown_signal) = triggered::trigger();
         let listener = DummyListener::new();
 
@@ -221,7 +393,7 @@ mod tests {
         cm.poll_best_tip().await;
         assert_eq!(cm.last_known_block_header, new_tip);
         assert_eq!(
-            cm.dbm.lock().unwrap().load_last_known_block().unwrap(),
+            cm.dbm.load_last_known_block().unwrap(),
             new_tip.deref().header.block_hash()
         );
         assert!(listener
@@ -237,7 +409,7 @@ mod tests {
         let best_tip = chain.tip();
         chain.disconnect_tip();
 
-        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let dbm = Arc::new(DBM::in_memory().unwrap());
         let (_, shutdown_signal) = triggered::trigger();
         let listener = DummyListener::new();
 
@@ -259,10 +431,7 @@ mod tests {
         // If a new (worse, just one) block gets mined, nothing gets connected nor disconnected
         cm.poll_best_tip().await;
         assert_eq!(cm.last_known_block_header, best_tip);
-        assert!(matches!(
-            cm.dbm.lock().unwrap().load_last_known_block(),
-            Err { .. }
-        ));
+        assert!(matches!(cm.dbm.load_last_known_block(), Err { .. }));
         assert!(listener.connected_blocks.borrow().is_empty());
         assert!(listener.disconnected_blocks.borrow().is_empty());
     }
@@ -279,7 +448,7 @@ mod tests {
 
         let new_best = chain.tip();
 
-        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let dbm = Arc::new(DBM::in_memory().unwrap());
         let (_, shutdown_signal) = triggered::trigger();
         let listener = DummyListener::new();
 
@@ -302,7 +471,7 @@ mod tests {
         cm.poll_best_tip().await;
         assert_eq!(cm.last_known_block_header, new_best);
         assert_eq!(
-            cm.dbm.lock().unwrap().load_last_known_block().unwrap(),
+            cm.dbm.load_last_known_block().unwrap(),
             new_best.deref().header.block_hash()
         );
         assert_eq!(*listener.connected_blocks.borrow(), new_blocks);
@@ -318,7 +487,7 @@ mod tests {
         let chain_offline = chain.unreachable.clone();
         let tip = chain.tip();
 
-        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let dbm = Arc::new(DBM::in_memory().unwrap());
         let (_, shutdown_signal) = triggered::trigger();
         let listener = DummyListener::new();
 
@@ -359,4 +528,268 @@ mod tests {
         // This would hang if the cm didn't notify their subscribers about the bitcoind status, so it serves as out assert.
         t.join().unwrap();
     }
+
+    #[tokio::test]
+    async fn test_monitor_chain_shutdown_mid_poll() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let new_tip = chain.tip();
+        let old_tip = chain.at_height(START_HEIGHT - 3);
+        let new_blocks = (START_HEIGHT - 2..=START_HEIGHT)
+            .map(|h| chain.at_height(h).deref().header.block_hash())
+            .collect::<HashSet<BlockHash>>();
+
+        let dbm = Arc::new(DBM::in_memory().unwrap());
+        let (shutdown_trigger, shutdown_signal) = triggered::trigger();
+        let listener = ShutdownListener {
+            inner: DummyListener::new(),
+            shutdown_trigger,
+        };
+
+        let poller = ChainPoller::new(&mut chain, Network::Bitcoin);
+        let cache = &mut UnboundedCache::new();
+        let spv_client = SpvClient::new(old_tip, poller, cache, &listener);
+        let bitcoind_reachable = Arc::new((Mutex::new(true), Condvar::new()));
+
+        let mut cm = ChainMonitor::new(
+            spv_client,
+            old_tip,
+            dbm,
+            1,
+            shutdown_signal,
+            bitcoind_reachable,
+        )
+        .await;
+
+        // The shutdown signal is received while the first block is being processed. The ongoing cycle must be completed
+        // (all blocks handed to the listeners and the tip persisted) before monitor_chain returns.
+        cm.monitor_chain().await;
+        assert_eq!(*listener.inner.connected_blocks.borrow(), new_blocks);
+        assert_eq!(cm.last_known_block_header, new_tip);
+        assert_eq!(
+            cm.dbm.load_last_known_block().unwrap(),
+            new_tip.deref().header.block_hash()
+        );
+
+        // Draining once everything has been processed leaves the state untouched
+        cm.drain().await;
+        assert_eq!(*listener.inner.connected_blocks.borrow(), new_blocks);
+        assert_eq!(
+            cm.dbm.load_last_known_block().unwrap(),
+            new_tip.deref().header.block_hash()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_drain() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let new_tip = chain.tip();
+        let old_tip = chain.at_height(START_HEIGHT - 1);
+
+        let dbm = Arc::new(DBM::in_memory().unwrap());
+        let (shutdown_trigger, shutdown_signal) = triggered::trigger();
+        let listener = DummyListener::new();
+
+        let poller = ChainPoller::new(&mut chain, Network::Bitcoin);
+        let cache = &mut UnboundedCache::new();
+        let spv_client = SpvClient::new(old_tip, poller, cache, &listener);
+        let bitcoind_reachable = Arc::new((Mutex::new(true), Condvar::new()));
+
+        let mut cm = ChainMonitor::new(
+            spv_client,
+            old_tip,
+            dbm,
+            1,
+            shutdown_signal,
+            bitcoind_reachable,
+        )
+        .await;
+
+        // Blocks found after the shutdown signal has been received are not processed when draining, they are left
+        // for the next run. The last processed block is persisted instead
+        shutdown_trigger.trigger();
+        cm.drain().await;
+        assert!(listener.connected_blocks.borrow().is_empty());
+        assert_eq!(cm.last_known_block_header, old_tip);
+        assert_ne!(old_tip, new_tip);
+        assert_eq!(
+            cm.dbm.load_last_known_block().unwrap(),
+            old_tip.deref().header.block_hash()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_drain_persists_data() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let old_tip = chain.tip();
+
+        let dbm = Arc::new(DBM::in_memory().unwrap());
+        let gk = Arc::new(Gatekeeper::new(
+            chain.get_block_count(),
+            SLOTS,
+            DURATION,
+            EXPIRY_DELTA,
+            0,
+            dbm.clone(),
+        ));
+        let bitcoind_mock = BitcoindMock::new(MockOptions::empty());
+        let responder = create_responder(old_tip, gk.clone(), dbm.clone(), bitcoind_mock.url());
+        let watcher = create_watcher(
+            &mut chain,
+            Arc::new(responder),
+            gk,
+            bitcoind_mock,
+            dbm.clone(),
+        )
+        .await;
+
+        // Add two appointments, one of them triggered by the next block
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        watcher.register(user_id, None).unwrap();
+        let dispute_tx = get_random_tx();
+        let mut uuids = Vec::new();
+        for dispute_txid in [Some(dispute_tx.txid()), None] {
+            let appointment = generate_dummy_appointment(dispute_txid.as_ref()).inner;
+            let signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+            watcher
+                .add_appointment(appointment.clone(), signature)
+                .unwrap();
+            uuids.push(UUID::new(appointment.locator, user_id));
+        }
+        let new_tip = chain.generate(Some(vec![dispute_tx])).block_hash();
+
+        let (shutdown_trigger, shutdown_signal) = triggered::trigger();
+        let shutdown_listener = ShutdownListener {
+            inner: DummyListener::new(),
+            shutdown_trigger,
+        };
+        let listener = &(&shutdown_listener, &watcher);
+        let poller = ChainPoller::new(&mut chain, Network::Bitcoin);
+        let cache = &mut UnboundedCache::new();
+        let spv_client = SpvClient::new(old_tip, poller, cache, listener);
+        let bitcoind_reachable = Arc::new((Mutex::new(true), Condvar::new()));
+
+        let mut cm = ChainMonitor::new(
+            spv_client,
+            old_tip,
+            dbm.clone(),
+            1,
+            shutdown_signal,
+            bitcoind_reachable,
+        )
+        .await;
+
+        // Stopping mid-poll and draining leaves everything on disk: the triggered appointment with its tracker, the
+        // pending one, and the block they were processed up to
+        cm.monitor_chain().await;
+        cm.drain().await;
+
+        assert!(dbm.load_appointment(uuids[0]).is_ok());
+        assert!(dbm.load_tracker(uuids[0]).is_ok());
+        assert!(dbm.load_appointment(uuids[1]).is_ok());
+        assert!(dbm.load_tracker(uuids[1]).is_err());
+        assert_eq!(dbm.load_last_known_block().unwrap(), new_tip);
+    }
+
+    /// A listener that records the blocks it is handed, in order.
+    struct OrderedListener {
+        connected_blocks: RefCell<Vec<(BlockHash, u32)>>,
+    }
+
+    impl chain::Listen for OrderedListener {
+        fn block_connected(&self, block: &bitcoin::Block, height: u32) {
+            self.connected_blocks
+                .borrow_mut()
+                .push((block.block_hash(), height));
+        }
+
+        fn block_disconnected(&self, _: &bitcoin::BlockHeader, _: u32) {}
+    }
+
+    #[tokio::test]
+    async fn test_connect_blocks() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let old_tip = chain.at_height(10);
+        let expected: Vec<(BlockHash, u32)> = (11..=START_HEIGHT)
+            .map(|h| (chain.blocks[h].block_hash(), h as u32))
+            .collect();
+
+        let best = chain.tip();
+        let mut poller = ChainPoller::new(&mut chain, Network::Bitcoin);
+        let headers = headers_between(&mut poller, &old_tip, best)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(headers.len(), START_HEIGHT - 10);
+
+        // Blocks are connected in order no matter how many are fetched at a time, matching the serial path
+        for concurrency in [1, 4, 7] {
+            let listener = OrderedListener {
+                connected_blocks: RefCell::new(Vec::new()),
+            };
+            let mut sources = vec![chain.clone(); concurrency];
+            let mut tip = old_tip;
+            connect_blocks(&mut sources, &headers, &listener, &mut tip)
+                .await
+                .unwrap();
+
+            assert_eq!(*listener.connected_blocks.borrow(), expected);
+            assert_eq!(tip, best);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_blocks_missing_block() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let old_tip = chain.at_height(10);
+        let best = chain.tip();
+        let headers = headers_between(
+            &mut ChainPoller::new(&mut chain, Network::Bitcoin),
+            &old_tip,
+            best,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        // Blocks from height 50 onwards cannot be fetched, so only the ones before are connected
+        let listener = OrderedListener {
+            connected_blocks: RefCell::new(Vec::new()),
+        };
+        let mut sources = vec![chain.clone().without_blocks(50..); 4];
+        let mut tip = old_tip;
+        assert!(connect_blocks(&mut sources, &headers, &listener, &mut tip)
+            .await
+            .is_err());
+
+        let connected = listener.connected_blocks.borrow();
+        assert_eq!(connected.len(), 50 - 11);
+        assert_eq!(connected.last().unwrap().1, 49);
+        assert_eq!(tip, chain.at_height(49));
+    }
+
+    #[tokio::test]
+    async fn test_headers_between_reorg() {
+        let chain = Blockchain::default().with_height(START_HEIGHT);
+        let old_tip = chain.at_height(START_HEIGHT - 5);
+
+        // The best chain forks before our tip, so there is nothing to connect on top of it
+        let mut fork = chain.fork_at_height(START_HEIGHT - 10);
+        let best = fork.tip();
+        let mut poller = ChainPoller::new(&mut fork, Network::Bitcoin);
+        assert!(headers_between(&mut poller, &old_tip, best)
+            .await
+            .unwrap()
+            .is_none());
+
+        // Nothing to fetch if we are already at the tip
+        let mut chain = chain;
+        let tip = chain.tip();
+        let mut poller = ChainPoller::new(&mut chain, Network::Bitcoin);
+        assert!(headers_between(&mut poller, &tip, tip)
+            .await
+            .unwrap()
+            .unwrap()
+            .is_empty());
+    }
 }
@@ -1,3 +1,6 @@
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::secp256k1::SecretKey;
+use bitcoin::Txid;
 use serde_json::to_string_pretty as pretty_json;
 use std::fs;
 use std::str::FromStr;
@@ -8,6 +11,8 @@ use teos::cli_config::{Command, Config, Opt};
 use teos::config;
 use teos::protos as msgs;
 use teos::protos::private_tower_services_client::PrivateTowerServicesClient;
+use teos_common::appointment::{AppointmentStatus, Locator};
+use teos_common::cryptography;
 use teos_common::UserId;
 
 #[tokio::main]
@@ -23,31 +28,107 @@ async fn main() {
 
     let command = opt.command.clone();
 
+    // Commands that can be run locally do not need a connection to the tower
+    if let Command::ComputeLocator(data) = command {
+        match Locator::from_txid_hex(&data.txid) {
+            Ok(locator) => println!(
+                "{}",
+                pretty_json(&serde_json::json!({ "locator": locator.to_string() })).unwrap()
+            ),
+            Err(e) => println!("{}", e),
+        }
+        return;
+    }
+
     // Load conf (from file or defaults) and patch it with the command line parameters received (if any)
     let mut conf = config::from_file::<Config>(path.join("teos.toml"));
     conf.patch_with_options(opt);
 
     // Create gRPC client and send request
     let mut client =
-        PrivateTowerServicesClient::connect(format!("http://{}:{}", conf.rpc_bind, conf.rpc_port))
-            .await
-            .unwrap_or_else(|e| {
-                eprintln!("Cannot connect to the tower. Connection refused");
-                if conf.debug {
-                    eprintln!("{:?}", e);
-                }
-                std::process::exit(1);
-            });
+        PrivateTowerServicesClient::new(conf.connect(&path).await.unwrap_or_else(|e| {
+            eprintln!("Cannot connect to the tower. Connection refused");
+            if conf.debug {
+                eprintln!("{:?}", e);
+            }
+            std::process::exit(1);
+        }));
 
     match command {
         Command::GetAllAppointments => {
             let appointments = client.get_all_appointments(Request::new(())).await.unwrap();
             println!("{}", pretty_json(&appointments.into_inner()).unwrap());
         }
+        Command::GetAppointments(data) => {
+            let user_id = match data.user_id.map(|id| UserId::from_str(&id)).transpose() {
+                Ok(user_id) => user_id.map(|id| id.serialize()).unwrap_or_default(),
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            };
+            let status = match data
+                .status
+                .map(|s| AppointmentStatus::from_str(&s))
+                .transpose()
+            {
+                Ok(Some(AppointmentStatus::NotFound)) => {
+                    println!("Status must be either being_watched or dispute_responded");
+                    return;
+                }
+                Ok(status) => status.map(|s| s as i32).unwrap_or_default(),
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            };
+
+            match client
+                .get_appointments(Request::new(msgs::GetAppointmentsRequest {
+                    user_id,
+                    status,
+                    limit: data.limit,
+                    offset: data.offset,
+                }))
+                .await
+            {
+                Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+                Err(status) => println!("{}", status.message()),
+            }
+        }
+        Command::GetAppointmentByTxid(data) => match Txid::from_hex(&data.txid) {
+            Ok(txid) => {
+                match client
+                    .get_appointment_by_txid(Request::new(msgs::GetAppointmentByTxidRequest {
+                        dispute_txid: txid.to_vec(),
+                    }))
+                    .await
+                {
+                    Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+                    Err(status) => println!("{}", status.message()),
+                }
+            }
+            Err(_) => println!("Txid must be a 32-byte hex encoded value"),
+        },
         Command::GetTowerInfo => {
             let info = client.get_tower_info(Request::new(())).await.unwrap();
             println!("{}", pretty_json(&info.into_inner()).unwrap())
         }
+        Command::GetHealth => {
+            let health = client.get_health(Request::new(())).await.unwrap();
+            println!("{}", pretty_json(&health.into_inner()).unwrap())
+        }
+        Command::GetStats => match client.get_stats(Request::new(())).await {
+            Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+            Err(status) => println!("{}", status.message()),
+        },
+        Command::GetConfig => {
+            let response = client.get_config(Request::new(())).await.unwrap();
+            match serde_json::from_str::<serde_json::Value>(&response.into_inner().config) {
+                Ok(config) => println!("{}", pretty_json(&config).unwrap()),
+                Err(e) => println!("Cannot parse the tower config: {}", e),
+            }
+        }
         Command::GetUsers => {
             let users = client.get_users(Request::new(())).await.unwrap();
             println!("{}", pretty_json(&users.into_inner()).unwrap());
@@ -70,9 +151,156 @@ async fn main() {
                 Err(e) => println!("{}", e),
             };
         }
-        Command::Stop => {
-            println!("Shutting down tower");
-            client.stop(Request::new(())).await.unwrap();
+        Command::GetUserSubscriptionHistory(data) => {
+            match UserId::from_str(&data.user_id) {
+                Ok(user_id) => {
+                    match client
+                        .get_user_subscription_history(Request::new(msgs::GetUserRequest {
+                            user_id: user_id.serialize(),
+                        }))
+                        .await
+                    {
+                        Ok(response) => {
+                            println!("{}", pretty_json(&response.into_inner()).unwrap())
+                        }
+                        Err(status) => println!("{}", status.message()),
+                    }
+                }
+                Err(e) => println!("{}", e),
+            };
+        }
+        Command::BanUser(data) => {
+            match UserId::from_str(&data.user_id) {
+                Ok(user_id) => {
+                    match client
+                        .ban_user(Request::new(msgs::BanUserRequest {
+                            user_id: user_id.serialize(),
+                            drop_appointments: data.drop_appointments,
+                        }))
+                        .await
+                    {
+                        Ok(response) => {
+                            println!("{}", pretty_json(&response.into_inner()).unwrap())
+                        }
+                        Err(status) => println!("{}", status.message()),
+                    }
+                }
+                Err(e) => println!("{}", e),
+            };
+        }
+        Command::UnbanUser(data) => {
+            match UserId::from_str(&data.user_id) {
+                Ok(user_id) => {
+                    match client
+                        .unban_user(Request::new(msgs::GetUserRequest {
+                            user_id: user_id.serialize(),
+                        }))
+                        .await
+                    {
+                        Ok(_) => println!("User unbanned"),
+                        Err(status) => println!("{}", status.message()),
+                    }
+                }
+                Err(e) => println!("{}", e),
+            };
+        }
+        Command::Backup(data) => match client.export_data(Request::new(())).await {
+            Ok(response) => {
+                let mut stream = response.into_inner();
+                let mut exported = Vec::new();
+                loop {
+                    match stream.message().await {
+                        Ok(Some(chunk)) => exported.extend(chunk.data),
+                        Ok(None) => break,
+                        Err(status) => {
+                            println!("{}", status.message());
+                            return;
+                        }
+                    }
+                }
+
+                match fs::write(&data.out, exported) {
+                    Ok(_) => println!("Tower data exported to {}", data.out),
+                    Err(e) => println!("Cannot write {}: {}", data.out, e),
+                }
+            }
+            Err(status) => println!("{}", status.message()),
+        },
+        Command::Restore(data) => match fs::read(&data.input) {
+            Ok(exported) => {
+                match client
+                    .import_data(Request::new(msgs::ImportDataRequest { data: exported }))
+                    .await
+                {
+                    Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+                    Err(status) => println!("{}", status.message()),
+                }
+            }
+            Err(e) => println!("Cannot read {}: {}", data.input, e),
+        },
+        Command::RotateKey(data) => {
+            match client
+                .rotate_key(Request::new(msgs::RotateKeyRequest {
+                    grace_period: data.grace_period,
+                    reissue_receipts: data.reissue_receipts,
+                }))
+                .await
+            {
+                Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+                Err(status) => println!("{}", status.message()),
+            }
+        }
+        Command::BroadcastPenalty(data) => match hex::decode(&data.uuid) {
+            Ok(uuid) => {
+                match client
+                    .broadcast_penalty(Request::new(msgs::BroadcastPenaltyRequest { uuid }))
+                    .await
+                {
+                    Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+                    Err(status) => println!("{}", status.message()),
+                }
+            }
+            Err(_) => println!("uuid must be hex encoded"),
+        },
+        Command::Stop(data) => {
+            let signature = match data.key_file {
+                Some(key_file) => {
+                    let sk = match fs::read_to_string(&key_file)
+                        .map_err(|e| e.to_string())
+                        .and_then(|x| SecretKey::from_str(x.trim()).map_err(|e| e.to_string()))
+                    {
+                        Ok(sk) => sk,
+                        Err(e) => {
+                            println!("Cannot load the operator key from {}: {}", key_file, e);
+                            return;
+                        }
+                    };
+                    let challenge = match client.get_stop_challenge(Request::new(())).await {
+                        Ok(response) => response.into_inner().challenge,
+                        Err(status) => {
+                            println!("{}", status.message());
+                            return;
+                        }
+                    };
+                    match cryptography::sign(challenge.as_bytes(), &sk) {
+                        Ok(signature) => signature,
+                        Err(e) => {
+                            println!("Cannot sign the stop challenge: {}", e);
+                            return;
+                        }
+                    }
+                }
+                None => String::new(),
+            };
+
+            match client
+                .stop(Request::new(msgs::StopRequest { signature }))
+                .await
+            {
+                Ok(_) => println!("Shutting down tower"),
+                Err(status) => println!("{}", status.message()),
+            }
         }
+        Command::ComputeLocator(_) => unreachable!("Handled before connecting to the tower"),
     };
 }
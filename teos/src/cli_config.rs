@@ -1,21 +1,51 @@
 //! Logic related to the tower CLI configuration and command line parameter parsing.
 
 use serde::Deserialize;
+use std::path::Path;
 use structopt::StructOpt;
+use tonic::transport::{Channel, Endpoint};
+
+use crate::api::uds;
 
 #[derive(Debug, StructOpt, Clone)]
 #[structopt(rename_all = "snake_case")]
 pub enum Command {
     /// Gets information about all appointments stored in the tower
     GetAllAppointments,
+    /// Gets a page of the appointments stored in the tower, optionally filtered by user and status
+    GetAppointments(GetAppointmentsData),
+    /// Gets the appointments (from any user) triggered by a given dispute transaction
+    GetAppointmentByTxid(GetAppointmentByTxidData),
     /// Gets generic information about the tower, like tower id and aggregate data on users and appointments
     GetTowerInfo,
+    /// Gets the health status of the tower: bitcoind and database reachability, last known block and task failures
+    GetHealth,
+    /// Gets aggregate stats about the tower: users, appointments, slots and database size
+    GetStats,
+    /// Gets the effective configuration of the tower (config file patched with command line options), secrets redacted
+    GetConfig,
     /// Gets an array with the user ids of all the users registered to the tower
     GetUsers,
     /// Gets information about a specific user
     GetUser(GetUserData),
+    /// Gets the subscription history (registrations, renewals and expiries) of a specific user
+    GetUserSubscriptionHistory(GetUserData),
+    /// Bans a user from the tower, so all their requests are rejected
+    BanUser(BanUserData),
+    /// Lifts the ban of a given user
+    UnbanUser(GetUserData),
+    /// Exports all the data the tower holds about its users to a (versioned) JSON file
+    Backup(BackupData),
+    /// Imports the data from a file created by `backup` into a fresh tower
+    Restore(RestoreData),
+    /// Replaces the tower signing key with a fresh one. Signatures made with the old key are honoured for a grace period
+    RotateKey(RotateKeyData),
+    /// Sends the penalty transaction of a given tracker to the network again
+    BroadcastPenalty(BroadcastPenaltyData),
     /// Requests a graceful shutdown of the tower
-    Stop,
+    Stop(StopData),
+    /// Computes the locator of a given dispute txid. Does not require the tower to be running
+    ComputeLocator(ComputeLocatorData),
 }
 
 #[derive(Debug, StructOpt, Clone)]
@@ -25,6 +55,89 @@ pub struct GetUserData {
     pub user_id: String,
 }
 
+#[derive(Debug, StructOpt, Clone)]
+#[structopt(rename_all = "snake_case")]
+pub struct BanUserData {
+    /// The user identifier (33-byte compressed public key).
+    pub user_id: String,
+    /// Delete the appointments being watched for the user.
+    #[structopt(long)]
+    pub drop_appointments: bool,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+#[structopt(rename_all = "snake_case")]
+pub struct GetAppointmentsData {
+    /// Only return the appointments of this user (33-byte compressed public key).
+    #[structopt(long)]
+    pub user_id: Option<String>,
+    /// Only return the appointments with this status (being_watched or dispute_responded).
+    #[structopt(long)]
+    pub status: Option<String>,
+    /// Maximum number of appointments to return. Zero means no limit.
+    #[structopt(long, default_value = "0")]
+    pub limit: u32,
+    /// Number of appointments to skip.
+    #[structopt(long, default_value = "0")]
+    pub offset: u32,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+#[structopt(rename_all = "lowercase")]
+pub struct GetAppointmentByTxidData {
+    /// The dispute transaction id (32-byte hex encoded).
+    pub txid: String,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+#[structopt(rename_all = "lowercase")]
+pub struct BackupData {
+    /// Path of the file the data will be exported to.
+    #[structopt(long)]
+    pub out: String,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+#[structopt(rename_all = "lowercase")]
+pub struct RestoreData {
+    /// Path of the file the data will be imported from.
+    #[structopt(long = "in")]
+    pub input: String,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+#[structopt(rename_all = "snake_case")]
+pub struct RotateKeyData {
+    /// Number of blocks signatures made with the old key are still honoured for.
+    #[structopt(long, default_value = "144")]
+    pub grace_period: u32,
+    /// Issue registration receipts signed with the new key for all the users with an active subscription.
+    #[structopt(long)]
+    pub reissue_receipts: bool,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+#[structopt(rename_all = "snake_case")]
+pub struct StopData {
+    /// Path to a file holding the (hex encoded) operator secret key. Required if the tower only accepts signed stops.
+    #[structopt(long)]
+    pub key_file: Option<String>,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+#[structopt(rename_all = "lowercase")]
+pub struct BroadcastPenaltyData {
+    /// The appointment identifier (20-byte hex encoded), as returned by get_user.
+    pub uuid: String,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+#[structopt(rename_all = "lowercase")]
+pub struct ComputeLocatorData {
+    /// The dispute transaction id (32-byte hex encoded).
+    pub txid: String,
+}
+
 /// Holds all the command line options and commands.
 #[derive(StructOpt, Debug)]
 #[structopt(rename_all = "lowercase")]
@@ -42,6 +155,10 @@ pub struct Opt {
     #[structopt(long)]
     pub rpc_port: Option<u16>,
 
+    /// Unix domain socket teos RPC server is bind to, used instead of rpc_bind:rpc_port if set [default: none]
+    #[structopt(long)]
+    pub rpc_socket_path: Option<String>,
+
     /// Specify data directory
     #[structopt(long, default_value = "~/.teos")]
     pub data_dir: String,
@@ -66,6 +183,7 @@ pub struct Opt {
 pub struct Config {
     pub rpc_bind: String,
     pub rpc_port: u16,
+    pub rpc_socket_path: String,
     pub debug: bool,
 }
 
@@ -78,9 +196,26 @@ impl Config {
         if options.rpc_port.is_some() {
             self.rpc_port = options.rpc_port.unwrap();
         }
+        if let Some(rpc_socket_path) = options.rpc_socket_path {
+            self.rpc_socket_path = rpc_socket_path;
+        }
 
         self.debug |= options.debug;
     }
+
+    /// Creates a gRPC channel to the tower private API.
+    ///
+    /// The Unix domain socket at `rpc_socket_path` is used if set (relative paths are resolved against `data_dir`,
+    /// like the tower does), otherwise the API is reached over TCP at `rpc_bind`:`rpc_port`.
+    pub async fn connect(&self, data_dir: &Path) -> Result<Channel, tonic::transport::Error> {
+        if self.rpc_socket_path.is_empty() {
+            Endpoint::new(format!("http://{}:{}", self.rpc_bind, self.rpc_port))?
+                .connect()
+                .await
+        } else {
+            uds::connect(data_dir.join(&self.rpc_socket_path)).await
+        }
+    }
 }
 
 impl Default for Config {
@@ -94,6 +229,7 @@ impl Default for Config {
         Self {
             rpc_bind: "localhost".into(),
             rpc_port: 8814,
+            rpc_socket_path: String::new(),
             debug: false,
         }
     }
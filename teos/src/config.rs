@@ -1,12 +1,18 @@
 //! Logic related to the tower configuration and command line parameter parsing.
 
 use bitcoin::network::constants::Network;
-use serde::Deserialize;
+use bitcoin::secp256k1::PublicKey;
+use serde::{Deserialize, Serialize, Serializer};
 use std;
 use std::path::PathBuf;
 use std::str::FromStr;
 use structopt::StructOpt;
 
+use teos_common::constants::IRREVOCABLY_RESOLVED;
+
+use crate::chain_monitor::MIN_POLLING_DELTA;
+use crate::logging::LogFormat;
+
 pub fn data_dir_absolute_path(data_dir: String) -> PathBuf {
     if let Some(a) = data_dir.strip_prefix('~') {
         if let Some(b) = data_dir.strip_prefix("~/") {
@@ -52,6 +58,24 @@ impl std::fmt::Display for ConfigError {
 
 impl std::error::Error for ConfigError {}
 
+/// Placeholder used in place of secrets when serializing the config.
+pub const REDACTED: &str = "<redacted>";
+
+/// Serializes a secret config field as [REDACTED], so it never leaves the tower.
+fn redact<S: Serializer>(_: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(REDACTED)
+}
+
+/// Connection details of an additional `bitcoind` node used to broadcast transactions if the main one fails to.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RpcFallback {
+    pub connect: String,
+    pub port: u16,
+    pub user: String,
+    #[serde(serialize_with = "redact")]
+    pub password: String,
+}
+
 /// Holds all the command line options.
 #[derive(StructOpt, Debug, Clone)]
 #[structopt(rename_all = "lowercase")]
@@ -124,43 +148,85 @@ pub struct Opt {
 /// - Defaults
 /// - Configuration file
 /// - Command line options
-#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+///
+/// Serializing a [Config] redacts its secrets (check [REDACTED]).
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 #[serde(default)]
 pub struct Config {
     // API
     pub api_bind: String,
     pub api_port: u16,
+    pub max_batch_size: u16,
+    pub http_compression: bool,
+    pub events_enabled: bool,
+    pub rate_limit_refill: u32,
+    pub rate_limit_burst: u32,
+    pub rate_limit_shared_refill: u32,
+    pub rate_limit_shared_burst: u32,
 
     // RPC
     pub rpc_bind: String,
     pub rpc_port: u16,
+    pub rpc_socket_path: String,
+    pub rpc_keepalive_interval: u32,
+    pub rpc_keepalive_timeout: u32,
+    pub rpc_max_concurrent_streams: u32,
 
     // Bitcoind
     pub btc_network: String,
     pub btc_rpc_user: String,
+    #[serde(serialize_with = "redact")]
     pub btc_rpc_password: String,
     pub btc_rpc_connect: String,
     pub btc_rpc_port: u16,
+    pub btc_rpc_fallbacks: Vec<RpcFallback>,
+    pub btc_rpc_connect_retries: u32,
+    pub btc_rpc_connect_backoff: u32,
 
     // Flags
     pub debug: bool,
+    pub log_format: String,
     pub overwrite_key: bool,
+    pub allow_unsupported_bitcoind: bool,
+    pub dry_run: bool,
 
     // General
     pub subscription_slots: u32,
     pub subscription_duration: u32,
     pub expiry_delta: u32,
+    pub max_appointments_per_user: u32,
+    pub max_user_slots: u32,
+    pub expiry_warning_window: u32,
+    pub max_appointment_size: usize,
     pub min_to_self_delay: u16,
     pub polling_delta: u16,
+    pub bootstrap_concurrency: u16,
+    pub retention_blocks: u32,
+    pub check_spent_outputs: bool,
+    pub max_concurrent_broadcasts: u16,
 
     // Internal API
     pub internal_api_bind: String,
     pub internal_api_port: u32,
+    pub require_signed_stop: bool,
+    pub stop_auth_key: String,
 
     // Tor
     pub tor_support: bool,
     pub tor_control_port: u16,
     pub onion_hidden_service_port: u16,
+    pub tor_persist_key: bool,
+    pub tor_key_path: String,
+
+    // Backups
+    pub backup_interval: u32,
+    pub backup_dir: String,
+    pub backup_retention: u16,
+
+    // Metrics
+    pub metrics_enabled: bool,
+    pub metrics_bind: String,
+    pub metrics_port: u16,
 }
 
 impl Config {
@@ -210,6 +276,15 @@ impl Config {
     /// This includes:
     /// - `bitcoind` credentials have been set
     /// - The Bitcoin network has been properly set (to either bitcoin, testnet, signet or regtest)
+    /// - The log format is either text or json
+    /// - Keepalive pings can be answered if enabled
+    /// - At least one backup is retained if backups are enabled
+    /// - `bitcoind` is not polled more often than [MIN_POLLING_DELTA]
+    /// - At least one block is fetched at a time on bootstrap
+    /// - The per-user slots limit (if any) allows users to get at least a default subscription
+    /// - The retention period for responded appointments is shorter than the time they are kept for anyway
+    /// - `bitcoind` fallbacks (if any) have their port and credentials set
+    /// - A valid public key is set to authorize `stop` requests if they are required to be signed
     ///
     /// This will also assign the default `btc_rpc_port` depending on the network if it has not
     /// been overwritten at this point.
@@ -220,6 +295,75 @@ impl Config {
         if self.btc_rpc_password == String::new() {
             return Err(ConfigError("btc_rpc_password must be set".to_owned()));
         }
+        for fallback in self.btc_rpc_fallbacks.iter() {
+            if fallback.port == 0 || fallback.user.is_empty() || fallback.password.is_empty() {
+                return Err(ConfigError(format!(
+                    "btc_rpc_fallbacks must set port, user and password (connect: {})",
+                    fallback.connect
+                )));
+            }
+        }
+        LogFormat::from_str(&self.log_format).map_err(ConfigError)?;
+        if self.require_signed_stop && PublicKey::from_str(&self.stop_auth_key).is_err() {
+            return Err(ConfigError(
+                "stop_auth_key must be a valid public key if require_signed_stop is set".to_owned(),
+            ));
+        }
+        if self.rpc_keepalive_interval != 0 && self.rpc_keepalive_timeout == 0 {
+            return Err(ConfigError(
+                "rpc_keepalive_timeout must be greater than zero if keepalives are enabled"
+                    .to_owned(),
+            ));
+        }
+        if self.rate_limit_refill != 0 && self.rate_limit_burst == 0 {
+            return Err(ConfigError(
+                "rate_limit_burst must be greater than zero if rate limiting is enabled".to_owned(),
+            ));
+        }
+        if (self.rate_limit_shared_refill == 0) != (self.rate_limit_shared_burst == 0) {
+            return Err(ConfigError(
+                "rate_limit_shared_refill and rate_limit_shared_burst must be both set or both zero"
+                    .to_owned(),
+            ));
+        }
+        if self.rate_limit_shared_refill > self.rate_limit_refill
+            || self.rate_limit_shared_burst > self.rate_limit_burst
+        {
+            return Err(ConfigError(
+                "The shared rate limits cannot be less strict than the per-user ones".to_owned(),
+            ));
+        }
+        if self.backup_interval != 0 && self.backup_retention == 0 {
+            return Err(ConfigError(
+                "backup_retention must be greater than zero if backups are enabled".to_owned(),
+            ));
+        }
+
+        if self.polling_delta < MIN_POLLING_DELTA {
+            return Err(ConfigError(format!(
+                "polling_delta must be at least {} seconds",
+                MIN_POLLING_DELTA
+            )));
+        }
+
+        if self.bootstrap_concurrency == 0 {
+            return Err(ConfigError(
+                "bootstrap_concurrency must be at least 1".to_owned(),
+            ));
+        }
+
+        if self.max_user_slots != 0 && self.max_user_slots < self.subscription_slots {
+            return Err(ConfigError(
+                "max_user_slots must be zero (unlimited) or at least subscription_slots".to_owned(),
+            ));
+        }
+
+        if self.retention_blocks >= IRREVOCABLY_RESOLVED {
+            return Err(ConfigError(format!(
+                "retention_blocks must be lower than {} (responded appointments are removed after that many confirmations anyway)",
+                IRREVOCABLY_RESOLVED
+            )));
+        }
 
         match Network::from_str(&self.btc_network) {
             Ok(network) => {
@@ -254,26 +398,61 @@ impl Default for Config {
         Self {
             api_bind: "127.0.0.1".into(),
             api_port: 9814,
+            max_batch_size: 100,
+            http_compression: false,
+            events_enabled: false,
+            rate_limit_refill: 0,
+            rate_limit_burst: 10,
+            rate_limit_shared_refill: 0,
+            rate_limit_shared_burst: 0,
             tor_support: false,
             tor_control_port: 9051,
             onion_hidden_service_port: 2121,
+            tor_persist_key: false,
+            tor_key_path: String::new(),
             rpc_bind: "127.0.0.1".into(),
             rpc_port: 8814,
+            rpc_socket_path: String::new(),
+            rpc_keepalive_interval: 0,
+            rpc_keepalive_timeout: 20,
+            rpc_max_concurrent_streams: 0,
             btc_network: "bitcoin".into(),
             btc_rpc_user: String::new(),
             btc_rpc_password: String::new(),
             btc_rpc_connect: "localhost".into(),
             btc_rpc_port: 0,
+            btc_rpc_fallbacks: Vec::new(),
+            btc_rpc_connect_retries: 0,
+            btc_rpc_connect_backoff: 1,
 
             debug: false,
+            log_format: "text".into(),
             overwrite_key: false,
+            allow_unsupported_bitcoind: false,
+            dry_run: false,
             subscription_slots: 10000,
             subscription_duration: 4320,
             expiry_delta: 6,
+            max_appointments_per_user: 0,
+            max_user_slots: 0,
+            expiry_warning_window: 0,
+            max_appointment_size: 100000,
             min_to_self_delay: 20,
             polling_delta: 60,
+            bootstrap_concurrency: 1,
+            retention_blocks: 0,
+            check_spent_outputs: false,
+            max_concurrent_broadcasts: 0,
             internal_api_bind: "127.0.0.1".into(),
             internal_api_port: 50051,
+            require_signed_stop: false,
+            stop_auth_key: String::new(),
+            backup_interval: 0,
+            backup_dir: String::new(),
+            backup_retention: 7,
+            metrics_enabled: false,
+            metrics_bind: "127.0.0.1".into(),
+            metrics_port: 9815,
         }
     }
 }
@@ -366,4 +545,219 @@ mod tests {
 
         config.verify().unwrap()
     }
+
+    #[test]
+    fn test_config_verify_fallbacks() {
+        // Tests that fallbacks with missing data will make verify fail
+        let fallback = RpcFallback {
+            connect: "localhost".to_owned(),
+            port: 18443,
+            user: "user".to_owned(),
+            password: "password".to_owned(),
+        };
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            btc_rpc_fallbacks: vec![fallback.clone()],
+            ..Default::default()
+        };
+        config.verify().unwrap();
+
+        config.btc_rpc_fallbacks.push(RpcFallback {
+            password: String::new(),
+            ..fallback
+        });
+        assert!(matches!(config.verify(), Err(ConfigError { .. })));
+    }
+
+    #[test]
+    fn test_config_fallbacks_from_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            btc_rpc_user = "user"
+
+            [[btc_rpc_fallbacks]]
+            connect = "10.0.0.2"
+            port = 8332
+            user = "user"
+            password = "password"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.btc_rpc_user, "user");
+        assert_eq!(config.btc_rpc_fallbacks.len(), 1);
+        assert_eq!(config.btc_rpc_fallbacks[0].connect, "10.0.0.2");
+    }
+
+    #[test]
+    fn test_config_serialize_redacted() {
+        let config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "secret_password".to_owned(),
+            btc_rpc_fallbacks: vec![RpcFallback {
+                connect: "10.0.0.2".to_owned(),
+                port: 8332,
+                user: "fallback_user".to_owned(),
+                password: "secret_fallback_password".to_owned(),
+            }],
+            ..Default::default()
+        };
+
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(!json.contains("secret"));
+
+        let value = serde_json::to_value(&config).unwrap();
+        assert_eq!(value["btc_rpc_password"], REDACTED);
+        assert_eq!(value["btc_rpc_fallbacks"][0]["password"], REDACTED);
+        // Non-sensitive fields are kept as is
+        assert_eq!(value["btc_rpc_user"], "user");
+        assert_eq!(value["btc_rpc_fallbacks"][0]["user"], "fallback_user");
+        assert_eq!(value["api_port"], config.api_port);
+    }
+
+    #[test]
+    fn test_config_verify_signed_stop() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            require_signed_stop: true,
+            ..Default::default()
+        };
+        assert!(matches!(config.verify(), Err(ConfigError { .. })));
+
+        config.stop_auth_key = "not a key".to_owned();
+        assert!(matches!(config.verify(), Err(ConfigError { .. })));
+
+        let (_, pk) = teos_common::cryptography::get_random_keypair();
+        config.stop_auth_key = pk.to_string();
+        config.verify().unwrap();
+    }
+
+    #[test]
+    fn test_config_verify_bootstrap_concurrency() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            bootstrap_concurrency: 0,
+            ..Default::default()
+        };
+        assert!(matches!(config.verify(), Err(ConfigError { .. })));
+
+        config.bootstrap_concurrency = 8;
+        config.verify().unwrap();
+    }
+
+    #[test]
+    fn test_config_verify_max_user_slots() {
+        // Tests that a per-user slots limit below the default subscription will make verify fail
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            max_user_slots: 1,
+            ..Default::default()
+        };
+        assert!(matches!(config.verify(), Err(ConfigError { .. })));
+
+        config.max_user_slots = config.subscription_slots;
+        config.verify().unwrap();
+    }
+
+    #[test]
+    fn test_config_verify_rate_limit_shared() {
+        // Tests that the shared rate limits must be set together and be at most as permissive as the per-user ones
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            rate_limit_refill: 60,
+            rate_limit_shared_refill: 10,
+            ..Default::default()
+        };
+        assert!(matches!(config.verify(), Err(ConfigError { .. })));
+
+        config.rate_limit_shared_burst = config.rate_limit_burst + 1;
+        assert!(matches!(config.verify(), Err(ConfigError { .. })));
+
+        config.rate_limit_shared_burst = config.rate_limit_burst;
+        config.verify().unwrap();
+
+        config.rate_limit_shared_refill = config.rate_limit_refill + 1;
+        assert!(matches!(config.verify(), Err(ConfigError { .. })));
+    }
+
+    #[test]
+    fn test_config_verify_backup_retention() {
+        // Tests that enabling backups without retaining any of them will make verify fail
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            backup_interval: 3600,
+            backup_retention: 0,
+            ..Default::default()
+        };
+        assert!(matches!(config.verify(), Err(ConfigError { .. })));
+
+        // Retention does not matter if backups are disabled
+        config.backup_interval = 0;
+        config.verify().unwrap();
+    }
+
+    #[test]
+    fn test_config_verify_rpc_keepalive() {
+        // Tests that enabling keepalives without giving peers any time to answer them will make verify fail
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            rpc_keepalive_interval: 60,
+            rpc_keepalive_timeout: 0,
+            ..Default::default()
+        };
+        assert!(matches!(config.verify(), Err(ConfigError { .. })));
+
+        // The timeout does not matter if keepalives are disabled
+        config.rpc_keepalive_interval = 0;
+        config.verify().unwrap();
+    }
+
+    #[test]
+    fn test_config_verify_retention_blocks() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            retention_blocks: IRREVOCABLY_RESOLVED,
+            ..Default::default()
+        };
+        assert!(matches!(config.verify(), Err(ConfigError { .. })));
+
+        config.retention_blocks = IRREVOCABLY_RESOLVED - 1;
+        config.verify().unwrap();
+    }
+
+    #[test]
+    fn test_config_verify_log_format() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            log_format: "yaml".to_owned(),
+            ..Default::default()
+        };
+        assert!(matches!(config.verify(), Err(ConfigError { .. })));
+
+        config.log_format = "json".to_owned();
+        config.verify().unwrap();
+    }
+
+    #[test]
+    fn test_config_verify_polling_delta() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            polling_delta: MIN_POLLING_DELTA - 1,
+            ..Default::default()
+        };
+        assert!(matches!(config.verify(), Err(ConfigError { .. })));
+
+        config.polling_delta = MIN_POLLING_DELTA;
+        config.verify().unwrap();
+    }
 }
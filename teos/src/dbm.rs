@@ -2,25 +2,75 @@
 //!
 
 use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::{Condvar, Mutex, MutexGuard};
 
 use rusqlite::ffi::{SQLITE_CONSTRAINT_FOREIGNKEY, SQLITE_CONSTRAINT_PRIMARYKEY};
 use rusqlite::limits::Limit;
-use rusqlite::{params, params_from_iter, Connection, Error as SqliteError, ErrorCode, Params};
+use rusqlite::{
+    params, params_from_iter, Connection, DatabaseName, Error as SqliteError, ErrorCode, OpenFlags,
+    Params, Row, Transaction, TransactionBehavior,
+};
 
 use bitcoin::consensus;
 use bitcoin::hashes::Hash;
 use bitcoin::secp256k1::SecretKey;
 use bitcoin::BlockHash;
 
-use teos_common::appointment::{Appointment, Locator};
+use teos_common::appointment::{Appointment, AppointmentStatus, Locator};
 use teos_common::constants::ENCRYPTED_BLOB_MAX_SIZE;
 use teos_common::UserId;
 
+use crate::export::{
+    ExportedAppointment, ExportedData, ExportedSubscriptionEvent, ExportedTracker, ExportedUser,
+};
 use crate::extended_appointment::{compute_appointment_slots, ExtendedAppointment, UUID};
-use crate::gatekeeper::UserInfo;
+use crate::gatekeeper::{SubscriptionEvent, UserInfo};
 use crate::responder::{ConfirmationStatus, TransactionTracker};
+use crate::watcher::AppointmentInfo;
+
+/// Selects the data needed to build an [AppointmentInfo], see [appointment_info_from_row].
+const SELECT_APPOINTMENT_INFO: &str =
+    "SELECT a.UUID, a.locator, a.encrypted_blob, a.to_self_delay, a.user_id, \
+    t.dispute_tx, t.penalty_tx, t.height, t.confirmed \
+    FROM appointments as a LEFT JOIN trackers as t ON a.UUID=t.UUID";
+
+/// Builds an [AppointmentInfo] out of a row selected with [SELECT_APPOINTMENT_INFO]. Triggered appointments are
+/// returned as trackers.
+fn appointment_info_from_row(row: &Row) -> (UUID, AppointmentInfo) {
+    let raw_uuid: Vec<u8> = row.get(0).unwrap();
+    let uuid = UUID::deserialize(&raw_uuid[0..20]).unwrap();
+    let raw_userid: Vec<u8> = row.get(4).unwrap();
+    let user_id = UserId::deserialize(&raw_userid).unwrap();
+    let raw_dispute_tx: Option<Vec<u8>> = row.get(5).unwrap();
+
+    let info = match raw_dispute_tx {
+        Some(raw_dispute_tx) => {
+            let raw_penalty_tx: Vec<u8> = row.get(6).unwrap();
+            let height: u32 = row.get(7).unwrap();
+            let confirmed: bool = row.get(8).unwrap();
+            AppointmentInfo::Tracker(TransactionTracker {
+                dispute_tx: consensus::deserialize(&raw_dispute_tx).unwrap(),
+                penalty_tx: consensus::deserialize(&raw_penalty_tx).unwrap(),
+                status: ConfirmationStatus::from_db_data(height, confirmed),
+                user_id,
+            })
+        }
+        None => {
+            let raw_locator: Vec<u8> = row.get(1).unwrap();
+            let locator = Locator::deserialize(&raw_locator).unwrap();
+            AppointmentInfo::Appointment(Appointment::new(
+                locator,
+                row.get(2).unwrap(),
+                row.get(3).unwrap(),
+            ))
+        }
+    };
+
+    (uuid, info)
+}
 
 /// Packs the errors than can raise when interacting with the underlying database.
 #[derive(Debug)]
@@ -32,24 +82,159 @@ pub enum Error {
     Unknown(SqliteError),
 }
 
+/// Aggregate figures about the data held by the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DBStats {
+    /// Number of registered users.
+    pub n_users: u64,
+    /// Number of appointments being watched (i.e. not triggered yet).
+    pub n_watched_appointments: u64,
+    /// Number of appointments that have been responded to (i.e. trackers).
+    pub n_responded_appointments: u64,
+    /// Number of slots taken by the appointments held by the tower.
+    pub used_slots: u64,
+    /// Number of slots the registered users have left.
+    pub available_slots: u64,
+    /// Size of the database, in bytes.
+    pub db_size: u64,
+}
+
+/// Number of read-only connections opened by the [DBM] (on top of the one used for writing).
+pub const READ_CONNECTIONS: usize = 4;
+
+/// A pool of read-only database connections.
+#[derive(Debug)]
+struct ConnectionPool {
+    /// The number of connections managed by the pool.
+    size: usize,
+    /// The idle connections.
+    connections: Mutex<Vec<Connection>>,
+    /// Used to wait for a connection to be handed back when all of them are in use.
+    available: Condvar,
+}
+
+impl ConnectionPool {
+    /// Creates a new [ConnectionPool] holding the given connections.
+    fn new(connections: Vec<Connection>) -> Self {
+        ConnectionPool {
+            size: connections.len(),
+            connections: Mutex::new(connections),
+            available: Condvar::new(),
+        }
+    }
+
+    /// Takes a connection from the pool, waiting for one to be handed back if all of them are in use.
+    fn get(&self) -> PooledConnection<'_> {
+        let mut connections = self.connections.lock().unwrap();
+        loop {
+            match connections.pop() {
+                Some(connection) => {
+                    return PooledConnection {
+                        pool: self,
+                        connection: Some(connection),
+                    }
+                }
+                None => connections = self.available.wait(connections).unwrap(),
+            }
+        }
+    }
+}
+
+/// A connection borrowed from a [ConnectionPool]. It is handed back to the pool when dropped.
+struct PooledConnection<'a> {
+    pool: &'a ConnectionPool,
+    connection: Option<Connection>,
+}
+
+impl Deref for PooledConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.connection.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        let connection = self.connection.take().unwrap();
+        self.pool.connections.lock().unwrap().push(connection);
+        self.pool.available.notify_one();
+    }
+}
+
+/// A connection used to read from the database. Either a pooled connection or the write connection itself for
+/// databases that cannot be opened more than once (e.g. in-memory databases).
+enum ReadConnection<'a> {
+    Pooled(PooledConnection<'a>),
+    Writer(MutexGuard<'a, Connection>),
+}
+
+impl Deref for ReadConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        match self {
+            ReadConnection::Pooled(connection) => connection,
+            ReadConnection::Writer(connection) => connection,
+        }
+    }
+}
+
 /// Component in charge of interacting with the underlying database.
 ///
 /// Currently works for `SQLite`. `PostgreSQL` should also be added in the future.
+///
+/// Writes are serialized through a single connection, while reads are served by a pool of read-only connections so
+/// they can run in parallel (the database is run in `WAL` mode so readers do not block the writer, nor the other way
+/// around). The [DBM] is therefore meant to be shared as is, without any extra locking.
 #[derive(Debug)]
 pub struct DBM {
-    /// The underlying database connection.
-    connection: Connection,
+    /// The connection used to write to the database.
+    writer: Mutex<Connection>,
+    /// The connections used to read from the database. Empty if the database cannot be opened more than once.
+    readers: ConnectionPool,
 }
 
 impl DBM {
     /// Creates a new [DBM] instance.
     pub fn new(db_path: PathBuf) -> Result<Self, SqliteError> {
-        let connection = Connection::open(db_path)?;
+        let connection = Connection::open(&db_path)?;
         connection.execute("PRAGMA foreign_keys=1;", [])?;
-        let mut dbm = Self { connection };
+        connection.query_row("PRAGMA journal_mode=WAL;", [], |_| Ok(()))?;
+        let dbm = Self {
+            writer: Mutex::new(connection),
+            readers: ConnectionPool::new(Vec::new()),
+        };
         dbm.create_tables()?;
 
-        Ok(dbm)
+        let mut readers = Vec::with_capacity(READ_CONNECTIONS);
+        for _ in 0..READ_CONNECTIONS {
+            readers.push(Connection::open_with_flags(
+                &db_path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )?);
+        }
+
+        Ok(Self {
+            readers: ConnectionPool::new(readers),
+            ..dbm
+        })
+    }
+
+    /// Gets the connection used to write to the database. Writes are serialized.
+    fn writer(&self) -> MutexGuard<'_, Connection> {
+        self.writer.lock().unwrap()
+    }
+
+    /// Gets a connection to read from the database.
+    ///
+    /// Notice the returned connection may be the write connection, so it must be dropped before getting another one.
+    fn reader(&self) -> ReadConnection<'_> {
+        if self.readers.size == 0 {
+            ReadConnection::Writer(self.writer())
+        } else {
+            ReadConnection::Pooled(self.readers.get())
+        }
     }
 
     /// Creates the database tables if not present.
@@ -58,10 +243,16 @@ impl DBM {
     /// - users
     /// - appointments
     /// - trackers
+    /// - tracker_triggers
+    /// - pending_broadcasts
     /// - last_known_block
     /// - keys
-    fn create_tables(&mut self) -> Result<(), SqliteError> {
-        let tx = self.connection.transaction().unwrap();
+    /// - retired_keys
+    /// - subscription_history
+    /// - banned_users
+    fn create_tables(&self) -> Result<(), SqliteError> {
+        let mut connection = self.writer();
+        let tx = connection.transaction().unwrap();
         tx.execute(
             "CREATE TABLE IF NOT EXISTS users (
                     user_id INT PRIMARY KEY,
@@ -98,6 +289,37 @@ impl DBM {
             )",
             [],
         )?;
+        // Kept apart from the trackers table so existing databases do not need to be migrated
+        tx.execute(
+            "CREATE TABLE IF NOT EXISTS tracker_triggers (
+                UUID INT PRIMARY KEY,
+                block_hash INT NOT NULL,
+                FOREIGN KEY(UUID)
+                    REFERENCES trackers(UUID)
+                    ON DELETE CASCADE
+            )",
+            [],
+        )?;
+        tx.execute(
+            "CREATE TABLE IF NOT EXISTS pending_broadcasts (
+                UUID INT PRIMARY KEY,
+                FOREIGN KEY(UUID)
+                    REFERENCES trackers(UUID)
+                    ON DELETE CASCADE
+            )",
+            [],
+        )?;
+        // Kept apart from the appointments table so existing databases do not need to be migrated
+        tx.execute(
+            "CREATE TABLE IF NOT EXISTS appointment_versions (
+                UUID INT PRIMARY KEY,
+                version INT NOT NULL,
+                FOREIGN KEY(UUID)
+                    REFERENCES appointments(UUID)
+                    ON DELETE CASCADE
+            )",
+            [],
+        )?;
         tx.execute(
             "CREATE TABLE IF NOT EXISTS last_known_block (
                 id INT PRIMARY KEY,
@@ -113,12 +335,41 @@ impl DBM {
             )",
             [],
         )?;
+        tx.execute(
+            "CREATE TABLE IF NOT EXISTS retired_keys (
+                id INT PRIMARY KEY,
+                valid_until INT NOT NULL,
+                FOREIGN KEY(id)
+                    REFERENCES keys(id)
+                    ON DELETE CASCADE
+            )",
+            [],
+        )?;
+        // Not linked to the users table on purpose, so the history outlives the user
+        tx.execute(
+            "CREATE TABLE IF NOT EXISTS subscription_history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INT NOT NULL,
+                event TEXT NOT NULL,
+                available_slots INT NOT NULL,
+                subscription_expiry INT NOT NULL,
+                height INT NOT NULL
+            )",
+            [],
+        )?;
+        // Not linked to the users table either, so bans hold even if the user is not (or no longer) registered
+        tx.execute(
+            "CREATE TABLE IF NOT EXISTS banned_users (
+                user_id INT PRIMARY KEY
+            )",
+            [],
+        )?;
         tx.commit()
     }
 
     /// Generic method to store data into the database.
     fn store_data<P: Params>(&self, query: &str, params: P) -> Result<(), Error> {
-        match self.connection.execute(query, params) {
+        match self.writer().execute(query, params) {
             Ok(_) => Ok(()),
             Err(e) => match e {
                 SqliteError::SqliteFailure(ie, _) => match ie.code {
@@ -136,7 +387,7 @@ impl DBM {
 
     /// Generic method to remove data from the database.
     fn remove_data<P: Params>(&self, query: &str, params: P) -> Result<(), Error> {
-        match self.connection.execute(query, params).unwrap() {
+        match self.writer().execute(query, params).unwrap() {
             0 => Err(Error::NotFound),
             _ => Ok(()),
         }
@@ -194,10 +445,114 @@ impl DBM {
         }
     }
 
+    /// Appends a [SubscriptionEvent] to the subscription history of a given user.
+    pub(crate) fn store_subscription_event(
+        &self,
+        user_id: UserId,
+        event: &SubscriptionEvent,
+    ) -> Result<(), Error> {
+        let query = "INSERT INTO subscription_history (user_id, event, available_slots, subscription_expiry, height) VALUES (?1, ?2, ?3, ?4, ?5)";
+
+        match self.store_data(
+            query,
+            params![
+                user_id.serialize(),
+                event.kind.to_string(),
+                event.available_slots,
+                event.subscription_expiry,
+                event.height,
+            ],
+        ) {
+            Ok(x) => {
+                log::debug!("Subscription event successfully stored: {}", user_id);
+                Ok(x)
+            }
+            Err(e) => {
+                log::error!(
+                    "Couldn't store subscription event: {}. Error: {:?}",
+                    user_id,
+                    e
+                );
+                Err(e)
+            }
+        }
+    }
+
+    /// Loads the subscription history of a given user, sorted from oldest to newest.
+    pub(crate) fn load_subscription_history(&self, user_id: UserId) -> Vec<SubscriptionEvent> {
+        let connection = self.reader();
+        let mut stmt = connection
+            .prepare(
+                "SELECT event, available_slots, subscription_expiry, height FROM subscription_history WHERE user_id=(?) ORDER BY id",
+            )
+            .unwrap();
+        let mut rows = stmt.query([user_id.serialize()]).unwrap();
+
+        let mut events = Vec::new();
+        while let Ok(Some(row)) = rows.next() {
+            let kind: String = row.get(0).unwrap();
+            events.push(SubscriptionEvent::new(
+                kind.parse().unwrap(),
+                row.get(1).unwrap(),
+                row.get(2).unwrap(),
+                row.get(3).unwrap(),
+            ));
+        }
+
+        events
+    }
+
+    /// Stores a banned user into the database.
+    pub(crate) fn store_banned_user(&self, user_id: UserId) -> Result<(), Error> {
+        match self.store_data(
+            "INSERT INTO banned_users (user_id) VALUES (?)",
+            params![user_id.serialize()],
+        ) {
+            Ok(x) => {
+                log::debug!("Banned user successfully stored: {}", user_id);
+                Ok(x)
+            }
+            Err(e) => {
+                log::error!("Couldn't store banned user: {}. Error: {:?}", user_id, e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Removes a banned user from the database.
+    pub(crate) fn remove_banned_user(&self, user_id: UserId) -> Result<(), Error> {
+        self.remove_data(
+            "DELETE FROM banned_users WHERE user_id=(?)",
+            params![user_id.serialize()],
+        )
+    }
+
+    /// Loads all banned users from the database.
+    pub(crate) fn load_banned_users(&self) -> HashSet<UserId> {
+        let connection = self.reader();
+        let mut stmt = connection
+            .prepare("SELECT user_id FROM banned_users")
+            .unwrap();
+        let mut rows = stmt.query([]).unwrap();
+
+        let mut banned_users = HashSet::new();
+        while let Ok(Some(row)) = rows.next() {
+            let raw_userid: Vec<u8> = row.get(0).unwrap();
+            banned_users.insert(UserId::deserialize(&raw_userid).unwrap());
+        }
+
+        banned_users
+    }
+
     /// Loads the associated appointments ([Appointment]) of a given user ([UserInfo]).
+    #[cfg(test)]
     pub(crate) fn load_user_appointments(&self, user_id: UserId) -> HashMap<UUID, u32> {
-        let mut stmt = self
-            .connection
+        DBM::user_appointments(&self.reader(), user_id)
+    }
+
+    /// Loads the associated appointments of a given user using the given connection.
+    fn user_appointments(connection: &Connection, user_id: UserId) -> HashMap<UUID, u32> {
+        let mut stmt = connection
             .prepare("SELECT UUID, encrypted_blob FROM appointments WHERE user_id=(?)")
             .unwrap();
         let mut rows = stmt.query([user_id.serialize()]).unwrap();
@@ -220,7 +575,8 @@ impl DBM {
     /// Loads all users from the database.
     pub(crate) fn load_all_users(&self) -> HashMap<UserId, UserInfo> {
         let mut users = HashMap::new();
-        let mut stmt = self.connection.prepare("SELECT * FROM users").unwrap();
+        let connection = self.reader();
+        let mut stmt = connection.prepare("SELECT * FROM users").unwrap();
         let mut rows = stmt.query([]).unwrap();
 
         while let Ok(Some(row)) = rows.next() {
@@ -231,7 +587,11 @@ impl DBM {
 
             users.insert(
                 user_id,
-                UserInfo::with_appointments(slots, expiry, self.load_user_appointments(user_id)),
+                UserInfo::with_appointments(
+                    slots,
+                    expiry,
+                    DBM::user_appointments(&connection, user_id),
+                ),
             );
         }
 
@@ -239,9 +599,10 @@ impl DBM {
     }
 
     /// Removes some users from the database in batch.
-    pub(crate) fn batch_remove_users(&mut self, users: &HashSet<UserId>) -> usize {
-        let limit = self.connection.limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER) as usize;
-        let tx = self.connection.transaction().unwrap();
+    pub(crate) fn batch_remove_users(&self, users: &HashSet<UserId>) -> usize {
+        let mut connection = self.writer();
+        let limit = connection.limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER) as usize;
+        let tx = connection.transaction().unwrap();
         let iter = users
             .iter()
             .map(|uuid| uuid.serialize())
@@ -298,35 +659,85 @@ impl DBM {
         }
     }
 
-    /// Updates an existing [Appointment] in the database.
-    pub(crate) fn update_appointment(&self, uuid: UUID, appointment: &ExtendedAppointment) {
+    /// Updates an existing [Appointment] in the database, bumping its version (the number of times its data has been
+    /// replaced). Both happen within the same transaction, so the version is only bumped if the data is updated.
+    ///
+    /// Returns the new version of the appointment.
+    pub(crate) fn update_appointment(
+        &self,
+        uuid: UUID,
+        appointment: &ExtendedAppointment,
+    ) -> Result<u32, Error> {
         // DISCUSS: Check what fields we'd like to make updatable. e_blob and signature are the obvious, to_self_delay and start_block may not be necessary (or even risky)
         let query =
             "UPDATE appointments SET encrypted_blob=(?1), to_self_delay=(?2), user_signature=(?3), start_block=(?4) WHERE UUID=(?5)";
-        match self.update_data(
-            query,
-            params![
-                appointment.encrypted_blob(),
-                appointment.to_self_delay(),
-                appointment.user_signature,
-                appointment.start_block,
-                uuid.serialize(),
-            ],
-        ) {
-            Ok(_) => {
-                log::debug!("Appointment successfully updated: {}", uuid);
+        let mut connection = self.writer();
+        let tx = connection.transaction().map_err(Error::Unknown)?;
+
+        let updated = tx
+            .execute(
+                query,
+                params![
+                    appointment.encrypted_blob(),
+                    appointment.to_self_delay(),
+                    appointment.user_signature,
+                    appointment.start_block,
+                    uuid.serialize(),
+                ],
+            )
+            .map_err(Error::Unknown)?;
+        if updated == 0 {
+            log::error!("Appointment not found, data cannot be updated: {}", uuid);
+            return Err(Error::NotFound);
+        }
+
+        let version = tx
+            .execute(
+                "INSERT INTO appointment_versions (UUID, version) VALUES (?1, 1) ON CONFLICT(UUID) DO UPDATE SET version=version+1",
+                params![uuid.serialize()],
+            )
+            .and_then(|_| {
+                tx.query_row(
+                    "SELECT version FROM appointment_versions WHERE UUID=(?)",
+                    [uuid.serialize()],
+                    |row| row.get(0),
+                )
+            })
+            .and_then(|version| tx.commit().map(|_| version));
+
+        match version {
+            Ok(version) => {
+                log::debug!(
+                    "Appointment successfully updated: {} (version {})",
+                    uuid,
+                    version
+                );
+                Ok(version)
             }
-            Err(_) => {
-                log::error!("Appointment not found, data cannot be updated: {}", uuid);
+            Err(e) => {
+                log::error!("Couldn't update appointment: {}. Error: {:?}", uuid, e);
+                Err(Error::Unknown(e))
             }
         }
     }
 
+    /// Loads the version of an [Appointment]. Appointments that have never been updated (or cannot be found) are at
+    /// version zero.
+    pub(crate) fn load_appointment_version(&self, uuid: UUID) -> u32 {
+        let connection = self.reader();
+        let mut stmt = connection
+            .prepare("SELECT version FROM appointment_versions WHERE UUID=(?)")
+            .unwrap();
+
+        stmt.query_row([uuid.serialize()], |row| row.get(0))
+            .unwrap_or(0)
+    }
+
     /// Loads an [Appointment] from the database.
     pub(crate) fn load_appointment(&self, uuid: UUID) -> Result<ExtendedAppointment, Error> {
         let key = uuid.serialize();
-        let mut stmt = self
-            .connection
+        let connection = self.reader();
+        let mut stmt = connection
             .prepare("SELECT * FROM appointments WHERE UUID=(?)")
             .unwrap();
 
@@ -350,8 +761,8 @@ impl DBM {
     /// Loads all appointments from the database.
     pub(crate) fn load_all_appointments(&self) -> HashMap<UUID, ExtendedAppointment> {
         let mut appointments = HashMap::new();
-        let mut stmt = self
-            .connection
+        let connection = self.reader();
+        let mut stmt = connection
             .prepare("SELECT * FROM appointments as a LEFT JOIN trackers as t ON a.UUID=t.UUID WHERE t.UUID IS NULL")
             .unwrap();
         let mut rows = stmt.query([]).unwrap();
@@ -380,6 +791,75 @@ impl DBM {
         appointments
     }
 
+    /// Loads a page of appointments from the database, optionally filtered by user and status.
+    ///
+    /// Appointments with an associated tracker are returned as [AppointmentInfo::Tracker] (they are seen as triggered),
+    /// the rest as [AppointmentInfo::Appointment]. Results are sorted by `UUID` so pages are stable across calls.
+    /// A `limit` of zero means no limit. Filtering by [AppointmentStatus::NotFound] returns nothing.
+    pub(crate) fn load_appointments(
+        &self,
+        user_id: Option<UserId>,
+        status: Option<AppointmentStatus>,
+        limit: u32,
+        offset: u32,
+    ) -> Vec<(UUID, AppointmentInfo)> {
+        let triggered = match status {
+            None => None,
+            Some(AppointmentStatus::BeingWatched) => Some(false),
+            Some(AppointmentStatus::DisputeResponded) => Some(true),
+            Some(AppointmentStatus::NotFound) => return Vec::new(),
+        };
+        // SQLite treats a negative limit as no limit
+        let limit = if limit == 0 { -1 } else { limit as i64 };
+
+        let mut appointments = Vec::new();
+        let connection = self.reader();
+        let mut stmt = connection
+            .prepare(&format!(
+                "{} WHERE (?1 IS NULL OR a.user_id=?1) AND (?2 IS NULL OR (t.UUID IS NOT NULL)=?2) \
+                ORDER BY a.UUID LIMIT ?3 OFFSET ?4",
+                SELECT_APPOINTMENT_INFO
+            ))
+            .unwrap();
+        let mut rows = stmt
+            .query(params![
+                user_id.map(|id| id.serialize()),
+                triggered,
+                limit,
+                offset
+            ])
+            .unwrap();
+
+        while let Ok(Some(row)) = rows.next() {
+            appointments.push(appointment_info_from_row(row));
+        }
+
+        appointments
+    }
+
+    /// Loads the appointments (or their trackers, if they have been triggered) matching a given [Locator], from any
+    /// user.
+    pub(crate) fn load_appointments_by_locator(
+        &self,
+        locator: Locator,
+    ) -> Vec<(UUID, AppointmentInfo)> {
+        let mut appointments = Vec::new();
+        let connection = self.reader();
+        let mut stmt = connection
+            .prepare(&format!(
+                "{} WHERE a.locator=(?) ORDER BY a.UUID",
+                SELECT_APPOINTMENT_INFO
+            ))
+            .unwrap();
+        let mut rows = stmt.query([locator.serialize()]).unwrap();
+
+        while let Ok(Some(row)) = rows.next() {
+            appointments.push(appointment_info_from_row(row));
+        }
+
+        appointments
+    }
+
     /// Removes an [Appointment] from the database.
     pub(crate) fn remove_appointment(&self, uuid: UUID) {
         let query = "DELETE FROM appointments WHERE UUID=(?)";
@@ -396,12 +876,13 @@ impl DBM {
     /// Removes some appointments from the database in batch and updates the associated users giving back
     /// the freed appointment slots
     pub(crate) fn batch_remove_appointments(
-        &mut self,
+        &self,
         appointments: &HashSet<UUID>,
         updated_users: &HashMap<UserId, UserInfo>,
     ) -> usize {
-        let limit = self.connection.limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER) as usize;
-        let tx = self.connection.transaction().unwrap();
+        let mut connection = self.writer();
+        let limit = connection.limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER) as usize;
+        let tx = connection.transaction().unwrap();
         let iter = appointments
             .iter()
             .map(|uuid| uuid.serialize())
@@ -438,8 +919,8 @@ impl DBM {
 
     /// Loads the locator associated to a given UUID
     pub(crate) fn load_locator(&self, uuid: UUID) -> Result<Locator, Error> {
-        let mut stmt = self
-            .connection
+        let connection = self.reader();
+        let mut stmt = connection
             .prepare("SELECT locator FROM appointments WHERE UUID=(?)")
             .unwrap();
 
@@ -481,10 +962,173 @@ impl DBM {
         }
     }
 
+    /// Removes a [TransactionTracker] from the database.
+    ///
+    /// The associated appointment is kept, so it is considered to be watched again.
+    pub(crate) fn remove_tracker(&self, uuid: UUID) {
+        let query = "DELETE FROM trackers WHERE UUID=(?)";
+        match self.remove_data(query, params![uuid.serialize()]) {
+            Ok(_) => {
+                log::debug!("Tracker successfully removed: {}", uuid);
+            }
+            Err(_) => {
+                log::error!("Tracker not found, data cannot be removed: {}", uuid);
+            }
+        }
+    }
+
+    /// Stores the hash of the block where the dispute of a [TransactionTracker] was seen.
+    ///
+    /// The entry is removed alongside the tracker.
+    pub(crate) fn store_trigger_block(
+        &self,
+        uuid: UUID,
+        block_hash: &BlockHash,
+    ) -> Result<(), Error> {
+        let query = "INSERT OR REPLACE INTO tracker_triggers (UUID, block_hash) VALUES (?1, ?2)";
+        self.store_data(query, params![uuid.serialize(), block_hash.to_vec()])
+    }
+
+    /// Loads the [UUID]s of the trackers whose dispute was seen in a given block.
+    pub(crate) fn load_triggered_in(&self, block_hash: &BlockHash) -> HashSet<UUID> {
+        let connection = self.reader();
+        let mut stmt = connection
+            .prepare("SELECT UUID FROM tracker_triggers WHERE block_hash=(?)")
+            .unwrap();
+        let mut rows = stmt.query([block_hash.to_vec()]).unwrap();
+
+        let mut uuids = HashSet::new();
+        while let Ok(Some(row)) = rows.next() {
+            let raw_uuid: Vec<u8> = row.get(0).unwrap();
+            uuids.insert(UUID::deserialize(&raw_uuid[0..20]).unwrap());
+        }
+
+        uuids
+    }
+
+    /// Queues the penalty of a [TransactionTracker] to be broadcast once `bitcoind` is reachable.
+    ///
+    /// The entry is removed alongside the tracker.
+    pub(crate) fn store_pending_broadcast(&self, uuid: UUID) -> Result<(), Error> {
+        let query = "INSERT OR IGNORE INTO pending_broadcasts (UUID) VALUES (?)";
+        self.store_data(query, params![uuid.serialize()])
+    }
+
+    /// Removes the penalty of a [TransactionTracker] from the broadcast queue.
+    pub(crate) fn remove_pending_broadcast(&self, uuid: UUID) {
+        let query = "DELETE FROM pending_broadcasts WHERE UUID=(?)";
+        if let Err(e) = self.remove_data(query, params![uuid.serialize()]) {
+            log::error!(
+                "Couldn't dequeue penalty broadcast: {}. Error: {:?}",
+                uuid,
+                e
+            );
+        }
+    }
+
+    /// Loads the [UUID]s of the trackers whose penalty is queued to be broadcast.
+    pub(crate) fn load_pending_broadcasts(&self) -> HashSet<UUID> {
+        let connection = self.reader();
+        let mut stmt = connection
+            .prepare("SELECT UUID FROM pending_broadcasts")
+            .unwrap();
+        let mut rows = stmt.query([]).unwrap();
+
+        let mut uuids = HashSet::new();
+        while let Ok(Some(row)) = rows.next() {
+            let raw_uuid: Vec<u8> = row.get(0).unwrap();
+            uuids.insert(UUID::deserialize(&raw_uuid[0..20]).unwrap());
+        }
+
+        uuids
+    }
+
+    /// Updates the confirmation status of a [TransactionTracker] in the database.
+    pub(crate) fn update_tracker_status(&self, uuid: UUID, status: &ConfirmationStatus) {
+        let (height, confirmed) = match status.to_db_data() {
+            Some(data) => data,
+            None => {
+                log::error!("Tracker status cannot be stored: {:?} ({})", status, uuid);
+                return;
+            }
+        };
+
+        let query = "UPDATE trackers SET height=(?1), confirmed=(?2) WHERE UUID=(?3)";
+        match self.update_data(query, params![height, confirmed, uuid.serialize()]) {
+            Ok(_) => {
+                log::debug!("Tracker status successfully updated: {}", uuid);
+            }
+            Err(_) => {
+                log::error!("Tracker not found, data cannot be updated: {}", uuid);
+            }
+        }
+    }
+
+    /// Removes the appointments whose penalty transaction was confirmed below the given height (alongside their
+    /// trackers) and gives the freed slots back to their users.
+    ///
+    /// Returns the removed appointments alongside the users they belonged to.
+    pub(crate) fn prune_responded_before(&self, height: u32) -> HashMap<UUID, UserId> {
+        let mut connection = self.writer();
+        let tx = connection.transaction().unwrap();
+
+        let mut pruned = HashMap::new();
+        let mut freed_slots: HashMap<UserId, u32> = HashMap::new();
+        {
+            let mut stmt = tx
+                .prepare(
+                    "SELECT a.UUID, a.user_id, a.encrypted_blob FROM appointments as a INNER JOIN trackers as t ON a.UUID=t.UUID WHERE t.confirmed=1 AND t.height<(?)",
+                )
+                .unwrap();
+            let mut rows = stmt.query([height]).unwrap();
+
+            while let Ok(Some(row)) = rows.next() {
+                let raw_uuid: Vec<u8> = row.get(0).unwrap();
+                let uuid = UUID::deserialize(&raw_uuid[0..20]).unwrap();
+                let raw_userid: Vec<u8> = row.get(1).unwrap();
+                let user_id = UserId::deserialize(&raw_userid).unwrap();
+                let e_blob: Vec<u8> = row.get(2).unwrap();
+
+                *freed_slots.entry(user_id).or_default() +=
+                    compute_appointment_slots(e_blob.len(), ENCRYPTED_BLOB_MAX_SIZE);
+                pruned.insert(uuid, user_id);
+            }
+        }
+
+        for uuid in pruned.keys() {
+            if let Err(e) = tx.execute(
+                "DELETE FROM appointments WHERE UUID=(?)",
+                [uuid.serialize()],
+            ) {
+                log::error!("Couldn't add deletion query to transaction. Error: {:?}", e);
+            }
+        }
+        for (user_id, slots) in freed_slots.iter() {
+            if let Err(e) = tx.execute(
+                "UPDATE users SET available_slots=available_slots+(?1) WHERE user_id=(?2)",
+                params![slots, user_id.serialize()],
+            ) {
+                log::error!("Couldn't add update query to transaction. Error: {:?}", e);
+            }
+        }
+
+        match tx.commit() {
+            Ok(_) => {
+                log::debug!("Responded appointments successfully pruned");
+                pruned
+            }
+            Err(e) => {
+                log::error!("Couldn't prune responded appointments. Error: {:?}", e);
+                HashMap::new()
+            }
+        }
+    }
+
     /// Loads a [TransactionTracker] from the database.
     pub(crate) fn load_tracker(&self, uuid: UUID) -> Result<TransactionTracker, Error> {
         let key = uuid.serialize();
-        let mut stmt = self.connection.prepare(
+        let connection = self.reader();
+        let mut stmt = connection.prepare(
             "SELECT t.*, a.user_id FROM trackers as t INNER JOIN appointments as a ON t.UUID=a.UUID WHERE t.UUID=(?)").unwrap();
 
         stmt.query_row([key], |row| {
@@ -510,8 +1154,8 @@ impl DBM {
     /// Loads all trackers from the database.
     pub(crate) fn load_all_trackers(&self) -> HashMap<UUID, TransactionTracker> {
         let mut trackers = HashMap::new();
-        let mut stmt = self
-            .connection
+        let connection = self.reader();
+        let mut stmt = connection
             .prepare("SELECT t.*, a.user_id FROM trackers as t INNER JOIN appointments as a ON t.UUID=a.UUID")
             .unwrap();
         let mut rows = stmt.query([]).unwrap();
@@ -542,16 +1186,147 @@ impl DBM {
         trackers
     }
 
+    /// Exports all the data regarding users (users, subscription history, appointments and trackers) in a portable format.
+    ///
+    /// All the data is read within the same transaction, so the export is a consistent snapshot of the database even if
+    /// it is being written to meanwhile.
+    pub(crate) fn export_data(&self) -> ExportedData {
+        let mut users = Vec::new();
+        let connection = self.reader();
+        // The transaction is only used for reading, so it is simply rolled back when dropped
+        let tx = Transaction::new_unchecked(&connection, TransactionBehavior::Deferred).unwrap();
+        let mut stmt = tx
+            .prepare(
+                "SELECT user_id, available_slots, subscription_expiry FROM users ORDER BY rowid",
+            )
+            .unwrap();
+        let mut rows = stmt.query([]).unwrap();
+        while let Ok(Some(row)) = rows.next() {
+            users.push(ExportedUser {
+                user_id: row.get(0).unwrap(),
+                available_slots: row.get(1).unwrap(),
+                subscription_expiry: row.get(2).unwrap(),
+            });
+        }
+
+        let mut subscription_history = Vec::new();
+        let mut stmt = tx
+            .prepare("SELECT user_id, event, available_slots, subscription_expiry, height FROM subscription_history ORDER BY id")
+            .unwrap();
+        let mut rows = stmt.query([]).unwrap();
+        while let Ok(Some(row)) = rows.next() {
+            subscription_history.push(ExportedSubscriptionEvent {
+                user_id: row.get(0).unwrap(),
+                event: row.get(1).unwrap(),
+                available_slots: row.get(2).unwrap(),
+                subscription_expiry: row.get(3).unwrap(),
+                height: row.get(4).unwrap(),
+            });
+        }
+
+        let mut appointments = Vec::new();
+        let mut stmt = tx
+            .prepare(
+                "SELECT a.UUID, a.user_id, a.locator, a.encrypted_blob, a.to_self_delay, a.user_signature, a.start_block, \
+                t.dispute_tx, t.penalty_tx, t.height, t.confirmed \
+                FROM appointments as a LEFT JOIN trackers as t ON a.UUID=t.UUID ORDER BY a.rowid",
+            )
+            .unwrap();
+        let mut rows = stmt.query([]).unwrap();
+        while let Ok(Some(row)) = rows.next() {
+            let dispute_tx: Option<Vec<u8>> = row.get(7).unwrap();
+            appointments.push(ExportedAppointment {
+                uuid: row.get(0).unwrap(),
+                user_id: row.get(1).unwrap(),
+                locator: row.get(2).unwrap(),
+                encrypted_blob: row.get(3).unwrap(),
+                to_self_delay: row.get(4).unwrap(),
+                user_signature: row.get(5).unwrap(),
+                start_block: row.get(6).unwrap(),
+                tracker: dispute_tx.map(|dispute_tx| ExportedTracker {
+                    dispute_tx,
+                    penalty_tx: row.get(8).unwrap(),
+                    height: row.get(9).unwrap(),
+                    confirmed: row.get(10).unwrap(),
+                }),
+            });
+        }
+
+        ExportedData::new(users, subscription_history, appointments)
+    }
+
+    /// Imports data previously exported using [export_data](Self::export_data).
+    ///
+    /// The data is imported atomically: either everything is stored or nothing is.
+    pub(crate) fn import_data(&self, data: &ExportedData) -> Result<(), Error> {
+        let mut connection = self.writer();
+        let tx = connection.transaction().unwrap();
+
+        for user in data.users.iter() {
+            tx.execute(
+                "INSERT INTO users (user_id, available_slots, subscription_expiry) VALUES (?1, ?2, ?3)",
+                params![user.user_id, user.available_slots, user.subscription_expiry],
+            )
+            .map_err(Error::Unknown)?;
+        }
+
+        for event in data.subscription_history.iter() {
+            tx.execute(
+                "INSERT INTO subscription_history (user_id, event, available_slots, subscription_expiry, height) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    event.user_id,
+                    event.event,
+                    event.available_slots,
+                    event.subscription_expiry,
+                    event.height
+                ],
+            )
+            .map_err(Error::Unknown)?;
+        }
+
+        for appointment in data.appointments.iter() {
+            tx.execute(
+                "INSERT INTO appointments (UUID, locator, encrypted_blob, to_self_delay, user_signature, start_block, user_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    appointment.uuid,
+                    appointment.locator,
+                    appointment.encrypted_blob,
+                    appointment.to_self_delay,
+                    appointment.user_signature,
+                    appointment.start_block,
+                    appointment.user_id,
+                ],
+            )
+            .map_err(Error::Unknown)?;
+
+            if let Some(tracker) = &appointment.tracker {
+                tx.execute(
+                    "INSERT INTO trackers (UUID, dispute_tx, penalty_tx, height, confirmed) VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![
+                        appointment.uuid,
+                        tracker.dispute_tx,
+                        tracker.penalty_tx,
+                        tracker.height,
+                        tracker.confirmed
+                    ],
+                )
+                .map_err(Error::Unknown)?;
+            }
+        }
+
+        tx.commit().map_err(Error::Unknown)
+    }
+
     /// Stores the last known block into the database.
-    pub(crate) fn store_last_known_block(&self, block_hash: &BlockHash) -> Result<(), Error> {
+    pub fn store_last_known_block(&self, block_hash: &BlockHash) -> Result<(), Error> {
         let query = "INSERT OR REPLACE INTO last_known_block (id, block_hash) VALUES (0, ?)";
         self.store_data(query, params![block_hash.to_vec()])
     }
 
     /// Loads the last known block from the database.
     pub fn load_last_known_block(&self) -> Result<BlockHash, Error> {
-        let mut stmt = self
-            .connection
+        let connection = self.reader();
+        let mut stmt = connection
             .prepare("SELECT block_hash FROM last_known_block WHERE id=0")
             .unwrap();
 
@@ -575,8 +1350,8 @@ impl DBM {
     /// Loads the key with higher id from the database. Old keys are not overwritten just in case a recovery is needed,
     /// but they are not accessible from the API either.
     pub fn load_tower_key(&self) -> Result<SecretKey, Error> {
-        let mut stmt = self
-            .connection
+        let connection = self.reader();
+        let mut stmt = connection
             .prepare(
                 "SELECT key FROM keys WHERE id = (SELECT seq FROM sqlite_sequence WHERE name=(?))",
             )
@@ -588,33 +1363,143 @@ impl DBM {
         })
         .map_err(|_| Error::NotFound)
     }
+
+    /// Replaces the tower secret key with a new one.
+    ///
+    /// The current key is retired instead of forgotten, so receipts signed with it can still be verified until
+    /// `valid_until` (block height).
+    pub(crate) fn rotate_tower_key(&self, sk: &SecretKey, valid_until: u32) -> Result<(), Error> {
+        let mut connection = self.writer();
+        let tx = connection.transaction().unwrap();
+        tx.execute(
+            "INSERT INTO retired_keys (id, valid_until) SELECT seq, (?) FROM sqlite_sequence WHERE name='keys'",
+            params![valid_until],
+        )
+        .map_err(Error::Unknown)?;
+        tx.execute("INSERT INTO keys (key) VALUES (?)", params![sk.to_string()])
+            .map_err(Error::Unknown)?;
+
+        tx.commit().map_err(Error::Unknown)
+    }
+
+    /// Loads the retired tower keys that are still valid at the given height, alongside the height they are valid until.
+    pub fn load_retired_tower_keys(&self, height: u32) -> Vec<(SecretKey, u32)> {
+        let connection = self.reader();
+        let mut stmt = connection
+            .prepare(
+                "SELECT k.key, r.valid_until FROM retired_keys as r INNER JOIN keys as k ON r.id=k.id WHERE r.valid_until >= (?)",
+            )
+            .unwrap();
+
+        stmt.query_map([height], |row| {
+            let sk: String = row.get(0).unwrap();
+            Ok((SecretKey::from_str(&sk).unwrap(), row.get(1).unwrap()))
+        })
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect()
+    }
+
+    /// Loads aggregate figures about the data held by the database, see [DBStats].
+    pub(crate) fn load_stats(&self) -> Result<DBStats, Error> {
+        let connection = self.reader();
+        let count = |query: &str| {
+            connection
+                .query_row(query, [], |row| row.get::<_, i64>(0))
+                .map(|count| count as u64)
+                .map_err(Error::Unknown)
+        };
+
+        let n_users = count("SELECT COUNT(*) FROM users")?;
+        let available_slots = count("SELECT COALESCE(SUM(available_slots), 0) FROM users")?;
+        let n_watched_appointments = count(
+            "SELECT COUNT(*) FROM appointments WHERE UUID NOT IN (SELECT UUID FROM trackers)",
+        )?;
+        let n_responded_appointments = count("SELECT COUNT(*) FROM trackers")?;
+        let db_size =
+            count("SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()")?;
+
+        let mut stmt = connection
+            .prepare("SELECT length(encrypted_blob) FROM appointments")
+            .map_err(Error::Unknown)?;
+        let used_slots = stmt
+            .query_map([], |row| row.get::<_, usize>(0))
+            .map_err(Error::Unknown)?
+            .map(|blob_size| {
+                blob_size
+                    .map(|size| compute_appointment_slots(size, ENCRYPTED_BLOB_MAX_SIZE) as u64)
+                    .map_err(Error::Unknown)
+            })
+            .sum::<Result<u64, Error>>()?;
+
+        Ok(DBStats {
+            n_users,
+            n_watched_appointments,
+            n_responded_appointments,
+            used_slots,
+            available_slots,
+            db_size,
+        })
+    }
+
+    /// Checks whether the database can be queried.
+    pub(crate) fn is_reachable(&self) -> bool {
+        self.reader()
+            .query_row("SELECT 1", [], |row| row.get::<_, u8>(0))
+            .is_ok()
+    }
+
+    /// Copies the database to `dst` using `SQLite`'s online backup API.
+    ///
+    /// The copy is consistent even if the database is being written to, so it is safe to call while the tower is running.
+    pub fn backup(&self, dst: &Path) -> Result<(), SqliteError> {
+        self.reader().backup(DatabaseName::Main, dst, None)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use crate::gatekeeper::SubscriptionEventKind;
     use crate::test_utils::{
         generate_dummy_appointment, generate_dummy_appointment_with_user, generate_uuid,
-        get_random_tracker, get_random_user_id,
+        get_random_tracker, get_random_tx, get_random_user_id,
     };
     use std::iter::FromIterator;
-    use teos_common::cryptography::get_random_bytes;
+    use teos_common::cryptography::{get_random_bytes, get_random_keypair};
 
     impl DBM {
         pub(crate) fn in_memory() -> Result<Self, SqliteError> {
             let connection = Connection::open_in_memory()?;
             connection.execute("PRAGMA foreign_keys=1;", [])?;
-            let mut dbm = Self { connection };
+            let dbm = Self {
+                writer: Mutex::new(connection),
+                readers: ConnectionPool::new(Vec::new()),
+            };
             dbm.create_tables()?;
 
             Ok(dbm)
         }
 
+        /// Makes any further write to the database fail, simulating a failure on the database side.
+        pub(crate) fn make_read_only(&self) {
+            self.writer().execute_batch("PRAGMA query_only=1;").unwrap();
+        }
+
+        /// Makes any further appointment update fail, simulating a failure on the database side.
+        pub(crate) fn make_appointment_updates_fail(&self) {
+            self.writer()
+                .execute_batch(
+                    "CREATE TRIGGER fail_update BEFORE UPDATE ON appointments BEGIN SELECT RAISE(ABORT, 'update failed'); END;",
+                )
+                .unwrap();
+        }
+
         pub(crate) fn load_user(&self, user_id: UserId) -> Result<UserInfo, Error> {
             let key = user_id.serialize();
-            let mut stmt = self
-                .connection
+            let connection = self.reader();
+            let mut stmt = connection
                 .prepare("SELECT available_slots, subscription_expiry FROM users WHERE user_id=(?)")
                 .unwrap();
             let user = stmt
@@ -624,20 +1509,80 @@ mod tests {
                     Ok(UserInfo::with_appointments(
                         slots,
                         expiry,
-                        self.load_user_appointments(user_id),
+                        DBM::user_appointments(&connection, user_id),
                     ))
                 })
                 .map_err(|_| Error::NotFound)?;
 
             Ok(user)
         }
-    }
+    }
+
+    #[test]
+    fn test_create_tables() {
+        let connection = Connection::open_in_memory().unwrap();
+        let dbm = DBM {
+            writer: Mutex::new(connection),
+            readers: ConnectionPool::new(Vec::new()),
+        };
+        dbm.create_tables().unwrap();
+    }
+
+    #[test]
+    fn test_is_reachable() {
+        let dbm = DBM::in_memory().unwrap();
+        assert!(dbm.is_reachable());
+    }
+
+    #[test]
+    fn test_load_stats() {
+        let dbm = DBM::in_memory().unwrap();
+
+        // An empty database holds nothing but has some size anyway
+        let stats = dbm.load_stats().unwrap();
+        assert_eq!(
+            (stats.n_users, stats.used_slots, stats.available_slots),
+            (0, 0, 0)
+        );
+        assert!(stats.db_size > 0);
+
+        // Three users with two appointments each, one of them big enough to take two slots
+        let mut uuids = Vec::new();
+        for _ in 0..3 {
+            let user_id = get_random_user_id();
+            dbm.store_user(user_id, &UserInfo::new(21, 42)).unwrap();
+            for _ in 0..2 {
+                let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+                dbm.store_appointment(uuid, &appointment).unwrap();
+                uuids.push((uuid, user_id));
+            }
+        }
+        let uuid = uuids[0].0;
+        let mut appointment = dbm.load_appointment(uuid).unwrap();
+        appointment.inner.encrypted_blob = get_random_bytes(ENCRYPTED_BLOB_MAX_SIZE + 1);
+        dbm.update_appointment(uuid, &appointment).unwrap();
+
+        // Two of the appointments have been responded to
+        for (uuid, user_id) in [uuids[1], uuids[2]] {
+            dbm.store_tracker(
+                uuid,
+                &get_random_tracker(user_id, ConfirmationStatus::InMempoolSince(42)),
+            )
+            .unwrap();
+        }
 
-    #[test]
-    fn test_create_tables() {
-        let connection = Connection::open_in_memory().unwrap();
-        let mut dbm = DBM { connection };
-        dbm.create_tables().unwrap();
+        let stats = dbm.load_stats().unwrap();
+        assert_eq!(
+            stats,
+            DBStats {
+                n_users: 3,
+                n_watched_appointments: 4,
+                n_responded_appointments: 2,
+                used_slots: 7,
+                available_slots: 63,
+                db_size: stats.db_size,
+            }
+        );
     }
 
     #[test]
@@ -728,14 +1673,41 @@ mod tests {
         assert_eq!(dbm.load_all_users(), users);
     }
 
+    #[test]
+    fn test_store_load_remove_banned_users() {
+        let dbm = DBM::in_memory().unwrap();
+        assert!(dbm.load_banned_users().is_empty());
+
+        // Users do not need to be registered to be banned
+        let user_id = get_random_user_id();
+        let other_user_id = get_random_user_id();
+        dbm.store_banned_user(user_id).unwrap();
+        dbm.store_banned_user(other_user_id).unwrap();
+        assert!(matches!(
+            dbm.store_banned_user(user_id),
+            Err(Error::AlreadyExists)
+        ));
+        assert_eq!(
+            dbm.load_banned_users(),
+            HashSet::from_iter([user_id, other_user_id])
+        );
+
+        dbm.remove_banned_user(user_id).unwrap();
+        assert!(matches!(
+            dbm.remove_banned_user(user_id),
+            Err(Error::NotFound)
+        ));
+        assert_eq!(dbm.load_banned_users(), HashSet::from_iter([other_user_id]));
+    }
+
     #[test]
     fn test_batch_remove_users() {
-        let mut dbm = DBM::in_memory().unwrap();
+        let dbm = DBM::in_memory().unwrap();
 
         // Set a limit value for the maximum number of variables in SQLite so we can
         // test splitting big queries into chunks.
         let limit = 10;
-        dbm.connection
+        dbm.writer()
             .set_limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER, limit);
 
         let mut to_be_deleted = HashSet::new();
@@ -767,7 +1739,7 @@ mod tests {
     #[test]
     fn test_batch_remove_users_cascade() {
         // Test that removing users cascade deleted appointments and trackers
-        let mut dbm = DBM::in_memory().unwrap();
+        let dbm = DBM::in_memory().unwrap();
         let uuid = generate_uuid();
         let appointment = generate_dummy_appointment(None);
         // The confirmation status doesn't really matter here, it can be any of {ConfirmedIn, InMempoolSince}.
@@ -810,7 +1782,7 @@ mod tests {
 
     #[test]
     fn test_batch_remove_nonexistent_users() {
-        let mut dbm = DBM::in_memory().unwrap();
+        let dbm = DBM::in_memory().unwrap();
         let users = (0..10)
             .map(|_| get_random_user_id())
             .collect::<HashSet<UserId>>();
@@ -819,6 +1791,33 @@ mod tests {
         dbm.batch_remove_users(&users);
     }
 
+    #[test]
+    fn test_store_load_subscription_history() {
+        let dbm = DBM::in_memory().unwrap();
+        let user_id = get_random_user_id();
+        assert!(dbm.load_subscription_history(user_id).is_empty());
+
+        let events = vec![
+            SubscriptionEvent::new(SubscriptionEventKind::Registered, 21, 42, 1),
+            SubscriptionEvent::new(SubscriptionEventKind::Renewed, 42, 43, 2),
+            SubscriptionEvent::new(SubscriptionEventKind::Expired, 42, 43, 50),
+        ];
+        for event in events.iter() {
+            dbm.store_subscription_event(user_id, event).unwrap();
+        }
+        // Events from other users are not mixed up
+        dbm.store_subscription_event(get_random_user_id(), &events[0])
+            .unwrap();
+
+        assert_eq!(dbm.load_subscription_history(user_id), events);
+
+        // The history is kept even if the user is deleted
+        let user = UserInfo::new(21, 42);
+        dbm.store_user(user_id, &user).unwrap();
+        dbm.batch_remove_users(&HashSet::from_iter(vec![user_id]));
+        assert_eq!(dbm.load_subscription_history(user_id), events);
+    }
+
     #[test]
     fn test_store_load_appointment() {
         let dbm = DBM::in_memory().unwrap();
@@ -843,6 +1842,64 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_concurrent_reads() {
+        let db_dir =
+            std::env::temp_dir().join(format!("teos_db_{}", hex::encode(get_random_bytes(8))));
+        std::fs::create_dir_all(&db_dir).unwrap();
+        let dbm = std::sync::Arc::new(DBM::new(db_dir.join("teos_db.sql3")).unwrap());
+        assert_eq!(dbm.readers.size, READ_CONNECTIONS);
+
+        let user_id = get_random_user_id();
+        dbm.store_user(user_id, &UserInfo::new(21, 42)).unwrap();
+        let mut appointments = HashMap::new();
+        for _ in 0..10 {
+            let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+            dbm.store_appointment(uuid, &appointment).unwrap();
+            appointments.insert(uuid, appointment);
+        }
+        let appointments = std::sync::Arc::new(appointments);
+
+        // Spawn more readers than pooled connections, alongside a writer, and check they all get served
+        let mut handles = Vec::new();
+        for _ in 0..READ_CONNECTIONS * 2 {
+            let dbm = dbm.clone();
+            let appointments = appointments.clone();
+            handles.push(std::thread::spawn(move || {
+                for _ in 0..20 {
+                    for (uuid, appointment) in appointments.iter() {
+                        assert_eq!(&dbm.load_appointment(*uuid).unwrap(), appointment);
+                    }
+                    assert_eq!(dbm.load_all_users()[&user_id].appointments.len(), 10);
+                }
+            }));
+        }
+        let writer = {
+            let dbm = dbm.clone();
+            std::thread::spawn(move || {
+                for _ in 0..50 {
+                    dbm.store_user(get_random_user_id(), &UserInfo::new(21, 42))
+                        .unwrap();
+                }
+            })
+        };
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        writer.join().unwrap();
+
+        // All the connections are back in the pool and the readers see the data stored by the writer
+        assert_eq!(
+            dbm.readers.connections.lock().unwrap().len(),
+            READ_CONNECTIONS
+        );
+        assert_eq!(dbm.load_all_users().len(), 51);
+
+        drop(dbm);
+        std::fs::remove_dir_all(db_dir).unwrap();
+    }
+
     #[test]
     fn test_store_appointment_missing_user() {
         let dbm = DBM::in_memory().unwrap();
@@ -888,7 +1945,11 @@ mod tests {
         another_modified_appointment.user_id = get_random_user_id();
 
         // Check how only the modifiable fields have been updated
-        dbm.update_appointment(uuid, &another_modified_appointment);
+        assert_eq!(
+            dbm.update_appointment(uuid, &another_modified_appointment)
+                .unwrap(),
+            1
+        );
         assert_eq!(dbm.load_appointment(uuid).unwrap(), modified_appointment);
         assert_ne!(
             dbm.load_appointment(uuid).unwrap(),
@@ -896,6 +1957,50 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_update_appointment_version() {
+        let dbm = DBM::in_memory().unwrap();
+
+        let user_id = get_random_user_id();
+        dbm.store_user(user_id, &UserInfo::new(21, 42)).unwrap();
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+
+        // Unknown appointments cannot be updated, so they get no version either
+        assert!(matches!(
+            dbm.update_appointment(uuid, &appointment),
+            Err(Error::NotFound)
+        ));
+        assert_eq!(dbm.load_appointment_version(uuid), 0);
+
+        // Known ones start at zero and go up by one with every update
+        dbm.store_appointment(uuid, &appointment).unwrap();
+        assert_eq!(dbm.load_appointment_version(uuid), 0);
+        for version in 1..4 {
+            assert_eq!(dbm.update_appointment(uuid, &appointment).unwrap(), version);
+            assert_eq!(dbm.load_appointment_version(uuid), version);
+        }
+
+        // The version goes away with the appointment
+        dbm.remove_appointment(uuid);
+        assert_eq!(dbm.load_appointment_version(uuid), 0);
+
+        // If the version cannot be bumped, the data is not updated either
+        dbm.store_appointment(uuid, &appointment).unwrap();
+        dbm.writer()
+            .execute_batch(
+                "CREATE TRIGGER fail_bump BEFORE INSERT ON appointment_versions BEGIN SELECT RAISE(ABORT, 'bump failed'); END;",
+            )
+            .unwrap();
+        let mut modified_appointment = appointment.clone();
+        modified_appointment.inner.encrypted_blob.reverse();
+        assert!(matches!(
+            dbm.update_appointment(uuid, &modified_appointment),
+            Err(Error::Unknown(_))
+        ));
+        assert_eq!(dbm.load_appointment(uuid).unwrap(), appointment);
+        assert_eq!(dbm.load_appointment_version(uuid), 0);
+    }
+
     #[test]
     fn test_load_all_appointments() {
         let dbm = DBM::in_memory().unwrap();
@@ -930,14 +2035,152 @@ mod tests {
         assert_eq!(dbm.load_all_appointments(), appointments);
     }
 
+    #[test]
+    fn test_load_appointments() {
+        let dbm = DBM::in_memory().unwrap();
+
+        // Seed the database with two users, each with some appointments being watched and some triggered
+        let mut watched = HashMap::new();
+        let mut responded = HashMap::new();
+        let users = [get_random_user_id(), get_random_user_id()];
+        for (i, user_id) in users.iter().enumerate() {
+            dbm.store_user(*user_id, &UserInfo::new(21, 42)).unwrap();
+
+            for j in 0..(3 + i) {
+                let (uuid, appointment) = generate_dummy_appointment_with_user(*user_id, None);
+                dbm.store_appointment(uuid, &appointment).unwrap();
+
+                if j % 2 == 0 {
+                    watched.insert(uuid, *user_id);
+                } else {
+                    let tracker =
+                        get_random_tracker(*user_id, ConfirmationStatus::InMempoolSince(42));
+                    dbm.store_tracker(uuid, &tracker).unwrap();
+                    responded.insert(uuid, *user_id);
+                }
+            }
+        }
+
+        let uuids = |user_id: Option<UserId>, status: Option<AppointmentStatus>| {
+            let mut uuids = HashSet::new();
+            for (uuid, info) in dbm.load_appointments(user_id, status, 0, 0) {
+                match info {
+                    AppointmentInfo::Appointment(_) => assert!(watched.contains_key(&uuid)),
+                    AppointmentInfo::Tracker(_) => assert!(responded.contains_key(&uuid)),
+                }
+                uuids.insert(uuid);
+            }
+            uuids
+        };
+        let expected = |filter: &dyn Fn(&UserId) -> bool, include_watched, include_responded| {
+            let mut expected = HashSet::new();
+            for (map, include) in [(&watched, include_watched), (&responded, include_responded)] {
+                if include {
+                    expected.extend(map.iter().filter(|(_, u)| filter(u)).map(|(uuid, _)| *uuid));
+                }
+            }
+            expected
+        };
+
+        // No filters
+        assert_eq!(uuids(None, None), expected(&|_| true, true, true));
+
+        // Filter by status
+        assert_eq!(
+            uuids(None, Some(AppointmentStatus::BeingWatched)),
+            expected(&|_| true, true, false)
+        );
+        assert_eq!(
+            uuids(None, Some(AppointmentStatus::DisputeResponded)),
+            expected(&|_| true, false, true)
+        );
+        assert!(uuids(None, Some(AppointmentStatus::NotFound)).is_empty());
+
+        // Filter by user, with and without status
+        for user_id in users.iter() {
+            assert_eq!(
+                uuids(Some(*user_id), None),
+                expected(&|u| u == user_id, true, true)
+            );
+            assert_eq!(
+                uuids(Some(*user_id), Some(AppointmentStatus::BeingWatched)),
+                expected(&|u| u == user_id, true, false)
+            );
+            assert_eq!(
+                uuids(Some(*user_id), Some(AppointmentStatus::DisputeResponded)),
+                expected(&|u| u == user_id, false, true)
+            );
+        }
+
+        // Unknown users get nothing back
+        assert!(uuids(Some(get_random_user_id()), None).is_empty());
+
+        // Pages do not overlap and, together, cover all the appointments
+        let all: Vec<UUID> = dbm
+            .load_appointments(None, None, 0, 0)
+            .into_iter()
+            .map(|(uuid, _)| uuid)
+            .collect();
+        let mut paged = Vec::new();
+        for offset in (0..all.len() as u32).step_by(2) {
+            let page = dbm.load_appointments(None, None, 2, offset);
+            assert!(page.len() <= 2);
+            paged.extend(page.into_iter().map(|(uuid, _)| uuid));
+        }
+        assert_eq!(paged, all);
+        assert!(dbm
+            .load_appointments(None, None, 2, all.len() as u32)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_load_appointments_by_locator() {
+        let dbm = DBM::in_memory().unwrap();
+
+        // Two users with an appointment for the same dispute (one of them already triggered) and an unrelated one
+        let dispute_txid = get_random_tx().txid();
+        let mut stored = Vec::new();
+        for dispute_txid in [Some(&dispute_txid), Some(&dispute_txid), None] {
+            let user_id = get_random_user_id();
+            let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, dispute_txid);
+            dbm.store_user(user_id, &UserInfo::new(21, 42)).unwrap();
+            dbm.store_appointment(uuid, &appointment).unwrap();
+            stored.push((uuid, user_id));
+        }
+        let (triggered_uuid, triggered_user) = stored[1];
+        dbm.store_tracker(
+            triggered_uuid,
+            &get_random_tracker(triggered_user, ConfirmationStatus::InMempoolSince(42)),
+        )
+        .unwrap();
+
+        let found: HashMap<UUID, AppointmentInfo> = dbm
+            .load_appointments_by_locator(Locator::new(dispute_txid))
+            .into_iter()
+            .collect();
+        assert_eq!(found.len(), 2);
+        assert!(
+            matches!(&found[&stored[0].0], AppointmentInfo::Appointment(a) if a.locator == Locator::new(dispute_txid))
+        );
+        assert!(matches!(
+            &found[&triggered_uuid],
+            AppointmentInfo::Tracker(_)
+        ));
+
+        // Unknown locators get nothing back
+        assert!(dbm
+            .load_appointments_by_locator(Locator::new(get_random_tx().txid()))
+            .is_empty());
+    }
+
     #[test]
     fn test_batch_remove_appointments() {
-        let mut dbm = DBM::in_memory().unwrap();
+        let dbm = DBM::in_memory().unwrap();
 
         // Set a limit value for the maximum number of variables in SQLite so we can
         // test splitting big queries into chunks.
         let limit = 10;
-        dbm.connection
+        dbm.writer()
             .set_limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER, limit);
 
         let user_id = get_random_user_id();
@@ -985,7 +2228,7 @@ mod tests {
 
     #[test]
     fn test_batch_remove_appointments_cascade() {
-        let mut dbm = DBM::in_memory().unwrap();
+        let dbm = DBM::in_memory().unwrap();
         let uuid = generate_uuid();
         let appointment = generate_dummy_appointment(None);
         // The confirmation status doesn't really matter here, it can be any of {ConfirmedIn, InMempoolSince}.
@@ -1025,7 +2268,7 @@ mod tests {
 
     #[test]
     fn test_batch_remove_nonexistent_appointments() {
-        let mut dbm = DBM::in_memory().unwrap();
+        let dbm = DBM::in_memory().unwrap();
         let appointments = (0..10).map(|_| generate_uuid()).collect::<HashSet<UUID>>();
 
         // Test it does not fail even if the user does not exist (it will log though)
@@ -1078,6 +2321,145 @@ mod tests {
         assert_eq!(dbm.load_tracker(uuid).unwrap(), tracker);
     }
 
+    #[test]
+    fn test_update_tracker_status() {
+        let dbm = DBM::in_memory().unwrap();
+
+        let user_id = get_random_user_id();
+        dbm.store_user(user_id, &UserInfo::new(21, 42)).unwrap();
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        dbm.store_appointment(uuid, &appointment).unwrap();
+        let mut tracker = get_random_tracker(user_id, ConfirmationStatus::InMempoolSince(21));
+        dbm.store_tracker(uuid, &tracker).unwrap();
+
+        tracker.status = ConfirmationStatus::ConfirmedIn(42);
+        dbm.update_tracker_status(uuid, &tracker.status);
+        assert_eq!(dbm.load_tracker(uuid).unwrap(), tracker);
+
+        // Statuses that cannot be stored are ignored
+        dbm.update_tracker_status(uuid, &ConfirmationStatus::ReorgedOut);
+        assert_eq!(dbm.load_tracker(uuid).unwrap(), tracker);
+    }
+
+    #[test]
+    fn test_prune_responded_before() {
+        let dbm = DBM::in_memory().unwrap();
+
+        let user_id = get_random_user_id();
+        let mut user = UserInfo::new(21, 42);
+        dbm.store_user(user_id, &user).unwrap();
+
+        // Add some trackers confirmed at different heights, and one that has not been confirmed yet
+        let mut uuids = Vec::new();
+        for status in [
+            ConfirmationStatus::ConfirmedIn(100),
+            ConfirmationStatus::ConfirmedIn(110),
+            ConfirmationStatus::ConfirmedIn(120),
+            ConfirmationStatus::InMempoolSince(100),
+        ] {
+            let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+            dbm.store_appointment(uuid, &appointment).unwrap();
+            dbm.store_tracker(uuid, &get_random_tracker(user_id, status))
+                .unwrap();
+            user.appointments.insert(uuid, 1);
+            uuids.push(uuid);
+        }
+        // Appointments that have not been triggered are never pruned
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        dbm.store_appointment(uuid, &appointment).unwrap();
+        user.appointments.insert(uuid, 1);
+
+        // Only the trackers confirmed below the given height are pruned
+        assert!(dbm.prune_responded_before(100).is_empty());
+        assert_eq!(
+            dbm.prune_responded_before(111),
+            HashMap::from_iter([(uuids[0], user_id), (uuids[1], user_id)])
+        );
+        for uuid in uuids.iter().take(2) {
+            assert!(matches!(dbm.load_appointment(*uuid), Err(Error::NotFound)));
+            assert!(matches!(dbm.load_tracker(*uuid), Err(Error::NotFound)));
+            user.appointments.remove(uuid);
+        }
+        for uuid in uuids.iter().skip(2) {
+            assert!(dbm.load_tracker(*uuid).is_ok());
+        }
+
+        // The freed slots are given back to the user
+        user.available_slots += 2;
+        assert_eq!(dbm.load_user(user_id).unwrap(), user);
+    }
+
+    #[test]
+    fn test_remove_tracker() {
+        let dbm = DBM::in_memory().unwrap();
+
+        let user_id = get_random_user_id();
+        dbm.store_user(user_id, &UserInfo::new(21, 42)).unwrap();
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        dbm.store_appointment(uuid, &appointment).unwrap();
+        let tracker = get_random_tracker(user_id, ConfirmationStatus::ConfirmedIn(21));
+        dbm.store_tracker(uuid, &tracker).unwrap();
+        assert!(!dbm.load_all_appointments().contains_key(&uuid));
+
+        // Removing the tracker leaves the appointment in place, which is now considered to be watched
+        dbm.remove_tracker(uuid);
+        assert!(matches!(dbm.load_tracker(uuid), Err(Error::NotFound)));
+        assert_eq!(dbm.load_all_appointments()[&uuid], appointment);
+    }
+
+    #[test]
+    fn test_store_load_trigger_block() {
+        let dbm = DBM::in_memory().unwrap();
+
+        let user_id = get_random_user_id();
+        dbm.store_user(user_id, &UserInfo::new(21, 42)).unwrap();
+        let block_hash = BlockHash::from_slice(&get_random_bytes(32)).unwrap();
+        assert!(dbm.load_triggered_in(&block_hash).is_empty());
+
+        // Triggers can only be stored for existing trackers
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        dbm.store_appointment(uuid, &appointment).unwrap();
+        assert!(dbm.store_trigger_block(uuid, &block_hash).is_err());
+
+        let tracker = get_random_tracker(user_id, ConfirmationStatus::InMempoolSince(42));
+        dbm.store_tracker(uuid, &tracker).unwrap();
+        dbm.store_trigger_block(uuid, &block_hash).unwrap();
+        assert_eq!(
+            dbm.load_triggered_in(&block_hash),
+            HashSet::from_iter([uuid])
+        );
+
+        // The trigger is removed alongside the tracker
+        dbm.remove_tracker(uuid);
+        assert!(dbm.load_triggered_in(&block_hash).is_empty());
+    }
+
+    #[test]
+    fn test_store_load_remove_pending_broadcast() {
+        let dbm = DBM::in_memory().unwrap();
+
+        let user_id = get_random_user_id();
+        dbm.store_user(user_id, &UserInfo::new(21, 42)).unwrap();
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        dbm.store_appointment(uuid, &appointment).unwrap();
+        assert!(dbm.store_pending_broadcast(uuid).is_err());
+
+        let tracker = get_random_tracker(user_id, ConfirmationStatus::InMempoolSince(42));
+        dbm.store_tracker(uuid, &tracker).unwrap();
+        dbm.store_pending_broadcast(uuid).unwrap();
+        // Queueing the same penalty twice is fine
+        dbm.store_pending_broadcast(uuid).unwrap();
+        assert_eq!(dbm.load_pending_broadcasts(), HashSet::from_iter([uuid]));
+
+        dbm.remove_pending_broadcast(uuid);
+        assert!(dbm.load_pending_broadcasts().is_empty());
+
+        // The queue entry is also removed alongside the tracker
+        dbm.store_pending_broadcast(uuid).unwrap();
+        dbm.remove_tracker(uuid);
+        assert!(dbm.load_pending_broadcasts().is_empty());
+    }
+
     #[test]
     fn test_store_duplicate_tracker() {
         let dbm = DBM::in_memory().unwrap();
@@ -1146,6 +2528,98 @@ mod tests {
         assert_eq!(dbm.load_all_trackers(), trackers);
     }
 
+    #[test]
+    fn test_export_import_data() {
+        let dbm = DBM::in_memory().unwrap();
+
+        // Seed the database with some users, their subscription history and appointments (some of them triggered)
+        for i in 1..6 {
+            let user_id = get_random_user_id();
+            dbm.store_user(user_id, &UserInfo::new(i, i * 2)).unwrap();
+            dbm.store_subscription_event(
+                user_id,
+                &SubscriptionEvent::new(SubscriptionEventKind::Registered, i, i * 2, i),
+            )
+            .unwrap();
+
+            for j in 0..i {
+                let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+                dbm.store_appointment(uuid, &appointment).unwrap();
+
+                if j % 2 == 1 {
+                    let tracker = get_random_tracker(user_id, ConfirmationStatus::ConfirmedIn(j));
+                    dbm.store_tracker(uuid, &tracker).unwrap();
+                }
+            }
+        }
+
+        let exported = dbm.export_data();
+        assert_eq!(exported.users.len(), 5);
+        assert_eq!(exported.subscription_history.len(), 5);
+        assert_eq!(exported.appointments.len(), 15);
+
+        // Importing the data into a fresh database should get us the exact same state
+        let imported_dbm = DBM::in_memory().unwrap();
+        imported_dbm.import_data(&exported).unwrap();
+
+        assert_eq!(imported_dbm.export_data(), exported);
+        assert_eq!(imported_dbm.load_all_users(), dbm.load_all_users());
+        assert_eq!(
+            imported_dbm.load_all_appointments(),
+            dbm.load_all_appointments()
+        );
+        assert_eq!(imported_dbm.load_all_trackers(), dbm.load_all_trackers());
+        for user in exported.users.iter() {
+            let user_id = UserId::deserialize(&user.user_id).unwrap();
+            assert_eq!(
+                imported_dbm.load_subscription_history(user_id),
+                dbm.load_subscription_history(user_id)
+            );
+        }
+    }
+
+    #[test]
+    fn test_import_data_inconsistent() {
+        let dbm = DBM::in_memory().unwrap();
+        let user_id = get_random_user_id();
+        dbm.store_user(user_id, &UserInfo::new(21, 42)).unwrap();
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        dbm.store_appointment(uuid, &appointment).unwrap();
+
+        // Drop the user so the appointment references a missing one. Nothing should be imported
+        let mut exported = dbm.export_data();
+        exported.users.clear();
+
+        let imported_dbm = DBM::in_memory().unwrap();
+        assert!(imported_dbm.import_data(&exported).is_err());
+        assert!(imported_dbm.load_all_appointments().is_empty());
+        assert!(imported_dbm.load_all_users().is_empty());
+    }
+
+    #[test]
+    fn test_rotate_tower_key() {
+        let dbm = DBM::in_memory().unwrap();
+
+        let (old_sk, _) = get_random_keypair();
+        dbm.store_tower_key(&old_sk).unwrap();
+
+        let (new_sk, _) = get_random_keypair();
+        dbm.rotate_tower_key(&new_sk, 100).unwrap();
+
+        assert_eq!(dbm.load_tower_key().unwrap(), new_sk);
+        assert_eq!(dbm.load_retired_tower_keys(100), vec![(old_sk, 100)]);
+        assert!(dbm.load_retired_tower_keys(101).is_empty());
+
+        // Rotating again retires the newest key
+        let (newer_sk, _) = get_random_keypair();
+        dbm.rotate_tower_key(&newer_sk, 200).unwrap();
+        assert_eq!(dbm.load_tower_key().unwrap(), newer_sk);
+        assert_eq!(
+            dbm.load_retired_tower_keys(0),
+            vec![(old_sk, 100), (new_sk, 200)]
+        );
+    }
+
     #[test]
     fn test_store_load_last_known_block() {
         let dbm = DBM::in_memory().unwrap();
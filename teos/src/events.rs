@@ -0,0 +1,123 @@
+//! Logic related to the tower events, published by the tower components as they happen so they can be streamed to
+//! subscribers (e.g. dashboards).
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use bitcoin::Txid;
+
+use teos_common::appointment::Locator;
+use teos_common::UserId;
+
+/// Number of events a subscriber can fall behind before being dropped.
+pub const EVENTS_CHANNEL_CAPACITY: usize = 1024;
+
+/// Sending half of the events channel. Shared by all the components publishing events.
+pub type EventSender = broadcast::Sender<Event>;
+
+/// Creates a new events channel, returning its sending half. Subscribers are created on demand.
+pub fn channel() -> EventSender {
+    broadcast::channel(EVENTS_CHANNEL_CAPACITY).0
+}
+
+/// Something relevant that happened in the tower.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// An appointment has been accepted by the [Watcher](crate::watcher::Watcher).
+    AppointmentAccepted { user_id: String, locator: String },
+    /// An appointment from a registered user has been rejected by the [Watcher](crate::watcher::Watcher).
+    AppointmentRejected {
+        user_id: String,
+        locator: String,
+        reason: String,
+    },
+    /// A penalty transaction has been broadcast by the [Responder](crate::responder::Responder).
+    PenaltyBroadcast {
+        user_id: String,
+        locator: String,
+        penalty_txid: String,
+    },
+    /// A subscription is about to be outdated by the [Gatekeeper](crate::gatekeeper::Gatekeeper), at which point its
+    /// appointments are dropped unless it is renewed.
+    SubscriptionExpiring {
+        user_id: String,
+        subscription_expiry: u32,
+        outdated_at: u32,
+    },
+}
+
+impl Event {
+    /// Creates an [Event::AppointmentAccepted].
+    pub(crate) fn appointment_accepted(user_id: UserId, locator: Locator) -> Self {
+        Event::AppointmentAccepted {
+            user_id: user_id.to_string(),
+            locator: locator.to_string(),
+        }
+    }
+
+    /// Creates an [Event::AppointmentRejected].
+    pub(crate) fn appointment_rejected(user_id: UserId, locator: Locator, reason: String) -> Self {
+        Event::AppointmentRejected {
+            user_id: user_id.to_string(),
+            locator: locator.to_string(),
+            reason,
+        }
+    }
+
+    /// Creates an [Event::PenaltyBroadcast].
+    pub(crate) fn penalty_broadcast(user_id: UserId, locator: Locator, penalty_txid: Txid) -> Self {
+        Event::PenaltyBroadcast {
+            user_id: user_id.to_string(),
+            locator: locator.to_string(),
+            penalty_txid: penalty_txid.to_string(),
+        }
+    }
+
+    /// Creates an [Event::SubscriptionExpiring].
+    pub(crate) fn subscription_expiring(
+        user_id: UserId,
+        subscription_expiry: u32,
+        outdated_at: u32,
+    ) -> Self {
+        Event::SubscriptionExpiring {
+            user_id: user_id.to_string(),
+            subscription_expiry,
+            outdated_at,
+        }
+    }
+
+    /// Gets the identifier of the user the event refers to.
+    pub(crate) fn user_id(&self) -> &str {
+        match self {
+            Event::AppointmentAccepted { user_id, .. }
+            | Event::AppointmentRejected { user_id, .. }
+            | Event::PenaltyBroadcast { user_id, .. }
+            | Event::SubscriptionExpiring { user_id, .. } => user_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_utils::{get_random_tx, get_random_user_id};
+
+    #[test]
+    fn test_serialize_event() {
+        let user_id = get_random_user_id();
+        let locator = Locator::new(get_random_tx().txid());
+        let event = Event::appointment_accepted(user_id, locator);
+
+        assert_eq!(event.user_id(), user_id.to_string());
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({
+                "event": "appointment_accepted",
+                "user_id": user_id.to_string(),
+                "locator": locator.to_string(),
+            })
+        );
+    }
+}
@@ -0,0 +1,234 @@
+//! Logic related to exporting (and importing) the tower data in a portable, versioned, JSON format.
+//!
+//! Exported data can be used to move a tower between machines without having to copy the underlying database.
+
+use serde::{Deserialize, Serialize};
+
+use bitcoin::consensus;
+use bitcoin::Transaction;
+
+use teos_common::appointment::Locator;
+use teos_common::UserId;
+
+use crate::extended_appointment::UUID;
+use crate::gatekeeper::SubscriptionEventKind;
+
+/// Current version of the export format. Bump it (and handle the old one in [ExportedData::from_json]) whenever
+/// the format changes.
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+/// A user, as stored by the tower.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ExportedUser {
+    #[serde(with = "hex::serde")]
+    pub(crate) user_id: Vec<u8>,
+    pub(crate) available_slots: u32,
+    pub(crate) subscription_expiry: u32,
+}
+
+/// An entry of the subscription history of a user.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ExportedSubscriptionEvent {
+    #[serde(with = "hex::serde")]
+    pub(crate) user_id: Vec<u8>,
+    pub(crate) event: String,
+    pub(crate) available_slots: u32,
+    pub(crate) subscription_expiry: u32,
+    pub(crate) height: u32,
+}
+
+/// The tracker of a triggered appointment, as stored by the tower.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ExportedTracker {
+    #[serde(with = "hex::serde")]
+    pub(crate) dispute_tx: Vec<u8>,
+    #[serde(with = "hex::serde")]
+    pub(crate) penalty_tx: Vec<u8>,
+    pub(crate) height: u32,
+    pub(crate) confirmed: bool,
+}
+
+/// An appointment, as stored by the tower, alongside its tracker if the appointment has been triggered.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ExportedAppointment {
+    #[serde(with = "hex::serde")]
+    pub(crate) uuid: Vec<u8>,
+    #[serde(with = "hex::serde")]
+    pub(crate) user_id: Vec<u8>,
+    #[serde(with = "hex::serde")]
+    pub(crate) locator: Vec<u8>,
+    #[serde(with = "hex::serde")]
+    pub(crate) encrypted_blob: Vec<u8>,
+    pub(crate) to_self_delay: u32,
+    pub(crate) user_signature: String,
+    pub(crate) start_block: u32,
+    pub(crate) tracker: Option<ExportedTracker>,
+}
+
+/// All the data held by the tower regarding its users.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ExportedData {
+    pub(crate) version: u32,
+    pub(crate) users: Vec<ExportedUser>,
+    pub(crate) subscription_history: Vec<ExportedSubscriptionEvent>,
+    pub(crate) appointments: Vec<ExportedAppointment>,
+}
+
+impl ExportedData {
+    /// Creates a new [ExportedData] instance using the current format version.
+    pub(crate) fn new(
+        users: Vec<ExportedUser>,
+        subscription_history: Vec<ExportedSubscriptionEvent>,
+        appointments: Vec<ExportedAppointment>,
+    ) -> Self {
+        ExportedData {
+            version: EXPORT_FORMAT_VERSION,
+            users,
+            subscription_history,
+            appointments,
+        }
+    }
+
+    /// Serializes the data as a JSON document.
+    pub(crate) fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+
+    /// Parses (and validates) a JSON document holding exported data.
+    ///
+    /// Documents from older format versions should be migrated here so the rest of the tower only deals with the
+    /// current one.
+    pub(crate) fn from_json(data: &[u8]) -> Result<Self, String> {
+        let version = serde_json::from_slice::<serde_json::Value>(data)
+            .map_err(|e| format!("Invalid export document: {}", e))?
+            .get("version")
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| "Missing export format version".to_owned())?;
+
+        let exported = match version as u32 {
+            EXPORT_FORMAT_VERSION => serde_json::from_slice::<ExportedData>(data)
+                .map_err(|e| format!("Invalid export document: {}", e))?,
+            v => return Err(format!("Unsupported export format version: {}", v)),
+        };
+
+        exported.validate()?;
+        Ok(exported)
+    }
+
+    /// Checks that all the exported fields can be decoded into their tower counterparts.
+    fn validate(&self) -> Result<(), String> {
+        for user in self.users.iter() {
+            UserId::deserialize(&user.user_id)
+                .map_err(|_| format!("Invalid user_id: {}", hex::encode(&user.user_id)))?;
+        }
+
+        for event in self.subscription_history.iter() {
+            UserId::deserialize(&event.user_id)
+                .map_err(|_| format!("Invalid user_id: {}", hex::encode(&event.user_id)))?;
+            event.event.parse::<SubscriptionEventKind>()?;
+        }
+
+        for appointment in self.appointments.iter() {
+            UUID::deserialize(&appointment.uuid)
+                .map_err(|_| format!("Invalid uuid: {}", hex::encode(&appointment.uuid)))?;
+            UserId::deserialize(&appointment.user_id)
+                .map_err(|_| format!("Invalid user_id: {}", hex::encode(&appointment.user_id)))?;
+            Locator::deserialize(&appointment.locator)
+                .map_err(|_| format!("Invalid locator: {}", hex::encode(&appointment.locator)))?;
+
+            if let Some(tracker) = &appointment.tracker {
+                for raw_tx in [&tracker.dispute_tx, &tracker.penalty_tx] {
+                    consensus::deserialize::<Transaction>(raw_tx)
+                        .map_err(|_| format!("Invalid transaction: {}", hex::encode(raw_tx)))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_utils::{generate_uuid, get_random_tx, get_random_user_id};
+
+    fn get_random_exported_data() -> ExportedData {
+        let user_id = get_random_user_id().serialize();
+        ExportedData::new(
+            vec![ExportedUser {
+                user_id: user_id.clone(),
+                available_slots: 21,
+                subscription_expiry: 42,
+            }],
+            vec![ExportedSubscriptionEvent {
+                user_id: user_id.clone(),
+                event: "registered".into(),
+                available_slots: 21,
+                subscription_expiry: 42,
+                height: 1,
+            }],
+            vec![ExportedAppointment {
+                uuid: generate_uuid().serialize(),
+                user_id,
+                locator: Locator::new(get_random_tx().txid()).serialize(),
+                encrypted_blob: vec![1, 2, 3],
+                to_self_delay: 42,
+                user_signature: "signature".into(),
+                start_block: 1,
+                tracker: Some(ExportedTracker {
+                    dispute_tx: consensus::serialize(&get_random_tx()),
+                    penalty_tx: consensus::serialize(&get_random_tx()),
+                    height: 1,
+                    confirmed: false,
+                }),
+            }],
+        )
+    }
+
+    #[test]
+    fn test_to_from_json() {
+        let data = get_random_exported_data();
+        assert_eq!(ExportedData::from_json(&data.to_json()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_from_json_wrong_version() {
+        let mut data = get_random_exported_data();
+        data.version = EXPORT_FORMAT_VERSION + 1;
+        assert_eq!(
+            ExportedData::from_json(&data.to_json()).unwrap_err(),
+            format!(
+                "Unsupported export format version: {}",
+                EXPORT_FORMAT_VERSION + 1
+            )
+        );
+
+        assert_eq!(
+            ExportedData::from_json(b"{}").unwrap_err(),
+            "Missing export format version"
+        );
+    }
+
+    #[test]
+    fn test_from_json_invalid_data() {
+        let mut data = get_random_exported_data();
+        data.appointments[0].locator = vec![0; 3];
+        assert!(ExportedData::from_json(&data.to_json())
+            .unwrap_err()
+            .starts_with("Invalid locator"));
+
+        let mut data = get_random_exported_data();
+        data.subscription_history[0].event = "unknown".into();
+        assert!(ExportedData::from_json(&data.to_json())
+            .unwrap_err()
+            .starts_with("Unknown subscription event"));
+
+        let mut data = get_random_exported_data();
+        data.appointments[0].tracker.as_mut().unwrap().penalty_tx = vec![0; 3];
+        assert!(ExportedData::from_json(&data.to_json())
+            .unwrap_err()
+            .starts_with("Invalid transaction"));
+    }
+}
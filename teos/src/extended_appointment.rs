@@ -4,10 +4,8 @@ use std::array::TryFromSliceError;
 use std::convert::TryInto;
 use std::fmt;
 
-use bitcoin::hashes::{ripemd160, Hash};
-
 use crate::protos as msgs;
-use teos_common::appointment::{Appointment, Locator};
+use teos_common::appointment::{compute_uuid, Appointment, Locator};
 use teos_common::UserId;
 
 /// Unique identifier used to identify appointments.
@@ -22,9 +20,7 @@ impl UUID {
     /// when a user requests it without having to perform lookups based on the [Locator], and match what [UUID] belongs to what user (if any).
     /// Therefore, it provides a hard-to-forge id while reducing the tower lookups and the required data to be stored (no reverse maps).
     pub fn new(locator: Locator, user_id: UserId) -> Self {
-        let mut uuid_data = locator.serialize();
-        uuid_data.extend(&user_id.0.serialize());
-        UUID(ripemd160::Hash::hash(&uuid_data).into_inner())
+        UUID(compute_uuid(user_id, locator))
     }
 
     /// Serializes the [UUID] returning its byte representation.
@@ -152,4 +148,16 @@ mod tests {
         assert_eq!(e.locator(), s.locator);
         assert_eq!(e.user_id, s.user_id);
     }
+
+    #[test]
+    fn test_uuid_matches_compute_uuid() {
+        // Users must be able to derive the same identifiers the tower assigns to their appointments
+        let locator = Locator::deserialize(&get_random_bytes(16)).unwrap();
+        let user_id = get_random_user_id();
+
+        assert_eq!(
+            UUID::new(locator, user_id).serialize(),
+            compute_uuid(user_id, locator).to_vec()
+        );
+    }
 }
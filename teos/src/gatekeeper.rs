@@ -1,7 +1,9 @@
 //! Logic related to the Gatekeeper, the component in charge of managing access to the tower resources.
 
 use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::iter::FromIterator;
+use std::str::FromStr;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 
@@ -13,7 +15,9 @@ use teos_common::receipts::RegistrationReceipt;
 use teos_common::UserId;
 
 use crate::dbm::DBM;
+use crate::events::{self, Event, EventSender};
 use crate::extended_appointment::{compute_appointment_slots, ExtendedAppointment, UUID};
+use crate::metrics::Metrics;
 
 /// Data regarding a user subscription with the tower.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -50,19 +54,96 @@ impl UserInfo {
     }
 }
 
-/// Error raised if the user cannot be authenticated.
+/// Changes a user subscription can go through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SubscriptionEventKind {
+    Registered,
+    Renewed,
+    Expired,
+}
+
+impl fmt::Display for SubscriptionEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            SubscriptionEventKind::Registered => "registered",
+            SubscriptionEventKind::Renewed => "renewed",
+            SubscriptionEventKind::Expired => "expired",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for SubscriptionEventKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "registered" => Ok(SubscriptionEventKind::Registered),
+            "renewed" => Ok(SubscriptionEventKind::Renewed),
+            "expired" => Ok(SubscriptionEventKind::Expired),
+            _ => Err(format!("Unknown subscription event: {}", s)),
+        }
+    }
+}
+
+/// An entry of the subscription history of a user.
+///
+/// Holds the state of the subscription right after the event took place.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SubscriptionEvent {
+    /// The kind of change the subscription went through.
+    pub(crate) kind: SubscriptionEventKind,
+    /// Number of appointment slots available after the event.
+    pub(crate) available_slots: u32,
+    /// Block height where the subscription expires after the event.
+    pub(crate) subscription_expiry: u32,
+    /// Block height at which the event took place.
+    pub(crate) height: u32,
+}
+
+impl SubscriptionEvent {
+    /// Creates a new [SubscriptionEvent] instance.
+    pub fn new(
+        kind: SubscriptionEventKind,
+        available_slots: u32,
+        subscription_expiry: u32,
+        height: u32,
+    ) -> Self {
+        SubscriptionEvent {
+            kind,
+            available_slots,
+            subscription_expiry,
+            height,
+        }
+    }
+}
+
+/// Reasons why a user cannot be authenticated.
 #[derive(Debug, PartialEq)]
-pub(crate) struct AuthenticationFailure<'a>(&'a str);
+pub(crate) enum AuthenticationFailure<'a> {
+    /// The signature is not valid or the user is not registered.
+    Failed(&'a str),
+    /// The user has been banned by the tower admin.
+    Banned,
+}
 
-/// Error raised if the user subscription has not enough slots to fit a new appointment.
+/// Reasons why adding (or updating) an appointment to a user subscription may fail.
 #[derive(Debug, PartialEq)]
-pub(crate) struct NotEnoughSlots;
+pub(crate) enum AddUpdateAppointmentFailure {
+    /// The user subscription has not enough slots to fit the appointment.
+    NotEnoughSlots,
+    /// The user already holds the maximum number of appointments allowed by the tower.
+    MaxAppointmentsReached(u32),
+}
 
-/// Error raised if the user subscription slots limit has been reached.
-///
-/// This is currently set to [u32::MAX].
+/// Packs the reasons why adding (or updating) a user may fail.
 #[derive(Debug, PartialEq)]
-pub(crate) struct MaxSlotsReached;
+pub(crate) enum AddUpdateUserFailure {
+    /// The user subscription slots limit has been reached. This is currently set to [u32::MAX].
+    MaxSlotsReached,
+    /// The new user could not be stored in the database.
+    DBError,
+}
 
 /// Component in charge of managing access to the tower resources.
 ///
@@ -81,10 +162,23 @@ pub struct Gatekeeper {
     subscription_duration: u32,
     /// Grace period given to renew subscriptions, in blocks.
     expiry_delta: u32,
+    /// Maximum number of appointments a single user can hold, regardless of their available slots. Zero means unlimited.
+    max_appointments_per_user: u32,
+    /// Maximum number of available slots a single user can hold. Zero means no limit, in which case each request is
+    /// capped at [subscription_slots](Self::subscription_slots) instead.
+    max_user_slots: u32,
+    /// Number of blocks before their subscription is outdated users are warned at. Zero means no warning.
+    expiry_warning_window: u32,
     /// Map of users registered within the tower.
     registered_users: Mutex<HashMap<UserId, UserInfo>>,
+    /// Users banned by the tower admin. Their requests are rejected whether they are registered or not.
+    banned_users: Mutex<HashSet<UserId>>,
     /// A [DBM] (database manager) instance. Used to persist appointment data into disk.
-    dbm: Arc<Mutex<DBM>>,
+    dbm: Arc<DBM>,
+    /// A [Metrics] instance. Keeps track of the number of registered users.
+    metrics: Arc<Metrics>,
+    /// An [EventSender]. Used to publish the subscriptions that are about to be outdated.
+    events: EventSender,
 }
 
 impl Gatekeeper {
@@ -94,19 +188,62 @@ impl Gatekeeper {
         subscription_slots: u32,
         subscription_duration: u32,
         expiry_delta: u32,
-        dbm: Arc<Mutex<DBM>>,
+        max_appointments_per_user: u32,
+        dbm: Arc<DBM>,
     ) -> Self {
-        let registered_users = dbm.lock().unwrap().load_all_users();
+        let registered_users = dbm.load_all_users();
+        let banned_users = dbm.load_banned_users();
         Gatekeeper {
             last_known_block_height: AtomicU32::new(last_known_block_height),
             subscription_slots,
             subscription_duration,
             expiry_delta,
+            max_appointments_per_user,
+            max_user_slots: 0,
+            expiry_warning_window: 0,
             registered_users: Mutex::new(registered_users),
+            banned_users: Mutex::new(banned_users),
             dbm,
+            metrics: Arc::new(Metrics::new()),
+            events: events::channel(),
         }
     }
 
+    /// Sets the [Metrics] instance the [Gatekeeper] reports to.
+    pub fn with_metrics(self, metrics: Arc<Metrics>) -> Self {
+        metrics.set_registered_users(self.get_registered_users_count());
+        Gatekeeper { metrics, ..self }
+    }
+
+    /// Sets the maximum number of available slots a single user can hold. Zero means no limit, but requests are capped
+    /// at the default subscription size.
+    pub fn with_max_user_slots(self, max_user_slots: u32) -> Self {
+        Gatekeeper {
+            max_user_slots,
+            ..self
+        }
+    }
+
+    /// Sets the [EventSender] the [Gatekeeper] publishes events to.
+    pub fn with_events(self, events: EventSender) -> Self {
+        Gatekeeper { events, ..self }
+    }
+
+    /// Sets how many blocks before their subscription is outdated users are warned. Zero means no warning.
+    pub fn with_expiry_warning_window(self, expiry_warning_window: u32) -> Self {
+        Gatekeeper {
+            expiry_warning_window,
+            ..self
+        }
+    }
+
+    /// Reloads the registered users from the database, replacing the ones held in memory.
+    pub(crate) fn reload_users(&self) {
+        let registered_users = self.dbm.load_all_users();
+        self.metrics.set_registered_users(registered_users.len());
+        *self.registered_users.lock().unwrap() = registered_users;
+    }
+
     /// Returns whether the [Gatekeeper] has been created from scratch (fresh) or from backed-up data.
     pub fn is_fresh(&self) -> bool {
         self.registered_users.lock().unwrap().is_empty()
@@ -132,6 +269,13 @@ impl Gatekeeper {
         self.registered_users.lock().unwrap().get(&user_id).cloned()
     }
 
+    /// Gets the subscription history of a given user, from oldest to newest.
+    ///
+    /// The history is kept even after the user is deleted from the tower.
+    pub(crate) fn get_subscription_history(&self, user_id: UserId) -> Vec<SubscriptionEvent> {
+        self.dbm.load_subscription_history(user_id)
+    }
+
     /// Authenticates a user.
     ///
     /// User authentication is performed using ECRecover against fixed messages (one for each command).
@@ -143,54 +287,121 @@ impl Gatekeeper {
     ) -> Result<UserId, AuthenticationFailure> {
         let user_id = UserId(
             cryptography::recover_pk(message, signature)
-                .map_err(|_| AuthenticationFailure("Wrong message or signature."))?,
+                .map_err(|_| AuthenticationFailure::Failed("Wrong message or signature."))?,
         );
 
+        if self.is_banned(user_id) {
+            return Err(AuthenticationFailure::Banned);
+        }
+
         if self.registered_users.lock().unwrap().contains_key(&user_id) {
             Ok(user_id)
         } else {
-            Err(AuthenticationFailure("User not found."))
+            Err(AuthenticationFailure::Failed("User not found."))
+        }
+    }
+
+    /// Checks whether a given user has been banned.
+    pub(crate) fn is_banned(&self, user_id: UserId) -> bool {
+        self.banned_users.lock().unwrap().contains(&user_id)
+    }
+
+    /// Bans a user from the tower. Returns whether the user was not already banned.
+    ///
+    /// The user subscription (if any) is kept, so the user can carry on from where they were if unbanned.
+    pub(crate) fn ban_user(&self, user_id: UserId) -> bool {
+        let mut banned_users = self.banned_users.lock().unwrap();
+        if banned_users.insert(user_id) {
+            self.dbm.store_banned_user(user_id).unwrap();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Lifts the ban of a given user. Returns whether the user was banned.
+    pub(crate) fn unban_user(&self, user_id: UserId) -> bool {
+        let mut banned_users = self.banned_users.lock().unwrap();
+        if banned_users.remove(&user_id) {
+            self.dbm.remove_banned_user(user_id).unwrap();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Computes how many of the requested slots can be granted to a user currently holding `available_slots`.
+    ///
+    /// Requests are capped so the user does not end up holding more than [max_user_slots](Self::max_user_slots) (if set).
+    /// Otherwise, they are capped at [subscription_slots](Self::subscription_slots), so no single request can get more
+    /// than the default subscription. Users already at the limit get no extra slots, but can still renew.
+    fn granted_slots(&self, available_slots: u32, requested_slots: u32) -> u32 {
+        if self.max_user_slots == 0 {
+            requested_slots.min(self.subscription_slots)
+        } else {
+            requested_slots.min(self.max_user_slots.saturating_sub(available_slots))
         }
     }
 
     /// Adds a new user to the tower (or updates its subscription if already registered).
+    ///
+    /// Users get [subscription_slots](Self::subscription_slots) unless they request a different amount, in which case
+    /// the request is capped by [max_user_slots](Self::max_user_slots).
     pub(crate) fn add_update_user(
         &self,
         user_id: UserId,
-    ) -> Result<RegistrationReceipt, MaxSlotsReached> {
+        requested_slots: Option<u32>,
+    ) -> Result<RegistrationReceipt, AddUpdateUserFailure> {
         let block_count = self.last_known_block_height.load(Ordering::Acquire);
+        let requested_slots = requested_slots.unwrap_or(self.subscription_slots);
 
-        // TODO: For now, new calls to `add_update_user` add subscription_slots to the current count and reset the expiry time
+        // TODO: For now, new calls to `add_update_user` add the granted slots to the current count and reset the expiry time
         let mut registered_users = self.registered_users.lock().unwrap();
-        let user_info = match registered_users.get_mut(&user_id) {
+        let (user_info, event_kind) = match registered_users.get_mut(&user_id) {
             // User already exists, updating the info
             Some(user_info) => {
                 user_info.available_slots = user_info
                     .available_slots
-                    .checked_add(self.subscription_slots)
-                    .ok_or(MaxSlotsReached)?;
+                    .checked_add(self.granted_slots(user_info.available_slots, requested_slots))
+                    .ok_or(AddUpdateUserFailure::MaxSlotsReached)?;
                 user_info.subscription_expiry = block_count + self.subscription_duration;
-                self.dbm.lock().unwrap().update_user(user_id, user_info);
+                self.dbm.update_user(user_id, user_info);
 
-                user_info
+                (user_info, SubscriptionEventKind::Renewed)
             }
             // New user
             None => {
                 let user_info = UserInfo::new(
-                    self.subscription_slots,
+                    self.granted_slots(0, requested_slots),
                     block_count + self.subscription_duration,
                 );
-                self.dbm
-                    .lock()
-                    .unwrap()
-                    .store_user(user_id, &user_info)
-                    .unwrap();
+                if let Err(e) = self.dbm.store_user(user_id, &user_info) {
+                    log::error!(user_id:% = user_id; "Couldn't store the new user. Error: {:?}", e);
+                    return Err(AddUpdateUserFailure::DBError);
+                }
 
                 registered_users.insert(user_id, user_info);
-                registered_users.get_mut(&user_id).unwrap()
+                self.metrics.set_registered_users(registered_users.len());
+                (
+                    registered_users.get_mut(&user_id).unwrap(),
+                    SubscriptionEventKind::Registered,
+                )
             }
         };
 
+        // The subscription history is only kept for auditing purposes, so failing to store it is not fatal.
+        self.dbm
+            .store_subscription_event(
+                user_id,
+                &SubscriptionEvent::new(
+                    event_kind,
+                    user_info.available_slots,
+                    user_info.subscription_expiry,
+                    block_count,
+                ),
+            )
+            .ok();
+
         Ok(RegistrationReceipt::new(
             user_id,
             user_info.available_slots,
@@ -199,15 +410,27 @@ impl Gatekeeper {
     }
 
     /// Adds an appointment to a given user, or updates it if already present in the system (and belonging to the requester).
+    ///
+    /// New appointments are rejected if the user already holds [max_appointments_per_user](Self::max_appointments_per_user)
+    /// appointments. Updates are not affected by the limit.
     pub(crate) fn add_update_appointment(
         &self,
         user_id: UserId,
         uuid: UUID,
         appointment: &ExtendedAppointment,
-    ) -> Result<u32, NotEnoughSlots> {
+    ) -> Result<u32, AddUpdateAppointmentFailure> {
         // For updates, the difference between the existing appointment size and the update is computed.
         let mut registered_users = self.registered_users.lock().unwrap();
         let user_info = registered_users.get_mut(&user_id).unwrap();
+
+        if self.max_appointments_per_user != 0
+            && !user_info.appointments.contains_key(&uuid)
+            && user_info.appointments.len() >= self.max_appointments_per_user as usize
+        {
+            return Err(AddUpdateAppointmentFailure::MaxAppointmentsReached(
+                self.max_appointments_per_user,
+            ));
+        }
         let used_slots = user_info.appointments.get(&uuid).map_or(0, |x| *x);
 
         let required_slots =
@@ -220,11 +443,11 @@ impl Gatekeeper {
             user_info.appointments.insert(uuid, required_slots);
             user_info.available_slots = (user_info.available_slots as i64 - diff) as u32;
 
-            self.dbm.lock().unwrap().update_user(user_id, user_info);
+            self.dbm.update_user(user_id, user_info);
 
             Ok(user_info.available_slots)
         } else {
-            Err(NotEnoughSlots)
+            Err(AddUpdateAppointmentFailure::NotEnoughSlots)
         }
     }
 
@@ -234,7 +457,7 @@ impl Gatekeeper {
         user_id: UserId,
     ) -> Result<(bool, u32), AuthenticationFailure<'_>> {
         self.registered_users.lock().unwrap().get(&user_id).map_or(
-            Err(AuthenticationFailure("User not found.")),
+            Err(AuthenticationFailure::Failed("User not found.")),
             |user_info| {
                 Ok((
                     self.last_known_block_height.load(Ordering::Acquire)
@@ -247,11 +470,15 @@ impl Gatekeeper {
 
     /// Gets a map of outdated users. Outdated users are those whose subscription has expired and the renewal grace period
     /// has already passed ([expiry_delta](Self::expiry_delta)).
+    ///
+    /// Users are normally outdated at the exact height their grace period ends, but the ones that should have been
+    /// outdated earlier (e.g. imported users, or users loaded after changing `expiry_delta`) are also included, so their
+    /// slots are not held forever.
     pub(crate) fn get_outdated_users(&self, block_height: u32) -> HashMap<UserId, HashSet<UUID>> {
         let registered_users = self.registered_users.lock().unwrap().clone();
         registered_users
             .into_iter()
-            .filter(|(_, info)| block_height == info.subscription_expiry + self.expiry_delta)
+            .filter(|(_, info)| block_height >= info.subscription_expiry + self.expiry_delta)
             .map(|(id, info)| (id, info.appointments.keys().cloned().collect()))
             .collect()
     }
@@ -273,6 +500,87 @@ impl Gatekeeper {
         )
     }
 
+    /// Warns about the users that will be outdated [expiry_warning_window](Self::expiry_warning_window) blocks after a
+    /// given height, and returns their ids.
+    ///
+    /// Users are warned once (at the exact height their warning window starts), both in the log and through an
+    /// [Event::SubscriptionExpiring], so they can be prompted to renew before their appointments are dropped.
+    pub(crate) fn warn_expiring_users(&self, block_height: u32) -> HashSet<UserId> {
+        if self.expiry_warning_window == 0 {
+            return HashSet::new();
+        }
+
+        let outdated_at = block_height + self.expiry_warning_window;
+        let expiring_users: HashMap<UserId, u32> = self
+            .registered_users
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, info)| info.subscription_expiry + self.expiry_delta == outdated_at)
+            .map(|(id, info)| (*id, info.subscription_expiry))
+            .collect();
+
+        for (user_id, subscription_expiry) in expiring_users.iter() {
+            log::warn!(
+                "Subscription of user {} will be outdated at height {} ({} blocks left)",
+                user_id,
+                outdated_at,
+                self.expiry_warning_window
+            );
+            // Sending only fails if there are no subscribers
+            self.events
+                .send(Event::subscription_expiring(
+                    *user_id,
+                    *subscription_expiry,
+                    outdated_at,
+                ))
+                .ok();
+        }
+
+        expiring_users.into_keys().collect()
+    }
+
+    /// Deletes the users that are outdated at a given height, alongside their appointments, and returns their ids.
+    ///
+    /// The data held by the [Watcher](crate::watcher::Watcher) and the [Responder](crate::responder::Responder) is
+    /// deleted from the database as a consequence of this (by cascade). Both are expected to drop their in-memory state
+    /// on their own (through [get_outdated_appointments](Self::get_outdated_appointments)) before this is called.
+    pub(crate) fn collect_expired_users(&self, block_height: u32) -> HashSet<UserId> {
+        let outdated_users = self.get_outdated_user_ids(block_height);
+        if outdated_users.is_empty() {
+            return outdated_users;
+        }
+
+        {
+            let mut registered_users = self.registered_users.lock().unwrap();
+            for user_id in outdated_users.iter() {
+                if let Some(user_info) = registered_users.get(user_id) {
+                    self.dbm
+                        .store_subscription_event(
+                            *user_id,
+                            &SubscriptionEvent::new(
+                                SubscriptionEventKind::Expired,
+                                user_info.available_slots,
+                                user_info.subscription_expiry,
+                                block_height,
+                            ),
+                        )
+                        .ok();
+                }
+            }
+            registered_users.retain(|id, _| !outdated_users.contains(id));
+            self.metrics.set_registered_users(registered_users.len());
+        }
+        self.dbm.batch_remove_users(&outdated_users);
+        log::info!(
+            "{} outdated users deleted at height {}",
+            outdated_users.len(),
+            block_height
+        );
+
+        outdated_users
+    }
+
     /// Deletes a collection of appointments from the users' subscriptions (from memory only)
     /// and updates the available_slots count for the given user.
     ///
@@ -308,12 +616,8 @@ impl chain::Listen for Gatekeeper {
         log::info!("New block received: {}", block.block_hash());
 
         // Expired user deletion is delayed. Users are deleted when their subscription is outdated, not expired.
-        let outdated_users = self.get_outdated_user_ids(height);
-        self.registered_users
-            .lock()
-            .unwrap()
-            .retain(|id, _| !outdated_users.contains(id));
-        self.dbm.lock().unwrap().batch_remove_users(&outdated_users);
+        self.collect_expired_users(height);
+        self.warn_expiring_users(height);
 
         // Update last known block height
         self.last_known_block_height
@@ -352,6 +656,7 @@ mod tests {
                 && self.subscription_duration == other.subscription_duration
                 && self.expiry_delta == other.expiry_delta
                 && *self.registered_users.lock().unwrap() == *other.registered_users.lock().unwrap()
+                && *self.banned_users.lock().unwrap() == *other.banned_users.lock().unwrap()
                 && self.last_known_block_height.load(Ordering::Relaxed)
                     == other.last_known_block_height.load(Ordering::Relaxed)
         }
@@ -369,7 +674,7 @@ mod tests {
             outdates_at: u32,
             appointments: Option<Vec<UUID>>,
         ) {
-            self.add_update_user(user_id).unwrap();
+            self.add_update_user(user_id, None).unwrap();
             let mut registered_users = self.registered_users.lock().unwrap();
             let mut user = registered_users.get_mut(&user_id).unwrap();
             user.subscription_expiry = outdates_at - self.expiry_delta;
@@ -382,21 +687,29 @@ mod tests {
     }
 
     fn init_gatekeeper(chain: &Blockchain) -> Gatekeeper {
-        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
-        Gatekeeper::new(chain.get_block_count(), SLOTS, DURATION, EXPIRY_DELTA, dbm)
+        let dbm = Arc::new(DBM::in_memory().unwrap());
+        Gatekeeper::new(
+            chain.get_block_count(),
+            SLOTS,
+            DURATION,
+            EXPIRY_DELTA,
+            0,
+            dbm,
+        )
     }
 
     #[test]
     fn test_new() {
         // A fresh gatekeeper has no associated data
         let chain = Blockchain::default().with_height(START_HEIGHT);
-        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let dbm = Arc::new(DBM::in_memory().unwrap());
 
         let gatekeeper = Gatekeeper::new(
             chain.get_block_count(),
             SLOTS,
             DURATION,
             EXPIRY_DELTA,
+            0,
             dbm.clone(),
         );
         assert!(gatekeeper.is_fresh());
@@ -405,7 +718,7 @@ mod tests {
         // (as if simulating a bootstrap from existing data), the data should be properly loaded.
         for _ in 0..10 {
             let user_id = get_random_user_id();
-            gatekeeper.add_update_user(user_id).unwrap();
+            gatekeeper.add_update_user(user_id, None).unwrap();
 
             let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
             gatekeeper
@@ -414,15 +727,19 @@ mod tests {
             // Add the appointment to the database. This is normally done by the Watcher.
             gatekeeper
                 .dbm
-                .lock()
-                .unwrap()
                 .store_appointment(uuid, &appointment)
                 .unwrap();
         }
 
         // Create a new GK reusing the same DB and check that the data is loaded
-        let another_gk =
-            Gatekeeper::new(chain.get_block_count(), SLOTS, DURATION, EXPIRY_DELTA, dbm);
+        let another_gk = Gatekeeper::new(
+            chain.get_block_count(),
+            SLOTS,
+            DURATION,
+            EXPIRY_DELTA,
+            0,
+            dbm,
+        );
         assert!(!another_gk.is_fresh());
         assert_eq!(gatekeeper, another_gk);
     }
@@ -438,7 +755,7 @@ mod tests {
         let wrong_signature = "signature";
         assert_eq!(
             gatekeeper.authenticate_user(message, wrong_signature),
-            Err(AuthenticationFailure("Wrong message or signature."))
+            Err(AuthenticationFailure::Failed("Wrong message or signature."))
         );
 
         // Let's now provide data generated by an actual user, still the user is unknown
@@ -446,32 +763,76 @@ mod tests {
         let signature = cryptography::sign(message, &user_sk).unwrap();
         assert_eq!(
             gatekeeper.authenticate_user(message, &signature),
-            Err(AuthenticationFailure("User not found."))
+            Err(AuthenticationFailure::Failed("User not found."))
         );
 
         // Last, let's add the user to the Gatekeeper and try again.
         let user_id = UserId(user_pk);
-        gatekeeper.add_update_user(user_id).unwrap();
+        gatekeeper.add_update_user(user_id, None).unwrap();
         assert_eq!(
             gatekeeper.authenticate_user(message, &signature),
             Ok(user_id)
         );
     }
 
+    #[test]
+    fn test_ban_unban_user() {
+        let gatekeeper = init_gatekeeper(&Blockchain::default().with_height(START_HEIGHT));
+        let message = "message".as_bytes();
+
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        let signature = cryptography::sign(message, &user_sk).unwrap();
+        gatekeeper.add_update_user(user_id, None).unwrap();
+
+        // Banned users cannot be authenticated, even if registered
+        assert!(gatekeeper.ban_user(user_id));
+        assert!(!gatekeeper.ban_user(user_id));
+        assert!(gatekeeper.is_banned(user_id));
+        assert_eq!(
+            gatekeeper.authenticate_user(message, &signature),
+            Err(AuthenticationFailure::Banned)
+        );
+
+        // Other users are not affected
+        let (other_sk, other_pk) = get_random_keypair();
+        gatekeeper.add_update_user(UserId(other_pk), None).unwrap();
+        assert_eq!(
+            gatekeeper.authenticate_user(message, &cryptography::sign(message, &other_sk).unwrap()),
+            Ok(UserId(other_pk))
+        );
+
+        // Bans are persisted
+        assert_eq!(
+            gatekeeper.dbm.load_banned_users(),
+            HashSet::from_iter([user_id])
+        );
+
+        // Once unbanned, the user can carry on with their subscription
+        assert!(gatekeeper.unban_user(user_id));
+        assert!(!gatekeeper.unban_user(user_id));
+        assert!(!gatekeeper.is_banned(user_id));
+        assert_eq!(
+            gatekeeper.authenticate_user(message, &signature),
+            Ok(user_id)
+        );
+        assert!(gatekeeper.dbm.load_banned_users().is_empty());
+    }
+
     #[test]
     fn test_add_update_user() {
         let mut chain = Blockchain::default().with_height(START_HEIGHT);
         let gatekeeper = init_gatekeeper(&chain);
 
         // add_update_user adds a user to the system if it is not still registered, otherwise it add slots to the user subscription
-        // and refreshes the subscription expiry. Slots are added up to u32:MAX, further call will return an MaxSlotsReached error.
+        // and refreshes the subscription expiry. Slots are added up to u32:MAX, further call will return a MaxSlotsReached error.
 
         // Let's start by adding new user
         let user_id = get_random_user_id();
-        let receipt = gatekeeper.add_update_user(user_id).unwrap();
+        let receipt = gatekeeper.add_update_user(user_id, None).unwrap();
         // The data should have been also added to the database
         assert_eq!(
-            gatekeeper.dbm.lock().unwrap().load_user(user_id).unwrap(),
+            gatekeeper.dbm.load_user(user_id).unwrap(),
             UserInfo::new(receipt.available_slots(), receipt.subscription_expiry())
         );
 
@@ -480,7 +841,7 @@ mod tests {
         gatekeeper
             .last_known_block_height
             .store(chain.get_block_count(), Ordering::Relaxed);
-        let updated_receipt = gatekeeper.add_update_user(user_id).unwrap();
+        let updated_receipt = gatekeeper.add_update_user(user_id, None).unwrap();
 
         assert_eq!(
             updated_receipt.available_slots(),
@@ -493,7 +854,7 @@ mod tests {
 
         // Data in the database should have been updated too
         assert_eq!(
-            gatekeeper.dbm.lock().unwrap().load_user(user_id).unwrap(),
+            gatekeeper.dbm.load_user(user_id).unwrap(),
             UserInfo::new(
                 updated_receipt.available_slots(),
                 updated_receipt.subscription_expiry()
@@ -510,13 +871,13 @@ mod tests {
             .available_slots = u32::MAX;
 
         assert!(matches!(
-            gatekeeper.add_update_user(user_id),
-            Err(MaxSlotsReached)
+            gatekeeper.add_update_user(user_id, None),
+            Err(AddUpdateUserFailure::MaxSlotsReached)
         ));
 
         // Data in the database remains untouched
         assert_eq!(
-            gatekeeper.dbm.lock().unwrap().load_user(user_id).unwrap(),
+            gatekeeper.dbm.load_user(user_id).unwrap(),
             UserInfo::new(
                 updated_receipt.available_slots(),
                 updated_receipt.subscription_expiry()
@@ -524,6 +885,82 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_add_update_user_requested_slots() {
+        let gatekeeper = init_gatekeeper(&Blockchain::default().with_height(START_HEIGHT))
+            .with_max_user_slots(SLOTS * 2);
+
+        // Users get the default subscription unless they request something else
+        let receipt = gatekeeper
+            .add_update_user(get_random_user_id(), None)
+            .unwrap();
+        assert_eq!(receipt.available_slots(), SLOTS);
+
+        // Requests below the limit are granted as is
+        let user_id = get_random_user_id();
+        let receipt = gatekeeper.add_update_user(user_id, Some(5)).unwrap();
+        assert_eq!(receipt.available_slots(), 5);
+
+        // Requests above the limit are capped, so the user ends up holding max_user_slots
+        let receipt = gatekeeper.add_update_user(user_id, Some(u32::MAX)).unwrap();
+        assert_eq!(receipt.available_slots(), SLOTS * 2);
+        assert_eq!(
+            gatekeeper.dbm.load_user(user_id).unwrap().available_slots,
+            SLOTS * 2
+        );
+
+        // Once the limit is reached, further requests grant no extra slots but still renew the subscription
+        gatekeeper
+            .last_known_block_height
+            .fetch_add(1, Ordering::Relaxed);
+        let renewed_receipt = gatekeeper.add_update_user(user_id, Some(1)).unwrap();
+        assert_eq!(renewed_receipt.available_slots(), SLOTS * 2);
+        assert_eq!(
+            renewed_receipt.subscription_expiry(),
+            receipt.subscription_expiry() + 1
+        );
+        assert_eq!(
+            gatekeeper
+                .dbm
+                .load_user(user_id)
+                .unwrap()
+                .subscription_expiry,
+            renewed_receipt.subscription_expiry()
+        );
+
+        // Without a limit (the default), requests are capped at the default subscription
+        let gatekeeper = init_gatekeeper(&Blockchain::default().with_height(START_HEIGHT));
+        let user_id = get_random_user_id();
+        let receipt = gatekeeper.add_update_user(user_id, Some(u32::MAX)).unwrap();
+        assert_eq!(receipt.available_slots(), SLOTS);
+        let receipt = gatekeeper.add_update_user(user_id, Some(u32::MAX)).unwrap();
+        assert_eq!(receipt.available_slots(), SLOTS * 2);
+
+        // Smaller requests are still granted as is
+        let receipt = gatekeeper
+            .add_update_user(get_random_user_id(), Some(5))
+            .unwrap();
+        assert_eq!(receipt.available_slots(), 5);
+    }
+
+    #[test]
+    fn test_add_update_user_db_error() {
+        let gatekeeper = init_gatekeeper(&Blockchain::default().with_height(START_HEIGHT));
+
+        // If the user cannot be stored, it is not registered either
+        gatekeeper.dbm.make_read_only();
+        let user_id = get_random_user_id();
+        assert!(matches!(
+            gatekeeper.add_update_user(user_id, None),
+            Err(AddUpdateUserFailure::DBError)
+        ));
+        assert!(!gatekeeper
+            .registered_users
+            .lock()
+            .unwrap()
+            .contains_key(&user_id));
+    }
+
     #[test]
     fn test_add_update_appointment() {
         let gatekeeper = init_gatekeeper(&Blockchain::default().with_height(START_HEIGHT));
@@ -533,7 +970,7 @@ mod tests {
 
         // Let's first add the a user to the Gatekeeper (inputs are always sanitized here, so we don't need tests for non-registered users)
         let user_id = get_random_user_id();
-        gatekeeper.add_update_user(user_id).unwrap();
+        gatekeeper.add_update_user(user_id, None).unwrap();
 
         // Now let's add a new appointment
         let slots_before = gatekeeper
@@ -555,7 +992,7 @@ mod tests {
 
         // Slots should have been updated in the database too. Notice the appointment won't be there yet
         // given the Watcher is responsible for adding it, and it will do so after calling this method
-        let mut loaded_user = gatekeeper.dbm.lock().unwrap().load_user(user_id).unwrap();
+        let mut loaded_user = gatekeeper.dbm.load_user(user_id).unwrap();
         assert_eq!(loaded_user.available_slots, available_slots);
 
         // Adding the exact same appointment should leave the slots count unchanged
@@ -566,7 +1003,7 @@ mod tests {
             .appointments
             .contains_key(&uuid));
         assert_eq!(updated_slot_count, available_slots);
-        loaded_user = gatekeeper.dbm.lock().unwrap().load_user(user_id).unwrap();
+        loaded_user = gatekeeper.dbm.load_user(user_id).unwrap();
         assert_eq!(loaded_user.available_slots, updated_slot_count);
 
         // If we add an update to an existing appointment with a bigger data blob (modulo ENCRYPTED_BLOB_MAX_SIZE), additional slots should be taken
@@ -579,7 +1016,7 @@ mod tests {
             .appointments
             .contains_key(&uuid));
         assert_eq!(updated_slot_count, available_slots - 1);
-        loaded_user = gatekeeper.dbm.lock().unwrap().load_user(user_id).unwrap();
+        loaded_user = gatekeeper.dbm.load_user(user_id).unwrap();
         assert_eq!(loaded_user.available_slots, updated_slot_count);
 
         // Adding back a smaller update (modulo ENCRYPTED_BLOB_MAX_SIZE) should reduce the count
@@ -590,7 +1027,7 @@ mod tests {
             .appointments
             .contains_key(&uuid));
         assert_eq!(updated_slot_count, available_slots);
-        loaded_user = gatekeeper.dbm.lock().unwrap().load_user(user_id).unwrap();
+        loaded_user = gatekeeper.dbm.load_user(user_id).unwrap();
         assert_eq!(loaded_user.available_slots, updated_slot_count);
 
         // Adding an appointment with a different uuid should not count as an update
@@ -602,7 +1039,7 @@ mod tests {
             .appointments
             .contains_key(&new_uuid));
         assert_eq!(updated_slot_count, available_slots - 1);
-        loaded_user = gatekeeper.dbm.lock().unwrap().load_user(user_id).unwrap();
+        loaded_user = gatekeeper.dbm.load_user(user_id).unwrap();
         assert_eq!(loaded_user.available_slots, updated_slot_count);
 
         // Finally, trying to add an appointment when the user has no enough slots should fail
@@ -615,13 +1052,61 @@ mod tests {
             .available_slots = 0;
         assert!(matches!(
             gatekeeper.add_update_appointment(user_id, generate_uuid(), &appointment),
-            Err(NotEnoughSlots)
+            Err(AddUpdateAppointmentFailure::NotEnoughSlots)
         ));
         // The entry in the database should remain unchanged in this case
-        loaded_user = gatekeeper.dbm.lock().unwrap().load_user(user_id).unwrap();
+        loaded_user = gatekeeper.dbm.load_user(user_id).unwrap();
         assert_eq!(loaded_user.available_slots, updated_slot_count);
     }
 
+    #[test]
+    fn test_add_update_appointment_max_appointments_per_user() {
+        let chain = Blockchain::default().with_height(START_HEIGHT);
+        let dbm = Arc::new(DBM::in_memory().unwrap());
+        let max_appointments = 3;
+        let gatekeeper = Gatekeeper::new(
+            chain.get_block_count(),
+            SLOTS,
+            DURATION,
+            EXPIRY_DELTA,
+            max_appointments,
+            dbm,
+        );
+
+        let user_id = get_random_user_id();
+        gatekeeper.add_update_user(user_id, None).unwrap();
+
+        // Appointments can be added up to the limit
+        let mut uuids = Vec::new();
+        for _ in 0..max_appointments {
+            let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+            gatekeeper
+                .add_update_appointment(user_id, uuid, &appointment)
+                .unwrap();
+            uuids.push((uuid, appointment));
+        }
+        let slots_at_limit = gatekeeper.registered_users.lock().unwrap()[&user_id].available_slots;
+
+        // Going past the limit is rejected even if the user has available slots, and nothing is consumed
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        assert_eq!(
+            gatekeeper.add_update_appointment(user_id, uuid, &appointment),
+            Err(AddUpdateAppointmentFailure::MaxAppointmentsReached(
+                max_appointments
+            ))
+        );
+        let user_info = gatekeeper.get_user_info(user_id).unwrap();
+        assert!(!user_info.appointments.contains_key(&uuid));
+        assert_eq!(user_info.appointments.len(), max_appointments as usize);
+        assert_eq!(user_info.available_slots, slots_at_limit);
+
+        // Updates to existing appointments are still accepted
+        let (uuid, appointment) = &uuids[0];
+        assert!(gatekeeper
+            .add_update_appointment(user_id, *uuid, appointment)
+            .is_ok());
+    }
+
     #[test]
     fn test_has_subscription_expired() {
         let gatekeeper = init_gatekeeper(&Blockchain::default().with_height(START_HEIGHT));
@@ -630,11 +1115,11 @@ mod tests {
         let user_id = get_random_user_id();
         assert!(matches!(
             gatekeeper.has_subscription_expired(user_id),
-            Err(AuthenticationFailure { .. })
+            Err(AuthenticationFailure::Failed(..))
         ));
 
         // If the user is registered and the subscription is active we should get (false, expiry)
-        gatekeeper.add_update_user(user_id).unwrap();
+        gatekeeper.add_update_user(user_id, None).unwrap();
         assert_eq!(
             gatekeeper.has_subscription_expired(user_id),
             Ok((false, DURATION + START_HEIGHT as u32))
@@ -667,7 +1152,7 @@ mod tests {
 
         // Adding a user whose subscription is outdated should return an entry
         let user_id = get_random_user_id();
-        gatekeeper.add_update_user(user_id).unwrap();
+        gatekeeper.add_update_user(user_id, None).unwrap();
 
         // Add also an appointment so we can check the returned data
         let appointment = generate_dummy_appointment(None);
@@ -743,7 +1228,7 @@ mod tests {
 
         // If there's matching data in the gatekeeper it should be deleted
         for (uuid, user_id) in to_be_deleted.iter() {
-            gatekeeper.add_update_user(*user_id).unwrap();
+            gatekeeper.add_update_user(*user_id, None).unwrap();
             gatekeeper
                 .add_update_appointment(*user_id, *uuid, &generate_dummy_appointment(None))
                 .unwrap();
@@ -762,13 +1247,7 @@ mod tests {
                 gatekeeper.subscription_slots
             );
             assert_ne!(
-                gatekeeper
-                    .dbm
-                    .lock()
-                    .unwrap()
-                    .load_user(*user_id)
-                    .unwrap()
-                    .available_slots,
+                gatekeeper.dbm.load_user(*user_id).unwrap().available_slots,
                 gatekeeper.subscription_slots
             );
         }
@@ -829,7 +1308,7 @@ mod tests {
                 .unwrap()
                 .contains_key(user_id));
             assert!(matches!(
-                gatekeeper.dbm.lock().unwrap().load_user(*user_id),
+                gatekeeper.dbm.load_user(*user_id),
                 Err(DBError::NotFound)
             ));
         }
@@ -841,6 +1320,144 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_warn_expiring_users() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let event_sender = events::channel();
+        let mut receiver = event_sender.subscribe();
+        let window = 10;
+        let gatekeeper = init_gatekeeper(&chain)
+            .with_events(event_sender)
+            .with_expiry_warning_window(window);
+
+        let user_id = get_random_user_id();
+        let receipt = gatekeeper.add_update_user(user_id, None).unwrap();
+        let outdated_at = receipt.subscription_expiry() + EXPIRY_DELTA;
+
+        // Nothing is reported until the warning window starts
+        for height in chain.get_block_count() + 1..outdated_at - window {
+            assert!(gatekeeper.warn_expiring_users(height).is_empty());
+        }
+
+        // Once it does, the user is warned
+        assert_eq!(
+            gatekeeper.warn_expiring_users(outdated_at - window),
+            HashSet::from_iter([user_id])
+        );
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            Event::subscription_expiring(user_id, receipt.subscription_expiry(), outdated_at)
+        );
+
+        // But only once
+        assert!(gatekeeper
+            .warn_expiring_users(outdated_at - window + 1)
+            .is_empty());
+        assert!(receiver.try_recv().is_err());
+
+        // The warning is issued as part of the chain monitoring
+        while chain.get_block_count() < outdated_at - window - 1 {
+            chain.generate(None);
+        }
+        gatekeeper.block_connected(&chain.generate(None), chain.get_block_count());
+        assert!(receiver.try_recv().is_ok());
+
+        // A window of zero means no warnings
+        let gatekeeper = gatekeeper.with_expiry_warning_window(0);
+        assert!(gatekeeper
+            .warn_expiring_users(outdated_at - window)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_collect_expired_users() {
+        let chain = Blockchain::default().with_height(START_HEIGHT);
+        let gatekeeper = init_gatekeeper(&chain);
+        let height = chain.get_block_count();
+
+        // A user that should have been outdated a while ago, one that outdates at the next block and an active one
+        let stale_user_id = get_random_user_id();
+        let (uuid, appointment) = generate_dummy_appointment_with_user(stale_user_id, None);
+        gatekeeper.add_outdated_user(stale_user_id, height - 10, Some(vec![uuid]));
+        gatekeeper
+            .dbm
+            .store_appointment(uuid, &appointment)
+            .unwrap();
+
+        let outdated_user_id = get_random_user_id();
+        gatekeeper.add_outdated_user(outdated_user_id, height + 1, None);
+        let active_user_id = get_random_user_id();
+        gatekeeper.add_update_user(active_user_id, None).unwrap();
+
+        // Only the stale user is collected for now, alongside its appointments
+        assert_eq!(
+            gatekeeper.collect_expired_users(height),
+            HashSet::from_iter([stale_user_id])
+        );
+        assert!(gatekeeper.get_user_info(stale_user_id).is_none());
+        assert!(matches!(
+            gatekeeper.dbm.load_appointment(uuid),
+            Err(DBError::NotFound)
+        ));
+
+        assert_eq!(
+            gatekeeper.collect_expired_users(height + 1),
+            HashSet::from_iter([outdated_user_id])
+        );
+        assert!(gatekeeper.collect_expired_users(height + 1).is_empty());
+        assert!(gatekeeper.get_user_info(active_user_id).is_some());
+    }
+
+    #[test]
+    fn test_subscription_history() {
+        // Registering, renewing and expiring a subscription must leave an event trail behind
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let gatekeeper = init_gatekeeper(&chain);
+        let user_id = get_random_user_id();
+        assert!(gatekeeper.get_subscription_history(user_id).is_empty());
+
+        gatekeeper.add_update_user(user_id, None).unwrap();
+        let height = chain.get_block_count();
+        gatekeeper.block_connected(&chain.generate(None), height + 1);
+        gatekeeper.add_update_user(user_id, None).unwrap();
+
+        // Outdate the user and connect a block so it gets deleted
+        let outdates_at = chain.get_block_count() + 1;
+        gatekeeper
+            .registered_users
+            .lock()
+            .unwrap()
+            .get_mut(&user_id)
+            .unwrap()
+            .subscription_expiry = outdates_at - EXPIRY_DELTA;
+        gatekeeper.block_connected(&chain.generate(None), outdates_at);
+        assert!(gatekeeper.get_user_info(user_id).is_none());
+
+        assert_eq!(
+            gatekeeper.get_subscription_history(user_id),
+            vec![
+                SubscriptionEvent::new(
+                    SubscriptionEventKind::Registered,
+                    SLOTS,
+                    height + DURATION,
+                    height
+                ),
+                SubscriptionEvent::new(
+                    SubscriptionEventKind::Renewed,
+                    SLOTS * 2,
+                    height + 1 + DURATION,
+                    height + 1
+                ),
+                SubscriptionEvent::new(
+                    SubscriptionEventKind::Expired,
+                    SLOTS * 2,
+                    outdates_at - EXPIRY_DELTA,
+                    outdates_at
+                ),
+            ]
+        );
+    }
+
     #[test]
     fn test_block_disconnected() {
         // Block disconnected simply updates the last known block
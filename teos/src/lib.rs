@@ -2,10 +2,13 @@
 //!
 //! A watchtower implementation written in Rust.
 
+// Streaming endpoints lead to generated associated types such as `export_dataStream`
+#[allow(non_camel_case_types)]
 pub mod protos {
     tonic::include_proto!("teos.v2");
 }
 pub mod api;
+pub mod backup;
 pub mod bitcoin_cli;
 pub mod carrier;
 pub mod chain_monitor;
@@ -14,8 +17,12 @@ pub mod config;
 pub mod dbm;
 #[doc(hidden)]
 mod errors;
+pub mod events;
+pub mod export;
 mod extended_appointment;
 pub mod gatekeeper;
+pub mod logging;
+pub mod metrics;
 pub mod responder;
 #[doc(hidden)]
 mod rpc_errors;
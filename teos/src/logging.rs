@@ -0,0 +1,196 @@
+//! Logic related to the tower logs. Logs can either be human-readable or JSON formatted (one object per line) so they
+//! can be ingested by log aggregators.
+//!
+//! Log records can carry structured fields (e.g. `log::info!(user_id:% = user_id; "...")`). Those are only output in
+//! JSON format, the human-readable output is not affected by them.
+
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::kv::{self, Key, Value, VisitSource};
+use log::{Level, Log, Metadata, Record, SetLoggerError};
+use serde_json::{Map, Value as JsonValue};
+
+/// The format the tower logs are output in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "log_format not recognized. Expected {{text, json}}, received {}",
+                s
+            )),
+        }
+    }
+}
+
+/// Sets the global logger for the given level and format.
+pub fn init(level: Level, format: LogFormat) -> Result<(), SetLoggerError> {
+    match format {
+        LogFormat::Text => simple_logger::init_with_level(level),
+        LogFormat::Json => {
+            log::set_boxed_logger(Box::new(JsonLogger::new(level, Box::new(io::stdout()))))?;
+            log::set_max_level(level.to_level_filter());
+            Ok(())
+        }
+    }
+}
+
+/// Collects the structured fields of a log record into a JSON map.
+struct JsonFields<'a>(&'a mut Map<String, JsonValue>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(v) = value.to_u64() {
+            JsonValue::from(v)
+        } else if let Some(v) = value.to_i64() {
+            JsonValue::from(v)
+        } else if let Some(v) = value.to_bool() {
+            JsonValue::from(v)
+        } else {
+            JsonValue::from(value.to_string())
+        };
+        self.0.insert(key.to_string(), value);
+
+        Ok(())
+    }
+}
+
+/// Logger that outputs every record as a single line JSON object.
+///
+/// Every object contains the timestamp (in milliseconds since epoch), the level, the component that emitted the
+/// record (i.e. the module, such as `watcher` or `responder`), the message and the structured fields of the record.
+pub struct JsonLogger {
+    /// The maximum level to log.
+    level: Level,
+    /// Where records are written to.
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonLogger {
+    /// Creates a new [JsonLogger] instance.
+    pub fn new(level: Level, out: Box<dyn Write + Send>) -> Self {
+        JsonLogger {
+            level,
+            out: Mutex::new(out),
+        }
+    }
+
+    /// Builds the JSON object for a given record.
+    fn to_json(record: &Record) -> JsonValue {
+        let mut object = Map::new();
+        record.key_values().visit(&mut JsonFields(&mut object)).ok();
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let target = record.target();
+        object.insert("timestamp".into(), timestamp.into());
+        object.insert("level".into(), record.level().to_string().into());
+        object.insert(
+            "component".into(),
+            target.rsplit("::").next().unwrap_or(target).into(),
+        );
+        object.insert("target".into(), target.into());
+        object.insert("message".into(), record.args().to_string().into());
+
+        JsonValue::Object(object)
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let mut out = self.out.lock().unwrap();
+            writeln!(out, "{}", JsonLogger::to_json(record)).ok();
+        }
+    }
+
+    fn flush(&self) {
+        self.out.lock().unwrap().flush().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    /// Buffer shared between the logger and the test, so the output can be inspected.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_log_format_from_str() {
+        assert_eq!(LogFormat::from_str("text"), Ok(LogFormat::Text));
+        assert_eq!(LogFormat::from_str("json"), Ok(LogFormat::Json));
+        assert!(LogFormat::from_str("yaml").is_err());
+    }
+
+    #[test]
+    fn test_json_logger() {
+        let buffer = SharedBuffer::default();
+        let logger = JsonLogger::new(Level::Info, Box::new(buffer.clone()));
+
+        let fields: &[(&str, Value)] = &[
+            ("user_id", Value::from("02abcd")),
+            ("slots", Value::from(21u32)),
+        ];
+        logger.log(
+            &Record::builder()
+                .level(Level::Info)
+                .target("teos::watcher")
+                .args(format_args!("Appointment accepted"))
+                .key_values(&fields)
+                .build(),
+        );
+        // Records above the logger level are skipped
+        logger.log(
+            &Record::builder()
+                .level(Level::Debug)
+                .target("teos::watcher")
+                .args(format_args!("Skipped"))
+                .build(),
+        );
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 1);
+
+        let json: JsonValue = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(json["level"], "INFO");
+        assert_eq!(json["component"], "watcher");
+        assert_eq!(json["target"], "teos::watcher");
+        assert_eq!(json["message"], "Appointment accepted");
+        assert_eq!(json["user_id"], "02abcd");
+        assert_eq!(json["slots"], 21);
+        assert!(json["timestamp"].is_u64());
+    }
+}
@@ -1,9 +1,11 @@
-use simple_logger::init_with_level;
 use std::fs;
 use std::io::ErrorKind;
 use std::ops::{Deref, DerefMut};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
 use structopt::StructOpt;
 use tokio::task;
 use tonic::transport::Server;
@@ -11,20 +13,28 @@ use tonic::transport::Server;
 use bitcoin::network::constants::Network;
 use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
 use bitcoincore_rpc::{Auth, Client};
+use lightning::chain;
 use lightning_block_sync::init::validate_best_block_header;
 use lightning_block_sync::poll::{
     ChainPoller, Poll, Validate, ValidatedBlock, ValidatedBlockHeader,
 };
-use lightning_block_sync::{BlockSource, SpvClient, UnboundedCache};
+use lightning_block_sync::{
+    BlockSource, BlockSourceError, BlockSourceResult, SpvClient, UnboundedCache,
+};
 
 use teos::api::internal::InternalAPI;
-use teos::api::{http, tor};
-use teos::bitcoin_cli::BitcoindClient;
+use teos::api::rate_limiter::RateLimiter;
+use teos::api::{http, tor, uds};
+use teos::backup;
+use teos::bitcoin_cli::{self, BitcoindClient};
 use teos::carrier::Carrier;
-use teos::chain_monitor::ChainMonitor;
+use teos::chain_monitor::{self, ChainMonitor};
 use teos::config::{self, Config, Opt};
 use teos::dbm::DBM;
+use teos::events;
 use teos::gatekeeper::Gatekeeper;
+use teos::logging::{self, LogFormat};
+use teos::metrics::{self, Metrics};
 use teos::protos::private_tower_services_server::PrivateTowerServicesServer;
 use teos::protos::public_tower_services_server::PublicTowerServicesServer;
 use teos::responder::Responder;
@@ -55,12 +65,74 @@ where
     last_n_blocks
 }
 
+/// Connects the blocks between `tip` and the best block known by `bitcoind` to `listener`, fetching `concurrency`
+/// blocks at a time. Nothing is done if the best chain does not build on top of `tip`.
+async fn bootstrap<B, T, L>(
+    poller: &mut ChainPoller<B, T>,
+    bitcoin_cli: &BitcoindClient<'_>,
+    concurrency: usize,
+    listener: L,
+    tip: &mut ValidatedBlockHeader,
+) -> BlockSourceResult<()>
+where
+    B: DerefMut<Target = T> + Sized + Send + Sync,
+    T: BlockSource,
+    L: Deref,
+    L::Target: chain::Listen,
+{
+    let mut block_source = bitcoin_cli;
+    let best = validate_best_block_header(&mut block_source).await?;
+    let headers = match chain_monitor::headers_between(poller, tip, best).await? {
+        Some(headers) if !headers.is_empty() => headers,
+        _ => return Ok(()),
+    };
+    log::info!(
+        "Catching up with {} blocks ({} at a time)",
+        headers.len(),
+        concurrency
+    );
+
+    // Every BitcoindClient serializes its requests, so each block being fetched at the same time needs its own client
+    let mut sources = (0..concurrency)
+        .map(|_| bitcoin_cli.get_new_rpc_client())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(BlockSourceError::transient)?;
+    chain_monitor::connect_blocks(&mut sources, &headers, listener, tip).await
+}
+
 fn create_new_tower_keypair(db: &DBM) -> (SecretKey, PublicKey) {
     let (sk, pk) = get_random_keypair();
     db.store_tower_key(&sk).unwrap();
     (sk, pk)
 }
 
+/// Creates a gRPC server builder with the connection settings from the config.
+fn create_rpc_server(conf: &Config) -> Server {
+    let keepalive = (conf.rpc_keepalive_interval != 0)
+        .then(|| Duration::from_secs(conf.rpc_keepalive_interval as u64));
+
+    Server::builder()
+        .tcp_keepalive(keepalive)
+        .http2_keepalive_interval(keepalive)
+        .http2_keepalive_timeout(Some(Duration::from_secs(conf.rpc_keepalive_timeout as u64)))
+        .max_concurrent_streams(
+            (conf.rpc_max_concurrent_streams != 0).then_some(conf.rpc_max_concurrent_streams),
+        )
+}
+
+fn create_rpc_client(connect: &str, port: u16, user: &str, password: &str) -> Client {
+    let schema = if !connect.starts_with("http") {
+        "http://"
+    } else {
+        ""
+    };
+    Client::new(
+        &format!("{}{}:{}", schema, connect, port),
+        Auth::UserPass(user.to_owned(), password.to_owned()),
+    )
+    .unwrap()
+}
+
 #[tokio::main]
 async fn main() {
     let opt = Opt::from_args();
@@ -80,12 +152,13 @@ async fn main() {
         std::process::exit(1);
     });
 
-    // Set log level
-    if conf.debug {
-        init_with_level(log::Level::Debug).unwrap()
+    // Set log level and format
+    let log_level = if conf.debug {
+        log::Level::Debug
     } else {
-        init_with_level(log::Level::Info).unwrap()
-    }
+        log::Level::Info
+    };
+    logging::init(log_level, LogFormat::from_str(&conf.log_format).unwrap()).unwrap();
 
     // Create network dir
     let path_network = path.join(conf.btc_network.clone());
@@ -93,23 +166,20 @@ async fn main() {
         eprintln!("Cannot create network dir: {:?}", e);
         std::process::exit(1);
     });
-    let dbm = Arc::new(Mutex::new(
-        DBM::new(path_network.join("teos_db.sql3")).unwrap(),
-    ));
+    let dbm = Arc::new(DBM::new(path_network.join("teos_db.sql3")).unwrap());
 
     // Load tower secret key or create a fresh one if none is found. If overwrite key is set, create a new
     // key straightaway
     let (tower_sk, tower_pk) = {
-        let locked_db = dbm.lock().unwrap();
         if conf.overwrite_key {
             log::info!("Overwriting tower keys");
-            create_new_tower_keypair(&locked_db)
+            create_new_tower_keypair(&dbm)
         } else {
-            match locked_db.load_tower_key() {
+            match dbm.load_tower_key() {
                 Ok(sk) => (sk, PublicKey::from_secret_key(&Secp256k1::new(), &sk)),
                 Err(_) => {
                     log::info!("Tower keys not found. Creating a fresh set");
-                    create_new_tower_keypair(&locked_db)
+                    create_new_tower_keypair(&dbm)
                 }
             }
         }
@@ -117,45 +187,87 @@ async fn main() {
     log::info!("tower_id: {}", tower_pk);
 
     // Initialize our bitcoind client
-    let (bitcoin_cli, bitcoind_reachable) = match BitcoindClient::new(
-        &conf.btc_rpc_connect,
-        conf.btc_rpc_port,
-        &conf.btc_rpc_user,
-        &conf.btc_rpc_password,
-    )
-    .await
-    {
-        Ok(client) => (
-            Arc::new(client),
-            Arc::new((Mutex::new(true), Condvar::new())),
-        ),
+    // bitcoind may still be starting up (e.g. if both are started at the same time), so give it some time if requested
+    let retry_backoff = Duration::from_secs(conf.btc_rpc_connect_backoff as u64);
+    let (bitcoin_cli, bitcoind_reachable) =
+        match bitcoin_cli::with_retries(conf.btc_rpc_connect_retries, retry_backoff, || {
+            BitcoindClient::new(
+                &conf.btc_rpc_connect,
+                conf.btc_rpc_port,
+                &conf.btc_rpc_user,
+                &conf.btc_rpc_password,
+            )
+        })
+        .await
+        {
+            Ok(client) => (
+                Arc::new(client),
+                Arc::new((Mutex::new(true), Condvar::new())),
+            ),
+            Err(e) => {
+                let e_msg = match e.kind() {
+                    ErrorKind::InvalidData => "invalid btcrpcuser or btcrpcpassword".into(),
+                    _ => e.to_string(),
+                };
+                log::error!("Failed to connect to bitcoind. Error: {}", e_msg);
+                return;
+            }
+        };
+
+    // Check bitcoind is running on the network the tower is configured for. Penalties would target the wrong chain otherwise
+    match bitcoin_cli.get_chain().await {
+        Ok(chain) => {
+            if let Err(e) =
+                bitcoin_cli::check_network(&chain, Network::from_str(&conf.btc_network).unwrap())
+            {
+                log::error!("{}. Please check btc_network or the bitcoind config", e);
+                return;
+            }
+        }
         Err(e) => {
-            let e_msg = match e.kind() {
-                ErrorKind::InvalidData => "invalid btcrpcuser or btcrpcpassword".into(),
-                _ => e.to_string(),
-            };
-            log::error!("Failed to connect to bitcoind. Error: {}", e_msg);
+            log::error!("Failed to get the bitcoind network. Error: {}", e);
             return;
         }
-    };
+    }
+
+    // Check the bitcoind version is supported. Refuse to start otherwise, unless the user has explicitly allowed it
+    match bitcoin_cli.get_version().await {
+        Ok(version) => {
+            log::info!(
+                "Connected to bitcoind {}",
+                bitcoin_cli::format_version(version)
+            );
+            if let Err(e) = bitcoin_cli::check_version(version) {
+                if conf.allow_unsupported_bitcoind {
+                    log::warn!("{}. Some functionality may not work as expected", e);
+                } else {
+                    log::error!("{}. Please upgrade bitcoind", e);
+                    return;
+                }
+            }
+        }
+        Err(e) => {
+            log::error!("Failed to get bitcoind version. Error: {}", e);
+            return;
+        }
+    }
 
     // FIXME: Temporary. We're using bitcoin_core_rpc and rust-lightning's rpc until they both get merged
     // https://github.com/rust-bitcoin/rust-bitcoincore-rpc/issues/166
-    let schema = if !conf.btc_rpc_connect.starts_with("http") {
-        "http://"
-    } else {
-        ""
-    };
-    let rpc = Arc::new(
-        Client::new(
-            &format!("{}{}:{}", schema, conf.btc_rpc_connect, conf.btc_rpc_port),
-            Auth::UserPass(conf.btc_rpc_user.clone(), conf.btc_rpc_password.clone()),
-        )
-        .unwrap(),
-    );
+    let rpc = Arc::new(create_rpc_client(
+        &conf.btc_rpc_connect,
+        conf.btc_rpc_port,
+        &conf.btc_rpc_user,
+        &conf.btc_rpc_password,
+    ));
+    let fallback_rpcs = conf
+        .btc_rpc_fallbacks
+        .iter()
+        .map(|f| Arc::new(create_rpc_client(&f.connect, f.port, &f.user, &f.password)))
+        .collect();
     let mut derefed = bitcoin_cli.deref();
     // Load last known block from DB if found. Poll it from Bitcoind otherwise.
-    let tip = if let Ok(block_hash) = dbm.lock().unwrap().load_last_known_block() {
+    let mut tip = if let Ok(block_hash) = dbm.load_last_known_block() {
         derefed
             .get_header(&block_hash, None)
             .await
@@ -163,7 +275,14 @@ async fn main() {
             .validate(block_hash)
             .unwrap()
     } else {
-        validate_best_block_header(&mut derefed).await.unwrap()
+        bitcoin_cli::with_retries(conf.btc_rpc_connect_retries, retry_backoff, || async {
+            validate_best_block_header(&mut bitcoin_cli.deref()).await
+        })
+        .await
+        .unwrap_or_else(|e| {
+            log::error!("Failed to get the best block from bitcoind. Error: {:?}", e);
+            std::process::exit(1);
+        })
     };
     log::info!("Last known block: {}", tip.header.block_hash());
 
@@ -171,25 +290,52 @@ async fn main() {
     let last_n_blocks = get_last_n_blocks(&mut poller, tip, 6).await;
 
     // Build components
-    let gatekeeper = Arc::new(Gatekeeper::new(
-        tip.height,
-        conf.subscription_slots,
-        conf.subscription_duration,
-        conf.expiry_delta,
-        dbm.clone(),
-    ));
+    let metrics = Arc::new(Metrics::new());
+    let event_sender = events::channel();
+    let gatekeeper = Arc::new(
+        Gatekeeper::new(
+            tip.height,
+            conf.subscription_slots,
+            conf.subscription_duration,
+            conf.expiry_delta,
+            conf.max_appointments_per_user,
+            dbm.clone(),
+        )
+        .with_max_user_slots(conf.max_user_slots)
+        .with_expiry_warning_window(conf.expiry_warning_window)
+        .with_metrics(metrics.clone())
+        .with_events(event_sender.clone()),
+    );
 
-    let carrier = Carrier::new(rpc, bitcoind_reachable.clone(), tip.deref().height);
-    let responder = Arc::new(Responder::new(carrier, gatekeeper.clone(), dbm.clone()));
-    let watcher = Arc::new(Watcher::new(
-        gatekeeper.clone(),
-        responder.clone(),
-        last_n_blocks,
-        tip.height,
-        tower_sk,
-        UserId(tower_pk),
-        dbm.clone(),
-    ));
+    if conf.dry_run {
+        log::warn!("Running in dry-run mode. Penalty transactions will NOT be broadcast");
+    }
+    let carrier = Carrier::new(rpc, bitcoind_reachable.clone(), tip.deref().height)
+        .with_dry_run(conf.dry_run)
+        .with_fallbacks(fallback_rpcs);
+    let responder = Arc::new(
+        Responder::new(carrier, gatekeeper.clone(), dbm.clone())
+            .with_metrics(metrics.clone())
+            .with_events(event_sender.clone())
+            .with_retention_blocks(conf.retention_blocks)
+            .with_spent_output_checks(conf.check_spent_outputs)
+            .with_max_concurrent_broadcasts(conf.max_concurrent_broadcasts as usize),
+    );
+    let watcher = Arc::new(
+        Watcher::new(
+            gatekeeper.clone(),
+            responder.clone(),
+            last_n_blocks,
+            tip.height,
+            tower_sk,
+            UserId(tower_pk),
+            dbm.clone(),
+        )
+        .with_max_appointment_size(conf.max_appointment_size)
+        .with_min_to_self_delay(conf.min_to_self_delay as u32)
+        .with_metrics(metrics.clone())
+        .with_events(event_sender.clone()),
+    );
 
     if watcher.is_fresh() & responder.is_fresh() & gatekeeper.is_fresh() {
         log::info!("Fresh bootstrap");
@@ -202,16 +348,63 @@ async fn main() {
     let shutdown_signal_http = shutdown_signal_rpc_api.clone();
     let shutdown_signal_cm = shutdown_signal_rpc_api.clone();
     let shutdown_signal_tor = shutdown_signal_rpc_api.clone();
+    let shutdown_signal_backup = shutdown_signal_rpc_api.clone();
+    let shutdown_signal_metrics = shutdown_signal_rpc_api.clone();
+    let shutdown_signal_responder = shutdown_signal_rpc_api.clone();
+
+    // Penalties that could not be broadcast because bitcoind was unreachable are sent once it is back
+    let retry_responder = responder.clone();
+    let retry_thread =
+        thread::spawn(move || retry_responder.retry_pending_broadcasts(shutdown_signal_responder));
+
+    // Only registered users get a rate limiting bucket of their own, so the limiter needs to query the gatekeeper
+    let rate_limiter = (conf.rate_limit_refill != 0).then(|| {
+        let rate_limiter = RateLimiter::new(
+            conf.rate_limit_refill,
+            conf.rate_limit_burst,
+            gatekeeper.clone(),
+        );
+        if conf.rate_limit_shared_refill != 0 {
+            rate_limiter
+                .with_shared_limits(conf.rate_limit_shared_refill, conf.rate_limit_shared_burst)
+        } else {
+            rate_limiter
+        }
+    });
 
     // The ordering here actually matters. Listeners are called by order, and we want the gatekeeper to be called
     // last, so both the Watcher and the Responder can query the necessary data from it during data deletion.
     let listener = &(watcher.clone(), &(responder, gatekeeper));
+
+    // Catch up with a backlog of blocks fetching several of them at a time, if requested. Anything left (e.g. reorgs or
+    // blocks that could not be fetched) is handled by the SpvClient, block by block
+    if conf.bootstrap_concurrency > 1 {
+        if let Err(e) = bootstrap(
+            &mut poller,
+            &bitcoin_cli,
+            conf.bootstrap_concurrency as usize,
+            listener,
+            &mut tip,
+        )
+        .await
+        {
+            log::warn!(
+                "Concurrent bootstrap stopped at block {}. Error: {:?}",
+                tip.height,
+                e
+            );
+        }
+        if let Err(e) = dbm.store_last_known_block(&tip.header.block_hash()) {
+            log::error!("Couldn't persist the last known block. Error: {:?}", e);
+        }
+    }
+
     let cache = &mut UnboundedCache::new();
     let spv_client = SpvClient::new(tip, poller, cache, listener);
     let mut chain_monitor = ChainMonitor::new(
         spv_client,
         tip,
-        dbm,
+        dbm.clone(),
         conf.polling_delta,
         shutdown_signal_cm,
         bitcoind_reachable.clone(),
@@ -223,10 +416,15 @@ async fn main() {
     log::info!("Bootstrap completed. Turning on interfaces");
 
     // Build interfaces
+    let tor_available = Arc::new(AtomicBool::new(false));
+    let task_panicked = Arc::new(AtomicBool::new(false));
     let rpc_api = Arc::new(InternalAPI::new(
         watcher,
         bitcoind_reachable.clone(),
         shutdown_trigger,
+        tor_available.clone(),
+        task_panicked.clone(),
+        conf.clone(),
     ));
     let internal_rpc_api = rpc_api.clone();
 
@@ -244,17 +442,45 @@ async fn main() {
         .parse()
         .unwrap();
 
+    // Flag panics so they can be reported by the health check. The default hook is kept so panics are still printed
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        task_panicked.store(true, Ordering::Release);
+        default_hook(info);
+    }));
+
     // Start tasks
-    let private_api_task = task::spawn(async move {
-        Server::builder()
-            .add_service(PrivateTowerServicesServer::new(rpc_api))
-            .serve_with_shutdown(rpc_api_addr, shutdown_signal_rpc_api)
-            .await
-            .unwrap();
-    });
+    let private_rpc_server =
+        create_rpc_server(&conf).add_service(PrivateTowerServicesServer::new(rpc_api));
+    let private_api_task = if conf.rpc_socket_path.is_empty() {
+        task::spawn(async move {
+            private_rpc_server
+                .serve_with_shutdown(rpc_api_addr, shutdown_signal_rpc_api)
+                .await
+                .unwrap();
+        })
+    } else {
+        let socket_path = path.join(&conf.rpc_socket_path);
+        let incoming = uds::incoming(&socket_path).unwrap_or_else(|e| {
+            log::error!(
+                "Cannot bind the RPC API to {}. Error: {}",
+                socket_path.display(),
+                e
+            );
+            std::process::exit(1);
+        });
+        log::info!("RPC API listening on {}", socket_path.display());
+        task::spawn(async move {
+            private_rpc_server
+                .serve_with_incoming_shutdown(incoming, shutdown_signal_rpc_api)
+                .await
+                .unwrap();
+        })
+    };
 
+    let mut public_rpc_server = create_rpc_server(&conf);
     let public_api_task = task::spawn(async move {
-        Server::builder()
+        public_rpc_server
             .add_service(PublicTowerServicesServer::new(internal_rpc_api))
             .serve_with_shutdown(internal_rpc_api_addr, shutdown_signal_internal_rpc_api)
             .await
@@ -264,6 +490,10 @@ async fn main() {
     let http_api_task = task::spawn(http::serve(
         http_api_addr,
         internal_rpc_api_uri,
+        http::BodyLimits::new(conf.max_appointment_size, conf.max_batch_size as usize),
+        conf.http_compression,
+        conf.events_enabled.then_some(event_sender),
+        rate_limiter,
         shutdown_signal_http,
     ));
 
@@ -274,22 +504,78 @@ async fn main() {
         let tor_control_port = conf.tor_control_port;
         let api_port = conf.api_port;
         let onion_port = conf.onion_hidden_service_port;
+        let key_path = if !conf.tor_persist_key {
+            None
+        } else if conf.tor_key_path.is_empty() {
+            Some(path_network.join("onion_v3_sk"))
+        } else {
+            Some(config::data_dir_absolute_path(conf.tor_key_path.clone()))
+        };
 
-        tor_task = Some(task::spawn(async move {
-            tor::expose_onion_service(tor_control_port, api_port, onion_port, shutdown_signal_tor)
-                .await
-                .unwrap();
-        }));
+        tor_task = Some(task::spawn(tor::run_onion_service(
+            tor_control_port,
+            api_port,
+            onion_port,
+            key_path,
+            shutdown_signal_tor,
+            tor_available,
+        )));
+    }
+
+    // Periodically back up the database (if enabled)
+    let mut backup_task = Option::None;
+    if conf.backup_interval > 0 {
+        let backup_dir = if conf.backup_dir.is_empty() {
+            path_network.join("backups")
+        } else {
+            config::data_dir_absolute_path(conf.backup_dir.clone())
+        };
+        log::info!("Backing up the database to {}", backup_dir.display());
+
+        backup_task = Some(task::spawn(backup::backup_periodically(
+            dbm,
+            backup_dir,
+            Duration::from_secs(conf.backup_interval as u64),
+            conf.backup_retention as usize,
+            shutdown_signal_backup,
+        )));
+    }
+
+    // Serve metrics (if enabled)
+    let mut metrics_task = Option::None;
+    if conf.metrics_enabled {
+        let metrics_addr = format!("{}:{}", conf.metrics_bind, conf.metrics_port)
+            .parse()
+            .unwrap();
+        log::info!("Serving metrics on {}", metrics_addr);
+
+        metrics_task = Some(task::spawn(metrics::serve(
+            metrics_addr,
+            metrics,
+            shutdown_signal_metrics,
+        )));
     }
 
     chain_monitor.monitor_chain().await;
 
-    // Wait until shutdown
+    // Wait until shutdown. The APIs stop accepting requests once the signal is received, but in-flight ones are completed,
+    // so the chain monitor is only drained once they are done.
     http_api_task.await.unwrap();
     private_api_task.await.unwrap();
     public_api_task.await.unwrap();
-    if conf.tor_support {
-        tor_task.unwrap().await.unwrap();
+    chain_monitor.drain().await;
+    retry_thread.join().unwrap();
+    if let Some(tor_task) = tor_task {
+        // Tor is not critical for the tower, so a failed task should not prevent a clean shutdown
+        if let Err(e) = tor_task.await {
+            log::error!("Tor task did not finish cleanly. Error: {}", e);
+        }
+    }
+    if let Some(backup_task) = backup_task {
+        backup_task.await.unwrap();
+    }
+    if let Some(metrics_task) = metrics_task {
+        metrics_task.await.unwrap();
     }
 
     log::info!("Shutting down tower");
@@ -0,0 +1,220 @@
+//! Logic related to the tower metrics, exposed in Prometheus' text format.
+//!
+
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use triggered::Listener;
+use warp::{http::header, reply, Filter, Rejection, Reply};
+
+/// Registry of the tower metrics.
+///
+/// Counters are plain atomics, so the registry can be shared between components and updated without any locking.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Number of appointments accepted by the [Watcher](crate::watcher::Watcher).
+    appointments_accepted: AtomicU64,
+    /// Number of appointments rejected by the [Watcher](crate::watcher::Watcher).
+    appointments_rejected: AtomicU64,
+    /// Number of triggered appointments whose encrypted blob did not decrypt to a valid transaction.
+    appointments_invalid: AtomicU64,
+    /// Number of penalty transactions accepted by `bitcoind` when broadcast by the [Responder](crate::responder::Responder).
+    penalties_broadcast: AtomicU64,
+    /// Number of users currently registered within the [Gatekeeper](crate::gatekeeper::Gatekeeper).
+    registered_users: AtomicU64,
+}
+
+impl Metrics {
+    /// Creates a new [Metrics] instance with all metrics set to zero.
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    /// Accounts for an accepted appointment.
+    pub(crate) fn appointment_accepted(&self) {
+        self.appointments_accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Accounts for a rejected appointment.
+    pub(crate) fn appointment_rejected(&self) {
+        self.appointments_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Accounts for a triggered appointment holding invalid data.
+    pub(crate) fn appointment_invalid(&self) {
+        self.appointments_invalid.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Accounts for a broadcast penalty transaction.
+    pub(crate) fn penalty_broadcast(&self) {
+        self.penalties_broadcast.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Sets the number of registered users.
+    pub(crate) fn set_registered_users(&self, count: usize) {
+        self.registered_users.store(count as u64, Ordering::Relaxed);
+    }
+
+    /// Renders the metrics using Prometheus' text exposition format.
+    pub fn render(&self) -> String {
+        let metrics = [
+            (
+                "appointments_accepted_total",
+                "counter",
+                "Number of appointments accepted by the tower.",
+                &self.appointments_accepted,
+            ),
+            (
+                "appointments_rejected_total",
+                "counter",
+                "Number of appointments rejected by the tower.",
+                &self.appointments_rejected,
+            ),
+            (
+                "appointments_invalid_total",
+                "counter",
+                "Number of triggered appointments whose data did not decrypt to a valid transaction.",
+                &self.appointments_invalid,
+            ),
+            (
+                "penalties_broadcast_total",
+                "counter",
+                "Number of penalty transactions broadcast by the tower.",
+                &self.penalties_broadcast,
+            ),
+            (
+                "registered_users",
+                "gauge",
+                "Number of users currently registered within the tower.",
+                &self.registered_users,
+            ),
+        ];
+
+        let mut out = String::new();
+        for (name, kind, help, value) in metrics.iter() {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+            writeln!(out, "{} {}", name, value.load(Ordering::Relaxed)).unwrap();
+        }
+
+        out
+    }
+}
+
+fn router(metrics: Arc<Metrics>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::get()
+        .and(warp::path("metrics"))
+        .and(warp::path::end())
+        .map(move || {
+            reply::with_header(
+                metrics.render(),
+                header::CONTENT_TYPE,
+                "text/plain; version=0.0.4",
+            )
+        })
+}
+
+/// Serves the metrics over HTTP (`GET /metrics`) until the shutdown signal is received.
+pub async fn serve(metrics_bind: SocketAddr, metrics: Arc<Metrics>, shutdown_signal: Listener) {
+    let (_, server) =
+        warp::serve(router(metrics)).bind_with_graceful_shutdown(metrics_bind, shutdown_signal);
+    server.await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use warp::http::StatusCode;
+
+    use teos_common::cryptography::{self, get_random_keypair};
+    use teos_common::UserId;
+
+    use crate::dbm::DBM;
+    use crate::extended_appointment::UUID;
+    use crate::gatekeeper::Gatekeeper;
+    use crate::responder::Responder;
+    use crate::test_utils::{
+        create_responder, create_watcher, generate_dummy_appointment, get_random_breach,
+        BitcoindMock, Blockchain, MockOptions, DURATION, EXPIRY_DELTA, SLOTS, START_HEIGHT,
+    };
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        metrics.appointment_accepted();
+        metrics.appointment_accepted();
+        metrics.appointment_rejected();
+        metrics.appointment_invalid();
+        metrics.set_registered_users(5);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("# TYPE appointments_accepted_total counter\n"));
+        assert!(rendered.contains("\nappointments_accepted_total 2\n"));
+        assert!(rendered.contains("\nappointments_rejected_total 1\n"));
+        assert!(rendered.contains("\nappointments_invalid_total 1\n"));
+        assert!(rendered.contains("\npenalties_broadcast_total 0\n"));
+        assert!(rendered.contains("# TYPE registered_users gauge\n"));
+        assert!(rendered.contains("\nregistered_users 5\n"));
+    }
+
+    #[tokio::test]
+    async fn test_scrape_metrics() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let dbm = Arc::new(DBM::in_memory().unwrap());
+        let bitcoind_mock = BitcoindMock::new(MockOptions::empty());
+        let metrics = Arc::new(Metrics::new());
+
+        let gk = Arc::new(
+            Gatekeeper::new(
+                chain.get_block_count(),
+                SLOTS,
+                DURATION,
+                EXPIRY_DELTA,
+                0,
+                dbm.clone(),
+            )
+            .with_metrics(metrics.clone()),
+        );
+        let responder: Arc<Responder> = Arc::new(
+            create_responder(chain.tip(), gk.clone(), dbm.clone(), bitcoind_mock.url())
+                .with_metrics(metrics.clone()),
+        );
+        let watcher = create_watcher(&mut chain, responder.clone(), gk, bitcoind_mock, dbm)
+            .await
+            .with_metrics(metrics.clone());
+
+        // Drive a couple of appointments through the tower, one accepted and one rejected (wrong signature)
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        watcher.register(user_id, None).unwrap();
+
+        let appointment = generate_dummy_appointment(None).inner;
+        let uuid = UUID::new(appointment.locator, user_id);
+        let signature = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        watcher.add_appointment(appointment, signature).unwrap();
+
+        let appointment = generate_dummy_appointment(None).inner;
+        let (wrong_sk, _) = get_random_keypair();
+        let signature = cryptography::sign(&appointment.serialize(), &wrong_sk).unwrap();
+        assert!(watcher.add_appointment(appointment, signature).is_err());
+
+        // And a penalty through the Responder
+        responder.handle_breach(uuid, get_random_breach(), user_id);
+
+        let res = warp::test::request()
+            .method("GET")
+            .path("/metrics")
+            .reply(&router(metrics))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let body = String::from_utf8(res.body().to_vec()).unwrap();
+        assert!(body.contains("\nappointments_accepted_total 1\n"));
+        assert!(body.contains("\nappointments_rejected_total 1\n"));
+        assert!(body.contains("\npenalties_broadcast_total 1\n"));
+        assert!(body.contains("\nregistered_users 1\n"));
+    }
+}
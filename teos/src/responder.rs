@@ -2,25 +2,33 @@
 
 use std::collections::{HashMap, HashSet};
 use std::iter::FromIterator;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::Duration;
 
 use bitcoin::consensus;
-use bitcoin::{BlockHeader, Transaction, Txid};
+use bitcoin::{BlockHeader, OutPoint, Transaction, Txid};
 use lightning::chain;
+use triggered::Listener;
 
+use teos_common::appointment::Locator;
 use teos_common::constants;
 use teos_common::UserId;
 
 use crate::carrier::Carrier;
 use crate::dbm::DBM;
+use crate::events::{self, Event, EventSender};
 use crate::extended_appointment::UUID;
 use crate::gatekeeper::{Gatekeeper, UserInfo};
+use crate::metrics::Metrics;
 use crate::protos as msgs;
 use crate::watcher::Breach;
 
 /// Number of missed confirmations to wait before rebroadcasting a transaction.
 const CONFIRMATIONS_BEFORE_RETRY: u8 = 6;
 
+/// Number of blocks penalty transactions are expected to confirm within. Used to query `bitcoind` for fee estimates.
+const FEE_ESTIMATE_TARGET: u16 = 6;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 /// The confirmation status of a given penalty transaction.
 pub enum ConfirmationStatus {
@@ -35,6 +43,9 @@ enum DeletionReason {
     Outdated,
     Rejected,
     Completed,
+    Reorged,
+    Pruned,
+    Resolved,
 }
 
 impl ConfirmationStatus {
@@ -61,6 +72,35 @@ impl ConfirmationStatus {
     }
 }
 
+/// Packs the reasons why trying to rebroadcast a penalty on demand may fail.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum BroadcastPenaltyFailure {
+    NotFound,
+    Unavailable,
+}
+
+/// Computes the fee rate (in sat/kw) a penalty transaction pays, given the dispute transaction it spends from.
+///
+/// The fee rate can only be derived if all the penalty inputs spend outputs of the dispute transaction. Returns [None]
+/// otherwise.
+pub(crate) fn get_fee_rate(dispute_tx: &Transaction, penalty_tx: &Transaction) -> Option<u64> {
+    let dispute_txid = dispute_tx.txid();
+    let mut input_value: u64 = 0;
+    for input in penalty_tx.input.iter() {
+        if input.previous_output.txid != dispute_txid {
+            return None;
+        }
+        input_value += dispute_tx
+            .output
+            .get(input.previous_output.vout as usize)?
+            .value;
+    }
+    let output_value: u64 = penalty_tx.output.iter().map(|o| o.value).sum();
+    let fee = input_value.checked_sub(output_value)?;
+
+    Some(fee * 1000 / penalty_tx.get_weight() as u64)
+}
+
 /// Minimal data required in memory to keep track of transaction trackers.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct TrackerSummary {
@@ -70,6 +110,8 @@ pub(crate) struct TrackerSummary {
     penalty_txid: Txid,
     /// The confirmation status of a given tracker.
     status: ConfirmationStatus,
+    /// The outputs of the dispute transaction spent by the penalty.
+    dispute_outpoints: Vec<OutPoint>,
 }
 
 /// Structure to keep track of triggered appointments.
@@ -98,12 +140,24 @@ impl TransactionTracker {
         }
     }
 
+    /// Gets the fee rate (in sat/kw) paid by the penalty transaction, if it can be derived. See [get_fee_rate].
+    pub fn fee_rate(&self) -> Option<u64> {
+        get_fee_rate(&self.dispute_tx, &self.penalty_tx)
+    }
+
     /// Computes the [TrackerSummary] of the [TransactionTracker].
     pub fn get_summary(&self) -> TrackerSummary {
         TrackerSummary {
             user_id: self.user_id,
             penalty_txid: self.penalty_tx.txid(),
             status: self.status,
+            dispute_outpoints: self
+                .penalty_tx
+                .input
+                .iter()
+                .map(|txin| txin.previous_output)
+                .filter(|outpoint| outpoint.txid == self.dispute_tx.txid())
+                .collect(),
         }
     }
 }
@@ -114,6 +168,7 @@ impl From<TransactionTracker> for msgs::Tracker {
             dispute_txid: t.dispute_tx.txid().to_vec(),
             penalty_txid: t.penalty_tx.txid().to_vec(),
             penalty_rawtx: consensus::serialize(&t.penalty_tx),
+            fee_rate: t.fee_rate(),
         }
     }
 }
@@ -131,20 +186,67 @@ pub struct Responder {
     /// A map between [Txid]s and [UUID]s.
     tx_tracker_map: Mutex<HashMap<Txid, HashSet<UUID>>>,
     /// A [Carrier] instance. Data is sent to the `bitcoind` through it.
-    carrier: Mutex<Carrier>,
+    ///
+    /// The [Carrier] can be shared, so broadcasts only take a read guard and may run concurrently (up to
+    /// [max_concurrent_broadcasts](Self::max_concurrent_broadcasts) at a time).
+    carrier: RwLock<Carrier>,
     /// A [Gatekeeper] instance. Data regarding users is requested to it.
     gatekeeper: Arc<Gatekeeper>,
     /// A [DBM] (database manager) instance. Used to persist tracker data into disk.
-    dbm: Arc<Mutex<DBM>>,
+    dbm: Arc<DBM>,
+    /// A [Metrics] instance. Keeps track of the broadcast penalties.
+    metrics: Arc<Metrics>,
+    /// An [EventSender]. Used to publish the broadcast penalties.
+    events: EventSender,
+    /// Number of blocks responded appointments are kept for once their penalty is confirmed (0 means they are kept
+    /// until irrevocably resolved).
+    retention_blocks: u32,
+    /// Trackers whose penalty could not be broadcast because `bitcoind` was unreachable. They are sent again once it
+    /// is back. The queue is also persisted, so it survives restarts.
+    pending_broadcasts: Mutex<HashSet<UUID>>,
+    /// Whether unconfirmed trackers are checked for outputs already spent by someone else, so they can be resolved
+    /// early.
+    check_spent_outputs: bool,
+    /// Fee rate (in sat/kw) penalties need to pay to confirm in time, as estimated by `bitcoind` on the last block.
+    fee_estimate: Mutex<Option<u64>>,
+    /// Maximum number of transactions sent to `bitcoind` at the same time (0 means no limit). Broadcasts beyond the
+    /// limit wait for a free slot, so `bitcoind`'s RPC is not flooded on a mass channel close, but none of them is
+    /// dropped.
+    max_concurrent_broadcasts: usize,
+    /// Number of broadcasts currently in flight, alongside its notifier. Used to enforce
+    /// [max_concurrent_broadcasts](Self::max_concurrent_broadcasts).
+    broadcasts_in_flight: (Mutex<usize>, Condvar),
 }
 
 impl Responder {
     /// Creates a new [Responder] instance.
-    pub fn new(carrier: Carrier, gatekeeper: Arc<Gatekeeper>, dbm: Arc<Mutex<DBM>>) -> Self {
+    pub fn new(carrier: Carrier, gatekeeper: Arc<Gatekeeper>, dbm: Arc<DBM>) -> Self {
+        let (trackers, tx_tracker_map) = Responder::load_trackers(&dbm);
+        let pending_broadcasts = dbm.load_pending_broadcasts();
+
+        Responder {
+            carrier: RwLock::new(carrier),
+            trackers: Mutex::new(trackers),
+            tx_tracker_map: Mutex::new(tx_tracker_map),
+            dbm,
+            gatekeeper,
+            metrics: Arc::new(Metrics::new()),
+            events: events::channel(),
+            retention_blocks: 0,
+            pending_broadcasts: Mutex::new(pending_broadcasts),
+            check_spent_outputs: false,
+            fee_estimate: Mutex::new(None),
+            max_concurrent_broadcasts: 0,
+            broadcasts_in_flight: (Mutex::new(0), Condvar::new()),
+        }
+    }
+
+    /// Loads the trackers stored in the database, alongside the map between their penalty [Txid]s and [UUID]s.
+    fn load_trackers(dbm: &DBM) -> (HashMap<UUID, TrackerSummary>, HashMap<Txid, HashSet<UUID>>) {
         let mut trackers = HashMap::new();
         let mut tx_tracker_map: HashMap<Txid, HashSet<UUID>> = HashMap::new();
 
-        for (uuid, tracker) in dbm.lock().unwrap().load_all_trackers() {
+        for (uuid, tracker) in dbm.load_all_trackers() {
             trackers.insert(uuid, tracker.get_summary());
 
             if let Some(map) = tx_tracker_map.get_mut(&tracker.penalty_tx.txid()) {
@@ -154,12 +256,47 @@ impl Responder {
             }
         }
 
+        (trackers, tx_tracker_map)
+    }
+
+    /// Reloads the trackers from the database, replacing the ones held in memory.
+    pub(crate) fn reload_trackers(&self) {
+        let (trackers, tx_tracker_map) = Responder::load_trackers(&self.dbm);
+        *self.trackers.lock().unwrap() = trackers;
+        *self.tx_tracker_map.lock().unwrap() = tx_tracker_map;
+    }
+
+    /// Sets the [Metrics] instance the [Responder] reports to.
+    pub fn with_metrics(self, metrics: Arc<Metrics>) -> Self {
+        Responder { metrics, ..self }
+    }
+
+    /// Sets the [EventSender] the [Responder] publishes events to.
+    pub fn with_events(self, events: EventSender) -> Self {
+        Responder { events, ..self }
+    }
+
+    /// Sets the number of blocks responded appointments are kept for once their penalty is confirmed.
+    pub fn with_retention_blocks(self, retention_blocks: u32) -> Self {
+        Responder {
+            retention_blocks,
+            ..self
+        }
+    }
+
+    /// Sets whether unconfirmed trackers are checked for outputs already spent by someone else.
+    pub fn with_spent_output_checks(self, check_spent_outputs: bool) -> Self {
         Responder {
-            carrier: Mutex::new(carrier),
-            trackers: Mutex::new(trackers),
-            tx_tracker_map: Mutex::new(tx_tracker_map),
-            dbm,
-            gatekeeper,
+            check_spent_outputs,
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of transactions sent to `bitcoind` at the same time (0 means no limit).
+    pub fn with_max_concurrent_broadcasts(self, max_concurrent_broadcasts: usize) -> Self {
+        Responder {
+            max_concurrent_broadcasts,
+            ..self
         }
     }
 
@@ -173,6 +310,28 @@ impl Responder {
         self.trackers.lock().unwrap().len()
     }
 
+    /// Runs a broadcast through the [Carrier], waiting for a free slot first if the number of concurrent broadcasts is
+    /// bounded. Broadcasts beyond the limit are held until one of the in-flight ones is done, they are never dropped.
+    fn broadcast<T>(&self, send: impl FnOnce(&Carrier) -> T) -> T {
+        if self.max_concurrent_broadcasts == 0 {
+            return send(&self.carrier.read().unwrap());
+        }
+
+        let (lock, notifier) = &self.broadcasts_in_flight;
+        let mut in_flight = lock.lock().unwrap();
+        while *in_flight >= self.max_concurrent_broadcasts {
+            in_flight = notifier.wait(in_flight).unwrap();
+        }
+        *in_flight += 1;
+        drop(in_flight);
+
+        let result = send(&self.carrier.read().unwrap());
+
+        *lock.lock().unwrap() -= 1;
+        notifier.notify_one();
+        result
+    }
+
     /// Data entry point for the [Responder]. Handles a [Breach] provided by the [Watcher](crate::watcher::Watcher).
     ///
     /// Breaches can either be added to the [Responder] in the form of a [TransactionTracker] if the [penalty transaction](Breach::penalty_tx)
@@ -189,18 +348,121 @@ impl Responder {
             return tracker.status;
         }
 
-        let status = self
-            .carrier
-            .lock()
-            .unwrap()
-            .send_transaction(&breach.penalty_tx);
+        let status = match self
+            .broadcast(|carrier| carrier.try_send_transaction(&breach.penalty_tx))
+        {
+            Some(status) => status,
+            None => {
+                // Penalties are time critical, so instead of waiting for bitcoind to be back (and holding the block
+                // processing), the tracker is added straightaway and the penalty is queued to be sent when possible.
+                let status =
+                    ConfirmationStatus::InMempoolSince(self.carrier.read().unwrap().get_height());
+                self.warn_if_underfunded(uuid, &breach);
+                log::warn!(
+                    uuid:% = uuid, penalty_txid:% = breach.penalty_tx.txid();
+                    "bitcoind is unreachable. Penalty transaction queued for broadcast"
+                );
+                self.add_tracker(uuid, breach, user_id, status);
+                self.pending_broadcasts.lock().unwrap().insert(uuid);
+                if let Err(e) = self.dbm.store_pending_broadcast(uuid) {
+                    log::error!(
+                        "Couldn't persist the queued penalty of {}. Error: {:?}",
+                        uuid,
+                        e
+                    );
+                }
+                return status;
+            }
+        };
+        self.warn_if_underfunded(uuid, &breach);
+
         if !matches!(status, ConfirmationStatus::Rejected { .. }) {
+            self.metrics.penalty_broadcast();
+            // Sending only fails if there are no subscribers
+            self.events
+                .send(Event::penalty_broadcast(
+                    user_id,
+                    Locator::new(breach.dispute_tx.txid()),
+                    breach.penalty_tx.txid(),
+                ))
+                .ok();
             self.add_tracker(uuid, breach, user_id, status);
         }
 
         status
     }
 
+    /// Sends the penalties queued while `bitcoind` was unreachable. The ones that still cannot be sent are kept queued.
+    ///
+    /// Rejected penalties are only logged, their trackers are handled like any other penalty missing confirmations.
+    pub(crate) fn drain_pending_broadcasts(&self) {
+        let pending: Vec<UUID> = self.pending_broadcasts.lock().unwrap().drain().collect();
+
+        for uuid in pending {
+            // The tracker may have been deleted in the meantime
+            let tracker = match self.get_tracker(uuid) {
+                Some(tracker) => tracker,
+                None => continue,
+            };
+            let penalty_txid = tracker.penalty_tx.txid();
+
+            let status =
+                self.broadcast(|carrier| carrier.try_send_transaction(&tracker.penalty_tx));
+            match status {
+                Some(ConfirmationStatus::Rejected(reason)) => {
+                    log::warn!(
+                        uuid:% = uuid, penalty_txid:% = penalty_txid, reason = reason;
+                        "Queued penalty transaction rejected: {}", penalty_txid
+                    );
+                    self.dbm.remove_pending_broadcast(uuid);
+                }
+                Some(status) => {
+                    if let Some(summary) = self.trackers.lock().unwrap().get_mut(&uuid) {
+                        summary.status = status;
+                    }
+                    self.dbm.update_tracker_status(uuid, &status);
+                    self.dbm.remove_pending_broadcast(uuid);
+                    self.metrics.penalty_broadcast();
+                    self.events
+                        .send(Event::penalty_broadcast(
+                            tracker.user_id,
+                            Locator::new(tracker.dispute_tx.txid()),
+                            penalty_txid,
+                        ))
+                        .ok();
+                    log::info!(
+                        uuid:% = uuid, penalty_txid:% = penalty_txid;
+                        "Queued penalty transaction sent: {}", penalty_txid
+                    );
+                }
+                None => {
+                    self.pending_broadcasts.lock().unwrap().insert(uuid);
+                }
+            }
+        }
+    }
+
+    /// Sends the penalties queued while `bitcoind` was unreachable as soon as it is reachable again, until the shutdown
+    /// signal is received.
+    ///
+    /// Blocks the calling thread, which is woken up every time bitcoind is flagged as reachable (i.e. on every
+    /// successful poll by the [ChainMonitor](crate::chain_monitor::ChainMonitor)), or every second otherwise so the
+    /// shutdown signal is not missed.
+    pub fn retry_pending_broadcasts(&self, shutdown_signal: Listener) {
+        let bitcoind_reachable = self.carrier.read().unwrap().bitcoind_reachable();
+        let (lock, notifier) = &*bitcoind_reachable;
+
+        while !shutdown_signal.is_triggered() {
+            let (reachable, _) = notifier
+                .wait_timeout(lock.lock().unwrap(), Duration::from_secs(1))
+                .unwrap();
+            if *reachable && !self.pending_broadcasts.lock().unwrap().is_empty() {
+                drop(reachable);
+                self.drain_pending_broadcasts();
+            }
+        }
+    }
+
     /// Adds a [TransactionTracker] to the [Responder] from a given [Breach].
     ///
     /// From this point on, transactions are accepted as valid. They may not end up being confirmed, but they
@@ -230,12 +492,13 @@ impl Responder {
             tx_tracker_map.insert(tracker.penalty_tx.txid(), HashSet::from_iter(vec![uuid]));
         }
 
-        self.dbm
-            .lock()
-            .unwrap()
-            .store_tracker(uuid, &tracker)
-            .unwrap();
-        log::info!("New tracker added (uuid={}).", uuid);
+        self.dbm.store_tracker(uuid, &tracker).unwrap();
+        log::info!(
+            user_id:% = user_id,
+            locator:% = Locator::new(tracker.dispute_tx.txid()),
+            penalty_txid:% = tracker.penalty_tx.txid();
+            "New tracker added (uuid={}).", uuid
+        );
     }
 
     /// Checks whether a given tracker can be found in the [Responder].
@@ -269,12 +532,87 @@ impl Responder {
     /// The [TransactionTracker] is queried to the [DBM].
     pub(crate) fn get_tracker(&self, uuid: UUID) -> Option<TransactionTracker> {
         if self.trackers.lock().unwrap().contains_key(&uuid) {
-            self.dbm.lock().unwrap().load_tracker(uuid).ok()
+            self.dbm.load_tracker(uuid).ok()
         } else {
             None
         }
     }
 
+    /// Checks whether a penalty paying the given fee rate (in sat/kw) is likely not to confirm in time, that is, whether
+    /// it is below `bitcoind`'s estimate as of the last block. Returns false if no estimate is available.
+    pub(crate) fn is_likely_underfunded(&self, fee_rate: u64) -> bool {
+        match *self.fee_estimate.lock().unwrap() {
+            Some(estimate) => fee_rate < estimate,
+            None => false,
+        }
+    }
+
+    /// Logs a warning if the penalty of a given breach is likely underfunded. This is only advisory, so it is checked
+    /// once the penalty has already been sent.
+    fn warn_if_underfunded(&self, uuid: UUID, breach: &Breach) {
+        if let Some(fee_rate) = get_fee_rate(&breach.dispute_tx, &breach.penalty_tx) {
+            if self.is_likely_underfunded(fee_rate) {
+                log::warn!(
+                    uuid:% = uuid, penalty_txid:% = breach.penalty_tx.txid(), fee_rate = fee_rate;
+                    "Penalty transaction is likely underfunded ({} sat/kw)", fee_rate
+                );
+            }
+        }
+    }
+
+    /// Queries `bitcoind` for a new fee estimate, replacing the cached one. Called once per block, so checking penalties
+    /// never needs a round trip to `bitcoind`.
+    fn update_fee_estimate(&self) {
+        let estimate = self
+            .carrier
+            .read()
+            .unwrap()
+            .estimate_fee_rate(FEE_ESTIMATE_TARGET);
+        *self.fee_estimate.lock().unwrap() = estimate;
+    }
+
+    /// Sends the penalty transaction of a given tracker to the network again, on demand.
+    ///
+    /// The tracker is kept no matter the outcome. If the penalty is accepted, the tracker status is updated so the
+    /// missed confirmation count starts over. Returns the penalty [Txid] alongside the [ConfirmationStatus] reported
+    /// by the [Carrier].
+    ///
+    /// The penalty is always sent, even if the [Carrier] already did so this block. Requests are not held waiting for
+    /// `bitcoind` though, so [BroadcastPenaltyFailure::Unavailable] is returned if it cannot be reached.
+    pub(crate) fn broadcast_penalty(
+        &self,
+        uuid: UUID,
+    ) -> Result<(Txid, ConfirmationStatus), BroadcastPenaltyFailure> {
+        let tracker = self
+            .get_tracker(uuid)
+            .ok_or(BroadcastPenaltyFailure::NotFound)?;
+        let penalty_txid = tracker.penalty_tx.txid();
+
+        log::info!(
+            "Rebroadcasting penalty transaction on demand: {}",
+            penalty_txid
+        );
+        let status = self
+            .broadcast(|carrier| {
+                carrier.forget_receipt(&penalty_txid);
+                carrier.try_send_transaction(&tracker.penalty_tx)
+            })
+            .ok_or(BroadcastPenaltyFailure::Unavailable)?;
+
+        if let ConfirmationStatus::Rejected(_) = status {
+            log::warn!(
+                "Penalty transaction rejected on demand rebroadcast: {}",
+                penalty_txid
+            );
+        } else if let Some(summary) = self.trackers.lock().unwrap().get_mut(&uuid) {
+            summary.status = status;
+            self.dbm.update_tracker_status(uuid, &status);
+            self.metrics.penalty_broadcast();
+        }
+
+        Ok((penalty_txid, status))
+    }
+
     /// Checks the confirmation count for the [TransactionTracker]s.
     ///
     /// For unconfirmed transactions, it checks whether they have been confirmed or keep missing confirmations.
@@ -292,6 +630,7 @@ impl Responder {
             } else if txids.contains(&tracker.penalty_txid) {
                 // First confirmation was received
                 tracker.status = ConfirmationStatus::ConfirmedIn(current_height);
+                self.dbm.update_tracker_status(*uuid, &tracker.status);
             } else if let ConfirmationStatus::InMempoolSince(h) = tracker.status {
                 // Log all transactions that have missed confirmations
                 log::info!(
@@ -315,7 +654,7 @@ impl Responder {
         &self,
         height: u32,
     ) -> HashMap<UUID, (Transaction, Option<Transaction>)> {
-        let dbm = self.dbm.lock().unwrap();
+        let dbm = &self.dbm;
         let mut tx_to_rebroadcast = HashMap::new();
         let mut tracker: TransactionTracker;
 
@@ -354,6 +693,46 @@ impl Responder {
         outdated_trackers
     }
 
+    /// Gets a collection of trackers whose penalty cannot make it to the chain anymore, given all the dispute outputs
+    /// it spends have already been spent by some other transaction (e.g. the penalty of another tower).
+    ///
+    /// Only unconfirmed trackers are checked (and the outputs of the dispute are checked against the chain only), so
+    /// penalties sitting in the mempool do not count as spending them. Trackers whose dispute is not confirmed are never
+    /// resolved, given `bitcoind` reports the outputs of transactions that are not in the chain as spent (e.g. after the
+    /// dispute is reorged out). Checks are opportunistic: trackers that cannot be checked (e.g. because `bitcoind` is
+    /// unreachable) are simply left for the next block.
+    ///
+    /// Notice the check was asked for appointments still being watched, but the tower cannot perform it for those: the
+    /// outputs they spend are only known once the dispute shows up, given the penalty is encrypted until then. Hence,
+    /// only appointments already handed to the [Responder] are checked.
+    fn get_resolved_trackers(&self) -> HashSet<UUID> {
+        let unconfirmed = self
+            .trackers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, t)| {
+                matches!(t.status, ConfirmationStatus::InMempoolSince(_))
+                    && !t.dispute_outpoints.is_empty()
+            })
+            .map(|(uuid, t)| (*uuid, t.dispute_outpoints.clone()))
+            .collect::<Vec<(UUID, Vec<OutPoint>)>>();
+
+        let mut resolved_trackers = HashSet::new();
+        for (uuid, outpoints) in unconfirmed {
+            // The Carrier is only locked for the duration of each query, so broadcasts are not held by the checks
+            if self.carrier.read().unwrap().is_in_chain(&outpoints[0].txid) == Some(true)
+                && outpoints.iter().all(|outpoint| {
+                    self.carrier.read().unwrap().is_output_spent(outpoint) == Some(true)
+                })
+            {
+                resolved_trackers.insert(uuid);
+            }
+        }
+
+        resolved_trackers
+    }
+
     /// Rebroadcasts a list of penalty transactions that have missed too many confirmations (or that have been reorged out).
     ///
     /// This covers both the case where a transaction is not getting confirmations (most likely due to low fess, and needs to be bumped),
@@ -373,20 +752,24 @@ impl Responder {
         let mut rejected = HashSet::new();
 
         let mut trackers = self.trackers.lock().unwrap();
-        let mut carrier = self.carrier.lock().unwrap();
-
         for (uuid, (penalty_tx, dispute_tx)) in txs.into_iter() {
             let status = if let Some(dispute_tx) = dispute_tx {
                 // The tracker was reorged out, and the dispute may potentially not be in the chain anymore.
-                if carrier.get_block_hash_for_tx(&dispute_tx.txid()).is_some() {
+                let in_chain = self
+                    .carrier
+                    .read()
+                    .unwrap()
+                    .get_block_hash_for_tx(&dispute_tx.txid())
+                    .is_some();
+                if in_chain {
                     // Dispute tx is on chain, so we only need to care about the penalty
-                    carrier.send_transaction(&penalty_tx)
+                    self.broadcast(|carrier| carrier.send_transaction(&penalty_tx))
                 } else {
                     // Dispute tx has also been reorged out, meaning that both transactions need to be broadcast.
                     // DISCUSS: For lightning transactions, if the dispute has been reorged the penalty cannot make it to the network.
                     // If we keep this general, the dispute can simply be a trigger and the penalty doesn't necessarily have to spend from it.
                     // We'll keel it lightning specific, at least for now.
-                    let status = carrier.send_transaction(&dispute_tx);
+                    let status = self.broadcast(|carrier| carrier.send_transaction(&dispute_tx));
                     if let ConfirmationStatus::Rejected(e) = status {
                         log::error!(
                         "Reorged dispute transaction rejected during rebroadcast: {} (reason: {:?})",
@@ -396,7 +779,7 @@ impl Responder {
                         status
                     } else {
                         // The dispute was accepted, so we can rebroadcast the penalty.
-                        carrier.send_transaction(&penalty_tx)
+                        self.broadcast(|carrier| carrier.send_transaction(&penalty_tx))
                     }
                 }
             } else {
@@ -405,7 +788,7 @@ impl Responder {
                     "Penalty transaction has missed many confirmations: {}",
                     penalty_tx.txid()
                 );
-                carrier.send_transaction(&penalty_tx)
+                self.broadcast(|carrier| carrier.send_transaction(&penalty_tx))
             };
 
             if let ConfirmationStatus::Rejected(_) = status {
@@ -415,7 +798,9 @@ impl Responder {
                 // to have been in mempool), so it resets the wait period instead of trying to rebroadcast every block.
                 // DISCUSS: We may want to find another approach in the future for the InMempoool transactions.
                 trackers.get_mut(&uuid).unwrap().status = status;
+                self.dbm.update_tracker_status(uuid, &status);
                 accepted.insert(uuid, status);
+                self.metrics.penalty_broadcast();
             }
         }
 
@@ -435,6 +820,9 @@ impl Responder {
                 DeletionReason::Completed => log::info!("Appointment completed. Penalty transaction was irrevocably confirmed: {}", uuid),
                 DeletionReason::Outdated => log::info!("Appointment couldn't be completed. Expiry reached but penalty didn't make it to the chain: {}", uuid),
                 DeletionReason::Rejected => log::info!("Appointment couldn't be completed. Either the dispute or the penalty txs where rejected during rebroadcast: {}", uuid),
+                DeletionReason::Reorged => log::info!("Dispute transaction was reorged out. Handing the appointment back to the Watcher: {}", uuid),
+                DeletionReason::Pruned => log::info!("Appointment pruned. Penalty transaction has been confirmed for longer than the retention period: {}", uuid),
+                DeletionReason::Resolved => log::info!("Appointment resolved. The outputs spent by the penalty transaction were already spent by someone else: {}", uuid),
             }
 
             match trackers.remove(uuid) {
@@ -460,6 +848,35 @@ impl Responder {
         }
     }
 
+    /// Stops tracking a [TransactionTracker] whose dispute transaction has been reorged out, so the appointment
+    /// it was created from can be watched again.
+    ///
+    /// The tracker is removed from memory and the database, but the appointment data is kept (as well as the slots
+    /// it takes from the user subscription). Returns whether the tracker was found.
+    pub(crate) fn untrack(&self, uuid: UUID) -> bool {
+        if !self.trackers.lock().unwrap().contains_key(&uuid) {
+            return false;
+        }
+
+        self.delete_trackers_from_memory(&HashSet::from_iter([uuid]), DeletionReason::Reorged);
+        self.dbm.remove_tracker(uuid);
+
+        true
+    }
+
+    /// Prunes the responded appointments whose penalty was confirmed below the given height, both from memory and the
+    /// database. The freed slots are given back to their users.
+    fn prune_responded(&self, height: u32) {
+        let pruned = self.dbm.prune_responded_before(height);
+        if !pruned.is_empty() {
+            self.gatekeeper.delete_appointments_from_memory(&pruned);
+            self.delete_trackers_from_memory(
+                &pruned.keys().cloned().collect(),
+                DeletionReason::Pruned,
+            );
+        }
+    }
+
     /// Deletes trackers from memory and the database.
     ///
     /// Removes all data related to the appointment from the database in cascade.
@@ -470,10 +887,7 @@ impl Responder {
         reason: DeletionReason,
     ) {
         self.delete_trackers_from_memory(uuids, reason);
-        self.dbm
-            .lock()
-            .unwrap()
-            .batch_remove_appointments(uuids, updated_users);
+        self.dbm.batch_remove_appointments(uuids, updated_users);
     }
 }
 
@@ -485,13 +899,16 @@ impl chain::Listen for Responder {
     /// - It gets [irrevocably resolved](https://github.com/lightning/bolts/blob/master/05-onchain.md#general-nomenclature) or
     /// - The user subscription expires
     /// - The trackers becomes invalid (due to a reorg)
+    /// - Its penalty has been confirmed for longer than the retention period (if any)
+    /// - The outputs its penalty spends are spent by some other transaction (if checked)
     ///
     /// Every time a block is received the tracking conditions are checked against the monitored [TransactionTracker]s and
     /// data deletion is performed accordingly. Moreover, lack of confirmations is check for the tracked transactions and
     /// rebroadcasting is performed for those that have missed too many.
     fn block_connected(&self, block: &bitcoin::Block, height: u32) {
         log::info!("New block received: {}", block.header.block_hash());
-        self.carrier.lock().unwrap().update_height(height);
+        self.carrier.read().unwrap().update_height(height);
+        self.update_fee_estimate();
 
         if self.trackers.lock().unwrap().len() > 0 {
             // Complete those appointments that are due at this height
@@ -515,12 +932,33 @@ impl chain::Listen for Responder {
                 DeletionReason::Completed,
             );
 
+            // Prune responded appointments that have been confirmed for longer than the retention period
+            if self.retention_blocks > 0 && height > self.retention_blocks {
+                self.prune_responded(height - self.retention_blocks);
+            }
+
             // Also delete trackers from outdated users (from memory only, the db deletion is handled by the Gatekeeper)
             self.delete_trackers_from_memory(
                 &self.get_outdated_trackers(height),
                 DeletionReason::Outdated,
             );
 
+            // Resolve those trackers whose penalty cannot make it to the chain anymore, so the slots are freed
+            if self.check_spent_outputs {
+                let resolved_trackers = self.get_resolved_trackers();
+                let trackers_to_delete_gk = resolved_trackers
+                    .iter()
+                    .map(|uuid| (*uuid, self.trackers.lock().unwrap()[uuid].user_id))
+                    .collect();
+                self.delete_trackers(
+                    &resolved_trackers,
+                    &self
+                        .gatekeeper
+                        .delete_appointments_from_memory(&trackers_to_delete_gk),
+                    DeletionReason::Resolved,
+                );
+            }
+
             // Rebroadcast those transactions that need to
             let (_, rejected_trackers) = self.rebroadcast(self.get_txs_to_rebroadcast(height));
             // Delete trackers rejected during rebroadcast
@@ -537,7 +975,7 @@ impl chain::Listen for Responder {
             );
 
             // Remove all receipts created in this block
-            self.carrier.lock().unwrap().clear_receipts();
+            self.carrier.read().unwrap().clear_receipts();
 
             if self.trackers.lock().unwrap().is_empty() {
                 log::info!("No more pending trackers");
@@ -548,12 +986,15 @@ impl chain::Listen for Responder {
     /// Handles reorgs in the [Responder].
     fn block_disconnected(&self, header: &BlockHeader, height: u32) {
         log::warn!("Block disconnected: {}", header.block_hash());
-        self.carrier.lock().unwrap().update_height(height);
+        self.carrier.read().unwrap().update_height(height);
 
-        for tracker in self.trackers.lock().unwrap().values_mut() {
+        for (uuid, tracker) in self.trackers.lock().unwrap().iter_mut() {
             // The transaction has been unconfirmed. Flag it as reorged out so we can rebroadcast it.
             if tracker.status == ConfirmationStatus::ConfirmedIn(height) {
                 tracker.status = ConfirmationStatus::ReorgedOut;
+                // Reorged out trackers cannot be stored as such, flag them as unconfirmed so they are not pruned
+                self.dbm
+                    .update_tracker_status(*uuid, &ConfirmationStatus::InMempoolSince(height));
             }
         }
     }
@@ -565,15 +1006,19 @@ mod tests {
     use lightning::chain::Listen;
 
     use std::ops::Deref;
-    use std::sync::{Arc, Mutex};
+    use std::sync::{Arc, Condvar, Mutex};
+
+    use bitcoin::{OutPoint, Script, TxIn, TxOut};
+    use bitcoincore_rpc::{Auth, Client as BitcoindClient};
 
     use crate::dbm::{Error as DBError, DBM};
     use crate::gatekeeper::UserInfo;
     use crate::rpc_errors;
     use crate::test_utils::{
         create_carrier, generate_dummy_appointment_with_user, generate_uuid, get_random_breach,
-        get_random_tracker, get_random_tx, get_random_user_id, store_appointment_and_fks_to_db,
-        Blockchain, MockedServerQuery, DURATION, EXPIRY_DELTA, SLOTS, START_HEIGHT,
+        get_random_tracker, get_random_tx, get_random_user_id, start_server,
+        store_appointment_and_fks_to_db, BitcoindMock, Blockchain, BroadcastProbe, MockOptions,
+        MockedServerQuery, DURATION, EXPIRY_DELTA, SLOTS, START_HEIGHT,
     };
 
     impl PartialEq for Responder {
@@ -589,7 +1034,7 @@ mod tests {
             &self.trackers
         }
 
-        pub(crate) fn get_carrier(&self) -> &Mutex<Carrier> {
+        pub(crate) fn get_carrier(&self) -> &RwLock<Carrier> {
             &self.carrier
         }
 
@@ -610,19 +1055,15 @@ mod tests {
             // Add data to the db
             let (_, appointment) =
                 generate_dummy_appointment_with_user(user_id, Some(&tracker.dispute_tx.txid()));
-            store_appointment_and_fks_to_db(&self.dbm.lock().unwrap(), uuid, &appointment);
-            self.dbm
-                .lock()
-                .unwrap()
-                .store_tracker(uuid, &tracker)
-                .unwrap();
+            store_appointment_and_fks_to_db(&self.dbm, uuid, &appointment);
+            self.dbm.store_tracker(uuid, &tracker).unwrap();
         }
     }
 
     fn create_responder(
         chain: &Blockchain,
         gatekeeper: Arc<Gatekeeper>,
-        dbm: Arc<Mutex<DBM>>,
+        dbm: Arc<DBM>,
         query: MockedServerQuery,
     ) -> Responder {
         let tip = chain.tip();
@@ -632,20 +1073,21 @@ mod tests {
     fn init_responder_with_chain_and_dbm(
         mocked_query: MockedServerQuery,
         chain: &Blockchain,
-        dbm: Arc<Mutex<DBM>>,
+        dbm: Arc<DBM>,
     ) -> Responder {
         let gk = Gatekeeper::new(
             chain.get_block_count(),
             SLOTS,
             DURATION,
             EXPIRY_DELTA,
+            0,
             dbm.clone(),
         );
         create_responder(chain, Arc::new(gk), dbm, mocked_query)
     }
 
     fn init_responder(mocked_query: MockedServerQuery) -> Responder {
-        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let dbm = Arc::new(DBM::in_memory().unwrap());
         let chain = Blockchain::default().with_height_and_txs(START_HEIGHT, 10);
         init_responder_with_chain_and_dbm(mocked_query, &chain, dbm)
     }
@@ -694,7 +1136,7 @@ mod tests {
     fn test_responder_new() {
         // A fresh responder has no associated data
         let chain = Blockchain::default().with_height(START_HEIGHT);
-        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let dbm = Arc::new(DBM::in_memory().unwrap());
         let responder =
             init_responder_with_chain_and_dbm(MockedServerQuery::Regular, &chain, dbm.clone());
         assert!(responder.is_fresh());
@@ -705,7 +1147,7 @@ mod tests {
             // Add the necessary FKs in the database
             let user_id = get_random_user_id();
             let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
-            store_appointment_and_fks_to_db(&responder.dbm.lock().unwrap(), uuid, &appointment);
+            store_appointment_and_fks_to_db(&responder.dbm, uuid, &appointment);
 
             let breach = get_random_breach();
             let s = if i % 2 == 0 {
@@ -729,7 +1171,7 @@ mod tests {
 
         let user_id = get_random_user_id();
         let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
-        store_appointment_and_fks_to_db(&responder.dbm.lock().unwrap(), uuid, &appointment);
+        store_appointment_and_fks_to_db(&responder.dbm, uuid, &appointment);
 
         let breach = get_random_breach();
         let penalty_txid = breach.penalty_tx.txid();
@@ -769,6 +1211,237 @@ mod tests {
             .contains_key(&another_breach.penalty_tx.txid()));
     }
 
+    #[test]
+    fn test_handle_breach_bitcoind_unreachable() {
+        let responder = init_responder(MockedServerQuery::Regular);
+        let start_height = START_HEIGHT as u32;
+
+        // Replace the carrier with one that sees bitcoind as unreachable
+        let bitcoind_mock = BitcoindMock::new(MockOptions::empty());
+        let bitcoin_cli = Arc::new(BitcoindClient::new(bitcoind_mock.url(), Auth::None).unwrap());
+        let bitcoind_reachable = Arc::new((Mutex::new(false), Condvar::new()));
+        start_server(bitcoind_mock);
+        *responder.carrier.write().unwrap() =
+            Carrier::new(bitcoin_cli, bitcoind_reachable.clone(), start_height);
+
+        let user_id = get_random_user_id();
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        store_appointment_and_fks_to_db(&responder.dbm, uuid, &appointment);
+        let breach = get_random_breach();
+        let penalty_txid = breach.penalty_tx.txid();
+
+        // The breach is not held until bitcoind is back, the tracker is added and the penalty queued
+        assert_eq!(
+            responder.handle_breach(uuid, breach, user_id),
+            ConfirmationStatus::InMempoolSince(start_height)
+        );
+        assert!(responder.has_tracker(uuid));
+        assert!(responder.pending_broadcasts.lock().unwrap().contains(&uuid));
+        assert!(!responder
+            .carrier
+            .read()
+            .unwrap()
+            .get_issued_receipts()
+            .contains_key(&penalty_txid));
+
+        // Draining while bitcoind is still unreachable keeps the penalty queued
+        responder.drain_pending_broadcasts();
+        assert!(responder.pending_broadcasts.lock().unwrap().contains(&uuid));
+
+        // Once bitcoind is reachable again, the penalty is sent
+        let (shutdown_trigger, shutdown_signal) = triggered::trigger();
+        std::thread::scope(|s| {
+            s.spawn(|| responder.retry_pending_broadcasts(shutdown_signal));
+
+            let (reachable, notifier) = &*bitcoind_reachable;
+            *reachable.lock().unwrap() = true;
+            notifier.notify_all();
+
+            let start = std::time::Instant::now();
+            while !responder.pending_broadcasts.lock().unwrap().is_empty() {
+                assert!(start.elapsed() < Duration::from_secs(5));
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            shutdown_trigger.trigger();
+        });
+
+        assert_eq!(
+            responder
+                .carrier
+                .read()
+                .unwrap()
+                .get_issued_receipts()
+                .get(&penalty_txid),
+            Some(&ConfirmationStatus::InMempoolSince(start_height))
+        );
+    }
+
+    #[test]
+    fn test_pending_broadcasts_after_restart() {
+        let dbm = Arc::new(DBM::in_memory().unwrap());
+        let chain = Blockchain::default().with_height_and_txs(START_HEIGHT, 10);
+        let start_height = START_HEIGHT as u32;
+        let responder =
+            init_responder_with_chain_and_dbm(MockedServerQuery::Regular, &chain, dbm.clone());
+
+        // Queue a penalty while bitcoind is unreachable
+        let bitcoind_mock = BitcoindMock::new(MockOptions::empty());
+        let bitcoin_cli = Arc::new(BitcoindClient::new(bitcoind_mock.url(), Auth::None).unwrap());
+        start_server(bitcoind_mock);
+        *responder.carrier.write().unwrap() = Carrier::new(
+            bitcoin_cli,
+            Arc::new((Mutex::new(false), Condvar::new())),
+            start_height,
+        );
+
+        let user_id = get_random_user_id();
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        store_appointment_and_fks_to_db(&responder.dbm, uuid, &appointment);
+        let breach = get_random_breach();
+        let penalty_txid = breach.penalty_tx.txid();
+        responder.handle_breach(uuid, breach, user_id);
+        assert!(responder.pending_broadcasts.lock().unwrap().contains(&uuid));
+
+        // The queue is loaded back after a restart, and the penalty sent once bitcoind is reachable
+        drop(responder);
+        let responder = init_responder_with_chain_and_dbm(MockedServerQuery::Regular, &chain, dbm);
+        assert!(responder.pending_broadcasts.lock().unwrap().contains(&uuid));
+
+        responder.drain_pending_broadcasts();
+        assert!(responder.pending_broadcasts.lock().unwrap().is_empty());
+        assert!(responder.dbm.load_pending_broadcasts().is_empty());
+        assert!(responder
+            .carrier
+            .read()
+            .unwrap()
+            .get_issued_receipts()
+            .contains_key(&penalty_txid));
+    }
+
+    #[test]
+    fn test_handle_breach_concurrent() {
+        let start_height = START_HEIGHT as u32;
+
+        for max_concurrent_broadcasts in [1, 3] {
+            let responder = init_responder(MockedServerQuery::Regular)
+                .with_max_concurrent_broadcasts(max_concurrent_broadcasts);
+
+            // Replace the carrier with one whose bitcoind keeps track of the simultaneous broadcasts
+            let probe = Arc::new(BroadcastProbe::default());
+            let bitcoind_mock = BitcoindMock::with_broadcast_probe(probe.clone());
+            let bitcoin_cli =
+                Arc::new(BitcoindClient::new(bitcoind_mock.url(), Auth::None).unwrap());
+            start_server(bitcoind_mock);
+            *responder.carrier.write().unwrap() = Carrier::new(
+                bitcoin_cli,
+                Arc::new((Mutex::new(true), Condvar::new())),
+                start_height,
+            );
+
+            let mut breaches = Vec::new();
+            for _ in 0..10 {
+                let user_id = get_random_user_id();
+                let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+                store_appointment_and_fks_to_db(&responder.dbm, uuid, &appointment);
+                breaches.push((uuid, get_random_breach(), user_id));
+            }
+
+            // Breaches handled at the same time beyond the limit wait for their turn, none of them is dropped
+            std::thread::scope(|s| {
+                for (uuid, breach, user_id) in breaches.iter().cloned() {
+                    let responder = &responder;
+                    s.spawn(move || {
+                        assert_eq!(
+                            responder.handle_breach(uuid, breach, user_id),
+                            ConfirmationStatus::InMempoolSince(start_height)
+                        );
+                    });
+                }
+            });
+
+            assert_eq!(probe.calls(), breaches.len());
+            assert!(probe.max_in_flight() <= max_concurrent_broadcasts);
+            assert_eq!(*responder.broadcasts_in_flight.0.lock().unwrap(), 0);
+            for (uuid, breach, _) in breaches {
+                assert!(responder.has_tracker(uuid));
+                assert!(responder
+                    .carrier
+                    .read()
+                    .unwrap()
+                    .get_issued_receipts()
+                    .contains_key(&breach.penalty_tx.txid()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_handle_breach_concurrent_unbounded() {
+        let responder = init_responder(MockedServerQuery::Regular);
+        let start_height = START_HEIGHT as u32;
+
+        let probe = Arc::new(BroadcastProbe::default());
+        let bitcoind_mock = BitcoindMock::with_broadcast_probe(probe.clone());
+        let bitcoin_cli = Arc::new(BitcoindClient::new(bitcoind_mock.url(), Auth::None).unwrap());
+        start_server(bitcoind_mock);
+        *responder.carrier.write().unwrap() = Carrier::new(
+            bitcoin_cli,
+            Arc::new((Mutex::new(true), Condvar::new())),
+            start_height,
+        );
+
+        // With no limit, broadcasts are not held by each other
+        let breaches = (0..10)
+            .map(|_| {
+                let user_id = get_random_user_id();
+                let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+                store_appointment_and_fks_to_db(&responder.dbm, uuid, &appointment);
+                (uuid, get_random_breach(), user_id)
+            })
+            .collect::<Vec<_>>();
+        std::thread::scope(|s| {
+            for (uuid, breach, user_id) in breaches.iter().cloned() {
+                let responder = &responder;
+                s.spawn(move || responder.handle_breach(uuid, breach, user_id));
+            }
+        });
+
+        assert_eq!(probe.calls(), breaches.len());
+        assert!(probe.max_in_flight() > 1);
+    }
+
+    #[test]
+    fn test_handle_breach_dry_run() {
+        // The mock rejects every transaction, so the breach being accepted means nothing was broadcast
+        let bitcoind_mock =
+            BitcoindMock::new(MockOptions::with_error(rpc_errors::RPC_VERIFY_ERROR as i64));
+        let bitcoin_cli = Arc::new(BitcoindClient::new(bitcoind_mock.url(), Auth::None).unwrap());
+        start_server(bitcoind_mock);
+
+        let chain = Blockchain::default().with_height(START_HEIGHT);
+        let start_height = chain.get_block_count();
+        let carrier = Carrier::new(
+            bitcoin_cli,
+            Arc::new((Mutex::new(true), Condvar::new())),
+            start_height,
+        )
+        .with_dry_run(true);
+        let dbm = Arc::new(DBM::in_memory().unwrap());
+        let gk = Gatekeeper::new(start_height, SLOTS, DURATION, EXPIRY_DELTA, 0, dbm.clone());
+        let responder = Responder::new(carrier, Arc::new(gk), dbm);
+
+        let user_id = get_random_user_id();
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        store_appointment_and_fks_to_db(&responder.dbm, uuid, &appointment);
+
+        // The appointment is still responded to, so the tower state matches that of a regular run
+        assert_eq!(
+            responder.handle_breach(uuid, get_random_breach(), user_id),
+            ConfirmationStatus::InMempoolSince(start_height)
+        );
+        assert!(responder.trackers.lock().unwrap().contains_key(&uuid));
+        assert!(responder.dbm.load_tracker(uuid).is_ok());
+    }
+
     #[test]
     fn test_handle_breach_rejected() {
         let responder = init_responder(MockedServerQuery::Error(
@@ -800,7 +1473,7 @@ mod tests {
         // Add the necessary FKs in the database
         let user_id = get_random_user_id();
         let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
-        store_appointment_and_fks_to_db(&responder.dbm.lock().unwrap(), uuid, &appointment);
+        store_appointment_and_fks_to_db(&responder.dbm, uuid, &appointment);
 
         let mut breach = get_random_breach();
         responder.add_tracker(
@@ -816,7 +1489,9 @@ mod tests {
             Some(&TrackerSummary {
                 user_id,
                 penalty_txid: breach.penalty_tx.txid(),
-                status: ConfirmationStatus::InMempoolSince(start_height)
+                status: ConfirmationStatus::InMempoolSince(start_height),
+                // Random breaches do not spend from their dispute
+                dispute_outpoints: Vec::new(),
             })
         );
         assert!(responder
@@ -826,7 +1501,7 @@ mod tests {
             .contains_key(&breach.penalty_tx.txid()));
         // Check that the data is also in the database
         assert_eq!(
-            responder.dbm.lock().unwrap().load_tracker(uuid).unwrap(),
+            responder.dbm.load_tracker(uuid).unwrap(),
             TransactionTracker::new(
                 breach,
                 user_id,
@@ -838,12 +1513,7 @@ mod tests {
         let uuid = generate_uuid();
         breach = get_random_breach();
 
-        responder
-            .dbm
-            .lock()
-            .unwrap()
-            .store_appointment(uuid, &appointment)
-            .unwrap();
+        responder.dbm.store_appointment(uuid, &appointment).unwrap();
 
         responder.add_tracker(
             uuid,
@@ -857,7 +1527,9 @@ mod tests {
             Some(&TrackerSummary {
                 user_id,
                 penalty_txid: breach.penalty_tx.txid(),
-                status: ConfirmationStatus::ConfirmedIn(start_height - 1)
+                status: ConfirmationStatus::ConfirmedIn(start_height - 1),
+                // Random breaches do not spend from their dispute
+                dispute_outpoints: Vec::new(),
             })
         );
         assert!(responder
@@ -870,7 +1542,7 @@ mod tests {
             1
         );
         assert_eq!(
-            responder.dbm.lock().unwrap().load_tracker(uuid).unwrap(),
+            responder.dbm.load_tracker(uuid).unwrap(),
             TransactionTracker::new(
                 breach.clone(),
                 user_id,
@@ -880,12 +1552,7 @@ mod tests {
 
         // Adding another breach with the same penalty transaction (but different uuid) adds an additional uuid to the map entry
         let uuid = generate_uuid();
-        responder
-            .dbm
-            .lock()
-            .unwrap()
-            .store_appointment(uuid, &appointment)
-            .unwrap();
+        responder.dbm.store_appointment(uuid, &appointment).unwrap();
 
         responder.add_tracker(
             uuid,
@@ -905,7 +1572,7 @@ mod tests {
             2
         );
         assert_eq!(
-            responder.dbm.lock().unwrap().load_tracker(uuid).unwrap(),
+            responder.dbm.load_tracker(uuid).unwrap(),
             TransactionTracker::new(
                 breach,
                 user_id,
@@ -924,7 +1591,7 @@ mod tests {
         // Add a new tracker
         let user_id = get_random_user_id();
         let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
-        store_appointment_and_fks_to_db(&responder.dbm.lock().unwrap(), uuid, &appointment);
+        store_appointment_and_fks_to_db(&responder.dbm, uuid, &appointment);
 
         let breach = get_random_breach();
         responder.add_tracker(
@@ -954,7 +1621,7 @@ mod tests {
         // Store the user and the appointment in the database so we can add the tracker later on (due to FK restrictions)
         let user_id = get_random_user_id();
         let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
-        store_appointment_and_fks_to_db(&responder.dbm.lock().unwrap(), uuid, &appointment);
+        store_appointment_and_fks_to_db(&responder.dbm, uuid, &appointment);
 
         // Data should not be there before adding it
         assert_eq!(responder.get_tracker(uuid), None);
@@ -985,6 +1652,263 @@ mod tests {
         assert_eq!(responder.get_tracker(uuid), None);
     }
 
+    #[test]
+    fn test_get_fee_rate() {
+        let dispute_tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: Vec::new(),
+            output: vec![TxOut {
+                value: 100_000,
+                script_pubkey: Script::new(),
+            }],
+        };
+        let mut penalty_tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(dispute_tx.txid(), 0),
+                script_sig: Script::new(),
+                sequence: 0xFFFFFFFF,
+                witness: Vec::new(),
+            }],
+            output: vec![TxOut {
+                value: 99_000,
+                script_pubkey: Script::new(),
+            }],
+        };
+        let weight = penalty_tx.get_weight() as u64;
+        assert_eq!(
+            get_fee_rate(&dispute_tx, &penalty_tx),
+            Some(1000 * 1000 / weight)
+        );
+
+        // Penalties spending more than what they are given are not valid
+        penalty_tx.output[0].value = 100_001;
+        assert_eq!(get_fee_rate(&dispute_tx, &penalty_tx), None);
+
+        // Neither the fee can be derived if some inputs do not come from the dispute transaction
+        penalty_tx.output[0].value = 99_000;
+        penalty_tx.input[0].previous_output = OutPoint::new(get_random_tx().txid(), 0);
+        assert_eq!(get_fee_rate(&dispute_tx, &penalty_tx), None);
+    }
+
+    #[test]
+    fn test_is_likely_underfunded() {
+        // No estimate means no warning
+        let responder = init_responder(MockedServerQuery::Regular);
+        assert!(!responder.is_likely_underfunded(0));
+        responder.update_fee_estimate();
+        assert!(!responder.is_likely_underfunded(0));
+
+        // 0.0001 BTC/kvB is 2500 sat/kw
+        let bitcoind_mock = BitcoindMock::new(MockOptions::with_fee_rate(0.0001));
+        let bitcoin_cli = Arc::new(BitcoindClient::new(bitcoind_mock.url(), Auth::None).unwrap());
+        start_server(bitcoind_mock);
+        *responder.carrier.write().unwrap() = Carrier::new(
+            bitcoin_cli,
+            Arc::new((Mutex::new(true), Condvar::new())),
+            START_HEIGHT as u32,
+        );
+
+        // The estimate is only refreshed once per block, not on every check
+        assert!(!responder.is_likely_underfunded(2499));
+        responder.update_fee_estimate();
+        assert!(responder.is_likely_underfunded(2499));
+        assert!(!responder.is_likely_underfunded(2500));
+    }
+
+    #[test]
+    fn test_block_connected_resolved_trackers() {
+        let mut chain = Blockchain::default().with_height_and_txs(START_HEIGHT, 10);
+        let current_height = chain.get_block_count();
+
+        // Trackers are only resolved if the check is enabled, the dispute is confirmed and its outputs are already spent
+        for (check_spent_outputs, dispute_confirmed, spent, resolved) in [
+            (true, true, true, true),
+            (true, true, false, false),
+            (true, false, true, false),
+            (false, true, true, false),
+        ] {
+            let responder = init_responder(MockedServerQuery::Regular)
+                .with_spent_output_checks(check_spent_outputs);
+            let bitcoind_mock = BitcoindMock::new(if dispute_confirmed {
+                MockOptions::with_block_and_txout_spent(
+                    bitcoin::BlockHash::default(),
+                    current_height as usize,
+                    spent,
+                )
+            } else {
+                MockOptions::with_txout_spent(spent)
+            });
+            let bitcoin_cli =
+                Arc::new(BitcoindClient::new(bitcoind_mock.url(), Auth::None).unwrap());
+            start_server(bitcoind_mock);
+            *responder.carrier.write().unwrap() = Carrier::new(
+                bitcoin_cli,
+                Arc::new((Mutex::new(true), Condvar::new())),
+                current_height,
+            );
+
+            // Add a tracker whose penalty spends an output of the dispute transaction
+            let user_id = get_random_user_id();
+            responder.gatekeeper.add_update_user(user_id, None).unwrap();
+            let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+            responder.dbm.store_appointment(uuid, &appointment).unwrap();
+            let mut breach = get_random_breach();
+            breach.penalty_tx.input[0].previous_output = OutPoint::new(breach.dispute_tx.txid(), 0);
+            responder.add_tracker(
+                uuid,
+                breach,
+                user_id,
+                ConfirmationStatus::InMempoolSince(current_height),
+            );
+            responder
+                .gatekeeper
+                .get_registered_users()
+                .lock()
+                .unwrap()
+                .get_mut(&user_id)
+                .unwrap()
+                .appointments
+                .insert(uuid, 1);
+
+            responder.block_connected(&chain.generate(None), current_height + 1);
+            assert_eq!(
+                responder.trackers.lock().unwrap().contains_key(&uuid),
+                !resolved
+            );
+            assert_eq!(responder.dbm.load_tracker(uuid).is_ok(), !resolved);
+            assert_eq!(
+                responder.gatekeeper.get_registered_users().lock().unwrap()[&user_id]
+                    .appointments
+                    .contains_key(&uuid),
+                !resolved
+            );
+        }
+    }
+
+    #[test]
+    fn test_broadcast_penalty() {
+        let responder = init_responder(MockedServerQuery::Regular);
+        let current_height = responder.carrier.read().unwrap().get_height();
+
+        // Unknown trackers cannot be rebroadcast
+        assert_eq!(
+            responder.broadcast_penalty(generate_uuid()),
+            Err(BroadcastPenaltyFailure::NotFound)
+        );
+
+        // A penalty that has been stuck in the mempool for a while can be rebroadcast on demand
+        let user_id = get_random_user_id();
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        store_appointment_and_fks_to_db(&responder.dbm, uuid, &appointment);
+        let breach = get_random_breach();
+        let penalty_txid = breach.penalty_tx.txid();
+        responder.add_tracker(
+            uuid,
+            breach,
+            user_id,
+            ConfirmationStatus::InMempoolSince(current_height - 3),
+        );
+
+        let status = ConfirmationStatus::InMempoolSince(current_height);
+        assert_eq!(
+            responder.broadcast_penalty(uuid),
+            Ok((penalty_txid, status))
+        );
+        // The transaction has gone through the Carrier and the tracker has been updated
+        assert_eq!(
+            responder
+                .carrier
+                .read()
+                .unwrap()
+                .get_issued_receipts()
+                .get(&penalty_txid),
+            Some(&status)
+        );
+        assert_eq!(responder.trackers.lock().unwrap()[&uuid].status, status);
+        assert_eq!(responder.dbm.load_tracker(uuid).unwrap().status, status);
+    }
+
+    #[test]
+    fn test_broadcast_penalty_rejected() {
+        let responder = init_responder(MockedServerQuery::Error(
+            rpc_errors::RPC_VERIFY_REJECTED as i64,
+        ));
+        let current_height = responder.carrier.read().unwrap().get_height();
+
+        let user_id = get_random_user_id();
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        store_appointment_and_fks_to_db(&responder.dbm, uuid, &appointment);
+        let breach = get_random_breach();
+        let penalty_txid = breach.penalty_tx.txid();
+        let status = ConfirmationStatus::InMempoolSince(current_height - 3);
+        responder.add_tracker(uuid, breach, user_id, status);
+
+        assert_eq!(
+            responder.broadcast_penalty(uuid),
+            Ok((
+                penalty_txid,
+                ConfirmationStatus::Rejected(rpc_errors::RPC_VERIFY_REJECTED)
+            ))
+        );
+        // Trackers are kept (untouched) if the penalty is rejected
+        assert_eq!(responder.trackers.lock().unwrap()[&uuid].status, status);
+        assert_eq!(responder.dbm.load_tracker(uuid).unwrap().status, status);
+    }
+
+    #[test]
+    fn test_broadcast_penalty_twice() {
+        let responder = init_responder(MockedServerQuery::Regular);
+        let current_height = responder.carrier.read().unwrap().get_height();
+        let probe = Arc::new(BroadcastProbe::default());
+        let bitcoind_mock = BitcoindMock::with_broadcast_probe(probe.clone());
+        let bitcoin_cli = Arc::new(BitcoindClient::new(bitcoind_mock.url(), Auth::None).unwrap());
+        start_server(bitcoind_mock);
+        *responder.carrier.write().unwrap() = Carrier::new(
+            bitcoin_cli,
+            Arc::new((Mutex::new(true), Condvar::new())),
+            current_height,
+        );
+
+        let user_id = get_random_user_id();
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        store_appointment_and_fks_to_db(&responder.dbm, uuid, &appointment);
+        responder.add_tracker(
+            uuid,
+            get_random_breach(),
+            user_id,
+            ConfirmationStatus::InMempoolSince(current_height - 3),
+        );
+
+        // On demand rebroadcasts are always sent to bitcoind, even if the penalty was already sent within the same block
+        assert!(responder.broadcast_penalty(uuid).is_ok());
+        assert!(responder.broadcast_penalty(uuid).is_ok());
+        assert_eq!(probe.calls(), 2);
+    }
+
+    #[test]
+    fn test_broadcast_penalty_unreachable() {
+        let responder = init_responder(MockedServerQuery::Regular);
+        let current_height = responder.carrier.read().unwrap().get_height();
+
+        let user_id = get_random_user_id();
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        store_appointment_and_fks_to_db(&responder.dbm, uuid, &appointment);
+        let status = ConfirmationStatus::InMempoolSince(current_height - 3);
+        responder.add_tracker(uuid, get_random_breach(), user_id, status);
+
+        // The request is not held waiting for bitcoind to be back
+        let bitcoind_reachable = responder.carrier.read().unwrap().bitcoind_reachable();
+        *bitcoind_reachable.0.lock().unwrap() = false;
+        assert_eq!(
+            responder.broadcast_penalty(uuid),
+            Err(BroadcastPenaltyFailure::Unavailable)
+        );
+        assert_eq!(responder.trackers.lock().unwrap()[&uuid].status, status);
+    }
+
     #[test]
     fn test_check_confirmations() {
         let responder = init_responder(MockedServerQuery::Regular);
@@ -1002,7 +1926,7 @@ mod tests {
             let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
             let breach = get_random_breach();
 
-            store_appointment_and_fks_to_db(&responder.dbm.lock().unwrap(), uuid, &appointment);
+            store_appointment_and_fks_to_db(&responder.dbm, uuid, &appointment);
 
             if i % 4 == 0 {
                 responder.add_tracker(
@@ -1099,8 +2023,6 @@ mod tests {
         let user_id = get_random_user_id();
         responder
             .dbm
-            .lock()
-            .unwrap()
             .store_user(user_id, &UserInfo::new(21, 42))
             .unwrap();
 
@@ -1110,12 +2032,7 @@ mod tests {
         for i in 0..CONFIRMATIONS_BEFORE_RETRY + 2 {
             // Add the appointment to the db so FK rules are satisfied
             let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
-            responder
-                .dbm
-                .lock()
-                .unwrap()
-                .store_appointment(uuid, &appointment)
-                .unwrap();
+            responder.dbm.store_appointment(uuid, &appointment).unwrap();
 
             // Create a breach and add it, setting all them as unconfirmed (at different heights)
             let breach = get_random_breach();
@@ -1144,8 +2061,6 @@ mod tests {
         let user_id = get_random_user_id();
         responder
             .dbm
-            .lock()
-            .unwrap()
             .store_user(user_id, &UserInfo::new(21, 42))
             .unwrap();
 
@@ -1155,12 +2070,7 @@ mod tests {
         for i in 0..10 {
             // Add the appointment to the db so FK rules are satisfied
             let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
-            responder
-                .dbm
-                .lock()
-                .unwrap()
-                .store_appointment(uuid, &appointment)
-                .unwrap();
+            responder.dbm.store_appointment(uuid, &appointment).unwrap();
 
             // Create a breach and add it, setting half of them as reorged
             let breach = get_random_breach();
@@ -1253,8 +2163,6 @@ mod tests {
         let user_id = get_random_user_id();
         responder
             .dbm
-            .lock()
-            .unwrap()
             .store_user(user_id, &UserInfo::new(21, 42))
             .unwrap();
 
@@ -1264,12 +2172,7 @@ mod tests {
         for i in 0..10 {
             // Generate appointment and also add it to the DB (FK checks)
             let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
-            responder
-                .dbm
-                .lock()
-                .unwrap()
-                .store_appointment(uuid, &appointment)
-                .unwrap();
+            responder.dbm.store_appointment(uuid, &appointment).unwrap();
 
             let breach = get_random_breach();
 
@@ -1320,8 +2223,6 @@ mod tests {
         let user_id = get_random_user_id();
         responder
             .dbm
-            .lock()
-            .unwrap()
             .store_user(user_id, &UserInfo::new(21, 42))
             .unwrap();
 
@@ -1331,12 +2232,7 @@ mod tests {
         for i in 0..30 {
             // Generate appointment and also add it to the DB (FK checks)
             let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
-            responder
-                .dbm
-                .lock()
-                .unwrap()
-                .store_appointment(uuid, &appointment)
-                .unwrap();
+            responder.dbm.store_appointment(uuid, &appointment).unwrap();
 
             let breach = get_random_breach();
 
@@ -1381,8 +2277,6 @@ mod tests {
         let user_id = get_random_user_id();
         responder
             .dbm
-            .lock()
-            .unwrap()
             .store_user(user_id, &UserInfo::new(21, 42))
             .unwrap();
 
@@ -1391,12 +2285,7 @@ mod tests {
 
         for _ in 0..10 {
             let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
-            responder
-                .dbm
-                .lock()
-                .unwrap()
-                .store_appointment(uuid, &appointment)
-                .unwrap();
+            responder.dbm.store_appointment(uuid, &appointment).unwrap();
 
             let breach = get_random_breach();
             responder.add_tracker(
@@ -1421,7 +2310,7 @@ mod tests {
 
             // But it can be found in the database
             assert!(matches!(
-                responder.dbm.lock().unwrap().load_tracker(uuid),
+                responder.dbm.load_tracker(uuid),
                 Ok(TransactionTracker { .. })
             ));
         }
@@ -1435,8 +2324,6 @@ mod tests {
         let user_id = get_random_user_id();
         responder
             .dbm
-            .lock()
-            .unwrap()
             .store_user(user_id, &UserInfo::new(21, 42))
             .unwrap();
 
@@ -1451,12 +2338,7 @@ mod tests {
         for i in 0..10 {
             // Generate appointment and also add it to the DB (FK checks)
             let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
-            responder
-                .dbm
-                .lock()
-                .unwrap()
-                .store_appointment(uuid, &appointment)
-                .unwrap();
+            responder.dbm.store_appointment(uuid, &appointment).unwrap();
 
             let breach = get_random_breach();
             responder.add_tracker(
@@ -1499,7 +2381,7 @@ mod tests {
             if target_trackers.contains(&uuid) {
                 assert!(!responder.trackers.lock().unwrap().contains_key(&uuid));
                 assert!(matches!(
-                    responder.dbm.lock().unwrap().load_tracker(uuid),
+                    responder.dbm.load_tracker(uuid),
                     Err(DBError::NotFound)
                 ));
                 let penalty_txid = &uuid_txid_map[&uuid];
@@ -1532,7 +2414,7 @@ mod tests {
                     .unwrap()
                     .contains_key(&uuid_txid_map[&uuid]));
                 assert!(matches!(
-                    responder.dbm.lock().unwrap().load_tracker(uuid),
+                    responder.dbm.load_tracker(uuid),
                     Ok(TransactionTracker { .. })
                 ));
             }
@@ -1541,13 +2423,7 @@ mod tests {
         // The users that needed to be updated in the database have been (just checking the slot count)
         for (id, info) in updated_users {
             assert_eq!(
-                responder
-                    .dbm
-                    .lock()
-                    .unwrap()
-                    .load_user(id)
-                    .unwrap()
-                    .available_slots,
+                responder.dbm.load_user(id).unwrap().available_slots,
                 info.available_slots
             )
         }
@@ -1555,7 +2431,7 @@ mod tests {
 
     #[test]
     fn test_block_connected() {
-        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let dbm = Arc::new(DBM::in_memory().unwrap());
         let start_height = START_HEIGHT * 2;
         let mut chain = Blockchain::default().with_height(start_height);
         let responder = init_responder_with_chain_and_dbm(MockedServerQuery::Regular, &chain, dbm);
@@ -1577,7 +2453,7 @@ mod tests {
         for _ in 2..23 {
             let user_id = get_random_user_id();
 
-            responder.gatekeeper.add_update_user(user_id).unwrap();
+            responder.gatekeeper.add_update_user(user_id, None).unwrap();
             users.push(user_id);
         }
 
@@ -1589,12 +2465,7 @@ mod tests {
             let user_id = users[i % 2];
             let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
 
-            responder
-                .dbm
-                .lock()
-                .unwrap()
-                .store_appointment(uuid, &appointment)
-                .unwrap();
+            responder.dbm.store_appointment(uuid, &appointment).unwrap();
 
             // Trackers complete in the next block.
             let breach = get_random_breach();
@@ -1630,8 +2501,6 @@ mod tests {
                 let (_, appointment) = generate_dummy_appointment_with_user(*user_id, None);
                 responder
                     .dbm
-                    .lock()
-                    .unwrap()
                     .store_appointment(*uuid, &appointment)
                     .unwrap();
 
@@ -1655,7 +2524,7 @@ mod tests {
         let standalone_user_id = get_random_user_id();
         responder
             .gatekeeper
-            .add_update_user(standalone_user_id)
+            .add_update_user(standalone_user_id, None)
             .unwrap();
 
         let mut transactions = Vec::new();
@@ -1663,12 +2532,7 @@ mod tests {
         for i in 0..10 {
             let (uuid, appointment) =
                 generate_dummy_appointment_with_user(standalone_user_id, None);
-            responder
-                .dbm
-                .lock()
-                .unwrap()
-                .store_appointment(uuid, &appointment)
-                .unwrap();
+            responder.dbm.store_appointment(uuid, &appointment).unwrap();
 
             let breach = get_random_breach();
             transactions.push(breach.clone().penalty_tx.txid());
@@ -1687,12 +2551,7 @@ mod tests {
         // REBROADCAST SETUP
         let (uuid, appointment) = generate_dummy_appointment_with_user(standalone_user_id, None);
 
-        responder
-            .dbm
-            .lock()
-            .unwrap()
-            .store_appointment(uuid, &appointment)
-            .unwrap();
+        responder.dbm.store_appointment(uuid, &appointment).unwrap();
 
         let tracker_to_rebroadcast = uuid;
         responder.add_tracker(
@@ -1708,7 +2567,7 @@ mod tests {
         // Add some dummy data in the cache to check that it gets cleared
         responder
             .carrier
-            .lock()
+            .read()
             .unwrap()
             .get_issued_receipts()
             .insert(get_random_tx().txid(), ConfirmationStatus::ConfirmedIn(21));
@@ -1722,14 +2581,14 @@ mod tests {
         // CARRIER CHECKS
         assert!(responder
             .carrier
-            .lock()
+            .read()
             .unwrap()
             .get_issued_receipts()
             .is_empty());
 
         // Check that the carrier last_known_block_height has been updated
         assert_eq!(
-            responder.carrier.lock().unwrap().get_height(),
+            responder.carrier.read().unwrap().get_height(),
             target_block_height
         );
 
@@ -1773,6 +2632,11 @@ mod tests {
                         responder.trackers.lock().unwrap()[uuid].status,
                         ConfirmationStatus::ConfirmedIn(target_block_height)
                     );
+                    // The confirmation is persisted too
+                    assert_eq!(
+                        responder.dbm.load_tracker(*uuid).unwrap().status,
+                        ConfirmationStatus::ConfirmedIn(target_block_height)
+                    );
                 }
             } else {
                 for uuid in uuids.iter() {
@@ -1797,9 +2661,58 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_block_connected_prune_responded() {
+        let dbm = Arc::new(DBM::in_memory().unwrap());
+        let mut chain = Blockchain::default().with_height(START_HEIGHT * 2);
+        let retention_blocks = 10;
+        let responder = init_responder_with_chain_and_dbm(MockedServerQuery::Regular, &chain, dbm)
+            .with_retention_blocks(retention_blocks);
+
+        let target_block_height = chain.get_block_count() + 1;
+        let user_id = get_random_user_id();
+        responder.gatekeeper.add_update_user(user_id, None).unwrap();
+        let initial_slots =
+            responder.gatekeeper.get_registered_users().lock().unwrap()[&user_id].available_slots;
+
+        // Add a tracker that has been confirmed for longer than the retention period and one that has not
+        let mut uuids = Vec::new();
+        for confirmations in [retention_blocks + 1, retention_blocks] {
+            let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+            responder.dbm.store_appointment(uuid, &appointment).unwrap();
+            responder.add_tracker(
+                uuid,
+                get_random_breach(),
+                user_id,
+                ConfirmationStatus::ConfirmedIn(target_block_height - confirmations),
+            );
+            let mut registered_users = responder.gatekeeper.get_registered_users().lock().unwrap();
+            let user = registered_users.get_mut(&user_id).unwrap();
+            user.appointments.insert(uuid, 1);
+            user.available_slots -= 1;
+            uuids.push(uuid);
+        }
+
+        responder.block_connected(&chain.generate(None), chain.get_block_count());
+
+        // The first tracker has been pruned, from both memory and the database, and its slot given back to the user
+        assert!(!responder.has_tracker(uuids[0]));
+        assert!(matches!(
+            responder.dbm.load_appointment(uuids[0]),
+            Err(DBError::NotFound)
+        ));
+        let user = responder.gatekeeper.get_registered_users().lock().unwrap()[&user_id].clone();
+        assert!(!user.appointments.contains_key(&uuids[0]));
+        assert_eq!(user.available_slots, initial_slots - 1);
+
+        // The second one is kept (until the next block)
+        assert!(responder.has_tracker(uuids[1]));
+        assert!(user.appointments.contains_key(&uuids[1]));
+    }
+
     #[test]
     fn test_block_disconnected() {
-        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let dbm = Arc::new(DBM::in_memory().unwrap());
         let chain = Blockchain::default().with_height_and_txs(START_HEIGHT, 10);
         let responder = init_responder_with_chain_and_dbm(MockedServerQuery::Regular, &chain, dbm);
 
@@ -1807,8 +2720,6 @@ mod tests {
         let user_id = get_random_user_id();
         responder
             .dbm
-            .lock()
-            .unwrap()
             .store_user(user_id, &UserInfo::new(21, 42))
             .unwrap();
 
@@ -1817,12 +2728,7 @@ mod tests {
         for i in 0..10 {
             // Generate appointment and also add it to the DB (FK checks)
             let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
-            responder
-                .dbm
-                .lock()
-                .unwrap()
-                .store_appointment(uuid, &appointment)
-                .unwrap();
+            responder.dbm.store_appointment(uuid, &appointment).unwrap();
 
             let breach = get_random_breach();
 
@@ -1850,9 +2756,18 @@ mod tests {
                     .status,
                 ConfirmationStatus::ReorgedOut
             );
+            // And that they are flagged as unconfirmed in the database
+            assert_eq!(
+                responder
+                    .dbm
+                    .load_tracker(reorged[i as usize])
+                    .unwrap()
+                    .status,
+                ConfirmationStatus::InMempoolSince(i)
+            );
 
             // Check that the carrier block_height has been updated
-            assert_eq!(responder.carrier.lock().unwrap().get_height(), i);
+            assert_eq!(responder.carrier.read().unwrap().get_height(), i);
         }
 
         // Check that all reorged trackers are still reorged
@@ -8,8 +8,8 @@
 */
 
 use rand::Rng;
-use std::convert::TryInto;
 use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 
@@ -29,6 +29,7 @@ use bitcoin::hash_types::Txid;
 use bitcoin::hashes::hex::FromHex;
 use bitcoin::hashes::Hash;
 use bitcoin::network::constants::Network;
+use bitcoin::secp256k1::PublicKey;
 use bitcoin::util::hash::bitcoin_merkle_root;
 use bitcoin::util::uint::Uint256;
 use lightning_block_sync::poll::{
@@ -38,13 +39,15 @@ use lightning_block_sync::{
     AsyncBlockSourceResult, BlockHeaderData, BlockSource, BlockSourceError, UnboundedCache,
 };
 
-use teos_common::appointment::{Appointment, Locator};
-use teos_common::cryptography::{encrypt, get_random_bytes, get_random_keypair};
+use teos_common::appointment::Appointment;
+use teos_common::cryptography::{get_random_bytes, get_random_keypair};
 use teos_common::UserId;
 
 use crate::api::internal::InternalAPI;
 use crate::carrier::Carrier;
+use crate::config::Config;
 use crate::dbm::DBM;
+use crate::events::{self, EventSender};
 use crate::extended_appointment::{ExtendedAppointment, UUID};
 use crate::gatekeeper::{Gatekeeper, UserInfo};
 use crate::responder::{ConfirmationStatus, Responder, TransactionTracker};
@@ -58,6 +61,8 @@ pub(crate) const SLOTS: u32 = 21;
 pub(crate) const DURATION: u32 = 500;
 pub(crate) const EXPIRY_DELTA: u32 = 42;
 pub(crate) const START_HEIGHT: usize = 100;
+pub(crate) const MAX_APPOINTMENT_SIZE: usize = 100000;
+pub(crate) const MIN_TO_SELF_DELAY: u32 = 20;
 
 #[derive(Clone, Default, Debug)]
 pub(crate) struct Blockchain {
@@ -334,12 +339,7 @@ pub(crate) fn generate_dummy_appointment(dispute_txid: Option<&Txid>) -> Extende
     let tx_bytes = Vec::from_hex(TX_HEX).unwrap();
     let penalty_tx = consensus::deserialize(&tx_bytes).unwrap();
 
-    let mut raw_locator: [u8; 16] = get_random_bytes(16).try_into().unwrap();
-    raw_locator.copy_from_slice(&dispute_txid[..16]);
-    let locator = Locator::deserialize(&raw_locator).unwrap();
-
-    let encrypted_blob = encrypt(&penalty_tx, &dispute_txid).unwrap();
-    let appointment = Appointment::new(locator, encrypted_blob, 21);
+    let appointment = Appointment::from_penalty(&dispute_txid, &penalty_tx, 21).unwrap();
     let user_id = get_random_user_id();
     let user_signature = String::new();
     let start_block = 42;
@@ -420,7 +420,7 @@ pub(crate) fn create_carrier(query: MockedServerQuery, height: u32) -> Carrier {
 pub(crate) fn create_responder(
     tip: ValidatedBlockHeader,
     gatekeeper: Arc<Gatekeeper>,
-    dbm: Arc<Mutex<DBM>>,
+    dbm: Arc<DBM>,
     server_url: &str,
 ) -> Responder {
     let bitcoin_cli = Arc::new(BitcoindClient::new(server_url, Auth::None).unwrap());
@@ -435,7 +435,7 @@ pub(crate) async fn create_watcher(
     responder: Arc<Responder>,
     gatekeeper: Arc<Gatekeeper>,
     bitcoind_mock: BitcoindMock,
-    dbm: Arc<Mutex<DBM>>,
+    dbm: Arc<DBM>,
 ) -> Watcher {
     let last_n_blocks = get_last_n_blocks(chain, 6).await;
 
@@ -451,12 +451,17 @@ pub(crate) async fn create_watcher(
         tower_id,
         dbm,
     )
+    .with_max_appointment_size(MAX_APPOINTMENT_SIZE)
+    .with_min_to_self_delay(MIN_TO_SELF_DELAY)
 }
 #[derive(Clone)]
 pub(crate) struct ApiConfig {
     slots: u32,
     duration: u32,
+    max_appointments_per_user: u32,
     bitcoind_reachable: bool,
+    event_sender: EventSender,
+    stop_auth_key: Option<PublicKey>,
 }
 
 impl ApiConfig {
@@ -464,14 +469,32 @@ impl ApiConfig {
         Self {
             slots,
             duration,
+            max_appointments_per_user: 0,
             bitcoind_reachable: true,
+            event_sender: events::channel(),
+            stop_auth_key: None,
         }
     }
 
+    pub fn max_appointments_per_user(&mut self, max_appointments_per_user: u32) -> Self {
+        self.max_appointments_per_user = max_appointments_per_user;
+        self.clone()
+    }
+
     pub fn bitcoind_unreachable(&mut self) -> Self {
         self.bitcoind_reachable = false;
         self.clone()
     }
+
+    pub fn event_sender(&mut self, event_sender: EventSender) -> Self {
+        self.event_sender = event_sender;
+        self.clone()
+    }
+
+    pub fn require_signed_stop(&mut self, stop_auth_key: PublicKey) -> Self {
+        self.stop_auth_key = Some(stop_auth_key);
+        self.clone()
+    }
 }
 
 impl Default for ApiConfig {
@@ -479,7 +502,10 @@ impl Default for ApiConfig {
         Self {
             slots: SLOTS,
             duration: DURATION,
+            max_appointments_per_user: 0,
             bitcoind_reachable: true,
+            event_sender: events::channel(),
+            stop_auth_key: None,
         }
     }
 }
@@ -488,15 +514,17 @@ pub(crate) async fn create_api_with_config(api_config: ApiConfig) -> Arc<Interna
     let bitcoind_mock = BitcoindMock::new(MockOptions::empty());
     let mut chain = Blockchain::default().with_height(START_HEIGHT);
 
-    let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+    let dbm = Arc::new(DBM::in_memory().unwrap());
     let gk = Arc::new(Gatekeeper::new(
         chain.get_block_count(),
         api_config.slots,
         api_config.duration,
         EXPIRY_DELTA,
+        api_config.max_appointments_per_user,
         dbm.clone(),
     ));
-    let responder = create_responder(chain.tip(), gk.clone(), dbm.clone(), bitcoind_mock.url());
+    let responder = create_responder(chain.tip(), gk.clone(), dbm.clone(), bitcoind_mock.url())
+        .with_events(api_config.event_sender.clone());
     let watcher = create_watcher(
         &mut chain,
         Arc::new(responder),
@@ -504,7 +532,17 @@ pub(crate) async fn create_api_with_config(api_config: ApiConfig) -> Arc<Interna
         bitcoind_mock,
         dbm.clone(),
     )
-    .await;
+    .await
+    .with_events(api_config.event_sender);
+
+    let config = match api_config.stop_auth_key {
+        Some(pk) => Config {
+            require_signed_stop: true,
+            stop_auth_key: pk.to_string(),
+            ..Default::default()
+        },
+        None => Config::default(),
+    };
 
     let bitcoind_reachable = Arc::new((Mutex::new(api_config.bitcoind_reachable), Condvar::new()));
     let (shutdown_trigger, _) = triggered::trigger();
@@ -512,6 +550,9 @@ pub(crate) async fn create_api_with_config(api_config: ApiConfig) -> Arc<Interna
         Arc::new(watcher),
         bitcoind_reachable,
         shutdown_trigger,
+        Arc::new(AtomicBool::new(false)),
+        Arc::new(AtomicBool::new(false)),
+        config,
     ))
 }
 
@@ -527,6 +568,8 @@ pub(crate) struct MockOptions {
     error_code: Option<i64>,
     block_hash: Option<BlockHash>,
     height: Option<usize>,
+    fee_rate: Option<f64>,
+    txout_spent: Option<bool>,
 }
 
 impl MockOptions {
@@ -535,6 +578,8 @@ impl MockOptions {
             error_code: Some(error_code),
             block_hash: Some(block_hash),
             height: Some(height),
+            fee_rate: None,
+            txout_spent: None,
         }
     }
 
@@ -543,6 +588,8 @@ impl MockOptions {
             error_code: None,
             block_hash: None,
             height: None,
+            fee_rate: None,
+            txout_spent: None,
         }
     }
 
@@ -551,6 +598,41 @@ impl MockOptions {
             error_code: Some(error_code),
             block_hash: None,
             height: None,
+            fee_rate: None,
+            txout_spent: None,
+        }
+    }
+
+    /// Makes `estimatesmartfee` return the given fee rate (in BTC/kvB).
+    pub fn with_fee_rate(fee_rate: f64) -> Self {
+        Self {
+            error_code: None,
+            block_hash: None,
+            height: None,
+            fee_rate: Some(fee_rate),
+            txout_spent: None,
+        }
+    }
+
+    /// Makes `gettxout` report every output as spent (or unspent).
+    pub fn with_txout_spent(spent: bool) -> Self {
+        Self {
+            error_code: None,
+            block_hash: None,
+            height: None,
+            fee_rate: None,
+            txout_spent: Some(spent),
+        }
+    }
+
+    /// Same as [with_block](Self::with_block), but also makes `gettxout` report every output as spent (or unspent).
+    pub fn with_block_and_txout_spent(block_hash: BlockHash, height: usize, spent: bool) -> Self {
+        Self {
+            error_code: None,
+            block_hash: Some(block_hash),
+            height: Some(height),
+            fee_rate: None,
+            txout_spent: Some(spent),
         }
     }
 
@@ -560,11 +642,59 @@ impl MockOptions {
             error_code: None,
             block_hash: Some(block_hash),
             height: Some(height),
+            fee_rate: None,
+            txout_spent: None,
         }
     }
 }
 
+/// Keeps track of the `sendrawtransaction` calls received by a [BitcoindMock].
+#[derive(Debug, Default)]
+pub(crate) struct BroadcastProbe {
+    calls: AtomicUsize,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+}
+
+impl BroadcastProbe {
+    /// Number of `sendrawtransaction` calls that have been served.
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+
+    /// Maximum number of `sendrawtransaction` calls that have been served simultaneously.
+    pub fn max_in_flight(&self) -> usize {
+        self.max_in_flight.load(Ordering::SeqCst)
+    }
+}
+
 impl BitcoindMock {
+    /// Creates a mock whose `sendrawtransaction` calls are reported to the given [BroadcastProbe]. Each call takes a
+    /// while to be served, so overlapping calls can be detected.
+    pub fn with_broadcast_probe(probe: Arc<BroadcastProbe>) -> Self {
+        let mut io = IoHandler::default();
+        io.add_sync_method("sendrawtransaction", move |_params: Params| {
+            let in_flight = probe.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            probe.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            thread::sleep(std::time::Duration::from_millis(20));
+            probe.in_flight.fetch_sub(1, Ordering::SeqCst);
+            probe.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(Value::String(TXID_HEX.to_owned()))
+        });
+
+        // Every server thread binds its own listener, so they need to share a fixed port for all of them to be reachable
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let server = ServerBuilder::new(io).threads(8).start_http(&addr).unwrap();
+
+        Self {
+            url: format!("http://{}", server.address()),
+            server,
+        }
+    }
+
     pub fn new(options: MockOptions) -> Self {
         let mut io = IoHandler::default();
 
@@ -584,6 +714,14 @@ impl BitcoindMock {
             }
         }
 
+        if let Some(fee_rate) = options.fee_rate {
+            BitcoindMock::add_estimatesmartfee(&mut io, fee_rate);
+        }
+
+        if let Some(spent) = options.txout_spent {
+            BitcoindMock::add_gettxout(&mut io, spent);
+        }
+
         let server = ServerBuilder::new(io)
             .threads(3)
             .start_http(&"127.0.0.1:0".parse().unwrap())
@@ -640,6 +778,23 @@ impl BitcoindMock {
         })
     }
 
+    fn add_estimatesmartfee(io: &mut IoHandler, fee_rate: f64) {
+        io.add_sync_method("estimatesmartfee", move |_params: Params| {
+            Ok(serde_json::json!({"feerate": fee_rate, "blocks": 6}))
+        });
+    }
+
+    fn add_gettxout(io: &mut IoHandler, spent: bool) {
+        io.add_sync_method("gettxout", move |_params: Params| {
+            if spent {
+                Ok(Value::Null)
+            } else {
+                Ok(serde_json::json!({"bestblock": BlockHash::default().to_string(), "confirmations": 1,
+                    "value": 0.001, "scriptPubKey": {"asm": "", "hex": "", "type": "nonstandard"}, "coinbase": false}))
+            }
+        });
+    }
+
     pub fn url(&self) -> &str {
         &self.url
     }
@@ -10,20 +10,30 @@ use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 
 use bitcoin::hash_types::BlockHash;
-use bitcoin::secp256k1::SecretKey;
-use bitcoin::{Block, BlockHeader, Transaction};
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use bitcoin::{Block, BlockHeader, Transaction, Txid};
 use lightning::chain;
 use lightning_block_sync::poll::ValidatedBlock;
 
-use teos_common::appointment::{Appointment, Locator};
+use teos_common::appointment::{Appointment, AppointmentStatus, Locator};
+use teos_common::constants::ENCRYPTED_BLOB_MIN_SIZE;
 use teos_common::cryptography;
 use teos_common::receipts::{AppointmentReceipt, RegistrationReceipt};
 use teos_common::UserId;
 
-use crate::dbm::DBM;
+use crate::dbm::{DBStats, Error as DBError, DBM};
+use crate::events::{self, Event, EventSender};
+use crate::export::ExportedData;
 use crate::extended_appointment::{AppointmentSummary, ExtendedAppointment, UUID};
-use crate::gatekeeper::{Gatekeeper, MaxSlotsReached, UserInfo};
-use crate::responder::{ConfirmationStatus, Responder, TransactionTracker};
+use crate::gatekeeper::{
+    AddUpdateAppointmentFailure, AddUpdateUserFailure, AuthenticationFailure, Gatekeeper,
+    SubscriptionEvent, UserInfo,
+};
+use crate::metrics::Metrics;
+use crate::protos as msgs;
+use crate::responder::{
+    BroadcastPenaltyFailure, ConfirmationStatus, Responder, TransactionTracker,
+};
 
 /// Data structure used to cache locators computed from parsed blocks.
 ///
@@ -180,15 +190,37 @@ impl Breach {
 #[derive(Debug)]
 pub(crate) enum AddAppointmentFailure {
     AuthenticationFailure,
+    UserBanned,
     NotEnoughSlots,
+    MaxAppointmentsReached(u32),
     SubscriptionExpired(u32),
     AlreadyTriggered,
+    BlobTooBig(usize),
+    BlobTooSmall(usize),
+    ToSelfDelayTooSmall(u32),
+    DBError,
+}
+
+/// Packs the reasons why trying to update an appointment may fail.
+#[derive(Debug)]
+pub(crate) enum UpdateAppointmentFailure {
+    /// The user has no appointment for the given locator.
+    NotFound,
+    /// The new data has been rejected, for the same reasons a new appointment would.
+    Rejected(AddAppointmentFailure),
+}
+
+impl From<AddAppointmentFailure> for UpdateAppointmentFailure {
+    fn from(e: AddAppointmentFailure) -> Self {
+        UpdateAppointmentFailure::Rejected(e)
+    }
 }
 
 /// Packs the reasons why trying to query an appointment may fail.
 #[derive(Debug)]
 pub(crate) enum GetAppointmentFailure {
     AuthenticationFailure,
+    UserBanned,
     SubscriptionExpired(u32),
     NotFound,
 }
@@ -197,9 +229,37 @@ pub(crate) enum GetAppointmentFailure {
 #[derive(Debug)]
 pub(crate) enum GetSubscriptionInfoFailure {
     AuthenticationFailure,
+    UserBanned,
     SubscriptionExpired(u32),
 }
 
+/// Packs the reasons why trying to register a user may fail.
+#[derive(Debug)]
+pub(crate) enum RegisterFailure {
+    MaxSlotsReached,
+    UserBanned,
+    DBError,
+}
+
+/// The keys used by the tower to sign the receipts handed to users.
+#[derive(Debug)]
+struct TowerKeys {
+    /// The current signing key.
+    signing_key: SecretKey,
+    /// The tower identifier (the public key matching the current signing key).
+    tower_id: UserId,
+    /// Identifiers of the keys the tower signed with before rotating its key, alongside the height until which
+    /// signatures made with them are still honoured.
+    retired: Vec<(UserId, u32)>,
+}
+
+/// Packs the reasons why trying to import data into the tower may fail.
+#[derive(Debug)]
+pub(crate) enum ImportDataFailure {
+    NotFresh,
+    InvalidData,
+}
+
 /// Wraps the returning information regarding a queried appointment.
 ///
 /// Either an [Appointment] or a [TransactionTracker] can be
@@ -210,19 +270,41 @@ pub(crate) enum AppointmentInfo {
     Tracker(TransactionTracker),
 }
 
+impl From<AppointmentInfo> for msgs::AppointmentData {
+    fn from(info: AppointmentInfo) -> Self {
+        msgs::AppointmentData {
+            appointment_data: Some(match info {
+                AppointmentInfo::Appointment(appointment) => {
+                    msgs::appointment_data::AppointmentData::Appointment(appointment.into())
+                }
+                AppointmentInfo::Tracker(tracker) => {
+                    msgs::appointment_data::AppointmentData::Tracker(tracker.into())
+                }
+            }),
+        }
+    }
+}
+
 /// Reason why the appointment is deleted. Used for logging purposes.
 enum DeletionReason {
     Outdated,
     Invalid,
+    Rejected,
     Accepted,
+    Banned,
 }
 
 /// Types of new appointments stored in the [Watcher].
 #[derive(Debug, PartialEq, Eq)]
 enum StoredAppointment {
     New,
-    Update,
+    /// The appointment data has been replaced. Holds the new version of the appointment.
+    Update(u32),
     Collision,
+    /// The appointment was meant to be updated, but it is not in the [Watcher] (anymore).
+    NotFound,
+    /// The appointment was meant to be updated, but the new data could not be stored.
+    DBError,
 }
 
 /// Types of new triggered appointments handled by the [Watcher].
@@ -248,12 +330,18 @@ pub struct Watcher {
     gatekeeper: Arc<Gatekeeper>,
     /// The last known block height.
     last_known_block_height: AtomicU32,
-    /// The tower signing key. Used to sign messages going to users.
-    signing_key: SecretKey,
-    /// The tower identifier.
-    pub tower_id: UserId,
+    /// The tower keys. Used to sign messages going to users.
+    keys: Mutex<TowerKeys>,
+    /// Maximum size (in bytes) of the encrypted blob of an appointment.
+    max_appointment_size: usize,
+    /// Minimum `to_self_delay` (in blocks) an appointment must have to be accepted.
+    min_to_self_delay: u32,
     /// A [DBM] (database manager) instance. Used to persist appointment data into disk.
-    dbm: Arc<Mutex<DBM>>,
+    dbm: Arc<DBM>,
+    /// A [Metrics] instance. Keeps track of accepted and rejected appointments.
+    metrics: Arc<Metrics>,
+    /// An [EventSender]. Used to publish accepted and rejected appointments.
+    events: EventSender,
 }
 
 impl Watcher {
@@ -265,11 +353,48 @@ impl Watcher {
         last_known_block_height: u32,
         signing_key: SecretKey,
         tower_id: UserId,
-        dbm: Arc<Mutex<DBM>>,
+        dbm: Arc<DBM>,
     ) -> Self {
+        let (appointments, locator_uuid_map) = Watcher::load_appointments(&dbm);
+        let retired_keys = dbm
+            .load_retired_tower_keys(last_known_block_height)
+            .into_iter()
+            .map(|(sk, valid_until)| {
+                let pk = PublicKey::from_secret_key(&Secp256k1::new(), &sk);
+                (UserId(pk), valid_until)
+            })
+            .collect();
+
+        Watcher {
+            appointments: Mutex::new(appointments),
+            locator_uuid_map: Mutex::new(locator_uuid_map),
+            locator_cache: Mutex::new(LocatorCache::new(last_n_blocks)),
+            responder,
+            gatekeeper,
+            last_known_block_height: AtomicU32::new(last_known_block_height),
+            keys: Mutex::new(TowerKeys {
+                signing_key,
+                tower_id,
+                retired: retired_keys,
+            }),
+            max_appointment_size: usize::MAX,
+            min_to_self_delay: 0,
+            dbm,
+            metrics: Arc::new(Metrics::new()),
+            events: events::channel(),
+        }
+    }
+
+    /// Loads the (non-triggered) appointments stored in the database, alongside the map between their [Locator]s and [UUID]s.
+    fn load_appointments(
+        dbm: &DBM,
+    ) -> (
+        HashMap<UUID, AppointmentSummary>,
+        HashMap<Locator, HashSet<UUID>>,
+    ) {
         let mut appointments = HashMap::new();
         let mut locator_uuid_map: HashMap<Locator, HashSet<UUID>> = HashMap::new();
-        for (uuid, appointment) in dbm.lock().unwrap().load_all_appointments() {
+        for (uuid, appointment) in dbm.load_all_appointments() {
             appointments.insert(uuid, appointment.get_summary());
 
             if let Some(map) = locator_uuid_map.get_mut(&appointment.locator()) {
@@ -279,19 +404,115 @@ impl Watcher {
             }
         }
 
+        (appointments, locator_uuid_map)
+    }
+
+    /// Sets the [Metrics] instance the [Watcher] reports to.
+    pub fn with_metrics(self, metrics: Arc<Metrics>) -> Self {
+        Watcher { metrics, ..self }
+    }
+
+    /// Sets the [EventSender] the [Watcher] publishes events to.
+    pub fn with_events(self, events: EventSender) -> Self {
+        Watcher { events, ..self }
+    }
+
+    /// Sets the maximum size (in bytes) of the encrypted blob of the appointments accepted by the [Watcher].
+    /// There is no limit otherwise.
+    pub fn with_max_appointment_size(self, max_appointment_size: usize) -> Self {
         Watcher {
-            appointments: Mutex::new(appointments),
-            locator_uuid_map: Mutex::new(locator_uuid_map),
-            locator_cache: Mutex::new(LocatorCache::new(last_n_blocks)),
-            responder,
-            gatekeeper,
-            last_known_block_height: AtomicU32::new(last_known_block_height),
-            signing_key,
-            tower_id,
-            dbm,
+            max_appointment_size,
+            ..self
+        }
+    }
+
+    /// Sets the minimum `to_self_delay` of the appointments accepted by the [Watcher].
+    /// Any `to_self_delay` is accepted otherwise.
+    pub fn with_min_to_self_delay(self, min_to_self_delay: u32) -> Self {
+        Watcher {
+            min_to_self_delay,
+            ..self
         }
     }
 
+    /// Gets the maximum size (in bytes) of the encrypted blob of the appointments accepted by the [Watcher].
+    pub(crate) fn get_max_appointment_size(&self) -> usize {
+        self.max_appointment_size
+    }
+
+    /// Gets the minimum `to_self_delay` of the appointments accepted by the [Watcher].
+    pub(crate) fn get_min_to_self_delay(&self) -> u32 {
+        self.min_to_self_delay
+    }
+
+    /// Gets the tower identifier.
+    pub fn tower_id(&self) -> UserId {
+        self.keys.lock().unwrap().tower_id
+    }
+
+    /// Rotates the tower signing key, returning the new tower identifier.
+    ///
+    /// The old key is kept so signatures made with it are still honoured for `grace_period` blocks. If `reissue_receipts`
+    /// is set, registration receipts signed with the new key are issued for all the users with an active subscription.
+    pub(crate) fn rotate_key(
+        &self,
+        grace_period: u32,
+        reissue_receipts: bool,
+    ) -> Result<(UserId, Vec<RegistrationReceipt>), DBError> {
+        let height = self.last_known_block_height.load(Ordering::Acquire);
+        let (signing_key, pk) = cryptography::get_random_keypair();
+
+        let mut keys = self.keys.lock().unwrap();
+        self.dbm
+            .rotate_tower_key(&signing_key, height + grace_period)?;
+
+        let retired_id = keys.tower_id;
+        keys.retired
+            .retain(|(_, valid_until)| *valid_until >= height);
+        keys.retired.push((retired_id, height + grace_period));
+        keys.signing_key = signing_key;
+        keys.tower_id = UserId(pk);
+        log::info!("Tower key rotated. New tower_id: {}", keys.tower_id);
+
+        let mut receipts = Vec::new();
+        if reissue_receipts {
+            for user_id in self.gatekeeper.get_user_ids() {
+                if let Some(user_info) = self.gatekeeper.get_user_info(user_id) {
+                    if user_info.subscription_expiry > height {
+                        let mut receipt = RegistrationReceipt::new(
+                            user_id,
+                            user_info.available_slots,
+                            user_info.subscription_expiry,
+                        );
+                        receipt.sign(&keys.signing_key);
+                        receipts.push(receipt);
+                    }
+                }
+            }
+        }
+
+        Ok((keys.tower_id, receipts))
+    }
+
+    /// Checks whether `signature` is a valid tower signature of `message`.
+    ///
+    /// Signatures made with the current key are always valid. Signatures made with retired keys are valid until the
+    /// grace period of the key is over.
+    pub fn verify_tower_signature(&self, message: &[u8], signature: &str) -> bool {
+        let signer = match cryptography::recover_pk(message, signature) {
+            Ok(pk) => UserId(pk),
+            Err(_) => return false,
+        };
+
+        let height = self.last_known_block_height.load(Ordering::Acquire);
+        let keys = self.keys.lock().unwrap();
+        signer == keys.tower_id
+            || keys
+                .retired
+                .iter()
+                .any(|(tower_id, valid_until)| signer == *tower_id && *valid_until >= height)
+    }
+
     /// Returns whether the [Watcher] has been created from scratch (fresh) or from backed-up data.
     pub fn is_fresh(&self) -> bool {
         self.appointments.lock().unwrap().is_empty()
@@ -299,16 +520,144 @@ impl Watcher {
 
     /// Registers a new user within the [Watcher]. This request is passed to the [Gatekeeper], who is in
     /// charge of managing users.
-    pub(crate) fn register(&self, user_id: UserId) -> Result<RegistrationReceipt, MaxSlotsReached> {
-        let mut receipt = self.gatekeeper.add_update_user(user_id)?;
-        receipt.sign(&self.signing_key);
+    ///
+    /// Banned users cannot register (nor renew their subscription). Users may request a given number of slots, which
+    /// is capped by the [Gatekeeper] policy (the default subscription is given otherwise).
+    pub(crate) fn register(
+        &self,
+        user_id: UserId,
+        requested_slots: Option<u32>,
+    ) -> Result<RegistrationReceipt, RegisterFailure> {
+        if self.gatekeeper.is_banned(user_id) {
+            return Err(RegisterFailure::UserBanned);
+        }
+        let mut receipt = self
+            .gatekeeper
+            .add_update_user(user_id, requested_slots)
+            .map_err(|e| match e {
+                AddUpdateUserFailure::MaxSlotsReached => RegisterFailure::MaxSlotsReached,
+                AddUpdateUserFailure::DBError => RegisterFailure::DBError,
+            })?;
+        receipt.sign(&self.keys.lock().unwrap().signing_key);
 
         Ok(receipt)
     }
 
-    /// Adds a new [Appointment] to the tower.
+    /// Adds a new [Appointment] to the tower, accounting for it in the tower [Metrics] and publishing the outcome
+    /// as an [Event].
+    ///
+    /// Check [try_add_appointment](Self::try_add_appointment) for the conditions the appointment must meet to be accepted.
+    pub(crate) fn add_appointment(
+        &self,
+        appointment: Appointment,
+        user_signature: String,
+    ) -> Result<(AppointmentReceipt, u32, u32), AddAppointmentFailure> {
+        self.process_appointment(appointment, user_signature, false)
+            .map(|(receipt, available_slots, subscription_expiry, _)| {
+                (receipt, available_slots, subscription_expiry)
+            })
+            .map_err(|e| match e {
+                UpdateAppointmentFailure::Rejected(e) => e,
+                UpdateAppointmentFailure::NotFound => {
+                    unreachable!("only updates can fail for the appointment not being found")
+                }
+            })
+    }
+
+    /// Adds (or updates, if `update_only` is set) an [Appointment], accounting for it in the tower [Metrics] and
+    /// publishing the outcome as an [Event].
+    fn process_appointment(
+        &self,
+        appointment: Appointment,
+        user_signature: String,
+        update_only: bool,
+    ) -> Result<(AppointmentReceipt, u32, u32, u32), UpdateAppointmentFailure> {
+        // Only bother recovering the user if someone is listening
+        let publish_to = if self.events.receiver_count() > 0 {
+            cryptography::recover_pk(&appointment.serialize(), &user_signature)
+                .ok()
+                .map(|pk| (UserId(pk), appointment.locator))
+        } else {
+            None
+        };
+
+        let locator = appointment.locator;
+        let result = self.try_add_appointment(appointment, user_signature, update_only);
+        match &result {
+            Ok(_) => self.metrics.appointment_accepted(),
+            Err(e) => {
+                log::debug!(locator:% = locator, reason:? = e; "Appointment rejected");
+                self.metrics.appointment_rejected()
+            }
+        }
+
+        if let Some((user_id, locator)) = publish_to {
+            let event = match &result {
+                Ok(_) => Some(Event::appointment_accepted(user_id, locator)),
+                // Non-registered (or banned) users cannot subscribe to events
+                Err(UpdateAppointmentFailure::Rejected(
+                    AddAppointmentFailure::AuthenticationFailure,
+                ))
+                | Err(UpdateAppointmentFailure::Rejected(AddAppointmentFailure::UserBanned)) => {
+                    None
+                }
+                // Nothing was added or updated, and not because of the appointment itself
+                Err(UpdateAppointmentFailure::NotFound)
+                | Err(UpdateAppointmentFailure::Rejected(AddAppointmentFailure::DBError)) => None,
+                Err(UpdateAppointmentFailure::Rejected(e)) => Some(Event::appointment_rejected(
+                    user_id,
+                    locator,
+                    format!("{:?}", e),
+                )),
+            };
+            if let Some(event) = event {
+                // Sending only fails if there are no subscribers
+                self.events.send(event).ok();
+            }
+        }
+
+        result
+    }
+
+    /// Replaces the data of an existing [Appointment] (e.g. with a penalty paying a higher fee), returning the new
+    /// version of the appointment alongside the same data [add_appointment](Self::add_appointment) does.
+    ///
+    /// Updates are only accepted provided the user already has an appointment for the given locator, and are checked
+    /// as new appointments are (check [try_add_appointment](Self::try_add_appointment)). Notice this means appointments
+    /// that have already been triggered cannot be updated. The slots taken by the appointment are recomputed based on
+    /// the new data.
+    pub(crate) fn update_appointment(
+        &self,
+        appointment: Appointment,
+        user_signature: String,
+    ) -> Result<(AppointmentReceipt, u32, u32, u32), UpdateAppointmentFailure> {
+        let user_id = self
+            .gatekeeper
+            .authenticate_user(&appointment.serialize(), &user_signature)
+            .map_err(|e| {
+                UpdateAppointmentFailure::Rejected(match e {
+                    AuthenticationFailure::Banned => AddAppointmentFailure::UserBanned,
+                    _ => AddAppointmentFailure::AuthenticationFailure,
+                })
+            })?;
+
+        // Bail early if there is nothing to update. The appointment could still be deleted from this point on, so the
+        // check is repeated when storing the new data
+        let uuid = UUID::new(appointment.locator, user_id);
+        if !self.appointments.lock().unwrap().contains_key(&uuid)
+            && !self.responder.has_tracker(uuid)
+        {
+            return Err(UpdateAppointmentFailure::NotFound);
+        }
+
+        self.process_appointment(appointment, user_signature, true)
+    }
+
+    /// Tries to add a new [Appointment] to the tower.
     ///
     /// Appointments are only added provided:
+    /// - The encrypted blob is not bigger than the maximum appointment size
+    /// - The `to_self_delay` is not smaller than the minimum required one
     /// - The user is registered into the system
     /// - The user subscription has not expired
     /// - The user has enough available slots to fit the appointment
@@ -318,21 +667,43 @@ impl Watcher {
     /// monitored by the [Watcher]. An [ExtendedAppointment] (constructed from the [Appointment]) will be persisted on disk.
     /// In case the locator for the given appointment can be found in the cache (meaning the appointment has been
     /// triggered recently) the data will be passed to the [Responder] straightaway (modulo it being valid).
-    pub(crate) fn add_appointment(
+    ///
+    /// If `update_only` is set, the appointment must still be in the [Watcher] by the time the new data is stored.
+    /// Otherwise, nothing is stored and [UpdateAppointmentFailure::NotFound] is returned.
+    ///
+    /// Alongside the receipt, returns the available slots and expiry of the user and the version of the appointment
+    /// (zero unless its data has been replaced).
+    fn try_add_appointment(
         &self,
         appointment: Appointment,
         user_signature: String,
-    ) -> Result<(AppointmentReceipt, u32, u32), AddAppointmentFailure> {
+        update_only: bool,
+    ) -> Result<(AppointmentReceipt, u32, u32, u32), UpdateAppointmentFailure> {
+        if appointment.encrypted_blob.len() > self.max_appointment_size {
+            return Err(AddAppointmentFailure::BlobTooBig(self.max_appointment_size).into());
+        }
+        // Blobs cannot be decrypted until the dispute is seen, but the ones that cannot even hold a transaction are
+        // obviously garbage
+        if appointment.encrypted_blob.len() < ENCRYPTED_BLOB_MIN_SIZE {
+            return Err(AddAppointmentFailure::BlobTooSmall(ENCRYPTED_BLOB_MIN_SIZE).into());
+        }
+        if appointment.to_self_delay < self.min_to_self_delay {
+            return Err(AddAppointmentFailure::ToSelfDelayTooSmall(self.min_to_self_delay).into());
+        }
+
         let user_id = self
             .gatekeeper
             .authenticate_user(&appointment.serialize(), &user_signature)
-            .map_err(|_| AddAppointmentFailure::AuthenticationFailure)?;
+            .map_err(|e| match e {
+                AuthenticationFailure::Banned => AddAppointmentFailure::UserBanned,
+                _ => AddAppointmentFailure::AuthenticationFailure,
+            })?;
 
         let (has_subscription_expired, expiry) =
             self.gatekeeper.has_subscription_expired(user_id).unwrap();
 
         if has_subscription_expired {
-            return Err(AddAppointmentFailure::SubscriptionExpired(expiry));
+            return Err(AddAppointmentFailure::SubscriptionExpired(expiry).into());
         }
 
         let extended_appointment = ExtendedAppointment::new(
@@ -345,20 +716,32 @@ impl Watcher {
         let uuid = UUID::new(extended_appointment.locator(), user_id);
 
         if self.responder.has_tracker(uuid) {
-            log::info!("Tracker for {} already found in Responder", uuid);
-            return Err(AddAppointmentFailure::AlreadyTriggered);
+            log::info!(
+                user_id:% = user_id,
+                locator:% = extended_appointment.locator(),
+                reason = "AlreadyTriggered";
+                "Tracker for {} already found in Responder", uuid
+            );
+            return Err(AddAppointmentFailure::AlreadyTriggered.into());
         }
 
         let available_slots = self
             .gatekeeper
             .add_update_appointment(user_id, uuid, &extended_appointment)
-            .map_err(|_| AddAppointmentFailure::NotEnoughSlots)?;
+            .map_err(|e| match e {
+                AddUpdateAppointmentFailure::NotEnoughSlots => {
+                    AddAppointmentFailure::NotEnoughSlots
+                }
+                AddUpdateAppointmentFailure::MaxAppointmentsReached(x) => {
+                    AddAppointmentFailure::MaxAppointmentsReached(x)
+                }
+            })?;
 
         // FIXME: There's an edge case here if store_triggered_appointment is called and bitcoind is unreachable.
         // This will hang, the request will timeout but be accepted. However, the user will not be handed the receipt.
         // This could be fixed adding a thread to take care of storing while the main thread returns the receipt.
         // Not fixing this atm since working with threads that call self.method is surprisingly non-trivial.
-        match self
+        let stored = match self
             .locator_cache
             .lock()
             .unwrap()
@@ -366,45 +749,79 @@ impl Watcher {
         {
             // Appointments that were triggered in blocks held in the cache
             Some(dispute_tx) => {
-                self.store_triggered_appointment(uuid, &extended_appointment, user_id, dispute_tx);
+                if !update_only || self.appointments.lock().unwrap().contains_key(&uuid) {
+                    self.store_triggered_appointment(
+                        uuid,
+                        &extended_appointment,
+                        user_id,
+                        dispute_tx,
+                    );
+                    // The data is handed to the Responder instead of replacing the stored one, so the version stays
+                    Some(self.dbm.load_appointment_version(uuid))
+                } else {
+                    None
+                }
             }
             // Regular appointments that have not been triggered (or, at least, not recently)
+            None => match self.store_appointment(uuid, &extended_appointment, update_only) {
+                StoredAppointment::New | StoredAppointment::Collision => Some(0),
+                StoredAppointment::Update(version) => Some(version),
+                StoredAppointment::NotFound => None,
+                StoredAppointment::DBError => return Err(AddAppointmentFailure::DBError.into()),
+            },
+        };
+
+        let version = match stored {
+            Some(version) => version,
             None => {
-                self.store_appointment(uuid, &extended_appointment);
+                // The appointment was deleted while being updated, so the slots taken for the new data are freed
+                log::debug!(user_id:% = user_id; "{} deleted before its update could be stored", uuid);
+                for (user_id, user_info) in self
+                    .gatekeeper
+                    .delete_appointments_from_memory(&HashMap::from_iter([(uuid, user_id)]))
+                {
+                    self.dbm.update_user(user_id, &user_info);
+                }
+                return Err(UpdateAppointmentFailure::NotFound);
             }
         };
 
+        log::debug!(
+            user_id:% = user_id,
+            locator:% = extended_appointment.locator();
+            "Appointment accepted (uuid={})", uuid
+        );
+
         let mut receipt = AppointmentReceipt::new(
             extended_appointment.user_signature,
             extended_appointment.start_block,
         );
-        receipt.sign(&self.signing_key);
-
-        Ok((receipt, available_slots, expiry))
+        receipt.sign(&self.keys.lock().unwrap().signing_key);
+        Ok((receipt, available_slots, expiry, version))
     }
 
     /// Stores an appointment in the [Watcher] memory and into the database (or updates it if it already exists).
     ///
-    /// Data is stored in `locator_uuid_map` and `appointments`.
+    /// Data is stored in `locator_uuid_map` and `appointments`. If `update_only` is set, appointments that are not
+    /// already in the [Watcher] are not stored ([StoredAppointment::NotFound] is returned instead).
     fn store_appointment(
         &self,
         uuid: UUID,
         appointment: &ExtendedAppointment,
+        update_only: bool,
     ) -> StoredAppointment {
-        self.appointments
-            .lock()
-            .unwrap()
-            .insert(uuid, appointment.get_summary());
+        // The check is performed holding the lock, so the appointment cannot be deleted before being updated
+        let mut appointments = self.appointments.lock().unwrap();
+        if update_only && !appointments.contains_key(&uuid) {
+            return StoredAppointment::NotFound;
+        }
+        appointments.insert(uuid, appointment.get_summary());
         let mut locator_uuid_map = self.locator_uuid_map.lock().unwrap();
         if let Entry::Vacant(e) = locator_uuid_map.entry(appointment.locator()) {
             // New appointment
             e.insert(HashSet::from_iter(vec![uuid]));
 
-            self.dbm
-                .lock()
-                .unwrap()
-                .store_appointment(uuid, appointment)
-                .unwrap();
+            self.dbm.store_appointment(uuid, appointment).unwrap();
             StoredAppointment::New
         } else {
             // Either an update or an appointment from another user sharing the same locator
@@ -418,19 +835,14 @@ impl Watcher {
                     appointment.locator(),
                     uuid
                 );
-                self.dbm
-                    .lock()
-                    .unwrap()
-                    .store_appointment(uuid, appointment)
-                    .unwrap();
+                self.dbm.store_appointment(uuid, appointment).unwrap();
                 StoredAppointment::Collision
             } else {
                 log::debug!("Update received for {}, locator map not modified", uuid);
-                self.dbm
-                    .lock()
-                    .unwrap()
-                    .update_appointment(uuid, appointment);
-                StoredAppointment::Update
+                match self.dbm.update_appointment(uuid, appointment) {
+                    Ok(version) => StoredAppointment::Update(version),
+                    Err(_) => StoredAppointment::DBError,
+                }
             }
         }
     }
@@ -454,11 +866,7 @@ impl Watcher {
             Ok(penalty_tx) => {
                 // Data needs to be added the database straightaway since appointments are
                 // FKs to trackers. If handle breach fails, data will be deleted later.
-                self.dbm
-                    .lock()
-                    .unwrap()
-                    .store_appointment(uuid, appointment)
-                    .unwrap();
+                self.dbm.store_appointment(uuid, appointment).unwrap();
 
                 if let ConfirmationStatus::Rejected(reason) = self.responder.handle_breach(
                     uuid,
@@ -467,12 +875,21 @@ impl Watcher {
                 ) {
                     // DISCUSS: We could either free the slots or keep it occupied as if this was misbehavior.
                     // Keeping it for now.
-                    log::warn!("Appointment bounced in the Responder. Reason: {:?}", reason);
+                    log::warn!(
+                        user_id:% = user_id,
+                        locator:% = appointment.locator(),
+                        reason:? = reason;
+                        "Appointment bounced in the Responder. Reason: {:?}", reason
+                    );
 
-                    self.dbm.lock().unwrap().remove_appointment(uuid);
+                    self.dbm.remove_appointment(uuid);
                     TriggeredAppointment::Rejected
                 } else {
-                    log::info!("Appointment went straight to the Responder");
+                    log::info!(
+                        user_id:% = user_id,
+                        locator:% = appointment.locator();
+                        "Appointment went straight to the Responder"
+                    );
                     TriggeredAppointment::Accepted
                 }
             }
@@ -481,11 +898,13 @@ impl Watcher {
             // If data inside the encrypted blob is invalid, the appointment is accepted but the data is dropped.
             // (same as with data that bounces in the Responder). This reduces the appointment slot count so it
             // could be used to discourage user misbehavior.
-            Err(_) => {
+            Err(e) => {
                 log::info!(
+                    reason:? = e;
                     "The appointment contained invalid data {}",
                     appointment.locator()
                 );
+                self.metrics.appointment_invalid();
                 TriggeredAppointment::Invalid
             }
         }
@@ -508,7 +927,10 @@ impl Watcher {
         let user_id = self
             .gatekeeper
             .authenticate_user(message.as_bytes(), user_signature)
-            .map_err(|_| GetAppointmentFailure::AuthenticationFailure)?;
+            .map_err(|e| match e {
+                AuthenticationFailure::Banned => GetAppointmentFailure::UserBanned,
+                _ => GetAppointmentFailure::AuthenticationFailure,
+            })?;
 
         let (has_subscription_expired, expiry) =
             self.gatekeeper.has_subscription_expired(user_id).unwrap();
@@ -521,12 +943,7 @@ impl Watcher {
 
         if self.appointments.lock().unwrap().contains_key(&uuid) {
             Ok(AppointmentInfo::Appointment(
-                self.dbm
-                    .lock()
-                    .unwrap()
-                    .load_appointment(uuid)
-                    .unwrap()
-                    .inner,
+                self.dbm.load_appointment(uuid).unwrap().inner,
             ))
         } else {
             self.responder
@@ -589,7 +1006,7 @@ impl Watcher {
         let mut decrypted_blobs: HashMap<Vec<u8>, Transaction> = HashMap::new();
 
         let locator_uuid_map = self.locator_uuid_map.lock().unwrap();
-        let dbm = self.dbm.lock().unwrap();
+        let dbm = &self.dbm;
         for (locator, dispute_tx) in breaches.into_iter() {
             for uuid in locator_uuid_map.get(&locator).unwrap() {
                 let appointment = dbm.load_appointment(*uuid).unwrap();
@@ -647,9 +1064,15 @@ impl Watcher {
                     "{} cannot be completed, it contains invalid data. Deleting appointment",
                     uuid
                 ),
+                DeletionReason::Rejected => {
+                    log::info!("{} rejected by the Responder. Deleting appointment", uuid)
+                }
                 DeletionReason::Accepted => {
                     log::info!("{} accepted by the Responder. Deleting appointment", uuid)
                 }
+                DeletionReason::Banned => {
+                    log::info!("{} belongs to a banned user. Deleting appointment", uuid)
+                }
             };
             match appointments.remove(uuid) {
                 Some(appointment) => {
@@ -679,10 +1102,22 @@ impl Watcher {
         reason: DeletionReason,
     ) {
         self.delete_appointments_from_memory(uuids, reason);
-        self.dbm
-            .lock()
-            .unwrap()
-            .batch_remove_appointments(uuids, updated_users);
+        self.dbm.batch_remove_appointments(uuids, updated_users);
+    }
+
+    /// Gets the height of the last block processed by the [Watcher].
+    pub(crate) fn get_last_known_block_height(&self) -> u32 {
+        self.last_known_block_height.load(Ordering::Acquire)
+    }
+
+    /// Checks whether the database can be reached.
+    pub(crate) fn is_db_reachable(&self) -> bool {
+        self.dbm.is_reachable()
+    }
+
+    /// Gets aggregate figures about the data held by the tower (from the database).
+    pub(crate) fn get_stats(&self) -> Result<DBStats, DBError> {
+        self.dbm.load_stats()
     }
 
     /// Ges the number of users currently registered with the tower.
@@ -702,12 +1137,92 @@ impl Watcher {
 
     /// Gets all the appointments stored in the [Watcher] (from the database).
     pub(crate) fn get_all_watcher_appointments(&self) -> HashMap<UUID, ExtendedAppointment> {
-        self.dbm.lock().unwrap().load_all_appointments()
+        self.dbm.load_all_appointments()
+    }
+
+    /// Gets a page of the appointments stored in the tower (from the database), optionally filtered by user and status.
+    pub(crate) fn get_appointments(
+        &self,
+        user_id: Option<UserId>,
+        status: Option<AppointmentStatus>,
+        limit: u32,
+        offset: u32,
+    ) -> Vec<(UUID, AppointmentInfo)> {
+        self.dbm.load_appointments(user_id, status, limit, offset)
+    }
+
+    /// Gets the appointments (or trackers) of any user matching the [Locator] of a given dispute txid.
+    pub(crate) fn get_appointments_by_txid(
+        &self,
+        dispute_txid: Txid,
+    ) -> Vec<(UUID, AppointmentInfo)> {
+        self.dbm
+            .load_appointments_by_locator(Locator::new(dispute_txid))
+    }
+
+    /// Exports all the data regarding users held by the tower (from the database).
+    pub(crate) fn export_data(&self) -> ExportedData {
+        self.dbm.export_data()
+    }
+
+    /// Imports data exported from another tower.
+    ///
+    /// Data can only be imported into a fresh tower. Once stored in the database, the data is loaded by the
+    /// [Gatekeeper], the [Responder] and the [Watcher].
+    pub(crate) fn import_data(&self, data: &ExportedData) -> Result<(), ImportDataFailure> {
+        if !(self.is_fresh() && self.responder.is_fresh() && self.gatekeeper.is_fresh()) {
+            return Err(ImportDataFailure::NotFresh);
+        }
+
+        self.dbm.import_data(data).map_err(|e| {
+            log::error!("Couldn't import data. Error: {:?}", e);
+            ImportDataFailure::InvalidData
+        })?;
+
+        self.gatekeeper.reload_users();
+        self.responder.reload_trackers();
+        let (appointments, locator_uuid_map) = Watcher::load_appointments(&self.dbm);
+        *self.appointments.lock().unwrap() = appointments;
+        *self.locator_uuid_map.lock().unwrap() = locator_uuid_map;
+
+        Ok(())
+    }
+
+    /// Gets the number of confirmations of the penalty transaction of a given tracker, based on its [ConfirmationStatus]
+    /// and the last block processed by the tower. Returns zero if the penalty is not confirmed yet.
+    ///
+    /// `bitcoind` is not queried, so this is cheap and never waits on it.
+    pub(crate) fn get_penalty_confirmations(&self, tracker: &TransactionTracker) -> u32 {
+        match tracker.status {
+            ConfirmationStatus::ConfirmedIn(height) => {
+                self.last_known_block_height
+                    .load(Ordering::Acquire)
+                    .saturating_sub(height)
+                    + 1
+            }
+            _ => 0,
+        }
+    }
+
+    /// Checks whether the penalty transaction of a given tracker is likely underfunded given the current fee estimates.
+    pub(crate) fn is_penalty_likely_underfunded(&self, tracker: &TransactionTracker) -> bool {
+        match tracker.fee_rate() {
+            Some(fee_rate) => self.responder.is_likely_underfunded(fee_rate),
+            None => false,
+        }
+    }
+
+    /// Sends the penalty transaction of a tracker held by the [Responder] to the network again.
+    pub(crate) fn broadcast_penalty(
+        &self,
+        uuid: UUID,
+    ) -> Result<(Txid, ConfirmationStatus), BroadcastPenaltyFailure> {
+        self.responder.broadcast_penalty(uuid)
     }
 
     /// Gets all the trackers stored in the [Responder] (from the database).
     pub(crate) fn get_all_responder_trackers(&self) -> HashMap<UUID, TransactionTracker> {
-        self.dbm.lock().unwrap().load_all_trackers()
+        self.dbm.load_all_trackers()
     }
 
     /// Gets the list of all registered user ids.
@@ -720,17 +1235,79 @@ impl Watcher {
         self.gatekeeper.get_user_info(user_id)
     }
 
+    /// Bans a user from the tower, so all their requests are rejected from now on. Returns `None` if the user was
+    /// already banned.
+    ///
+    /// If `drop_appointments` is set, the appointments of the user that are being watched are deleted and their slots
+    /// given back. Appointments that have already been triggered are still responded to. Returns the number of deleted
+    /// appointments otherwise.
+    pub(crate) fn ban_user(&self, user_id: UserId, drop_appointments: bool) -> Option<usize> {
+        if !self.gatekeeper.ban_user(user_id) {
+            return None;
+        }
+        log::info!("User banned: {}", user_id);
+
+        if !drop_appointments {
+            return Some(0);
+        }
+
+        let uuids: HashSet<UUID> = match self.gatekeeper.get_user_info(user_id) {
+            Some(user_info) => {
+                let appointments = self.appointments.lock().unwrap();
+                user_info
+                    .appointments
+                    .into_keys()
+                    .filter(|uuid| appointments.contains_key(uuid))
+                    .collect()
+            }
+            None => HashSet::new(),
+        };
+        if !uuids.is_empty() {
+            let updated_users = self.gatekeeper.delete_appointments_from_memory(
+                &uuids.iter().map(|uuid| (*uuid, user_id)).collect(),
+            );
+            self.delete_appointments(&uuids, &updated_users, DeletionReason::Banned);
+        }
+
+        Some(uuids.len())
+    }
+
+    /// Lifts the ban of a given user. Returns whether the user was banned.
+    pub(crate) fn unban_user(&self, user_id: UserId) -> bool {
+        let unbanned = self.gatekeeper.unban_user(user_id);
+        if unbanned {
+            log::info!("User unbanned: {}", user_id);
+        }
+        unbanned
+    }
+
+    /// Gets the subscription history of a given user.
+    pub(crate) fn get_subscription_history(&self, user_id: UserId) -> Vec<SubscriptionEvent> {
+        self.gatekeeper.get_subscription_history(user_id)
+    }
+
     /// Gets information about a user's subscription.
     pub(crate) fn get_subscription_info(
         &self,
         signature: &str,
+        challenge: Option<&str>,
     ) -> Result<(UserInfo, Vec<Locator>), GetSubscriptionInfoFailure> {
-        let message = "get subscription info".to_string();
+        let message = match challenge {
+            // Only nonces are accepted as challenges, so no other message signed by the user can be passed as one
+            Some(challenge) if matches!(hex::decode(challenge), Ok(nonce) if nonce.len() == 32) => {
+                challenge.to_owned()
+            }
+            Some(_) => return Err(GetSubscriptionInfoFailure::AuthenticationFailure),
+            None => "get subscription info".to_string(),
+        };
 
         let user_id = self
             .gatekeeper
             .authenticate_user(message.as_bytes(), signature)
-            .map_err(|_| GetSubscriptionInfoFailure::AuthenticationFailure)?;
+            .map_err(|e| match e {
+                AuthenticationFailure::Banned => GetSubscriptionInfoFailure::UserBanned,
+                _ => GetSubscriptionInfoFailure::AuthenticationFailure,
+            })?;
 
         let (has_subscription_expired, expiry) =
             self.gatekeeper.has_subscription_expired(user_id).unwrap();
@@ -743,7 +1320,7 @@ impl Watcher {
         let mut locators = Vec::new();
 
         let appointments = self.appointments.lock().unwrap();
-        let dbm = self.dbm.lock().unwrap();
+        let dbm = &self.dbm;
         for uuid in subscription_info.appointments.keys() {
             match appointments.get(uuid) {
                 Some(a) => locators.push(a.locator),
@@ -805,8 +1382,16 @@ impl chain::Listen for Watcher {
             let (valid_breaches, invalid_breaches) =
                 self.filter_breaches(self.get_breaches(locator_tx_map));
 
+            // Appointments whose blob does not decrypt to a transaction are never handed to the Responder
+            for (uuid, e) in invalid_breaches.iter() {
+                log::info!(reason:? = e; "Encrypted blob of {} does not decrypt to a valid transaction", uuid);
+                self.metrics.appointment_invalid();
+            }
+            let invalid_appointments: HashSet<UUID> =
+                HashSet::from_iter(invalid_breaches.into_keys());
+
             // Send data to the Responder
-            let mut appointments_to_delete = HashSet::from_iter(invalid_breaches.into_keys());
+            let mut rejected_appointments = HashSet::new();
             let mut delivered_appointments = HashSet::new();
             for (uuid, breach) in valid_breaches {
                 log::info!(
@@ -819,13 +1404,17 @@ impl chain::Listen for Watcher {
                     breach,
                     self.appointments.lock().unwrap()[&uuid].user_id,
                 ) {
-                    appointments_to_delete.insert(uuid);
+                    rejected_appointments.insert(uuid);
                 } else {
                     delivered_appointments.insert(uuid);
                 }
             }
 
             // Delete data
+            let appointments_to_delete: HashSet<UUID> = invalid_appointments
+                .union(&rejected_appointments)
+                .cloned()
+                .collect();
             let appointments_to_delete_gatekeeper = {
                 let appointments = self.appointments.lock().unwrap();
                 appointments_to_delete
@@ -834,12 +1423,23 @@ impl chain::Listen for Watcher {
                     .collect()
             };
             self.delete_appointments_from_memory(&delivered_appointments, DeletionReason::Accepted);
-            self.delete_appointments(
+            // Keep track of the triggering block so the appointments can be watched again if it gets disconnected
+            for uuid in delivered_appointments.iter() {
+                if let Err(e) = self.dbm.store_trigger_block(*uuid, &block.block_hash()) {
+                    log::error!(
+                        "Couldn't store the trigger block of {}. Error: {:?}",
+                        uuid,
+                        e
+                    );
+                }
+            }
+            self.delete_appointments_from_memory(&invalid_appointments, DeletionReason::Invalid);
+            self.delete_appointments_from_memory(&rejected_appointments, DeletionReason::Rejected);
+            self.dbm.batch_remove_appointments(
                 &appointments_to_delete,
                 &self
                     .gatekeeper
                     .delete_appointments_from_memory(&appointments_to_delete_gatekeeper),
-                DeletionReason::Invalid,
             );
 
             if self.appointments.lock().unwrap().is_empty() {
@@ -855,9 +1455,39 @@ impl chain::Listen for Watcher {
     /// Handle reorgs in the [Watcher].
     ///
     /// Fixes the [LocatorCache] by removing the disconnected data and updates the last_known_block_height.
+    /// Appointments triggered in the disconnected block are taken back from the [Responder] and watched again, so they
+    /// are triggered anew if the dispute transaction makes it to the new chain.
     fn block_disconnected(&self, header: &BlockHeader, height: u32) {
         log::warn!("Block disconnected: {}", header.block_hash());
         self.locator_cache.lock().unwrap().fix(header);
+
+        for uuid in self.dbm.load_triggered_in(&header.block_hash()) {
+            if !self.responder.untrack(uuid) {
+                // The tracker may have been deleted already (e.g. if it was rejected during rebroadcast)
+                log::info!("Tracker not found in the Responder: {}", uuid);
+                continue;
+            }
+            match self.dbm.load_appointment(uuid) {
+                Ok(appointment) => {
+                    log::info!("Watching reorged out appointment again: {}", uuid);
+                    self.appointments
+                        .lock()
+                        .unwrap()
+                        .insert(uuid, appointment.get_summary());
+                    self.locator_uuid_map
+                        .lock()
+                        .unwrap()
+                        .entry(appointment.locator())
+                        .or_default()
+                        .insert(uuid);
+                }
+                Err(_) => log::error!(
+                    "Reorged out appointment not found in the database: {}",
+                    uuid
+                ),
+            }
+        }
+
         self.last_known_block_height
             .store(height - 1, Ordering::Release);
     }
@@ -867,23 +1497,31 @@ impl chain::Listen for Watcher {
 mod tests {
     use super::*;
     use std::ops::Deref;
-    use std::sync::{Arc, Mutex};
+    use std::sync::{Arc, Condvar, Mutex};
 
+    use crate::chain_monitor::ChainMonitor;
     use crate::dbm::{Error as DBError, DBM};
     use crate::responder::ConfirmationStatus;
     use crate::rpc_errors;
     use crate::test_utils::{
         create_carrier, create_responder, create_watcher, generate_dummy_appointment,
         generate_dummy_appointment_with_user, generate_uuid, get_last_n_blocks, get_random_breach,
-        get_random_tx, store_appointment_and_fks_to_db, BitcoindMock, Blockchain, MockOptions,
-        MockedServerQuery, DURATION, EXPIRY_DELTA, SLOTS, START_HEIGHT,
+        get_random_tracker, get_random_tx, get_random_user_id, store_appointment_and_fks_to_db,
+        BitcoindMock, Blockchain, MockOptions, MockedServerQuery, DURATION, EXPIRY_DELTA,
+        MAX_APPOINTMENT_SIZE, MIN_TO_SELF_DELAY, SLOTS, START_HEIGHT,
     };
     use teos_common::cryptography::{get_random_bytes, get_random_keypair};
 
+    use bitcoin::consensus;
     use bitcoin::hash_types::Txid;
-    use bitcoin::hashes::Hash;
+    use bitcoin::hashes::{sha256, Hash};
+    use bitcoin::network::constants::Network;
     use bitcoin::secp256k1::{PublicKey, Secp256k1};
+    use chacha20poly1305::aead::{Aead, NewAead};
+    use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
     use lightning::chain::Listen;
+    use lightning_block_sync::poll::{ChainPoller, ValidatedBlockHeader};
+    use lightning_block_sync::{SpvClient, UnboundedCache};
 
     impl PartialEq for Watcher {
         fn eq(&self, other: &Self) -> bool {
@@ -901,14 +1539,22 @@ mod tests {
             self.responder
                 .add_random_tracker(uuid, ConfirmationStatus::ConfirmedIn(100));
         }
+
+        pub(crate) fn make_db_read_only(&self) {
+            self.dbm.make_read_only();
+        }
+
+        pub(crate) fn get_gatekeeper(&self) -> Arc<Gatekeeper> {
+            self.gatekeeper.clone()
+        }
     }
 
     async fn init_watcher(chain: &mut Blockchain) -> Watcher {
-        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let dbm = Arc::new(DBM::in_memory().unwrap());
         init_watcher_with_db(chain, dbm).await
     }
 
-    async fn init_watcher_with_db(chain: &mut Blockchain, dbm: Arc<Mutex<DBM>>) -> Watcher {
+    async fn init_watcher_with_db(chain: &mut Blockchain, dbm: Arc<DBM>) -> Watcher {
         let bitcoind_mock = BitcoindMock::new(MockOptions::empty());
 
         let gk = Arc::new(Gatekeeper::new(
@@ -916,6 +1562,7 @@ mod tests {
             SLOTS,
             DURATION,
             EXPIRY_DELTA,
+            0,
             dbm.clone(),
         ));
         let responder = create_responder(chain.tip(), gk.clone(), dbm.clone(), bitcoind_mock.url());
@@ -1066,55 +1713,166 @@ mod tests {
     async fn test_new() {
         // A fresh watcher has no associated data
         let mut chain = Blockchain::default().with_height(START_HEIGHT);
-        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
-        let watcher = init_watcher_with_db(&mut chain, dbm.clone()).await;
-        assert!(watcher.is_fresh());
+        let dbm = Arc::new(DBM::in_memory().unwrap());
+        let watcher = init_watcher_with_db(&mut chain, dbm.clone()).await;
+        assert!(watcher.is_fresh());
+
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        watcher.register(user_id, None).unwrap();
+        let appointment = generate_dummy_appointment(None).inner;
+
+        // If we add some trackers to the system and create a new Responder reusing the same db
+        // (as if simulating a bootstrap from existing data), the data should be properly loaded.
+        for _ in 0..10 {
+            let user_sig = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+            watcher
+                .add_appointment(appointment.clone(), user_sig.clone())
+                .unwrap();
+        }
+
+        // Create a new Responder reusing the same DB and check that the data is loaded
+        let another_w = init_watcher_with_db(&mut chain, dbm).await;
+        assert!(!another_w.is_fresh());
+        assert_eq!(watcher, another_w);
+    }
+
+    #[tokio::test]
+    async fn test_register() {
+        // register calls Gatekeeper::add_update_user and signs the UserInfo returned by it.
+        // Not testing the update / rejection logic, since that's already covered in the Gatekeeper, just that the data makes
+        // sense and the signature verifies.
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let watcher = init_watcher(&mut chain).await;
+        let tower_pk = watcher.tower_id().0;
+
+        let (_, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        let receipt = watcher.register(user_id, None).unwrap();
+
+        assert_eq!(receipt.user_id(), user_id);
+        assert_eq!(receipt.available_slots(), SLOTS);
+        assert_eq!(
+            receipt.subscription_expiry(),
+            START_HEIGHT as u32 + DURATION
+        );
+
+        assert!(cryptography::verify(
+            &receipt.serialize(),
+            &receipt.signature().unwrap(),
+            &tower_pk
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_ban_unban_user() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let watcher = init_watcher(&mut chain).await;
 
         let (user_sk, user_pk) = get_random_keypair();
         let user_id = UserId(user_pk);
-        watcher.register(user_id).unwrap();
+        watcher.register(user_id, None).unwrap();
         let appointment = generate_dummy_appointment(None).inner;
+        let user_sig = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        watcher
+            .add_appointment(appointment.clone(), user_sig.clone())
+            .unwrap();
 
-        // If we add some trackers to the system and create a new Responder reusing the same db
-        // (as if simulating a bootstrap from existing data), the data should be properly loaded.
-        for _ in 0..10 {
-            let user_sig = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
-            watcher
-                .add_appointment(appointment.clone(), user_sig.clone())
-                .unwrap();
-        }
+        // Banning a user keeps their appointments unless requested otherwise
+        assert_eq!(watcher.ban_user(user_id, false), Some(0));
+        assert_eq!(watcher.ban_user(user_id, true), None);
+        assert_eq!(watcher.get_all_watcher_appointments().len(), 1);
 
-        // Create a new Responder reusing the same DB and check that the data is loaded
-        let another_w = init_watcher_with_db(&mut chain, dbm).await;
-        assert!(!another_w.is_fresh());
-        assert_eq!(watcher, another_w);
+        // Banned users cannot interact with the tower
+        assert!(matches!(
+            watcher.register(user_id, None),
+            Err(RegisterFailure::UserBanned)
+        ));
+        assert!(matches!(
+            watcher.add_appointment(appointment.clone(), user_sig.clone()),
+            Err(AddAppointmentFailure::UserBanned)
+        ));
+        assert!(matches!(
+            watcher.get_subscription_info(
+                &cryptography::sign("get subscription info".as_bytes(), &user_sk).unwrap(),
+                None
+            ),
+            Err(GetSubscriptionInfoFailure::UserBanned)
+        ));
+
+        // Unbanned users can carry on
+        assert!(watcher.unban_user(user_id));
+        assert!(!watcher.unban_user(user_id));
+        watcher
+            .add_appointment(appointment.clone(), user_sig.clone())
+            .unwrap();
+
+        // If requested, the appointments of the user are dropped and their slots given back
+        assert_eq!(watcher.ban_user(user_id, true), Some(1));
+        assert!(watcher.get_all_watcher_appointments().is_empty());
+        assert!(watcher.locator_uuid_map.lock().unwrap().is_empty());
+        let user_info = watcher.get_user_info(user_id).unwrap();
+        assert!(user_info.appointments.is_empty());
+        assert_eq!(user_info.available_slots, SLOTS);
+        assert!(watcher.dbm.load_all_appointments().is_empty());
     }
 
     #[tokio::test]
-    async fn test_register() {
-        // register calls Gatekeeper::add_update_user and signs the UserInfo returned by it.
-        // Not testing the update / rejection logic, since that's already covered in the Gatekeeper, just that the data makes
-        // sense and the signature verifies.
+    async fn test_rotate_key() {
         let mut chain = Blockchain::default().with_height(START_HEIGHT);
         let watcher = init_watcher(&mut chain).await;
-        let tower_pk = watcher.tower_id.0;
+        let old_tower_id = watcher.tower_id();
 
         let (_, user_pk) = get_random_keypair();
         let user_id = UserId(user_pk);
-        let receipt = watcher.register(user_id).unwrap();
+        let old_receipt = watcher.register(user_id, None).unwrap();
+
+        // Rotate the key, reissuing the registration receipts
+        let grace_period = 10;
+        let (tower_id, receipts) = watcher.rotate_key(grace_period, true).unwrap();
+        assert_ne!(tower_id, old_tower_id);
+        assert_eq!(watcher.tower_id(), tower_id);
+        assert_eq!(
+            watcher.dbm.load_tower_key().unwrap(),
+            watcher.keys.lock().unwrap().signing_key
+        );
 
+        assert_eq!(receipts.len(), 1);
+        let receipt = &receipts[0];
         assert_eq!(receipt.user_id(), user_id);
-        assert_eq!(receipt.available_slots(), SLOTS);
+        assert_eq!(receipt.available_slots(), old_receipt.available_slots());
         assert_eq!(
             receipt.subscription_expiry(),
-            START_HEIGHT as u32 + DURATION
+            old_receipt.subscription_expiry()
         );
-
         assert!(cryptography::verify(
             &receipt.serialize(),
             &receipt.signature().unwrap(),
-            &tower_pk
+            &tower_id.0
+        ));
+
+        // New receipts are signed with the new key
+        let new_receipt = watcher.register(user_id, None).unwrap();
+        assert!(cryptography::verify(
+            &new_receipt.serialize(),
+            &new_receipt.signature().unwrap(),
+            &tower_id.0
         ));
+
+        // Old receipts are still honoured during the grace period, but not after it
+        let old_signature = old_receipt.signature().unwrap();
+        assert!(watcher.verify_tower_signature(&old_receipt.serialize(), &old_signature));
+        watcher
+            .last_known_block_height
+            .store(START_HEIGHT as u32 + grace_period, Ordering::Release);
+        assert!(watcher.verify_tower_signature(&old_receipt.serialize(), &old_signature));
+        watcher
+            .last_known_block_height
+            .store(START_HEIGHT as u32 + grace_period + 1, Ordering::Release);
+        assert!(!watcher.verify_tower_signature(&old_receipt.serialize(), &old_signature));
+
+        // Signatures made with the current key are always valid
+        assert!(watcher.verify_tower_signature(&receipt.serialize(), &receipt.signature().unwrap()));
     }
 
     #[tokio::test]
@@ -1135,11 +1893,11 @@ mod tests {
 
         let tower_id: UserId = UserId(PublicKey::from_secret_key(
             &Secp256k1::new(),
-            &watcher.signing_key,
+            &watcher.keys.lock().unwrap().signing_key,
         ));
         let (user_sk, user_pk) = get_random_keypair();
         let user_id = UserId(user_pk);
-        watcher.register(user_id).unwrap();
+        watcher.register(user_id, None).unwrap();
         let appointment = generate_dummy_appointment(None).inner;
 
         // Add the appointment for a new user (twice so we can check that updates work)
@@ -1155,7 +1913,7 @@ mod tests {
         // Add the same appointment but for another user
         let (user2_sk, user2_pk) = get_random_keypair();
         let user2_id = UserId(user2_pk);
-        watcher.register(user2_id).unwrap();
+        watcher.register(user2_id, None).unwrap();
 
         let user2_sig = cryptography::sign(&appointment.serialize(), &user2_sk).unwrap();
         let (receipt, slots, expiry) = watcher
@@ -1174,7 +1932,7 @@ mod tests {
         // Check data was added to the database
         for uuid in watcher.appointments.lock().unwrap().keys() {
             assert!(matches!(
-                watcher.dbm.lock().unwrap().load_appointment(*uuid),
+                watcher.dbm.load_appointment(*uuid),
                 Ok(ExtendedAppointment { .. })
             ));
         }
@@ -1224,11 +1982,11 @@ mod tests {
 
         // Check data was added to the database
         assert!(matches!(
-            watcher.dbm.lock().unwrap().load_appointment(uuid),
+            watcher.dbm.load_appointment(uuid),
             Ok(ExtendedAppointment { .. })
         ));
         assert!(matches!(
-            watcher.dbm.lock().unwrap().load_tracker(uuid),
+            watcher.dbm.load_tracker(uuid),
             Ok(TransactionTracker { .. })
         ));
 
@@ -1249,17 +2007,17 @@ mod tests {
 
         // Data should not be in the database
         assert!(matches!(
-            watcher.dbm.lock().unwrap().load_appointment(uuid),
+            watcher.dbm.load_appointment(uuid),
             Err(DBError::NotFound)
         ));
         assert!(matches!(
-            watcher.dbm.lock().unwrap().load_tracker(uuid),
+            watcher.dbm.load_tracker(uuid),
             Err(DBError::NotFound)
         ));
 
         // Transaction rejected
         // Update the Responder with a new Carrier
-        *watcher.responder.get_carrier().lock().unwrap() = create_carrier(
+        *watcher.responder.get_carrier().write().unwrap() = create_carrier(
             MockedServerQuery::Error(rpc_errors::RPC_VERIFY_ERROR as i64),
             chain.tip().deref().height,
         );
@@ -1276,7 +2034,7 @@ mod tests {
 
         // Data should not be in the database
         assert!(matches!(
-            watcher.dbm.lock().unwrap().load_appointment(uuid),
+            watcher.dbm.load_appointment(uuid),
             Err(DBError::NotFound)
         ));
 
@@ -1292,7 +2050,7 @@ mod tests {
         ));
         // Data should not be in the database
         assert!(matches!(
-            watcher.dbm.lock().unwrap().load_appointment(uuid),
+            watcher.dbm.load_appointment(uuid),
             Err(DBError::NotFound)
         ));
 
@@ -1317,7 +2075,7 @@ mod tests {
         ));
         // Data should not be in the database
         assert!(matches!(
-            watcher.dbm.lock().unwrap().load_appointment(uuid),
+            watcher.dbm.load_appointment(uuid),
             Err(DBError::NotFound)
         ));
 
@@ -1337,9 +2095,249 @@ mod tests {
         ));
         // Data should not be in the database
         assert!(matches!(
-            watcher.dbm.lock().unwrap().load_appointment(uuid),
+            watcher.dbm.load_appointment(uuid),
+            Err(DBError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_add_appointment_blob_too_big() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let watcher = init_watcher(&mut chain).await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        watcher.register(user_id, None).unwrap();
+
+        // Appointments with a blob bigger than the limit are rejected before touching the user subscription or the database
+        let mut appointment = generate_dummy_appointment(None).inner;
+        appointment.encrypted_blob = get_random_bytes(MAX_APPOINTMENT_SIZE + 1);
+        let user_sig = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        assert!(matches!(
+            watcher.add_appointment(appointment.clone(), user_sig),
+            Err(AddAppointmentFailure::BlobTooBig(MAX_APPOINTMENT_SIZE))
+        ));
+
+        let uuid = UUID::new(appointment.locator, user_id);
+        assert!(watcher.appointments.lock().unwrap().is_empty());
+        assert!(matches!(
+            watcher.dbm.load_appointment(uuid),
             Err(DBError::NotFound)
         ));
+        assert_eq!(
+            watcher.get_user_info(user_id).unwrap().available_slots,
+            SLOTS
+        );
+
+        // The same appointment with a regular sized blob is fine
+        appointment.encrypted_blob = generate_dummy_appointment(None).inner.encrypted_blob;
+        let user_sig = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        assert!(watcher.add_appointment(appointment, user_sig).is_ok());
+        assert!(watcher.dbm.load_appointment(uuid).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_add_appointment_blob_too_small() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let watcher = init_watcher(&mut chain).await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        watcher.register(user_id, None).unwrap();
+
+        // Blobs too short to hold an encrypted transaction are rejected
+        let mut appointment = generate_dummy_appointment(None).inner;
+        appointment.encrypted_blob = get_random_bytes(ENCRYPTED_BLOB_MIN_SIZE - 1);
+        let user_sig = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        assert!(matches!(
+            watcher.add_appointment(appointment.clone(), user_sig),
+            Err(AddAppointmentFailure::BlobTooSmall(ENCRYPTED_BLOB_MIN_SIZE))
+        ));
+        assert!(watcher.appointments.lock().unwrap().is_empty());
+        assert_eq!(
+            watcher.get_user_info(user_id).unwrap().available_slots,
+            SLOTS
+        );
+
+        // Properly encrypted blobs are accepted
+        let dispute_tx = get_random_tx();
+        appointment.locator = Locator::new(dispute_tx.txid());
+        appointment.encrypted_blob =
+            cryptography::encrypt(&get_random_tx(), &dispute_tx.txid()).unwrap();
+        let user_sig = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        assert!(watcher.add_appointment(appointment, user_sig).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_add_appointment_events() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let event_sender = events::channel();
+        let mut receiver = event_sender.subscribe();
+        let watcher = init_watcher(&mut chain).await.with_events(event_sender);
+
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        watcher.register(user_id, None).unwrap();
+
+        // Accepted appointments are published
+        let appointment = generate_dummy_appointment(None).inner;
+        let user_sig = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        watcher
+            .add_appointment(appointment.clone(), user_sig)
+            .unwrap();
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            Event::appointment_accepted(user_id, appointment.locator)
+        );
+
+        // So are rejected ones, as long as the user can be identified
+        let mut appointment = generate_dummy_appointment(None).inner;
+        appointment.to_self_delay = MIN_TO_SELF_DELAY - 1;
+        let user_sig = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        assert!(watcher
+            .add_appointment(appointment.clone(), user_sig)
+            .is_err());
+        assert!(matches!(
+            receiver.try_recv().unwrap(),
+            Event::AppointmentRejected { locator, .. } if locator == appointment.locator.to_string()
+        ));
+
+        // Appointments from non-registered users are not
+        let appointment = generate_dummy_appointment(None).inner;
+        let (wrong_sk, _) = get_random_keypair();
+        let user_sig = cryptography::sign(&appointment.serialize(), &wrong_sk).unwrap();
+        assert!(watcher.add_appointment(appointment, user_sig).is_err());
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_update_appointment() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let watcher = init_watcher(&mut chain).await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        watcher.register(user_id, None).unwrap();
+
+        // Appointments the user does not have cannot be updated
+        let mut appointment = generate_dummy_appointment(None).inner;
+        let user_sig = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        assert!(matches!(
+            watcher.update_appointment(appointment.clone(), user_sig.clone()),
+            Err(UpdateAppointmentFailure::NotFound)
+        ));
+        assert!(watcher.appointments.lock().unwrap().is_empty());
+
+        // Existing ones can, getting a new version every time
+        let (_, available_slots, _) = watcher
+            .add_appointment(appointment.clone(), user_sig)
+            .unwrap();
+        let uuid = UUID::new(appointment.locator, user_id);
+        for version in 1..3 {
+            appointment.encrypted_blob.reverse();
+            let user_sig = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+            let (receipt, slots, _, new_version) = watcher
+                .update_appointment(appointment.clone(), user_sig.clone())
+                .unwrap();
+
+            assert_eq!(new_version, version);
+            assert_eq!(slots, available_slots);
+            assert_eq!(receipt.user_signature(), user_sig);
+            let stored = watcher.dbm.load_appointment(uuid).unwrap();
+            assert_eq!(stored.inner, appointment);
+            assert_eq!(stored.user_signature, user_sig);
+        }
+
+        // But only by the user that sent them
+        let (other_sk, other_pk) = get_random_keypair();
+        watcher.register(UserId(other_pk), None).unwrap();
+        let other_sig = cryptography::sign(&appointment.serialize(), &other_sk).unwrap();
+        assert!(matches!(
+            watcher.update_appointment(appointment.clone(), other_sig),
+            Err(UpdateAppointmentFailure::NotFound)
+        ));
+
+        // Updates are checked as new appointments are, and rejected ones leave the appointment untouched
+        let mut rejected = appointment.clone();
+        rejected.to_self_delay = MIN_TO_SELF_DELAY - 1;
+        let user_sig = cryptography::sign(&rejected.serialize(), &user_sk).unwrap();
+        assert!(matches!(
+            watcher.update_appointment(rejected, user_sig),
+            Err(UpdateAppointmentFailure::Rejected(
+                AddAppointmentFailure::ToSelfDelayTooSmall(MIN_TO_SELF_DELAY)
+            ))
+        ));
+        assert_eq!(
+            watcher.dbm.load_appointment(uuid).unwrap().inner,
+            appointment
+        );
+        assert_eq!(watcher.dbm.load_appointment_version(uuid), 2);
+
+        // Appointments deleted after being checked are not re-created by the update, and the slots are given back
+        watcher
+            .delete_appointments_from_memory(&HashSet::from_iter([uuid]), DeletionReason::Outdated);
+        let user_sig = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        assert!(matches!(
+            watcher.try_add_appointment(appointment, user_sig, true),
+            Err(UpdateAppointmentFailure::NotFound)
+        ));
+        assert!(!watcher.appointments.lock().unwrap().contains_key(&uuid));
+        let user_info = watcher.gatekeeper.get_user_info(user_id).unwrap();
+        assert!(!user_info.appointments.contains_key(&uuid));
+        assert_eq!(user_info.available_slots, SLOTS);
+        assert_eq!(
+            watcher.dbm.load_user(user_id).unwrap().available_slots,
+            SLOTS
+        );
+
+        // Updates that cannot be stored fail, and the version is left untouched
+        let mut appointment = generate_dummy_appointment(None).inner;
+        let user_sig = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        watcher
+            .add_appointment(appointment.clone(), user_sig)
+            .unwrap();
+        let uuid = UUID::new(appointment.locator, user_id);
+
+        watcher.dbm.make_appointment_updates_fail();
+        appointment.encrypted_blob.reverse();
+        let user_sig = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        assert!(matches!(
+            watcher.update_appointment(appointment, user_sig),
+            Err(UpdateAppointmentFailure::Rejected(
+                AddAppointmentFailure::DBError
+            ))
+        ));
+        assert_eq!(watcher.dbm.load_appointment_version(uuid), 0);
+    }
+
+    #[tokio::test]
+    async fn test_add_appointment_min_to_self_delay() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let watcher = init_watcher(&mut chain).await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        watcher.register(UserId(user_pk), None).unwrap();
+
+        // Appointments below the threshold are rejected, returning the required minimum
+        let mut appointment = generate_dummy_appointment(None).inner;
+        appointment.to_self_delay = MIN_TO_SELF_DELAY - 1;
+        let user_sig = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        assert!(matches!(
+            watcher.add_appointment(appointment.clone(), user_sig),
+            Err(AddAppointmentFailure::ToSelfDelayTooSmall(
+                MIN_TO_SELF_DELAY
+            ))
+        ));
+        assert!(watcher.appointments.lock().unwrap().is_empty());
+
+        // Appointments at or above the threshold are accepted
+        for to_self_delay in [MIN_TO_SELF_DELAY, MIN_TO_SELF_DELAY + 1] {
+            appointment.to_self_delay = to_self_delay;
+            let user_sig = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+            assert!(watcher
+                .add_appointment(appointment.clone(), user_sig)
+                .is_ok());
+        }
     }
 
     #[tokio::test]
@@ -1350,13 +2348,25 @@ mod tests {
         // Register the user
         let (_, user_pk) = get_random_keypair();
         let user_id = UserId(user_pk);
-        watcher.register(user_id).unwrap();
+        watcher.register(user_id, None).unwrap();
 
         let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
 
+        // Appointments that are not in the Watcher are not stored if only updates are allowed
+        assert_eq!(
+            watcher.store_appointment(uuid, &appointment, true),
+            StoredAppointment::NotFound,
+        );
+        assert!(watcher.appointments.lock().unwrap().is_empty());
+        assert!(watcher.locator_uuid_map.lock().unwrap().is_empty());
+        assert!(matches!(
+            watcher.dbm.load_appointment(uuid),
+            Err(DBError::NotFound)
+        ));
+
         // Storing a new appointment should return New
         assert_eq!(
-            watcher.store_appointment(uuid, &appointment),
+            watcher.store_appointment(uuid, &appointment, false),
             StoredAppointment::New,
         );
         assert_eq!(
@@ -1371,8 +2381,8 @@ mod tests {
         // Adding an appointment with the same UUID should be seen as an updated
         // The appointment data here does not matter much, just the UUID and the locator since they are tied to each other.
         assert_eq!(
-            watcher.store_appointment(uuid, &appointment),
-            StoredAppointment::Update,
+            watcher.store_appointment(uuid, &appointment, false),
+            StoredAppointment::Update(1),
         );
         assert_eq!(
             *watcher.appointments.lock().unwrap(),
@@ -1387,7 +2397,7 @@ mod tests {
         // This means that a different user is sending an appointment with the same locator.
         let new_uuid = generate_uuid();
         assert_eq!(
-            watcher.store_appointment(new_uuid, &appointment),
+            watcher.store_appointment(new_uuid, &appointment, false),
             StoredAppointment::Collision,
         );
         assert_eq!(
@@ -1411,7 +2421,7 @@ mod tests {
         // Register the user
         let (_, user_pk) = get_random_keypair();
         let user_id = UserId(user_pk);
-        watcher.register(user_id).unwrap();
+        watcher.register(user_id, None).unwrap();
 
         let dispute_tx = get_random_tx();
         let (uuid, appointment) =
@@ -1425,13 +2435,13 @@ mod tests {
         // In this case the appointment is kept in the Responder and, therefore, in the database
         assert!(watcher.responder.has_tracker(uuid));
         assert!(matches!(
-            watcher.dbm.lock().unwrap().load_appointment(uuid),
+            watcher.dbm.load_appointment(uuid),
             Ok(ExtendedAppointment { .. })
         ));
 
         // A properly formatted but invalid transaction should be rejected by the Responder
         // Update the Responder with a new Carrier that will reject the transaction
-        *watcher.responder.get_carrier().lock().unwrap() = create_carrier(
+        *watcher.responder.get_carrier().write().unwrap() = create_carrier(
             MockedServerQuery::Error(rpc_errors::RPC_VERIFY_ERROR as i64),
             chain.tip().deref().height,
         );
@@ -1444,10 +2454,7 @@ mod tests {
         );
         // In this case the appointment is not kept in the Responder nor in the database
         assert!(!watcher.responder.has_tracker(uuid));
-        assert!(matches!(
-            watcher.dbm.lock().unwrap().load_appointment(uuid),
-            Err { .. }
-        ));
+        assert!(matches!(watcher.dbm.load_appointment(uuid), Err { .. }));
 
         // Invalid triggered appointments should not be passed to the Responder
         // Use a dispute_tx that does not match the appointment to replicate a decryption error
@@ -1459,10 +2466,25 @@ mod tests {
         );
         // The appointment is not kept anywhere
         assert!(!watcher.responder.has_tracker(uuid));
-        assert!(matches!(
-            watcher.dbm.lock().unwrap().load_appointment(uuid),
-            Err { .. }
-        ));
+        assert!(matches!(watcher.dbm.load_appointment(uuid), Err { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_get_penalty_confirmations() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let watcher = init_watcher(&mut chain).await;
+        let height = START_HEIGHT as u32;
+
+        // Confirmations are counted up to the last block processed by the tower
+        for (status, confirmations) in [
+            (ConfirmationStatus::ConfirmedIn(height), 1),
+            (ConfirmationStatus::ConfirmedIn(height - 5), 6),
+            (ConfirmationStatus::InMempoolSince(height - 5), 0),
+            (ConfirmationStatus::ReorgedOut, 0),
+        ] {
+            let tracker = get_random_tracker(get_random_user_id(), status);
+            assert_eq!(watcher.get_penalty_confirmations(&tracker), confirmations);
+        }
     }
 
     #[tokio::test]
@@ -1482,7 +2504,7 @@ mod tests {
         // If the user does exist and there's an appointment with the given locator belonging to him, it will be returned
         let (user_sk, user_pk) = get_random_keypair();
         let user_id = UserId(user_pk);
-        watcher.register(user_id).unwrap();
+        watcher.register(user_id, None).unwrap();
         watcher
             .add_appointment(
                 appointment.clone(),
@@ -1546,7 +2568,7 @@ mod tests {
         // should be returned.
         let (user2_sk, user2_pk) = get_random_keypair();
         let user2_id = UserId(user2_pk);
-        watcher.register(user2_id).unwrap();
+        watcher.register(user2_id, None).unwrap();
 
         let signature2 = cryptography::sign(message.as_bytes(), &user2_sk).unwrap();
         assert!(matches!(
@@ -1647,7 +2669,7 @@ mod tests {
                     .insert(*locator, HashSet::from_iter(vec![uuid]));
 
                 // Store data in the database (the user needs to be there as well since it is a FK for appointments)
-                store_appointment_and_fks_to_db(&watcher.dbm.lock().unwrap(), uuid, &appointment);
+                store_appointment_and_fks_to_db(&watcher.dbm, uuid, &appointment);
             }
         }
 
@@ -1694,7 +2716,7 @@ mod tests {
                 .unwrap()
                 .insert(appointment.locator(), HashSet::from_iter([uuid]));
 
-            store_appointment_and_fks_to_db(&watcher.dbm.lock().unwrap(), uuid, &appointment);
+            store_appointment_and_fks_to_db(&watcher.dbm, uuid, &appointment);
             to_be_deleted.insert(uuid, appointment.locator());
         }
 
@@ -1715,7 +2737,7 @@ mod tests {
 
             // But it can be found in the database
             assert!(matches!(
-                watcher.dbm.lock().unwrap().load_appointment(uuid),
+                watcher.dbm.load_appointment(uuid),
                 Ok(ExtendedAppointment { .. })
             ));
         }
@@ -1751,7 +2773,7 @@ mod tests {
                 .insert(appointment.locator(), HashSet::from_iter([uuid]));
 
             // Add data to the database to check data deletion
-            store_appointment_and_fks_to_db(&watcher.dbm.lock().unwrap(), uuid, &appointment);
+            store_appointment_and_fks_to_db(&watcher.dbm, uuid, &appointment);
 
             // Make it so some of the locators have multiple associated uuids
             if i % 3 == 0 {
@@ -1792,7 +2814,7 @@ mod tests {
             if target_appointments.contains(&uuid) {
                 assert!(!watcher.appointments.lock().unwrap().contains_key(&uuid));
                 assert!(matches!(
-                    watcher.dbm.lock().unwrap().load_appointment(uuid),
+                    watcher.dbm.load_appointment(uuid),
                     Err(DBError::NotFound)
                 ));
 
@@ -1826,7 +2848,7 @@ mod tests {
                     .unwrap()
                     .contains_key(&uuid_locator_map[&uuid]));
                 assert!(matches!(
-                    watcher.dbm.lock().unwrap().load_appointment(uuid),
+                    watcher.dbm.load_appointment(uuid),
                     Ok(ExtendedAppointment { .. })
                 ));
             }
@@ -1835,18 +2857,55 @@ mod tests {
         // The users that needed to be updated in the database have been (just checking the slot count)
         for (id, info) in updated_users {
             assert_eq!(
-                watcher
-                    .dbm
-                    .lock()
-                    .unwrap()
-                    .load_user(id)
-                    .unwrap()
-                    .available_slots,
+                watcher.dbm.load_user(id).unwrap().available_slots,
                 info.available_slots
             );
         }
     }
 
+    #[tokio::test]
+    async fn test_block_connected_invalid_blob() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let watcher = init_watcher(&mut chain).await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        watcher.register(user_id, None).unwrap();
+
+        // Build a blob that decrypts fine with the dispute txid but does not yield a transaction (a serialized
+        // transaction followed by some trailing garbage)
+        let dispute_tx = get_random_tx();
+        let mut not_a_tx = consensus::serialize(&get_random_tx());
+        not_a_tx.push(0);
+        let key = sha256::Hash::hash(&dispute_tx.txid());
+        let mut appointment = generate_dummy_appointment(Some(&dispute_tx.txid())).inner;
+        appointment.encrypted_blob = ChaCha20Poly1305::new(Key::from_slice(&key))
+            .encrypt(&Nonce::default(), not_a_tx.as_ref())
+            .unwrap();
+        assert!(matches!(
+            cryptography::decrypt(&appointment.encrypted_blob, &dispute_tx.txid()),
+            Err(cryptography::DecryptingError::Encode(_))
+        ));
+
+        let uuid = UUID::new(appointment.locator, user_id);
+        let user_sig = cryptography::sign(&appointment.serialize(), &user_sk).unwrap();
+        watcher.add_appointment(appointment, user_sig).unwrap();
+
+        watcher.block_connected(
+            &chain.generate(Some(vec![dispute_tx])),
+            chain.get_block_count(),
+        );
+
+        // The appointment is not handed to the Responder, but deleted and accounted for as invalid
+        assert!(!watcher.responder.has_tracker(uuid));
+        assert!(!watcher.appointments.lock().unwrap().contains_key(&uuid));
+        assert!(matches!(watcher.dbm.load_appointment(uuid), Err { .. }));
+        assert!(watcher
+            .metrics
+            .render()
+            .contains("\nappointments_invalid_total 1\n"));
+    }
+
     #[tokio::test]
     async fn test_block_connected() {
         let mut chain = Blockchain::default().with_height(START_HEIGHT);
@@ -1879,8 +2938,8 @@ mod tests {
         let user_id = UserId(user_pk);
         let (user2_sk, user2_pk) = get_random_keypair();
         let user2_id = UserId(user2_pk);
-        watcher.register(user_id).unwrap();
-        watcher.register(user2_id).unwrap();
+        watcher.register(user_id, None).unwrap();
+        watcher.register(user2_id, None).unwrap();
 
         let appointment = generate_dummy_appointment(None);
         let uuid1 = UUID::new(appointment.locator(), user_id);
@@ -1934,7 +2993,7 @@ mod tests {
                 .contains_key(&uuid1)
         );
         assert!(matches!(
-            watcher.dbm.lock().unwrap().load_appointment(uuid1),
+            watcher.dbm.load_appointment(uuid1),
             Ok(ExtendedAppointment { .. })
         ));
 
@@ -1946,7 +3005,7 @@ mod tests {
                 .contains_key(&uuid2)
         );
         assert!(matches!(
-            watcher.dbm.lock().unwrap().load_appointment(uuid2),
+            watcher.dbm.load_appointment(uuid2),
             Ok(ExtendedAppointment { .. })
         ));
 
@@ -1980,11 +3039,11 @@ mod tests {
 
         // Data should have been kept in the database
         assert!(matches!(
-            watcher.dbm.lock().unwrap().load_appointment(uuid),
+            watcher.dbm.load_appointment(uuid),
             Ok(ExtendedAppointment { .. })
         ));
         assert!(matches!(
-            watcher.dbm.lock().unwrap().load_tracker(uuid),
+            watcher.dbm.load_tracker(uuid),
             Ok(TransactionTracker { .. })
         ));
 
@@ -1996,7 +3055,7 @@ mod tests {
         watcher.add_appointment(appointment.inner, sig).unwrap();
 
         // Set the carrier response
-        *watcher.responder.get_carrier().lock().unwrap() = create_carrier(
+        *watcher.responder.get_carrier().write().unwrap() = create_carrier(
             MockedServerQuery::Error(rpc_errors::RPC_VERIFY_ERROR as i64),
             chain.tip().deref().height,
         );
@@ -2021,11 +3080,11 @@ mod tests {
         );
         // Data should also have been deleted from the database
         assert!(matches!(
-            watcher.dbm.lock().unwrap().load_appointment(uuid),
+            watcher.dbm.load_appointment(uuid),
             Err(DBError::NotFound)
         ));
         assert!(matches!(
-            watcher.dbm.lock().unwrap().load_tracker(uuid),
+            watcher.dbm.load_tracker(uuid),
             Err(DBError::NotFound)
         ));
 
@@ -2060,7 +3119,7 @@ mod tests {
                 .contains_key(&uuid)
         );
         assert!(matches!(
-            watcher.dbm.lock().unwrap().load_appointment(uuid),
+            watcher.dbm.load_appointment(uuid),
             Err(DBError::NotFound)
         ));
     }
@@ -2094,4 +3153,140 @@ mod tests {
             .blocks
             .contains(&last_block_header.block_hash()));
     }
+
+    /// Adds an appointment to the [Watcher] that is triggered by the returned dispute transaction.
+    fn watch_dispute(watcher: &Watcher) -> (UUID, Locator, Transaction) {
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        watcher.register(user_id, None).unwrap();
+
+        let dispute_tx = get_random_tx();
+        let appointment = generate_dummy_appointment(Some(&dispute_tx.txid()));
+        let sig = cryptography::sign(&appointment.inner.serialize(), &user_sk).unwrap();
+        let locator = appointment.locator();
+        watcher.add_appointment(appointment.inner, sig).unwrap();
+
+        (UUID::new(locator, user_id), locator, dispute_tx)
+    }
+
+    /// Feeds the [Watcher] the blocks from `old_tip` to the chain tip, disconnecting the stale ones if needed.
+    async fn sync_watcher(
+        watcher: &Watcher,
+        chain: &mut Blockchain,
+        old_tip: ValidatedBlockHeader,
+        cache: &mut UnboundedCache,
+    ) {
+        let (_, shutdown_signal) = triggered::trigger();
+        let poller = ChainPoller::new(chain, Network::Bitcoin);
+        let spv_client = SpvClient::new(old_tip, poller, cache, watcher);
+        let mut cm = ChainMonitor::new(
+            spv_client,
+            old_tip,
+            Arc::new(DBM::in_memory().unwrap()),
+            1,
+            shutdown_signal,
+            Arc::new((Mutex::new(true), Condvar::new())),
+        )
+        .await;
+        cm.poll_best_tip().await;
+    }
+
+    fn assert_watched_again(watcher: &Watcher, uuid: UUID, locator: Locator) {
+        assert!(watcher.appointments.lock().unwrap().contains_key(&uuid));
+        assert!(watcher.locator_uuid_map.lock().unwrap()[&locator].contains(&uuid));
+        assert!(!watcher.responder.has_tracker(uuid));
+        assert!(matches!(
+            watcher.dbm.load_tracker(uuid),
+            Err(DBError::NotFound)
+        ));
+        assert!(watcher.dbm.load_all_appointments().contains_key(&uuid));
+    }
+
+    #[tokio::test]
+    async fn test_block_disconnected_triggered_appointment() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let watcher = init_watcher(&mut chain).await;
+
+        // Add an appointment and trigger it
+        let (uuid, locator, dispute_tx) = watch_dispute(&watcher);
+        let old_tip = chain.tip();
+        chain.generate(Some(vec![dispute_tx]));
+        let triggering_tip = chain.tip();
+
+        let cache = &mut UnboundedCache::new();
+        sync_watcher(&watcher, &mut chain, old_tip, cache).await;
+
+        // The appointment is now being handled by the Responder
+        assert!(!watcher.appointments.lock().unwrap().contains_key(&uuid));
+        assert!(watcher.responder.has_tracker(uuid));
+
+        // Reorg the triggering block out. The dispute transaction does not make it to the new chain
+        chain.disconnect_tip();
+        chain.generate(None);
+        chain.generate(None);
+        sync_watcher(&watcher, &mut chain, triggering_tip, cache).await;
+
+        // The appointment is watched again, both in memory and in the database
+        assert_watched_again(&watcher, uuid, locator);
+    }
+
+    #[tokio::test]
+    async fn test_block_disconnected_triggered_appointment_deep_reorg() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let watcher = init_watcher(&mut chain).await;
+
+        // Trigger an appointment and bury the triggering block deeper than the LocatorCache goes
+        let (uuid, locator, dispute_tx) = watch_dispute(&watcher);
+        let old_tip = chain.tip();
+        chain.generate(Some(vec![dispute_tx]));
+        let depth = watcher.locator_cache.lock().unwrap().size + 2;
+        for _ in 0..depth {
+            chain.generate(None);
+        }
+
+        let cache = &mut UnboundedCache::new();
+        sync_watcher(&watcher, &mut chain, old_tip, cache).await;
+        assert!(watcher.responder.has_tracker(uuid));
+
+        // Reorg the whole branch out, triggering block included
+        let stale_tip = chain.tip();
+        for _ in 0..depth + 1 {
+            chain.disconnect_tip();
+        }
+        for _ in 0..depth + 2 {
+            chain.generate(None);
+        }
+        sync_watcher(&watcher, &mut chain, stale_tip, cache).await;
+
+        assert_watched_again(&watcher, uuid, locator);
+    }
+
+    #[tokio::test]
+    async fn test_block_disconnected_triggered_appointment_after_restart() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let dbm = Arc::new(DBM::in_memory().unwrap());
+        let watcher = init_watcher_with_db(&mut chain, dbm.clone()).await;
+
+        let (uuid, locator, dispute_tx) = watch_dispute(&watcher);
+        let old_tip = chain.tip();
+        chain.generate(Some(vec![dispute_tx]));
+        let triggering_tip = chain.tip();
+
+        let cache = &mut UnboundedCache::new();
+        sync_watcher(&watcher, &mut chain, old_tip, cache).await;
+        assert!(watcher.responder.has_tracker(uuid));
+
+        // Restart the tower and reorg the triggering block out
+        drop(watcher);
+        let watcher = init_watcher_with_db(&mut chain, dbm).await;
+        assert!(!watcher.appointments.lock().unwrap().contains_key(&uuid));
+        assert!(watcher.responder.has_tracker(uuid));
+
+        chain.disconnect_tip();
+        chain.generate(None);
+        chain.generate(None);
+        sync_watcher(&watcher, &mut chain, triggering_tip, cache).await;
+
+        assert_watched_again(&watcher, uuid, locator);
+    }
 }